@@ -0,0 +1,250 @@
+//! Benchmarks for the work done on each snapshot: parsing it, updating the
+//! aircraft state with it, and looking for close pairs.
+//!
+//! Parsing uses a small bundled fixture by default. To parse a real snapshot
+//! instead, point TRACON_BENCH_SNAPSHOT at one:
+//!
+//! ```text
+//! TRACON_BENCH_SNAPSHOT=2021-02-23-193853Z.json.gz cargo bench
+//! ```
+//!
+//! With the simd_json feature, parsing is also benchmarked with simd-json:
+//!
+//! ```text
+//! cargo bench --features simd_json -- parse
+//! ```
+//!
+//! find_close_pairs is benchmarked with each kind of spatial index.
+//!
+//! The screen group compares the two-pass mode's first pass,
+//! Screener::screen_response, with the full detect_frame_interceptions it's
+//! meant to be cheaper than.
+//!
+//! The state benchmarks use a synthetic snapshot of NUM_AIRCRAFT aircraft
+//! spread over the continental US, a few percent of them fast movers.
+
+use adsbx_json::v2::Response;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::{json, Value};
+use tracon::{
+    interception::{
+        classify_snapshot, detect_frame_interceptions, find_close_pairs, AircraftStateMap,
+        ClassifiedSnapshot, DetectionParams,
+    },
+    load_adsbx_json_file, load_adsbx_json_reader, parse_adsbx_json,
+    screen::{ScreenParams, Screener},
+    spatial::SpatialIndexKind,
+    JsonBackend,
+};
+
+const NUM_AIRCRAFT: usize = 10_000;
+
+/// The seconds between synthetic snapshots.
+const INTERVAL_SECS: i64 = 15;
+
+fn snapshot_path() -> String {
+    std::env::var("TRACON_BENCH_SNAPSHOT")
+        .unwrap_or_else(|_| "tests/data/2021-02-23-193853Z.json".to_string())
+}
+
+// A small linear congruential generator, so the synthetic snapshots are the
+// same every run.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next_f64(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.next_f64()
+    }
+}
+
+// Returns the nth synthetic snapshot. Each aircraft flies in a straight line
+// from the same starting position every time, so successive snapshots make
+// plausible tracks.
+fn synthetic_response(n: i64) -> Response {
+    let mut rng = Lcg(1);
+    let secs = n * INTERVAL_SECS;
+    let aircraft: Vec<Value> = (0..NUM_AIRCRAFT)
+        .map(|i| {
+            let (lon, lat) = (rng.range(-124.0, -70.0), rng.range(25.0, 49.0));
+            let track: f64 = rng.range(0.0, 360.0);
+            let fast = i % 25 == 0;
+            let gs = if fast {
+                rng.range(420.0, 550.0)
+            } else {
+                rng.range(100.0, 340.0)
+            };
+            let alt = rng.range(1000.0, 40_000.0).round() as i32;
+            let dist_deg = gs * secs as f64 / 3600.0 / 60.0;
+            let lat = lat + dist_deg * track.to_radians().cos();
+            let lon = lon + dist_deg * track.to_radians().sin() / lat.to_radians().cos();
+            json!({
+                "hex": format!("{:06x}", 0xa00000 + i), "type": "adsb_icao", "messages": 100,
+                "rssi": -20.0, "seen": 0.0, "seen_pos": 0.0, "lat": lat, "lon": lon, "gs": gs,
+                "track": track, "alt_baro": alt, "alt_geom": alt,
+            })
+        })
+        .collect();
+    let ms = 1614109133000 + secs * 1000;
+    serde_json::from_value(json!({
+        "now": ms, "ctime": ms, "ptime": 10, "total": aircraft.len(), "ac": aircraft,
+    }))
+    .unwrap()
+}
+
+// Returns a state that's seen enough snapshots for the fast movers to be
+// interceptors, and the next snapshot.
+fn warmed_up_state(params: &DetectionParams) -> (AircraftStateMap, Response) {
+    let mut state = AircraftStateMap::new();
+    let mut snapshot = ClassifiedSnapshot::default();
+    let num_snapshots = params.interceptor_min_fast_count as i64 + 1;
+    for n in 0..num_snapshots {
+        classify_snapshot(&mut state, &synthetic_response(n), params, &mut snapshot);
+    }
+    (state, synthetic_response(num_snapshots))
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let path = snapshot_path();
+    let mut group = c.benchmark_group("parse");
+    group.bench_function("load_adsbx_json_file", |b| {
+        b.iter(|| load_adsbx_json_file(&path).unwrap())
+    });
+    // Just the JSON parsing, without the file I/O (or decompression).
+    let json = serde_json::to_vec(&load_adsbx_json_file(&path).unwrap()).unwrap();
+    group.bench_function("load_adsbx_json_reader", |b| {
+        b.iter(|| load_adsbx_json_reader(json.as_slice()).unwrap())
+    });
+    // Each backend parsing from a buffer, both the snapshot and a synthetic
+    // one that's more like a full feed. simd-json clobbers the buffer, so
+    // each iteration gets a fresh copy.
+    let json_10k = serde_json::to_vec(&synthetic_response(0)).unwrap();
+    group.bench_function("load_adsbx_json_reader_10k", |b| {
+        b.iter(|| load_adsbx_json_reader(json_10k.as_slice()).unwrap())
+    });
+    #[allow(unused_mut)]
+    let mut backends = vec![("serde_json", JsonBackend::SerdeJson)];
+    #[cfg(feature = "simd_json")]
+    backends.push(("simd_json", JsonBackend::SimdJson));
+    for (name, backend) in backends {
+        for (input, json) in [("snapshot", &json), ("10k", &json_10k)] {
+            group.bench_function(format!("parse_adsbx_json_{}/{}", input, name), |b| {
+                b.iter_batched(
+                    || json.clone(),
+                    |mut json| parse_adsbx_json(&mut json, backend).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_update(c: &mut Criterion) {
+    let params = DetectionParams::default();
+    let (state, response) = warmed_up_state(&params);
+    c.bench_function("update_state_10k", |b| {
+        b.iter_batched(
+            || state.clone(),
+            |mut state| {
+                for aircraft in &response.aircraft {
+                    let _ = state.update(response.now, aircraft, &params);
+                }
+                state
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+// With each kind of spatial index, including building it.
+fn bench_close_pairs(c: &mut Criterion) {
+    for (name, spatial_index) in [
+        ("rtree", SpatialIndexKind::Rtree),
+        ("grid", SpatialIndexKind::Grid),
+    ] {
+        let params = DetectionParams {
+            spatial_index,
+            ..Default::default()
+        };
+        let (mut state, response) = warmed_up_state(&params);
+        let mut snapshot = ClassifiedSnapshot::default();
+        classify_snapshot(&mut state, &response, &params, &mut snapshot);
+        assert!(!snapshot.fast_movers.is_empty());
+        c.bench_function(&format!("find_close_pairs_10k/{}", name), |b| {
+            b.iter_batched(
+                || state.clone(),
+                |mut state| find_close_pairs(&mut state, response.now, &snapshot, &params),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+// The whole per-snapshot pass, which is what allocation churn shows up in.
+fn bench_detect(c: &mut Criterion) {
+    let params = DetectionParams::default();
+    let (mut state, response) = warmed_up_state(&params);
+    // Once, so the state has buffers from a previous snapshot to reuse.
+    detect_frame_interceptions(&mut state, &response, &params);
+    c.bench_function("detect_frame_interceptions_10k", |b| {
+        b.iter_batched(
+            || state.clone(),
+            |mut state| detect_frame_interceptions(&mut state, &response, &params),
+            BatchSize::LargeInput,
+        )
+    });
+    let num_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    state.set_num_threads(num_threads).unwrap();
+    c.bench_function("detect_frame_interceptions_10k_all_threads", |b| {
+        b.iter_batched(
+            || state.clone(),
+            |mut state| detect_frame_interceptions(&mut state, &response, &params),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+// Both on the same snapshot, after the same warm up.
+fn bench_screen(c: &mut Criterion) {
+    let params = DetectionParams::default();
+    let (mut state, response) = warmed_up_state(&params);
+    detect_frame_interceptions(&mut state, &response, &params);
+    let mut screener = Screener::new(&params, &ScreenParams::default());
+    for n in 0..=params.interceptor_min_fast_count as i64 {
+        screener.screen_response(&synthetic_response(n));
+    }
+    let mut group = c.benchmark_group("screen_10k");
+    group.bench_function("screen_response", |b| {
+        b.iter_batched(
+            || screener.clone(),
+            |mut screener| screener.screen_response(&response),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("detect_frame_interceptions", |b| {
+        b.iter_batched(
+            || state.clone(),
+            |mut state| detect_frame_interceptions(&mut state, &response, &params),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_update,
+    bench_close_pairs,
+    bench_detect,
+    bench_screen
+);
+criterion_main!(benches);
@@ -1,41 +1,1115 @@
+use std::{path::Path, sync::Arc};
+
+use adsbx_json::v2::Response;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
 use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
 use tracon::{
-    for_each_adsbx_json,
-    interception::{url, State},
+    airports::{load_airports, load_military_airfields, AirportDb},
+    config::{Config, InputConfig, LiveConfig},
+    error::Error,
+    events::JsonLinesWriter,
+    export::{export_file, write_groups, ExportFormat},
+    filter::{AircraftFilter, BoundingBoxFilter, MaxAltitudeFilter},
+    for_each_adsbx_json_from_reader,
+    heatmap::{write_heatmap, HeatmapGrid},
+    input::{expand_input_paths, TimeRange},
+    interception::{detect_interception_events, process_adsbx_response, url, State},
+    metrics::Metrics,
+    order::InputOrder,
+    pipeline::AdsbxPipeline,
+    region::Regions,
+    replay::{for_each_replay_snapshot, ReplayWriter},
+    screen::{detect_in_windows, screen_files, ScreenParams},
+    stats::Stats,
+    trace::analyze_trace_files,
+    tracker::InterceptionEvent,
+    trim::trim_files,
+    watch::{DirectoryWatcher, ProcessedFiles},
+    Compression,
 };
 
 #[derive(StructOpt, Debug)]
+#[structopt(about = "Finds military aircraft intercepting other aircraft in ADS-B data")]
 struct CliArgs {
-    #[structopt(help = "Input files")]
+    #[structopt(
+        long,
+        help = "Log level, or tracing filter like tracon=debug, instead of RUST_LOG [default: info]"
+    )]
+    pub log_level: Option<String>,
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Looks for interceptions in snapshot files, stdin, a watched directory,
+    /// or a live API
+    Detect(Box<DetectArgs>),
+    /// Looks for an interception in two aircraft's trace files
+    Analyze(AnalyzeArgs),
+    /// Converts interceptions saved with detect --json to another format
+    Export(ExportArgs),
+    /// Screens snapshot files for stretches of time worth a closer look,
+    /// then runs the detector on just those
+    Screen(ScreenArgs),
+    /// Trims snapshot files to a bounding box and time window, e.g. to make
+    /// test fixtures
+    Trim(TrimArgs),
+}
+
+// Where the settings come from. Flags override --params, which overrides
+// --config.
+#[derive(StructOpt, Debug)]
+struct ParamsArgs {
+    #[structopt(long, help = "TOML config file; see tracon::config")]
+    pub config: Option<String>,
+    #[structopt(long, help = "JSON file with detection thresholds")]
+    pub params: Option<String>,
+    #[structopt(long, help = "Minimum speed of an interceptor, in knots")]
+    pub interceptor_min_speed_kts: Option<f64>,
+    #[structopt(long, help = "Maximum speed of a target, in knots")]
+    pub target_max_speed_kts: Option<f64>,
+    #[structopt(
+        long,
+        help = "Maximum lateral separation for an interception, in meters"
+    )]
+    pub max_lateral_separation_m: Option<f64>,
+    #[structopt(
+        long,
+        help = "Maximum vertical separation for an interception, in feet"
+    )]
+    pub max_vertical_separation_ft: Option<i32>,
+}
+
+impl ParamsArgs {
+    fn config(&self) -> Result<Config, String> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path).map_err(|e| e.to_string())?,
+            None => Config::default(),
+        };
+        let params = &mut config.detection;
+        if let Some(path) = &self.params {
+            let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            *params = serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        if let Some(speed) = self.interceptor_min_speed_kts {
+            params.interceptor_min_speed_kts = speed;
+        }
+        if let Some(speed) = self.target_max_speed_kts {
+            params.target.max_speed_kts = speed;
+        }
+        if let Some(separation) = self.max_lateral_separation_m {
+            params.max_lateral_separation_m = separation;
+        }
+        if let Some(separation) = self.max_vertical_separation_ft {
+            params.max_vertical_separation_ft = separation;
+        }
+        Ok(config)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct AnalyzeArgs {
+    #[structopt(help = "The interceptor's trace file, e.g. trace_full_ae1234.json")]
+    pub interceptor: String,
+    #[structopt(help = "The target's trace file")]
+    pub target: String,
+    #[structopt(flatten)]
+    pub params: ParamsArgs,
+    #[structopt(long, help = "Write the interception to stdout as JSON")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct ExportArgs {
+    #[structopt(help = "JSON Lines file written by detect --json")]
+    pub input: String,
+    #[structopt(long, help = "geojson, kml, csv, html, or cot")]
+    pub format: ExportFormat,
+    #[structopt(short, long, help = "File to write (KML is zipped if it ends in .kmz)")]
+    pub output: String,
+}
+
+#[derive(StructOpt, Debug)]
+struct ScreenArgs {
+    #[structopt(help = "Input files, directories, or glob patterns")]
+    pub paths: Vec<String>,
+    #[structopt(flatten)]
+    pub params: ParamsArgs,
+    #[structopt(
+        long,
+        default_value = "20",
+        help = "Start the second pass this many minutes before each window's first hit"
+    )]
+    pub lead_mins: i64,
+    #[structopt(
+        long,
+        default_value = "5",
+        help = "End the second pass this many minutes after each window's last hit"
+    )]
+    pub trail_mins: i64,
+    #[structopt(long, help = "Only run the first pass, and list the windows")]
+    pub windows_only: bool,
+    #[structopt(
+        long,
+        help = "Write the windows and interceptions to stdout as JSON Lines"
+    )]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct TrimArgs {
+    #[structopt(help = "Input files, directories, or glob patterns")]
+    pub paths: Vec<String>,
+    #[structopt(
+        long,
+        help = "Only keep aircraft inside min_lon,min_lat,max_lon,max_lat"
+    )]
+    pub bbox: String,
+    #[structopt(
+        long,
+        help = "Only keep snapshots from this time on, e.g. 2021-02-23T19:30:00Z"
+    )]
+    pub start: DateTime<Utc>,
+    #[structopt(long, help = "Only keep snapshots before this time")]
+    pub end: DateTime<Utc>,
+    #[structopt(
+        long = "hex",
+        help = "Also keep this aircraft wherever it is; can be given more than once"
+    )]
+    pub hexes: Vec<String>,
+    #[structopt(long, help = "Compress the trimmed snapshots with bzip2")]
+    pub bzip2: bool,
+    #[structopt(short, long, help = "Directory to write the trimmed snapshots to")]
+    pub output: String,
+}
+
+#[derive(StructOpt, Debug)]
+struct DetectArgs {
+    #[structopt(
+        help = "Input files, directories, or glob patterns, or - to read newline-delimited responses from stdin"
+    )]
     pub paths: Vec<String>,
     #[structopt(long, help = "Skip JSON decoding errors")]
     pub skip_json_errors: bool,
+    #[structopt(
+        long,
+        help = "Re-sort out-of-order input within a window of this many files"
+    )]
+    pub reorder_window: Option<usize>,
+    #[structopt(long, help = "Number of threads to use for loading files")]
+    pub workers: Option<usize>,
+    #[structopt(
+        long,
+        help = "Read the snapshots from this replay file (see --write-replay) instead of the input files"
+    )]
+    pub replay: Option<String>,
+    #[structopt(
+        long,
+        help = "Save the snapshots to this replay file, for faster runs over the same input"
+    )]
+    pub write_replay: Option<String>,
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of threads to use for updating aircraft and detecting interceptions in each snapshot"
+    )]
+    pub detection_threads: usize,
+    #[structopt(flatten)]
+    pub params: ParamsArgs,
+    #[structopt(
+        long,
+        help = "OurAirports airports.csv file; interceptions in airport traffic are ignored"
+    )]
+    pub airports: Option<String>,
+    #[structopt(
+        long,
+        help = "OurAirports-format CSV file; interceptions by fighters that just took off from its military airfields are tagged"
+    )]
+    pub military_airfields: Option<String>,
+    #[structopt(
+        long,
+        help = "Ignore likely departures from military airfields instead of tagging them"
+    )]
+    pub suppress_departures: bool,
+    #[structopt(
+        long,
+        help = "Score criteria instead of requiring them, and only report interceptions with at least this confidence (0-100)"
+    )]
+    pub min_confidence: Option<f64>,
+    #[structopt(
+        long,
+        help = "GeoJSON file with Polygons or MultiPolygons; only look for interceptions inside them"
+    )]
+    pub regions: Option<String>,
+    #[structopt(
+        long,
+        help = "Ignore aircraft outside this box, given as min_lon,min_lat,max_lon,max_lat"
+    )]
+    pub bbox: Option<String>,
+    #[structopt(long, help = "Ignore aircraft above this altitude, in feet")]
+    pub max_altitude_ft: Option<i32>,
+    #[structopt(
+        long,
+        help = "Log why records were dropped every N snapshots (at info level)"
+    )]
+    pub log_drops_every: Option<usize>,
+    #[cfg(feature = "aircraft_db")]
+    #[structopt(
+        long,
+        help = "ADS-B Exchange basic-ac-db.json.gz (or CSV) file, for filling in registration, type, and operator"
+    )]
+    pub aircraft_db: Option<String>,
+    #[structopt(long, help = "Write interceptions to this GeoJSON file")]
+    pub geojson: Option<String>,
+    #[structopt(
+        long,
+        help = "Write interceptions to this KML file (zipped if it ends in .kmz)"
+    )]
+    pub kml: Option<String>,
+    #[structopt(long, help = "Write interceptions to this CSV file")]
+    pub csv: Option<String>,
+    #[structopt(long, help = "Write a report of the interceptions to this HTML file")]
+    pub html: Option<String>,
+    #[structopt(long, help = "Write interceptions to this Cursor-on-Target file")]
+    pub cot: Option<String>,
+    #[structopt(
+        long,
+        help = "Write event counts and fast-mover hours per lat/lon cell to this CSV (or .geojson) file"
+    )]
+    pub heatmap: Option<String>,
+    #[structopt(
+        long,
+        default_value = "0.5",
+        help = "The size of the heatmap's cells, in degrees"
+    )]
+    pub heatmap_cell_deg: f64,
+    #[structopt(
+        long,
+        help = "Send interceptions as Cursor-on-Target events to this host:port over UDP when they start and get closer"
+    )]
+    pub cot_udp: Option<String>,
+    #[structopt(
+        long,
+        help = "Send Cursor-on-Target events for every detection, not just when they start and get closer"
+    )]
+    pub cot_positions: bool,
+    #[structopt(
+        long,
+        default_value = "unknown",
+        help = "Show targets as unknown (a-u-A) or neutral (a-n-A) in Cursor-on-Target events"
+    )]
+    pub cot_target: tracon::cot::TargetAffiliation,
+    #[structopt(
+        long,
+        help = "Write interceptions, grouped by target, to this JSON Lines file"
+    )]
+    pub json: Option<String>,
+    #[structopt(
+        long,
+        help = "Include each interceptor's separation from the target over time in the JSON output"
+    )]
+    pub separation_series: bool,
+    #[structopt(
+        long,
+        help = "Print summary statistics of the interception events at the end"
+    )]
+    pub stats: bool,
+    #[structopt(
+        long,
+        help = "Record the full tracks of both aircraft of each interception, and include them in the JSON output"
+    )]
+    pub event_tracks: bool,
+    #[structopt(
+        long,
+        help = "Write every fast mover's check against its nearest potential targets, in every snapshot, to this JSON Lines file"
+    )]
+    pub explain: Option<String>,
+    #[structopt(
+        long,
+        help = "Write a summary of the checks of each interceptor and target to this JSON Lines file"
+    )]
+    pub explain_report: Option<String>,
+    #[structopt(
+        long,
+        help = "Write interception events to stdout as JSON Lines, instead of listing interceptions"
+    )]
+    pub events: bool,
+    #[structopt(
+        long,
+        help = "Watch this directory for new snapshot files instead of reading files, until ctrl-c or SIGTERM"
+    )]
+    pub watch: Option<String>,
+    #[structopt(long, help = "Delete watched files after processing them")]
+    pub delete_processed: bool,
+    #[structopt(
+        long,
+        help = "Move watched files to this directory after processing them"
+    )]
+    pub move_processed: Option<String>,
+    #[structopt(
+        long,
+        help = "Load the detector state from this file if it exists, and save it there periodically and before exiting"
+    )]
+    pub state_file: Option<String>,
+    #[structopt(
+        long,
+        default_value = "60",
+        help = "Seconds between saves of the --state-file"
+    )]
+    pub autosave_secs: u64,
+    #[structopt(
+        long,
+        help = "Save the position in the input files and the detector state to this checkpoint file, and resume from it if it exists"
+    )]
+    pub checkpoint: Option<String>,
+    #[structopt(
+        long,
+        default_value = "100",
+        help = "Files between saves of the --checkpoint"
+    )]
+    pub checkpoint_every: usize,
+    #[cfg(feature = "metrics")]
+    #[structopt(
+        long,
+        help = "Serve Prometheus metrics on this address, e.g. 0.0.0.0:9100"
+    )]
+    pub metrics_addr: Option<String>,
+    #[cfg(feature = "live")]
+    #[structopt(
+        long,
+        help = "Poll this API endpoint (e.g. https://api.adsb.lol/v2/mil) instead of reading files, until ctrl-c or SIGTERM"
+    )]
+    pub live: Option<String>,
+    #[cfg(feature = "live")]
+    #[structopt(long, help = "API key to send with each poll")]
+    pub api_key: Option<String>,
+    #[cfg(feature = "live")]
+    #[structopt(long, help = "Header to send the API key in [default: api-auth]")]
+    pub api_key_header: Option<String>,
+    #[cfg(feature = "live")]
+    #[structopt(long, help = "Seconds between polls [default: 10]")]
+    pub poll_secs: Option<u64>,
+    #[cfg(feature = "live")]
+    #[structopt(
+        long,
+        help = "POST each interception to this URL as JSON when it starts and ends"
+    )]
+    pub webhook: Option<String>,
+    #[cfg(feature = "live")]
+    #[structopt(
+        long,
+        help = "Send a one-line summary that Discord and Slack webhooks can display, instead of JSON"
+    )]
+    pub webhook_chat: bool,
+    #[cfg(feature = "mqtt")]
+    #[structopt(
+        long,
+        help = "Publish interceptions to this MQTT broker (host or host:port) when they start, get closer, and end"
+    )]
+    pub mqtt: Option<String>,
+    #[cfg(feature = "mqtt")]
+    #[structopt(
+        long,
+        default_value = tracon::mqtt::DEFAULT_TOPIC,
+        help = "MQTT topic; {interceptor_hex}, {target_hex}, and {event} are filled in"
+    )]
+    pub mqtt_topic: String,
+    #[cfg(feature = "mqtt")]
+    #[structopt(long, default_value = "1", help = "MQTT QoS level")]
+    pub mqtt_qos: u8,
+    #[cfg(feature = "mqtt")]
+    #[structopt(
+        long,
+        help = "Ask the MQTT broker to retain the last message on each topic"
+    )]
+    pub mqtt_retain: bool,
+    #[cfg(feature = "sqlite")]
+    #[structopt(long, help = "Add interceptions to this SQLite database")]
+    pub sqlite: Option<String>,
+    #[cfg(feature = "parquet")]
+    #[structopt(
+        long,
+        help = "Write every aircraft's state in every snapshot to this Parquet file"
+    )]
+    pub parquet: Option<String>,
+    #[cfg(feature = "parquet")]
+    #[structopt(
+        long,
+        default_value = "100000",
+        help = "The most rows in each Parquet row group"
+    )]
+    pub parquet_row_group_rows: usize,
+    #[cfg(feature = "parquet")]
+    #[structopt(
+        long,
+        default_value = "3",
+        help = "zstd level for the Parquet file, or 0 for no compression"
+    )]
+    pub parquet_zstd_level: i32,
 }
 
-fn main() -> Result<(), String> {
-    let args = CliArgs::from_args();
-    eprintln!("Processing {} files", args.paths.len());
-    let mut state = State::default();
-    for_each_adsbx_json(&args.paths, args.skip_json_errors, |response, bar| {
-        tracon::interception::process_adsbx_response(&mut state, response, bar)
-    })
-    .unwrap();
-    eprintln!(
-        "Indexed {} aircraft, processed {} aircraft, found {} interceptions",
-        state.num_ac_indexed,
-        state.num_ac_processed,
-        state.interceptions.len()
+impl DetectArgs {
+    // Returns the --config file's settings, overridden by any flags.
+    fn config(&self) -> Result<Config, String> {
+        let mut config = self.params.config()?;
+        config.detection.record_event_tracks |= self.event_tracks;
+        let input = &mut config.input;
+        if !self.paths.is_empty() {
+            input.paths = self.paths.clone();
+        }
+        input.skip_errors |= self.skip_json_errors;
+        input.reorder_window = self.reorder_window.or(input.reorder_window);
+        input.workers = self.workers.or(input.workers);
+        if self.replay.is_some() {
+            input.replay = self.replay.clone();
+        }
+        let output = &mut config.output;
+        for (flag, setting) in [
+            (&self.geojson, &mut output.geojson),
+            (&self.kml, &mut output.kml),
+            (&self.csv, &mut output.csv),
+            (&self.json, &mut output.json),
+            (&self.html, &mut output.html),
+            (&self.cot, &mut output.cot),
+            (&self.heatmap, &mut output.heatmap),
+            (&self.cot_udp, &mut output.cot_udp),
+            (&self.write_replay, &mut output.replay),
+        ] {
+            if flag.is_some() {
+                *setting = flag.clone();
+            }
+        }
+        #[cfg(feature = "sqlite")]
+        if self.sqlite.is_some() {
+            output.sqlite = self.sqlite.clone();
+        }
+        #[cfg(feature = "parquet")]
+        if self.parquet.is_some() {
+            output.parquet = self.parquet.clone();
+        }
+        #[cfg(feature = "live")]
+        {
+            if self.webhook.is_some() {
+                output.webhook = self.webhook.clone();
+            }
+            let live = &mut config.live;
+            if self.live.is_some() {
+                live.url = self.live.clone();
+            }
+            if self.api_key.is_some() {
+                live.api_key = self.api_key.clone();
+            }
+            if let Some(header) = &self.api_key_header {
+                live.api_key_header = header.clone();
+            }
+            live.poll_secs = self.poll_secs.unwrap_or(live.poll_secs);
+        }
+        config.validate().map_err(|e| e.to_string())?;
+        Ok(config)
+    }
+}
+
+// Polls the live endpoint, if there is one, until ctrl-c. Returns whether it
+// did.
+#[cfg(feature = "live")]
+fn poll_live<F>(live: &LiveConfig, metrics: &Arc<Metrics>, process: F) -> Result<bool, String>
+where
+    F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+{
+    let Some(url) = &live.url else {
+        return Ok(false);
+    };
+    let mut poller = tracon::live::LivePoller::new(url)
+        .interval(std::time::Duration::from_secs(live.poll_secs))
+        .metrics(metrics.clone());
+    if let Some(key) = &live.api_key {
+        poller = poller.api_key(&live.api_key_header, key);
+    }
+    tracing::info!("Polling {} every {} seconds", url, live.poll_secs);
+    let summary = poller
+        .stop_on_ctrl_c()
+        .and_then(|poller| poller.run(process))
+        .map_err(|e| e.to_string())?;
+    tracing::info!(
+        "Polled {} times ({} failed) for {} responses ({} stale) in {:.1?}",
+        summary.polls,
+        summary.failed_polls,
+        summary.responses,
+        summary.stale_responses,
+        summary.wall_time,
+    );
+    Ok(true)
+}
+
+#[cfg(not(feature = "live"))]
+fn poll_live<F>(_live: &LiveConfig, _metrics: &Arc<Metrics>, _process: F) -> Result<bool, String>
+where
+    F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+{
+    Ok(false)
+}
+
+// Watches the --watch directory, if there is one, until ctrl-c. Returns
+// whether it did.
+fn watch_dir<F>(
+    args: &DetectArgs,
+    input: &InputConfig,
+    metrics: &Arc<Metrics>,
+    process: F,
+) -> Result<bool, String>
+where
+    F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+{
+    let Some(dir) = &args.watch else {
+        return Ok(false);
+    };
+    let processed_files = match (&args.move_processed, args.delete_processed) {
+        (Some(dest), _) => ProcessedFiles::MoveTo(dest.clone()),
+        (None, true) => ProcessedFiles::Delete,
+        (None, false) => ProcessedFiles::Keep,
+    };
+    tracing::info!("Watching {}", dir);
+    let summary = DirectoryWatcher::new(dir)
+        .skip_errors(input.skip_errors)
+        .metrics(metrics.clone())
+        .processed_files(processed_files)
+        .stop_on_ctrl_c()
+        .and_then(|watcher| watcher.run(process))
+        .map_err(|e| e.to_string())?;
+    tracing::info!(
+        "Loaded {} files ({} failed, {} late) in {:.1?}",
+        summary.files_ok,
+        summary.files_failed.len(),
+        summary.files_late.len(),
+        summary.wall_time,
     );
-    for interception in state.interceptions {
-        println!("{} {} intercepted {} at {} with {:.0} ft lateral separation, {} ft vertical separation",
-        url(&interception.interceptor, &interception.target, interception.time),
-        interception.interceptor.hex,
-             interception.target.hex,
-             interception.time,
-             interception.lateral_separation_ft.round(),
-             interception.vertical_separation_ft,
+    Ok(true)
+}
+
+// Processes the input files, or stdin.
+fn process_paths<F>(
+    args: &DetectArgs,
+    input: &InputConfig,
+    metrics: &Arc<Metrics>,
+    state: &mut State,
+    mut process: F,
+) -> Result<(), String>
+where
+    F: FnMut(&mut State, Response, &ProgressBar) -> Result<(), Error>,
+{
+    if input.paths == ["-"] {
+        tracing::info!("Processing stdin");
+        if args.checkpoint.is_some() {
+            tracing::warn!("--checkpoint doesn't work with stdin, so it's ignored");
+        }
+        for_each_adsbx_json_from_reader(
+            std::io::stdin().lock(),
+            input.skip_errors,
+            |response, bar| process(state, response, bar),
+        )
+        .unwrap();
+    } else {
+        let paths = expand_input_paths(&input.paths).map_err(|e| e.to_string())?;
+        tracing::info!("Processing {} files", paths.len());
+        let order = match input.reorder_window {
+            Some(window) => InputOrder::Reorder(window),
+            None => InputOrder::Strict,
+        };
+        let metrics = metrics.clone();
+        let mut pipeline = AdsbxPipeline::new(&paths)
+            .skip_errors(input.skip_errors)
+            .order(order)
+            .on_error(Arc::new(move |path, err| {
+                tracing::warn!("Error reading file {}: {}", path, err);
+                Metrics::inc(&metrics.parse_errors);
+            }));
+        if let Some(workers) = input.workers {
+            pipeline = pipeline.workers(workers);
+        }
+        if let Some(range) = input.time_range() {
+            pipeline = pipeline.time_range(range);
+        }
+        if let Some(path) = &args.checkpoint {
+            pipeline = pipeline.checkpoint(path, args.checkpoint_every);
+        }
+        let summary = pipeline
+            .run_with_state(state, &mut process)
+            .map_err(|e| e.to_string())?;
+        tracing::info!(
+            "Loaded {} files ({} failed) with {} aircraft records ({} without position) in {:.1?}",
+            summary.files_ok,
+            summary.files_failed.len(),
+            summary.total_aircraft_records,
+            summary.records_missing_position,
+            summary.wall_time,
+        );
+        tracing::info!(
+            "Decompression {:.1?}, parsing {:.1?}, detection {:.1?}",
+            summary.phases.decompress,
+            summary.phases.parse,
+            summary.phases.callback,
         );
     }
     Ok(())
 }
+
+fn parse_bbox(s: &str) -> Result<BoundingBoxFilter, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("--bbox {}: {}", s, e))?;
+    match values[..] {
+        [min_lon, min_lat, max_lon, max_lat] => Ok(BoundingBoxFilter {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        }),
+        _ => Err(format!("--bbox {}: expected 4 numbers", s)),
+    }
+}
+
+// Prints a line describing an interception.
+fn print_event(event: &InterceptionEvent, airports: Option<&AirportDb>) {
+    let closest = &event.closest;
+    let nearest_airport = airports
+        .and_then(|airports| airports.nearest(closest.location()))
+        .map(|(airport, dist)| format!(", nearest airport: {}, {:.1} nm", airport.ident, dist))
+        .unwrap_or_default();
+    let countries = format!(
+        ", interceptor registered: {}, target registered: {}",
+        closest.interceptor.country.as_deref().unwrap_or("unknown"),
+        closest.target.country.as_deref().unwrap_or("unknown"),
+    );
+    let sources = format!(
+        ", positions from {} and {}",
+        closest.interceptor.source.as_deref().unwrap_or("unknown"),
+        closest.target.source.as_deref().unwrap_or("unknown"),
+    );
+    let emergency = event
+        .emergency
+        .map(|e| format!(", target squawking {}", e.code()))
+        .unwrap_or_default();
+    println!("{} {} intercepted {} from {} to {} with {:.0} ft minimum lateral separation, {} ft minimum vertical separation, CPA {:.0} ft lateral, {:.0} ft vertical at {}{}{}{}{}{}",
+    url(&closest.interceptor, &closest.target, closest.time),
+    closest.interceptor.description(),
+         closest.target.description(),
+         event.start,
+         event.end,
+         event.min_lateral_separation_ft.round(),
+         event.min_vertical_separation_ft,
+         event.cpa_lateral_ft,
+         event.cpa_vertical_ft,
+         event.cpa_time,
+         if event.cpa_low_confidence { " (low confidence)" } else { "" },
+         countries,
+         sources,
+         nearest_airport,
+         emergency,
+    );
+}
+
+fn analyze(args: AnalyzeArgs) -> Result<(), String> {
+    let params = args.params.config()?.detection;
+    let event =
+        analyze_trace_files(&args.interceptor, &args.target, &params).map_err(|e| e.to_string())?;
+    match event {
+        Some(event) if args.json => {
+            JsonLinesWriter::new(std::io::stdout())
+                .write(&event)
+                .map_err(|e| e.to_string())?;
+        }
+        Some(event) => print_event(&event, None),
+        None => tracing::info!("No interception found"),
+    }
+    Ok(())
+}
+
+fn export(args: ExportArgs) -> Result<(), String> {
+    let n = export_file(&args.input, args.format, &args.output).map_err(|e| e.to_string())?;
+    tracing::info!("Wrote {} interceptions to {}", n, args.output);
+    Ok(())
+}
+
+fn screen(args: ScreenArgs) -> Result<(), String> {
+    let params = args.params.config()?.detection;
+    let screen = ScreenParams {
+        lead_mins: args.lead_mins,
+        trail_mins: args.trail_mins,
+    };
+    let paths = expand_input_paths(&args.paths).map_err(|e| e.to_string())?;
+    let pipeline = AdsbxPipeline::new(&paths).on_error(Arc::new(|path, err| {
+        tracing::warn!("Error reading file {}: {}", path, err);
+    }));
+    let mut json = args.json.then(|| JsonLinesWriter::new(std::io::stdout()));
+    tracing::info!("Screening {} files", paths.len());
+    let windows = screen_files(pipeline.clone(), &params, &screen).map_err(|e| e.to_string())?;
+    for window in &windows {
+        match &mut json {
+            Some(json) => json.write(window).map_err(|e| e.to_string())?,
+            None => tracing::info!(
+                "Candidate window from {} to {}: {} hits from {} to {} in {:?}",
+                window.start,
+                window.end,
+                window.num_hits,
+                window.first_hit,
+                window.last_hit,
+                window.bbox
+            ),
+        }
+    }
+    if args.windows_only {
+        return Ok(());
+    }
+    tracing::info!("Running the detector on {} windows", windows.len());
+    let events = detect_in_windows(&pipeline, &windows, &params).map_err(|e| e.to_string())?;
+    for event in &events {
+        match &mut json {
+            Some(json) => json.write(event).map_err(|e| e.to_string())?,
+            None => print_event(event, None),
+        }
+    }
+    Ok(())
+}
+
+fn trim(args: TrimArgs) -> Result<(), String> {
+    let bbox = parse_bbox(&args.bbox)?;
+    let range = TimeRange::new(args.start, args.end);
+    let paths = expand_input_paths(&args.paths).map_err(|e| e.to_string())?;
+    let hexes = (!args.hexes.is_empty()).then_some(args.hexes.as_slice());
+    let compression = if args.bzip2 {
+        Compression::Bzip2
+    } else {
+        Compression::None
+    };
+    let n = trim_files(
+        &paths,
+        &range,
+        bbox,
+        hexes,
+        Path::new(&args.output),
+        compression,
+    )
+    .map_err(|e| e.to_string())?;
+    tracing::info!("Wrote {} snapshots to {}", n, args.output);
+    Ok(())
+}
+
+fn detect(args: DetectArgs) -> Result<(), String> {
+    let config = args.config()?;
+    let mut state = State {
+        params: config.detection.clone(),
+        ..Default::default()
+    };
+    if let Some(path) = &args.regions {
+        state.params.regions = Some(Regions::load(path).map_err(|e| e.to_string())?);
+    }
+    if let Some(path) = &args.airports {
+        state.airports = Some(load_airports(path).map_err(|e| e.to_string())?);
+    }
+    if let Some(path) = &args.military_airfields {
+        state.military_airfields = Some(load_military_airfields(path).map_err(|e| e.to_string())?);
+    }
+    if args.suppress_departures {
+        state.params.suppress_departures = true;
+    }
+    if let Some(min_confidence) = args.min_confidence {
+        state.params.min_confidence = Some(min_confidence);
+    }
+    if config.output.heatmap.is_some() {
+        state.heatmap = Some(HeatmapGrid::new(args.heatmap_cell_deg).map_err(|e| e.to_string())?);
+    }
+    let bbox = args.bbox.as_deref().map(parse_bbox).transpose()?;
+    let max_altitude = args.max_altitude_ft.map(MaxAltitudeFilter);
+    if bbox.is_some() || max_altitude.is_some() {
+        state.aircraft.set_prefilter(Some(move |aircraft: &_| {
+            bbox.is_none_or(|f| f.matches(aircraft))
+                && max_altitude.is_none_or(|f| f.matches(aircraft))
+        }));
+    }
+    state.aircraft.set_log_drops_every(args.log_drops_every);
+    state
+        .aircraft
+        .set_num_threads(args.detection_threads)
+        .map_err(|e| e.to_string())?;
+    #[cfg(feature = "aircraft_db")]
+    if let Some(path) = &args.aircraft_db {
+        state.aircraft.aircraft_db = Some(tracon::aircraft_db::SharedAircraftDb::new(path));
+    }
+    #[cfg(feature = "live")]
+    if let Some(url) = &config.output.webhook {
+        use tracon::notify::{WebhookFormat, WebhookNotifier};
+        let format = if args.webhook_chat {
+            WebhookFormat::Chat
+        } else {
+            WebhookFormat::Json
+        };
+        state
+            .notifiers
+            .push(Box::new(WebhookNotifier::new(url).format(format)));
+    }
+    if let Some(addr) = &config.output.cot_udp {
+        let sender = tracon::cot::CotSender::new(addr)
+            .map_err(|e| e.to_string())?
+            .target_affiliation(args.cot_target)
+            .positions(args.cot_positions);
+        state.notifiers.push(Box::new(sender));
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = &args.mqtt {
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Bad MQTT port: {}", port))?,
+            ),
+            None => (broker.as_str(), 1883),
+        };
+        let notifier = tracon::mqtt::MqttNotifier::builder("tracon", host, port)
+            .topic(&args.mqtt_topic)
+            .qos(args.mqtt_qos)
+            .map_err(|e| e.to_string())?
+            .retain(args.mqtt_retain)
+            .connect();
+        state.notifiers.push(Box::new(notifier));
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = &args.metrics_addr {
+        let addr = tracon::metrics::serve_metrics(state.metrics.clone(), addr)
+            .map_err(|e| e.to_string())?;
+        tracing::info!("Serving metrics at http://{}/metrics", addr);
+    }
+    if let Some(path) = args
+        .state_file
+        .as_ref()
+        .filter(|path| std::path::Path::new(path).exists())
+    {
+        state
+            .load_state(path, chrono::Utc::now())
+            .map_err(|e| e.to_string())?;
+        tracing::info!(
+            "Loaded {} aircraft and {} ongoing interceptions from {}",
+            state.aircraft.len(),
+            state.tracker.num_open(),
+            path
+        );
+    }
+    state
+        .aircraft
+        .set_explain(args.explain.is_some() || args.explain_report.is_some());
+    let mut explain = args
+        .explain
+        .as_ref()
+        .map(|path| {
+            std::fs::File::create(path)
+                .map(JsonLinesWriter::new)
+                .map_err(|e| format!("{}: {}", path, e))
+        })
+        .transpose()?;
+    #[cfg(feature = "parquet")]
+    let mut parquet = config
+        .output
+        .parquet
+        .as_ref()
+        .map(|path| {
+            use tracon::parquet::{ParquetCompression, ParquetSink};
+            let compression = match args.parquet_zstd_level {
+                0 => ParquetCompression::Uncompressed,
+                level => ParquetCompression::Zstd(level),
+            };
+            ParquetSink::create(path).map(|sink| {
+                sink.row_group_rows(args.parquet_row_group_rows)
+                    .compression(compression)
+            })
+        })
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let mut replay = config
+        .output
+        .replay
+        .as_deref()
+        .map(ReplayWriter::create)
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let metrics = state.metrics.clone();
+    let mut events = args.events.then(|| JsonLinesWriter::new(std::io::stdout()));
+    let autosave = std::time::Duration::from_secs(args.autosave_secs);
+    let mut last_save = std::time::Instant::now();
+    let mut process = |state: &mut State, response: Response, bar: &ProgressBar| {
+        #[cfg(feature = "parquet")]
+        let now = response.now;
+        if let Some(replay) = &mut replay {
+            replay.write(&response)?;
+        }
+        match &mut events {
+            Some(events) => {
+                for event in detect_interception_events(state, &response)? {
+                    events.write(&event)?;
+                }
+            }
+            None => process_adsbx_response(state, response, bar)?,
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = &mut parquet {
+            parquet.write_snapshot(&state.aircraft, now)?;
+        }
+        if let Some(explain) = &mut explain {
+            for check in state.aircraft.candidate_checks() {
+                explain.write(check)?;
+            }
+        }
+        if let Some(path) = &args.state_file {
+            if last_save.elapsed() >= autosave {
+                if let Err(e) = state.save_state(path) {
+                    tracing::warn!("Couldn't save state: {}", e);
+                }
+                last_save = std::time::Instant::now();
+            }
+        }
+        Ok(())
+    };
+    if let Some(path) = &config.input.replay {
+        tracing::info!("Replaying {}", path);
+        for_each_replay_snapshot(path, config.input.time_range().as_ref(), |response, bar| {
+            process(&mut state, response, bar)
+        })
+        .map_err(|e| e.to_string())?;
+    } else if !poll_live(&config.live, &metrics, |response, bar| {
+        process(&mut state, response, bar)
+    })? && !watch_dir(&args, &config.input, &metrics, |response, bar| {
+        process(&mut state, response, bar)
+    })? {
+        process_paths(&args, &config.input, &metrics, &mut state, &mut process)?;
+    }
+    if let Some(replay) = replay {
+        replay.finish().map_err(|e| e.to_string())?;
+    }
+    // Save before finishing, so the open events are saved as open.
+    if let Some(path) = &args.state_file {
+        state.save_state(path).map_err(|e| e.to_string())?;
+    }
+    let ended = state.finish();
+    tracing::info!(
+        "Indexed {} aircraft, processed {} aircraft, skipped {} records missing data, filtered out {} records, expired {} aircraft, evicted {} aircraft, found {} interceptions",
+        state.aircraft.num_ac_indexed,
+        state.aircraft.num_ac_processed,
+        state.aircraft.num_ac_missing_data,
+        state.aircraft.num_ac_filtered,
+        state.aircraft.num_ac_expired,
+        state.aircraft.num_ac_evicted,
+        state.events.len()
+    );
+    tracing::info!(
+        "Over {} snapshots, {}",
+        state.aircraft.num_snapshots,
+        state.aircraft.drops
+    );
+    // Export the closest detection from each interception, grouped when
+    // several interceptors went after the same target.
+    let groups = state.groups();
+    for (path, format) in [
+        (&config.output.geojson, ExportFormat::GeoJson),
+        (&config.output.kml, ExportFormat::Kml),
+        (&config.output.csv, ExportFormat::Csv),
+        (&config.output.html, ExportFormat::Html),
+        (&config.output.cot, ExportFormat::Cot),
+    ] {
+        if let Some(path) = path {
+            write_groups(&groups, format, path).map_err(|e| e.to_string())?;
+        }
+    }
+    if let (Some(path), Some(heatmap)) = (&config.output.heatmap, &state.heatmap) {
+        write_heatmap(heatmap, path).map_err(|e| e.to_string())?;
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &config.output.sqlite {
+        let interceptions: Vec<_> = state.events.iter().map(|e| e.closest.clone()).collect();
+        let n = tracon::sqlite::InterceptionDb::open(path)
+            .and_then(|mut db| db.insert_interceptions(&interceptions))
+            .map_err(|e| e.to_string())?;
+        tracing::info!("Added {} new interceptions to {}", n, path);
+    }
+    if let Some(path) = &config.output.json {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut writer = JsonLinesWriter::new(file);
+        for group in &groups {
+            let mut group = group.clone();
+            if !args.separation_series {
+                group.clear_separation_series();
+            }
+            writer
+                .write(&group)
+                .map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    if let Some(path) = &args.explain_report {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut writer = JsonLinesWriter::new(file);
+        for report in state.aircraft.candidate_reports() {
+            writer
+                .write(&report)
+                .map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    if let Some(events) = &mut events {
+        for event in &ended {
+            events.write(event).map_err(|e| e.to_string())?;
+        }
+    } else {
+        for event in &state.events {
+            print_event(event, state.airports.as_ref());
+        }
+        for group in groups.iter().filter(|g| g.interceptors.len() > 1) {
+            println!(
+                "{}: {} interceptors ({}) went after {} together from {} to {}",
+                group.event_id,
+                group.interceptors.len(),
+                group.interceptor_hexes().join(", "),
+                group.target_hex,
+                group.start,
+                group.end,
+            );
+        }
+        for chain in state.escort_chains.iter().filter(|c| c.num_events > 1) {
+            println!(
+                "{}: {} from {} to {} ({})",
+                chain.escort_chain_id,
+                chain.description(),
+                chain.start,
+                chain.end,
+                chain.interceptor_hexes.join(", "),
+            );
+        }
+    }
+    if args.stats {
+        let mut stats = Stats::new();
+        for event in &state.events {
+            stats.ingest(event);
+        }
+        print!("{}", stats.finalize());
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    let args = CliArgs::from_args();
+    // Log progress and interceptions by default. Rejected pairs are logged
+    // at debug level, in a span per snapshot.
+    let filter = match &args.log_level {
+        Some(filter) => EnvFilter::try_new(filter).map_err(|e| format!("--log-level: {}", e))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+    match args.command {
+        Command::Detect(args) => detect(*args),
+        Command::Analyze(args) => analyze(args),
+        Command::Export(args) => export(args),
+        Command::Screen(args) => screen(args),
+        Command::Trim(args) => trim(args),
+    }
+}
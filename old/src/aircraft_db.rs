@@ -0,0 +1,263 @@
+//! Registration, type, and operator for aircraft whose ADS-B data doesn't
+//! include them, from ADS-B Exchange's basic aircraft database.
+//!
+//! The database is published as basic-ac-db.json.gz
+//! (https://downloads.adsbexchange.com/downloads/basic-ac-db.json.gz), with
+//! one JSON object per line. A CSV file with the same column names (icao,
+//! reg, icaotype, ownOp) works too. Only those four fields are used.
+//!
+//! The file is big, so SharedAircraftDb only loads it the first time it's
+//! needed, and clones share the loaded copy.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read},
+    sync::{Arc, OnceLock},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::Error,
+    interception::{Ac, Interception},
+    open_adsbx_json_file,
+};
+
+/// One aircraft in the database.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AircraftRecord {
+    /// The hex ID, e.g. "a12345".
+    pub icao: String,
+    #[serde(default)]
+    pub reg: Option<String>,
+    /// The ICAO type designator, e.g. "F16".
+    #[serde(default)]
+    pub icaotype: Option<String>,
+    /// The owner or operator.
+    #[serde(default, rename = "ownOp")]
+    pub operator: Option<String>,
+}
+
+/// Aircraft records, keyed by lowercase hex ID.
+#[derive(Debug, Clone, Default)]
+pub struct AircraftDb {
+    records: HashMap<String, AircraftRecord>,
+}
+
+fn aircraft_db_error(e: impl std::fmt::Display) -> Error {
+    Error::AircraftDbError(e.to_string())
+}
+
+// The database uses "" for missing values in some rows.
+fn non_empty(field: Option<String>) -> Option<String> {
+    field
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+impl AircraftDb {
+    pub fn new(records: Vec<AircraftRecord>) -> Self {
+        AircraftDb {
+            records: records
+                .into_iter()
+                .map(|r| {
+                    let record = AircraftRecord {
+                        icao: r.icao.to_lowercase(),
+                        reg: non_empty(r.reg),
+                        icaotype: non_empty(r.icaotype),
+                        operator: non_empty(r.operator),
+                    };
+                    (record.icao.clone(), record)
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads newline-delimited JSON records, like basic-ac-db.json.
+    pub fn from_json_lines_reader<R: Read>(r: R) -> Result<Self, Error> {
+        let mut records = vec![];
+        for line in BufReader::new(r).lines() {
+            let line = line.map_err(aircraft_db_error)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(serde_json::from_str(&line).map_err(aircraft_db_error)?);
+        }
+        Ok(AircraftDb::new(records))
+    }
+
+    /// Reads CSV records.
+    pub fn from_csv_reader<R: Read>(r: R) -> Result<Self, Error> {
+        let records = ::csv::Reader::from_reader(r)
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(aircraft_db_error)?;
+        Ok(AircraftDb::new(records))
+    }
+
+    /// Loads a database file. Files ending in .csv (or .csv.gz, etc.) are
+    /// read as CSV, and anything else as newline-delimited JSON. Compressed
+    /// files are decompressed.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let add_path = |e: Error| aircraft_db_error(format!("{}: {}", path, e));
+        let reader = open_adsbx_json_file(path).map_err(add_path)?;
+        let is_csv = path
+            .trim_end_matches(".gz")
+            .trim_end_matches(".bz2")
+            .trim_end_matches(".zst")
+            .trim_end_matches(".zstd")
+            .ends_with(".csv");
+        if is_csv {
+            AircraftDb::from_csv_reader(reader).map_err(add_path)
+        } else {
+            AircraftDb::from_json_lines_reader(reader).map_err(add_path)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Looks up an aircraft by hex ID, in either case.
+    pub fn get(&self, hex: &str) -> Option<&AircraftRecord> {
+        self.records.get(&hex.to_lowercase())
+    }
+
+    /// Fills in an aircraft's registration, type, and operator from the
+    /// database, where the ADS-B data didn't have them.
+    pub fn enrich(&self, ac: &mut Ac) {
+        if let Some(record) = self.get(&ac.hex.to_string()) {
+            if ac.registration.is_none() {
+                ac.registration = record.reg.clone();
+            }
+            if ac.aircraft_type.is_none() {
+                ac.aircraft_type = record.icaotype.clone();
+            }
+            if ac.operator.is_none() {
+                ac.operator = record.operator.clone();
+            }
+        }
+    }
+
+    /// Enriches both aircraft in an interception.
+    pub fn enrich_interception(&self, interception: &mut Interception) {
+        self.enrich(&mut interception.interceptor);
+        self.enrich(&mut interception.target);
+    }
+}
+
+/// An AircraftDb that's loaded from a file the first time it's used. Clones
+/// share the same database, so it's only loaded once.
+#[derive(Debug, Clone)]
+pub struct SharedAircraftDb {
+    path: String,
+    db: Arc<OnceLock<Result<AircraftDb, String>>>,
+}
+
+impl SharedAircraftDb {
+    pub fn new(path: &str) -> Self {
+        SharedAircraftDb {
+            path: path.to_string(),
+            db: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Returns the database, loading it if this is the first use. If loading
+    /// failed, every call returns the same error.
+    pub fn get(&self) -> Result<&AircraftDb, Error> {
+        self.db
+            .get_or_init(|| AircraftDb::load(&self.path).map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(aircraft_db_error)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::parse_snapshot_filename_time,
+        interception::{AircraftStateMap, DetectionParams},
+    };
+    use adsbx_json::v2::Aircraft;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn ac(hex: &str, registration: Option<&str>) -> Ac {
+        let time = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        Ac {
+            registration: registration.map(|r| r.to_string()),
+            ..Ac::for_test(hex, &[(time, [-118.0, 34.0])], 10000)
+        }
+    }
+
+    #[test]
+    fn test_load_aircraft_db() {
+        let json = AircraftDb::load(&fixture("basic-ac-db.json.gz")).unwrap();
+        let csv = AircraftDb::load(&fixture("basic-ac-db.csv")).unwrap();
+        for db in [&json, &csv] {
+            assert_eq!(db.len(), 3);
+            let record = db.get("AE1234").unwrap();
+            assert_eq!(record.reg.as_deref(), Some("01-7050"));
+            assert_eq!(record.icaotype.as_deref(), Some("F16"));
+            assert_eq!(record.operator.as_deref(), Some("United States Air Force"));
+            // Blank fields are None.
+            assert_eq!(db.get("a12345").unwrap().operator, None);
+            assert!(db.get("~2d1a48").is_none());
+        }
+        assert!(AircraftDb::load(&fixture("no-such-file.json")).is_err());
+    }
+
+    #[test]
+    fn test_enrich() {
+        let db = SharedAircraftDb::new(&fixture("basic-ac-db.json.gz"));
+        let shared = db.clone();
+        let mut interceptor = ac("ae1234", None);
+        shared.get().unwrap().enrich(&mut interceptor);
+        assert_eq!(interceptor.registration.as_deref(), Some("01-7050"));
+        assert_eq!(interceptor.aircraft_type.as_deref(), Some("F16"));
+        assert_eq!(
+            interceptor.operator.as_deref(),
+            Some("United States Air Force")
+        );
+        // What the aircraft reported wins.
+        let mut target = ac("a12345", Some("N12345"));
+        db.get().unwrap().enrich(&mut target);
+        assert_eq!(target.registration.as_deref(), Some("N12345"));
+        assert_eq!(target.aircraft_type.as_deref(), Some("C172"));
+        // Aircraft that aren't in the database are left alone.
+        let mut unknown = ac("a00001", None);
+        db.get().unwrap().enrich(&mut unknown);
+        assert_eq!(unknown.registration, None);
+        assert!(SharedAircraftDb::new(&fixture("no-such-file.json"))
+            .get()
+            .is_err());
+    }
+
+    #[test]
+    fn test_enrich_new_aircraft() {
+        // Aircraft are enriched as soon as they're tracked, so the type can be
+        // used to classify them.
+        let mut state = AircraftStateMap::new();
+        state.aircraft_db = Some(SharedAircraftDb::new(&fixture("basic-ac-db.csv")));
+        let aircraft: Aircraft = serde_json::from_value(serde_json::json!({
+            "hex": "0d0abc", "type": "adsb_icao", "messages": 100, "rssi": -20.0, "seen": 0.0,
+            "seen_pos": 0.0, "lat": 34.0, "lon": -118.0, "gs": 450.0, "alt_baro": 10000,
+        }))
+        .unwrap();
+        let now = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        let ac = state
+            .update(now, &aircraft, &DetectionParams::default())
+            .unwrap();
+        assert_eq!(ac.aircraft_type.as_deref(), Some("B738"));
+        assert!(ac.is_airliner(&DetectionParams::default()));
+        assert_eq!(ac.operator.as_deref(), Some("Aeromexico"));
+    }
+}
@@ -0,0 +1,336 @@
+//! Airport locations, for suppressing detections in airport traffic patterns
+//! and for annotating output with the nearest airport.
+//!
+//! Airports are loaded from a CSV file in the OurAirports format
+//! (https://ourairports.com/data/airports.csv). Only the ident, type, name,
+//! latitude_deg, longitude_deg, and elevation_ft columns are used, and closed
+//! airports are skipped.
+//!
+//! load_military_airfields loads just the airfields that look military (see
+//! Airport::is_military), for tagging fighters that have just taken off; see
+//! departure_airfield.
+
+use std::io::Read;
+
+use chrono::Duration;
+use rstar::{primitives::GeomWithData, RTree};
+use serde::Deserialize;
+
+use crate::{
+    error::Error,
+    geo::{haversine_distance_m, locate_near},
+    interception::{Ac, DetectionParams, Interception},
+};
+
+/// An airport (or heliport, seaplane base, etc.).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Airport {
+    /// The ICAO code if there is one, e.g. "KLAS".
+    pub ident: String,
+    /// "large_airport", "heliport", etc.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    #[serde(rename = "latitude_deg")]
+    pub lat: f64,
+    #[serde(rename = "longitude_deg")]
+    pub lon: f64,
+    pub elevation_ft: Option<i32>,
+}
+
+// Words and phrases in the names of military airfields, e.g. "Nellis Air
+// Force Base", "NAS Fallon", or "Robert Gray AAF".
+const MILITARY_AIRFIELD_TERMS: &[&str] = &[
+    "AIR FORCE",
+    "AFB",
+    "AIR BASE",
+    "AIR STATION",
+    "ANGB",
+    "AIR NATIONAL GUARD",
+    "ARMY AIRFIELD",
+    "ARMY AIR FIELD",
+    "AAF",
+    "NAS",
+    "MCAS",
+    "NAVAL",
+    "JOINT BASE",
+    "MILITARY",
+    "RAF",
+];
+
+impl Airport {
+    /// Whether the airport looks like a military airfield. OurAirports
+    /// doesn't say, so this goes by its name.
+    pub fn is_military(&self) -> bool {
+        let words: Vec<String> = self
+            .name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_ascii_uppercase)
+            .collect();
+        let name = format!(" {} ", words.join(" "));
+        MILITARY_AIRFIELD_TERMS
+            .iter()
+            .any(|term| name.contains(&format!(" {} ", term)))
+    }
+}
+
+/// A spatial index of airports.
+#[derive(Debug, Default)]
+pub struct AirportDb {
+    index: RTree<GeomWithData<[f64; 2], Airport>>,
+}
+
+fn airport_error(e: impl std::fmt::Display) -> Error {
+    Error::AirportDataError(e.to_string())
+}
+
+fn distance_nm(a: [f64; 2], b: [f64; 2]) -> f64 {
+    haversine_distance_m(a, b) / 1852.0
+}
+
+impl AirportDb {
+    pub fn new(airports: Vec<Airport>) -> Self {
+        AirportDb {
+            index: RTree::bulk_load(
+                airports
+                    .into_iter()
+                    .map(|a| GeomWithData::new([a.lon, a.lat], a))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Reads airports from OurAirports-format CSV.
+    pub fn from_reader<R: Read>(r: R) -> Result<Self, Error> {
+        AirportDb::from_reader_filtered(r, |_| true)
+    }
+
+    /// Reads the airports that filter accepts from OurAirports-format CSV.
+    pub fn from_reader_filtered<R: Read>(
+        r: R,
+        filter: impl Fn(&Airport) -> bool,
+    ) -> Result<Self, Error> {
+        let mut airports = vec![];
+        for airport in ::csv::Reader::from_reader(r).deserialize() {
+            let airport: Airport = airport.map_err(airport_error)?;
+            if airport.kind != "closed" && filter(&airport) {
+                airports.push(airport);
+            }
+        }
+        Ok(AirportDb::new(airports))
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.size() == 0
+    }
+
+    /// Returns the airport nearest to a [lon, lat] position, and its distance
+    /// in nautical miles.
+    pub fn nearest(&self, coords: [f64; 2]) -> Option<(&Airport, f64)> {
+        // The r-tree measures distance in degrees, which exaggerates
+        // north-south distances compared to east-west ones, so check a few
+        // of the nearest candidates.
+        self.index
+            .nearest_neighbor_iter(&coords)
+            .take(10)
+            .map(|a| (&a.data, distance_nm(coords, *a.geom())))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Returns the airports within radius_nm of a [lon, lat] position, with
+    /// their distances in nautical miles.
+    pub fn within(&self, coords: [f64; 2], radius_nm: f64) -> Vec<(&Airport, f64)> {
+        locate_near(&self.index, coords, radius_nm * 1852.0)
+            .map(|a| (&a.data, distance_nm(coords, *a.geom())))
+            .filter(|(_, dist)| *dist <= radius_nm)
+            .collect()
+    }
+}
+
+/// Loads an OurAirports-format airports.csv file.
+pub fn load_airports(path: &str) -> Result<AirportDb, Error> {
+    load_airports_filtered(path, |_| true)
+}
+
+/// Loads the airports in an OurAirports-format file that filter accepts.
+pub fn load_airports_filtered(
+    path: &str,
+    filter: impl Fn(&Airport) -> bool,
+) -> Result<AirportDb, Error> {
+    let file = std::fs::File::open(path).map_err(|e| airport_error(format!("{}: {}", path, e)))?;
+    AirportDb::from_reader_filtered(file, filter)
+        .map_err(|e| airport_error(format!("{}: {}", path, e)))
+}
+
+/// Loads the military airfields in an OurAirports-format file: either a
+/// list of bases, or all of airports.csv.
+pub fn load_military_airfields(path: &str) -> Result<AirportDb, Error> {
+    load_airports_filtered(path, Airport::is_military)
+}
+
+// Whether an aircraft is within airport_radius_nm of an airport and less than
+// airport_max_alt_ft above it. An aircraft whose altitude is unknown isn't.
+fn is_near_airport(airports: &AirportDb, ac: &Ac, params: &DetectionParams) -> bool {
+    let Some(alt) = ac.known_alt() else {
+        return false;
+    };
+    airports
+        .within(ac.cur_coords().1, params.airport_radius_nm)
+        .iter()
+        .any(|(airport, _)| alt - airport.elevation_ft.unwrap_or(0) < params.airport_max_alt_ft)
+}
+
+/// Checks whether an interception looks like airport traffic: both aircraft
+/// low and close to an airport, like a fast mover descending to land over
+/// traffic in the pattern.
+pub fn is_airport_traffic(
+    airports: &AirportDb,
+    interception: &Interception,
+    params: &DetectionParams,
+) -> bool {
+    is_near_airport(airports, &interception.interceptor, params)
+        && is_near_airport(airports, &interception.target, params)
+}
+
+/// Checks whether an interception looks like the interceptor taking off from
+/// a military airfield: its first fix was within departure_radius_nm of one,
+/// the aircraft came close within departure_window_mins of that fix, and the
+/// interceptor is climbing at departure_min_climb_fpm or more. Returns the
+/// airfield.
+pub fn departure_airfield<'a>(
+    airfields: &'a AirportDb,
+    interception: &Interception,
+    params: &DetectionParams,
+) -> Option<&'a Airport> {
+    let interceptor = &interception.interceptor;
+    let (first_time, first_coords) = interceptor.first_fix?;
+    let climbing = interceptor
+        .cur_vertical_rate_fpm
+        .is_some_and(|rate| rate >= params.departure_min_climb_fpm);
+    if !climbing
+        || interception.proximity_start - first_time
+            > Duration::minutes(params.departure_window_mins)
+    {
+        return None;
+    }
+    airfields
+        .within(first_coords, params.departure_radius_nm)
+        .into_iter()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(airfield, _)| airfield)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_snapshot_filename_time;
+
+    fn airports() -> AirportDb {
+        load_airports(&format!(
+            "{}/tests/data/airports.csv",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap()
+    }
+
+    const MILITARY_CSV: &str = "\
+ident,type,name,latitude_deg,longitude_deg,elevation_ft
+KLSV,medium_airport,Nellis Air Force Base,36.2362,-115.0343,1870
+KNFL,medium_airport,NAS Fallon,39.4166,-118.701,3934
+KVGT,medium_airport,North Las Vegas Airport,36.2107,-115.194,2205
+KNAS,small_airport,Nassau Field,36.5,-115.5,2000
+";
+
+    fn ac(hex: &str, coords: [f64; 2], alt: i32) -> Ac {
+        let time = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        Ac::for_test(hex, &[(time, coords)], alt)
+    }
+
+    #[test]
+    fn test_nearest_airport() {
+        let airports = airports();
+        // The closed one is skipped.
+        assert_eq!(airports.len(), 2);
+        // About 3 nm north of KLAS.
+        let (airport, dist) = airports.nearest([-115.15, 36.133]).unwrap();
+        assert_eq!(airport.ident, "KLAS");
+        assert!((dist - 3.0).abs() < 0.1, "{}", dist);
+        assert_eq!(airport.elevation_ft, Some(2181));
+        assert_eq!(airports.within([-115.15, 36.133], 2.0).len(), 0);
+        assert_eq!(airports.within([-115.15, 36.133], 6.0).len(), 2);
+    }
+
+    #[test]
+    fn test_is_airport_traffic() {
+        let airports = airports();
+        let params = DetectionParams::default();
+        let interception = |interceptor_alt, target_coords| {
+            let interceptor = ac("ae1234", [-115.15, 36.1], interceptor_alt);
+            let time = interceptor.seen;
+            Interception {
+                lateral_separation_ft: 100.0,
+                ..Interception::for_test(interceptor, ac("a12345", target_coords, 4000), time)
+            }
+        };
+        assert!(is_airport_traffic(
+            &airports,
+            &interception(4000, [-115.15, 36.1]),
+            &params
+        ));
+        // The interceptor is 6000 ft above the airport.
+        assert!(!is_airport_traffic(
+            &airports,
+            &interception(8200, [-115.15, 36.1]),
+            &params
+        ));
+        // The target is nowhere near an airport.
+        assert!(!is_airport_traffic(
+            &airports,
+            &interception(4000, [-116.0, 36.1]),
+            &params
+        ));
+    }
+
+    #[test]
+    fn test_departure_airfield() {
+        let airfields =
+            AirportDb::from_reader_filtered(MILITARY_CSV.as_bytes(), Airport::is_military).unwrap();
+        // Not North Las Vegas, or Nassau just because it starts with "Nas".
+        assert_eq!(airfields.len(), 2);
+        let mut params = DetectionParams::default();
+        // A fighter first seen just off the end of Nellis' runway, climbing
+        // past a target two minutes later.
+        let mut interceptor = ac("ae1234", [-115.0, 36.25], 4000);
+        let first = interceptor.seen - Duration::minutes(2);
+        interceptor.first_fix = Some((first, [-115.02, 36.24]));
+        interceptor.cur_vertical_rate_fpm = Some(6000.0);
+        let time = interceptor.seen;
+        let mut interception = Interception {
+            lateral_separation_ft: 100.0,
+            ..Interception::for_test(interceptor, ac("a12345", [-115.0, 36.25], 4000), time)
+        };
+        let ident = |interception: &Interception, params: &DetectionParams| {
+            departure_airfield(&airfields, interception, params).map(|a| a.ident.as_str())
+        };
+        assert_eq!(ident(&interception, &params), Some("KLSV"));
+        // Too long after its first fix.
+        params.departure_window_mins = 1;
+        assert_eq!(ident(&interception, &params), None);
+        params.departure_window_mins = 5;
+        // Not climbing.
+        interception.interceptor.cur_vertical_rate_fpm = Some(0.0);
+        assert_eq!(ident(&interception, &params), None);
+        interception.interceptor.cur_vertical_rate_fpm = Some(6000.0);
+        // First seen too far away.
+        interception.interceptor.first_fix = Some((first, [-115.2, 36.25]));
+        assert_eq!(ident(&interception, &params), None);
+        interception.interceptor.first_fix = None;
+        assert_eq!(ident(&interception, &params), None);
+    }
+}
@@ -0,0 +1,265 @@
+//! Confidence scores: a grade from 0 to 100 of how much a detection looks
+//! like a real interception, so the tradeoff between precision and recall
+//! can be made downstream instead of by the detector's pass/fail criteria.
+//!
+//! A score starts at 100. Each criterion a pair falls short on takes off its
+//! weight (see ConfidenceWeights) times how far short it falls, and an
+//! emergency squawk adds its weight back, up to 100:
+//!
+//! - proximity_duration: how long the pair has been close, with full credit
+//!   at confidence_full_duration_secs.
+//! - cpa_distance: how close they are, with no credit at close_radius_m.
+//! - heading_convergence, speed_matching, descent, and data_quality: whether
+//!   the pair meets the NotConverging, SpeedNotMatched, NotDescending, and
+//!   LowQuality criteria. These are all or nothing.
+//! - military_interceptor: whether the interceptor is likely military.
+//! - emergency_squawk: whether the target is squawking an emergency.
+//!
+//! Every detection is scored, and an event's confidence is its best
+//! detection's. With min_confidence set, the four all-or-nothing criteria
+//! stop being pass/fail, and a pair that has been close for long enough is
+//! only detected if it scores at least min_confidence. The default weights
+//! put a pair that fails any of them under 50, so a min_confidence of 50
+//! detects about the same pairs as the criteria do. In explain mode, each
+//! CandidateCheck has its score's breakdown.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::interception::{meets_criterion, Ac, DetectionParams, Rejection};
+
+/// The criteria that are scored instead of pass/fail when min_confidence is
+/// set.
+pub const SCORED_CRITERIA: [Rejection; 4] = [
+    Rejection::NotConverging,
+    Rejection::SpeedNotMatched,
+    Rejection::NotDescending,
+    Rejection::LowQuality,
+];
+
+/// Something that counts toward a confidence score. Serialized in
+/// snake_case, e.g. "proximity_duration".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Criterion {
+    ProximityDuration,
+    CpaDistance,
+    HeadingConvergence,
+    SpeedMatching,
+    Descent,
+    MilitaryInterceptor,
+    EmergencySquawk,
+    DataQuality,
+}
+
+impl Criterion {
+    /// The criterion check_pair would reject the pair on instead, for the
+    /// ones that are also pass/fail.
+    fn rejection(self) -> Option<Rejection> {
+        match self {
+            Criterion::HeadingConvergence => Some(Rejection::NotConverging),
+            Criterion::SpeedMatching => Some(Rejection::SpeedNotMatched),
+            Criterion::Descent => Some(Rejection::NotDescending),
+            Criterion::DataQuality => Some(Rejection::LowQuality),
+            _ => None,
+        }
+    }
+}
+
+const ALL_CRITERIA: [Criterion; 8] = [
+    Criterion::ProximityDuration,
+    Criterion::CpaDistance,
+    Criterion::HeadingConvergence,
+    Criterion::SpeedMatching,
+    Criterion::Descent,
+    Criterion::MilitaryInterceptor,
+    Criterion::EmergencySquawk,
+    Criterion::DataQuality,
+];
+
+/// How many points each criterion is worth. In a config file they're a
+/// [detection.confidence_weights] table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfidenceWeights {
+    pub proximity_duration: f64,
+    pub cpa_distance: f64,
+    pub heading_convergence: f64,
+    pub speed_matching: f64,
+    pub descent: f64,
+    pub military_interceptor: f64,
+    pub emergency_squawk: f64,
+    pub data_quality: f64,
+}
+
+impl Default for ConfidenceWeights {
+    fn default() -> Self {
+        ConfidenceWeights {
+            proximity_duration: 20.0,
+            cpa_distance: 20.0,
+            heading_convergence: 70.0,
+            speed_matching: 70.0,
+            descent: 70.0,
+            military_interceptor: 10.0,
+            emergency_squawk: 15.0,
+            data_quality: 70.0,
+        }
+    }
+}
+
+impl ConfidenceWeights {
+    pub fn weight(&self, criterion: Criterion) -> f64 {
+        match criterion {
+            Criterion::ProximityDuration => self.proximity_duration,
+            Criterion::CpaDistance => self.cpa_distance,
+            Criterion::HeadingConvergence => self.heading_convergence,
+            Criterion::SpeedMatching => self.speed_matching,
+            Criterion::Descent => self.descent,
+            Criterion::MilitaryInterceptor => self.military_interceptor,
+            Criterion::EmergencySquawk => self.emergency_squawk,
+            Criterion::DataQuality => self.data_quality,
+        }
+    }
+}
+
+/// One criterion's part of a score.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfidenceTerm {
+    pub criterion: Criterion,
+    /// How well the pair meets it, from 0 to 1.
+    pub met: f64,
+    /// What it added to the score: negative for a shortfall, positive for an
+    /// emergency squawk.
+    pub points: f64,
+}
+
+/// A confidence score and how it was arrived at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Confidence {
+    /// From 0 to 100.
+    pub score: f64,
+    pub terms: Vec<ConfidenceTerm>,
+}
+
+impl Confidence {
+    /// Scores an interceptor and a target that are lateral_separation_m
+    /// apart, and have been close for close_secs.
+    pub fn score(
+        interceptor: &Ac,
+        target: &Ac,
+        lateral_separation_m: f64,
+        close_secs: i64,
+        now: DateTime<Utc>,
+        params: &DetectionParams,
+    ) -> Self {
+        let met = |criterion: Criterion| match criterion {
+            Criterion::ProximityDuration => {
+                if params.confidence_full_duration_secs <= 0 {
+                    1.0
+                } else {
+                    close_secs as f64 / params.confidence_full_duration_secs as f64
+                }
+            }
+            Criterion::CpaDistance => 1.0 - lateral_separation_m / params.close_radius_m(),
+            Criterion::MilitaryInterceptor => f64::from(u8::from(interceptor.military)),
+            Criterion::EmergencySquawk => f64::from(u8::from(target.emergency().is_some())),
+            _ => {
+                let rejection = criterion.rejection().unwrap();
+                let alt_diff = interceptor.vertical_separation_ft(target);
+                let meets = meets_criterion(
+                    rejection,
+                    interceptor,
+                    target,
+                    lateral_separation_m,
+                    alt_diff,
+                    now,
+                    params,
+                );
+                f64::from(u8::from(meets))
+            }
+        };
+        let terms: Vec<ConfidenceTerm> = ALL_CRITERIA
+            .into_iter()
+            .map(|criterion| {
+                let met = met(criterion).clamp(0.0, 1.0);
+                let weight = params.confidence_weights.weight(criterion);
+                let points = match criterion {
+                    Criterion::EmergencySquawk => met * weight,
+                    _ => -(1.0 - met) * weight,
+                };
+                ConfidenceTerm {
+                    criterion,
+                    met,
+                    points,
+                }
+            })
+            .collect();
+        let score = (100.0 + terms.iter().map(|t| t.points).sum::<f64>()).clamp(0.0, 100.0);
+        Confidence { score, terms }
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::tests::event;
+
+    #[test]
+    fn test_confidence() {
+        let event = event();
+        let (interceptor, target) = (&event.closest.interceptor, &event.closest.target);
+        let mut params = DetectionParams {
+            confidence_full_duration_secs: 300,
+            ..Default::default()
+        };
+        let now = event.closest.time;
+        let confidence = Confidence::score(interceptor, target, 0.0, 150, now, &params);
+        let term = |confidence: &Confidence, criterion| {
+            confidence
+                .terms
+                .iter()
+                .find(|t| t.criterion == criterion)
+                .unwrap()
+                .clone()
+        };
+        // Half the duration, and the fixture has no track to tell whether
+        // they're converging.
+        assert_eq!(
+            term(&confidence, Criterion::ProximityDuration).points,
+            -10.0
+        );
+        assert_eq!(term(&confidence, Criterion::CpaDistance).points, 0.0);
+        assert_eq!(
+            term(&confidence, Criterion::HeadingConvergence).points,
+            -70.0
+        );
+        assert_eq!(
+            term(&confidence, Criterion::MilitaryInterceptor).points,
+            0.0
+        );
+        assert_eq!(confidence.score, 20.0);
+
+        // Out at close_radius_m there's no credit for distance.
+        let far = Confidence::score(
+            interceptor,
+            target,
+            params.close_radius_m(),
+            300,
+            now,
+            &params,
+        );
+        assert_eq!(term(&far, Criterion::CpaDistance).points, -20.0);
+        assert_eq!(term(&far, Criterion::ProximityDuration).points, 0.0);
+
+        // Failing a pass/fail criterion puts it under 50.
+        params.require_descent = true;
+        let descent = Confidence::score(interceptor, target, 0.0, 300, now, &params);
+        assert_eq!(term(&descent, Criterion::Descent).met, 0.0);
+        assert!(descent.score < 50.0, "{}", descent.score);
+
+        let json = serde_json::to_value(&descent).unwrap();
+        assert_eq!(json["terms"][4]["criterion"], "descent");
+        assert_eq!(json["terms"][4]["points"], -70.0);
+    }
+}
@@ -0,0 +1,523 @@
+//! Loading settings from a TOML config file, e.g.
+//!
+//! ```toml
+//! [detection]
+//! interceptor_min_speed_kts = 350.0
+//! airliner_types = ["A320", "B738"]
+//!
+//! [input]
+//! paths = ["/data/adsbx/2023-01-02"]
+//! start = 2023-01-02T12:00:00Z
+//!
+//! [output]
+//! csv = "interceptions.csv"
+//!
+//! [live]
+//! url = "https://api.adsb.lol/v2/mil"
+//! ```
+//!
+//! Every section and key is optional, and anything that's left out gets its
+//! default value. The detection keys are DetectionParams' fields, and
+//! TargetCriteria's with a "target_" prefix; interceptor profiles are
+//! [[detection.interceptor_profiles]] tables (see InterceptorProfile), and
+//! confidence weights a [detection.confidence_weights] table (see
+//! ConfidenceWeights). Unknown keys are errors, so typos don't go unnoticed.
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use toml_edit::{Document, Item, Value};
+
+use crate::{
+    error::Error,
+    input::TimeRange,
+    interception::{DetectionParams, FAST_JET_PROFILE},
+};
+
+/// Everything a config file can set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub detection: DetectionParams,
+    pub input: InputConfig,
+    pub output: OutputConfig,
+    pub live: LiveConfig,
+}
+
+/// Which input to read, and how.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Files, directories, or glob patterns. See expand_input_paths.
+    pub paths: Vec<String>,
+    /// Only snapshots at or after start and before end are processed.
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    /// Skip files that can't be loaded instead of stopping.
+    pub skip_errors: bool,
+    /// Re-sort out-of-order input within a window of this many files.
+    pub reorder_window: Option<usize>,
+    /// The number of threads to load files with.
+    pub workers: Option<usize>,
+    /// A replay file to read instead of paths. See the replay module.
+    pub replay: Option<String>,
+}
+
+impl InputConfig {
+    /// Returns the time range to process, if start or end is set.
+    pub fn time_range(&self) -> Option<TimeRange> {
+        if self.start.is_none() && self.end.is_none() {
+            return None;
+        }
+        Some(TimeRange::new(
+            self.start.unwrap_or(DateTime::<Utc>::MIN_UTC),
+            self.end.unwrap_or(DateTime::<Utc>::MAX_UTC),
+        ))
+    }
+}
+
+/// Where to send interceptions. Each is off unless it's set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub csv: Option<String>,
+    pub geojson: Option<String>,
+    /// Zipped if it ends in .kmz.
+    pub kml: Option<String>,
+    /// JSON Lines, as read by load_groups.
+    pub json: Option<String>,
+    /// A self-contained HTML report; see the report module.
+    pub html: Option<String>,
+    /// Cursor-on-Target events; see the cot module.
+    pub cot: Option<String>,
+    /// Event counts and fast-mover hours per lat/lon cell, as CSV, or
+    /// GeoJSON if it ends in .geojson or .json; see the heatmap module.
+    pub heatmap: Option<String>,
+    /// Requires the "sqlite" feature.
+    pub sqlite: Option<String>,
+    /// Every aircraft's state in every snapshot. Requires the "parquet"
+    /// feature.
+    pub parquet: Option<String>,
+    /// The snapshots, to read back with input.replay.
+    pub replay: Option<String>,
+    /// A URL to POST each interception to when it starts and ends. Requires
+    /// the "live" feature.
+    pub webhook: Option<String>,
+    /// A host:port to send Cursor-on-Target events to over UDP as
+    /// interceptions are detected.
+    pub cot_udp: Option<String>,
+}
+
+/// Polling a live API instead of reading files. Requires the "live"
+/// feature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiveConfig {
+    /// The endpoint to poll, e.g. https://api.adsb.lol/v2/mil. Polling is
+    /// off unless it's set.
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    /// The header to send api_key in.
+    pub api_key_header: String,
+    pub poll_secs: u64,
+}
+
+impl Default for LiveConfig {
+    fn default() -> Self {
+        LiveConfig {
+            url: None,
+            api_key: None,
+            api_key_header: "api-auth".to_string(),
+            // live::DEFAULT_POLL_INTERVAL.
+            poll_secs: 10,
+        }
+    }
+}
+
+// Converts a TOML item to the equivalent JSON, so it can be deserialized
+// with serde_json. Datetimes become strings.
+fn item_to_json(item: &Item) -> serde_json::Value {
+    match item {
+        Item::None => serde_json::Value::Null,
+        Item::Value(v) => value_to_json(v),
+        Item::Table(t) => t
+            .iter()
+            .map(|(k, v)| (k.to_string(), item_to_json(v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        Item::ArrayOfTables(a) => a
+            .iter()
+            .map(|t| {
+                t.iter()
+                    .map(|(k, v)| (k.to_string(), item_to_json(v)))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into()
+            })
+            .collect::<Vec<serde_json::Value>>()
+            .into(),
+    }
+}
+
+fn value_to_json(v: &Value) -> serde_json::Value {
+    match v {
+        Value::String(s) => s.value().clone().into(),
+        Value::Integer(i) => (*i.value()).into(),
+        Value::Float(f) => (*f.value()).into(),
+        Value::Boolean(b) => (*b.value()).into(),
+        Value::Datetime(d) => d.value().to_string().into(),
+        Value::Array(a) => a.iter().map(value_to_json).collect::<Vec<_>>().into(),
+        Value::InlineTable(t) => t
+            .iter()
+            .map(|(k, v)| (k.to_string(), value_to_json(v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+// Returns the 1-based line and column of a byte offset in s.
+fn line_column(s: &str, offset: usize) -> (usize, usize) {
+    let before = &s[..offset.min(s.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+// A parsed config file, for finding where keys are.
+struct Source<'a> {
+    text: &'a str,
+    doc: Document<&'a str>,
+}
+
+impl Source<'_> {
+    // Returns an error about a key, given as its section and name (or just
+    // the section), with its line and column.
+    fn error(&self, section: &str, key: Option<&str>, message: String) -> Error {
+        let root = self.doc.as_table();
+        let span = match key {
+            None => root.key(section).and_then(|k| k.span()),
+            Some(key) => root
+                .get(section)
+                .and_then(Item::as_table_like)
+                .and_then(|t| t.get_key_value(key))
+                .and_then(|(k, item)| k.span().or_else(|| item.span())),
+        };
+        let (line, column) = span.map(|s| line_column(self.text, s.start)).unzip();
+        Error::Config {
+            key: match key {
+                Some(key) => format!("{}.{}", section, key),
+                None => section.to_string(),
+            },
+            line,
+            column,
+            message,
+        }
+    }
+
+    // Deserializes a section, or returns an error naming the key that's
+    // wrong with it: one that T doesn't have, or the first one that T
+    // deserializes without.
+    fn section<T: Default + Serialize + DeserializeOwned>(
+        &self,
+        json: &serde_json::Value,
+        section: &str,
+    ) -> Result<T, Error> {
+        let Some(value) = json.get(section) else {
+            return Ok(T::default());
+        };
+        let Some(table) = value.as_object() else {
+            return Err(self.error(section, None, "expected a table".to_string()));
+        };
+        let known = serde_json::to_value(T::default()).expect("config should serialize");
+        if let Some(key) = table.keys().find(|k| known.get(k.as_str()).is_none()) {
+            return Err(self.error(section, Some(key), "unknown key".to_string()));
+        }
+        serde_json::from_value(value.clone()).map_err(|e| {
+            let key = table.keys().find(|k| {
+                let mut without = table.clone();
+                without.remove(k.as_str());
+                serde_json::from_value::<T>(without.into()).is_ok()
+            });
+            self.error(section, key.map(String::as_str), e.to_string())
+        })
+    }
+}
+
+impl Config {
+    /// Parses a config from TOML. Syntax errors are Error::Parse, and bad
+    /// keys and values are Error::Config, naming the key.
+    pub fn from_toml(s: &str) -> Result<Config, Error> {
+        let doc = Document::parse(s).map_err(|e| {
+            let (line, column) = e.span().map(|span| line_column(s, span.start)).unzip();
+            Error::Parse {
+                line,
+                column,
+                source: Box::new(e),
+            }
+        })?;
+        let source = Source { text: s, doc };
+        let json = item_to_json(source.doc.as_item());
+        let sections = ["detection", "input", "output", "live"];
+        if let Some(key) = json
+            .as_object()
+            .and_then(|root| root.keys().find(|k| !sections.contains(&k.as_str())))
+        {
+            return Err(source.error(key, None, "unknown section".to_string()));
+        }
+        Ok(Config {
+            detection: source.section(&json, "detection")?,
+            input: source.section(&json, "input")?,
+            output: source.section(&json, "output")?,
+            live: source.section(&json, "live")?,
+        })
+    }
+
+    /// Loads and validates a config file.
+    pub fn load(path: &str) -> Result<Config, Error> {
+        std::fs::read_to_string(path)
+            .map_err(Error::Io)
+            .and_then(|s| Config::from_toml(&s))
+            .and_then(|config| config.validate().map(|_| config))
+            .map_err(|e| e.in_file(path))
+    }
+
+    /// Checks for settings that contradict each other, or that mean nothing
+    /// could ever be detected.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |key: &str, message: String| {
+            Err(Error::Config {
+                key: key.to_string(),
+                line: None,
+                column: None,
+                message,
+            })
+        };
+        let d = &self.detection;
+        if d.target.min_speed_kts >= d.target.max_speed_kts {
+            return invalid(
+                "detection.target_min_speed_kts",
+                format!(
+                    "must be less than target_max_speed_kts ({})",
+                    d.target.max_speed_kts
+                ),
+            );
+        }
+        if let (Some(min), Some(max)) = (d.target.min_alt_ft, d.target.max_alt_ft) {
+            if min > max {
+                return invalid(
+                    "detection.target_min_alt_ft",
+                    format!("must be at most target_max_alt_ft ({})", max),
+                );
+            }
+        }
+        if d.target.slow_min_passes == 0 {
+            return invalid(
+                "detection.target_slow_min_passes",
+                "must be at least 1".to_string(),
+            );
+        }
+        let mut names = std::collections::HashSet::from([FAST_JET_PROFILE]);
+        for profile in &d.interceptor_profiles {
+            if profile.name.is_empty() || !names.insert(&profile.name) {
+                return invalid(
+                    "detection.interceptor_profiles",
+                    format!(
+                        "each profile needs a name that isn't {:?} or another profile's",
+                        FAST_JET_PROFILE
+                    ),
+                );
+            }
+        }
+        if d.min_confidence
+            .is_some_and(|min| !(0.0..=100.0).contains(&min))
+        {
+            return invalid(
+                "detection.min_confidence",
+                "must be from 0 to 100".to_string(),
+            );
+        }
+        if d.max_vertical_separation_ft > d.vertical_gate_ft {
+            return invalid(
+                "detection.max_vertical_separation_ft",
+                format!(
+                    "must be at most vertical_gate_ft ({}), or nothing further apart than that is ever close",
+                    d.vertical_gate_ft
+                ),
+            );
+        }
+        if d.search_radius_nm <= 0.0 {
+            return invalid("detection.search_radius_nm", "must be positive".to_string());
+        }
+        if d.region_buffer_nm > d.region_margin_nm {
+            return invalid(
+                "detection.region_buffer_nm",
+                format!(
+                    "must be at most region_margin_nm ({}), or aircraft in the buffer aren't tracked",
+                    d.region_margin_nm
+                ),
+            );
+        }
+        if let (Some(start), Some(end)) = (self.input.start, self.input.end) {
+            if start >= end {
+                return invalid("input.start", format!("must be before end ({})", end));
+            }
+        }
+        if self.live.poll_secs == 0 {
+            return invalid("live.poll_secs", "must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [detection]
+            # Only fast jets.
+            interceptor_min_speed_kts = 400.0
+            max_vertical_separation_ft = 500
+            airliner_types = ["A320"]
+            hijack_min_proximity_frames = 2
+
+            [[detection.interceptor_profiles]]
+            name = "helicopter"
+            min_speed_kts = 60.0
+            categories = ["A7"]
+
+            [detection.confidence_weights]
+            descent = 40.0
+
+            [input]
+            paths = ["data/*.json"]
+            start = 2023-01-02T12:00:00Z
+            end = "2023-01-02T14:00:00Z"
+
+            [output]
+            csv = "out.csv"
+
+            [live]
+            url = "https://api.adsb.lol/v2/mil"
+            "#,
+        )
+        .unwrap();
+        let d = &config.detection;
+        assert_eq!(d.interceptor_min_speed_kts, 400.0);
+        assert_eq!(d.max_vertical_separation_ft, 500);
+        assert_eq!(d.airliner_types.iter().collect::<Vec<_>>(), vec!["A320"]);
+        assert_eq!(d.hijack_min_proximity_frames, Some(2));
+        assert_eq!(d.interceptor_profiles.len(), 1);
+        assert_eq!(d.interceptor_profiles[0].min_speed_kts, 60.0);
+        assert_eq!(d.interceptor_profiles[0].min_fast_count, 10);
+        assert_eq!(d.confidence_weights.descent, 40.0);
+        assert_eq!(d.confidence_weights.heading_convergence, 70.0);
+        assert_eq!(
+            d.target.max_speed_kts,
+            DetectionParams::default().target.max_speed_kts
+        );
+        assert_eq!(config.input.paths, vec!["data/*.json"]);
+        let range = config.input.time_range().unwrap();
+        assert_eq!(
+            range.start,
+            Utc.with_ymd_and_hms(2023, 1, 2, 12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            range.end,
+            Utc.with_ymd_and_hms(2023, 1, 2, 14, 0, 0).unwrap()
+        );
+        assert_eq!(config.output.csv.as_deref(), Some("out.csv"));
+        assert_eq!(config.output.sqlite, None);
+        assert_eq!(config.live.poll_secs, 10);
+        assert_eq!(config.live.api_key_header, "api-auth");
+        config.validate().unwrap();
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn test_bad_toml() {
+        match Config::from_toml("[detection]\nsearch_radius_nm = \n") {
+            Err(Error::Parse { line, .. }) => assert_eq!(line, Some(2)),
+            other => panic!("Expected Parse, got {:?}", other),
+        }
+        let config_error = |toml: &str| match Config::from_toml(toml) {
+            Err(Error::Config {
+                key, line, column, ..
+            }) => (key, line, column),
+            other => panic!("Expected Config, got {:?}", other),
+        };
+        assert_eq!(
+            config_error(
+                "[detection]\nsearch_radius_nm = 10.0\nmax_lateral_separation_m = \"far\"\n"
+            ),
+            (
+                "detection.max_lateral_separation_m".to_string(),
+                Some(3),
+                Some(1)
+            )
+        );
+        assert_eq!(
+            config_error("[output]\n  cvs = \"out.csv\"\n"),
+            ("output.cvs".to_string(), Some(2), Some(3))
+        );
+        assert_eq!(
+            config_error("[outputs]\ncsv = \"out.csv\"\n"),
+            ("outputs".to_string(), Some(1), Some(2))
+        );
+        assert_eq!(
+            config_error("[[detection.interceptor_profiles]]\nmin_speed = 60.0\n").0,
+            "detection.interceptor_profiles"
+        );
+        assert_eq!(
+            config_error("live = 3"),
+            ("live".to_string(), Some(1), Some(1))
+        );
+        let err = Config::from_toml("[input]\nstart = \"yesterday\"").unwrap_err();
+        assert!(
+            err.to_string().starts_with("line 2: input.start: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid_key = |toml: &str| match Config::from_toml(toml).unwrap().validate() {
+            Err(Error::Config { key, .. }) => key,
+            other => panic!("Expected Config, got {:?}", other),
+        };
+        assert_eq!(
+            invalid_key("[detection]\ntarget_min_speed_kts = 300.0\ntarget_max_speed_kts = 250.0"),
+            "detection.target_min_speed_kts"
+        );
+        assert_eq!(
+            invalid_key("[detection]\ntarget_min_alt_ft = 20000\ntarget_max_alt_ft = 10000"),
+            "detection.target_min_alt_ft"
+        );
+        assert_eq!(
+            invalid_key("[detection]\ntarget_slow_min_passes = 0"),
+            "detection.target_slow_min_passes"
+        );
+        assert_eq!(
+            invalid_key("[[detection.interceptor_profiles]]\nname = \"fast_jet\""),
+            "detection.interceptor_profiles"
+        );
+        assert_eq!(
+            invalid_key("[detection]\nmin_confidence = 150.0"),
+            "detection.min_confidence"
+        );
+        assert_eq!(
+            invalid_key("[detection]\nmax_vertical_separation_ft = 5000"),
+            "detection.max_vertical_separation_ft"
+        );
+        assert_eq!(
+            invalid_key("[input]\nstart = 2023-01-02T14:00:00Z\nend = 2023-01-02T12:00:00Z"),
+            "input.start"
+        );
+        assert_eq!(invalid_key("[live]\npoll_secs = 0"), "live.poll_secs");
+    }
+}
@@ -0,0 +1,344 @@
+//! Cursor-on-Target (CoT) output, for showing interceptions in ATAK and
+//! other TAK clients.
+//!
+//! Each detection becomes two CoT events, one per aircraft: the interceptor
+//! as a-f-A (friendly air) and the target as a-u-A (unknown air) or a-n-A
+//! (neutral air), with the callsign and what it was doing in the detail.
+//! Events go stale a few snapshot intervals after the snapshot they're from,
+//! so TAK clients drop markers that stop being updated.
+//!
+//! events_to_cot writes the closest detection of each event, one CoT event
+//! per line, the way TAK Server's streaming input accepts them. CotSender
+//! sends them over UDP as they're detected, e.g. to a TAK client's or TAK
+//! Server's mesh SA input.
+
+use std::{
+    fmt::Write as _,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+use crate::{
+    error::Error,
+    events::DetectionEventKind,
+    interception::{Ac, Interception},
+    notify::Notifier,
+    report::escape_html,
+    tracker::InterceptionEvent,
+};
+
+/// The CoT type of interceptors: friendly air.
+pub const INTERCEPTOR_TYPE: &str = "a-f-A";
+
+/// Each CoT event goes stale this many snapshot intervals after its
+/// snapshot.
+pub const STALE_SNAPSHOTS: i32 = 3;
+
+/// The snapshot interval used for aircraft with only one position, in
+/// seconds.
+const DEFAULT_SNAPSHOT_INTERVAL_SECS: i64 = 60;
+
+const METERS_PER_FOOT: f64 = 0.3048;
+const METERS_PER_SEC_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+// Sent before each event over UDP. Events in a file are on one line each,
+// without it.
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+
+/// What targets are shown as. Parses from "unknown" or "neutral".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetAffiliation {
+    /// a-u-A.
+    #[default]
+    Unknown,
+    /// a-n-A.
+    Neutral,
+}
+
+impl TargetAffiliation {
+    pub fn cot_type(self) -> &'static str {
+        match self {
+            TargetAffiliation::Unknown => "a-u-A",
+            TargetAffiliation::Neutral => "a-n-A",
+        }
+    }
+}
+
+impl FromStr for TargetAffiliation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "unknown" => Ok(TargetAffiliation::Unknown),
+            "neutral" => Ok(TargetAffiliation::Neutral),
+            _ => Err(Error::ExportError(format!(
+                "Unknown CoT target affiliation {}; expected unknown or neutral",
+                s
+            ))),
+        }
+    }
+}
+
+/// Formats a time the way CoT wants it, e.g. 2021-02-23T19:42:38.000Z.
+pub fn cot_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// The time between an aircraft's last two positions, which is usually the
+/// interval between snapshots.
+pub fn snapshot_interval(ac: &Ac) -> Duration {
+    let n = ac.coords.len();
+    if n < 2 {
+        return Duration::seconds(DEFAULT_SNAPSHOT_INTERVAL_SECS);
+    }
+    ac.coords[n - 1].0 - ac.coords[n - 2].0
+}
+
+fn name(ac: &Ac) -> String {
+    match ac.callsign.as_deref().map(str::trim) {
+        Some(callsign) if !callsign.is_empty() => callsign.to_string(),
+        _ => ac.hex.to_string(),
+    }
+}
+
+/// Returns the CoT event for an aircraft's latest position, as of time.
+pub fn aircraft_cot(ac: &Ac, cot_type: &str, remarks: &str, time: DateTime<Utc>) -> String {
+    let (_, [lon, lat]) = *ac.cur_coords();
+    let stale = time + snapshot_interval(ac) * STALE_SNAPSHOTS;
+    let mut cot = String::new();
+    write!(
+        cot,
+        r#"<event version="2.0" uid="ICAO-{}" type="{}" how="m-g" time="{}" start="{}" stale="{}">"#,
+        escape_html(&ac.hex.to_string().to_ascii_uppercase()),
+        cot_type,
+        cot_time(time),
+        cot_time(time),
+        cot_time(stale),
+    )
+    .unwrap();
+    // The geometric altitude is close enough to height above the
+    // ellipsoid. There's no error estimate.
+    write!(
+        cot,
+        r#"<point lat="{:.6}" lon="{:.6}" hae="{:.1}" ce="9999999.0" le="9999999.0"/>"#,
+        lat,
+        lon,
+        ac.cur_alt as f64 * METERS_PER_FOOT
+    )
+    .unwrap();
+    write!(
+        cot,
+        r#"<detail><contact callsign="{}"/>"#,
+        escape_html(&name(ac))
+    )
+    .unwrap();
+    if let Some(Some(track)) = ac.tracks.back() {
+        write!(
+            cot,
+            r#"<track course="{:.1}" speed="{:.1}"/>"#,
+            track,
+            ac.cur_speed * METERS_PER_SEC_PER_KNOT
+        )
+        .unwrap();
+    }
+    write!(
+        cot,
+        "<remarks>{}</remarks></detail></event>",
+        escape_html(remarks)
+    )
+    .unwrap();
+    cot
+}
+
+/// Returns the CoT events for the interceptor and the target of a
+/// detection.
+pub fn interception_cot(interception: &Interception, target: TargetAffiliation) -> [String; 2] {
+    let (interceptor_ac, target_ac) = (&interception.interceptor, &interception.target);
+    let separation = format!(
+        "{:.0} ft lateral, {} ft vertical separation",
+        interception.lateral_separation_ft, interception.vertical_separation_ft
+    );
+    [
+        aircraft_cot(
+            interceptor_ac,
+            INTERCEPTOR_TYPE,
+            &format!("Intercepting {}, {}", name(target_ac), separation),
+            interception.time,
+        ),
+        aircraft_cot(
+            target_ac,
+            target.cot_type(),
+            &format!("Intercepted by {}, {}", name(interceptor_ac), separation),
+            interception.time,
+        ),
+    ]
+}
+
+/// Converts the closest detection of each event to CoT, one event per line.
+pub fn events_to_cot(events: &[InterceptionEvent], target: TargetAffiliation) -> String {
+    let mut cot = String::new();
+    for event in events {
+        for line in interception_cot(&event.closest, target) {
+            cot.push_str(&line);
+            cot.push('\n');
+        }
+    }
+    cot
+}
+
+/// Sends CoT events to a UDP address as interceptions are detected.
+///
+/// By default it sends the aircraft's positions when an event starts and
+/// when they get closer than they've been so far; with positions, it sends
+/// them on every detection, so TAK clients can follow the aircraft.
+#[derive(Debug)]
+pub struct CotSender {
+    socket: UdpSocket,
+    target: TargetAffiliation,
+    positions: bool,
+}
+
+impl CotSender {
+    /// Creates a sender to addr, a host:port.
+    pub fn new(addr: &str) -> Result<Self, Error> {
+        let bad_addr = |e: String| Error::ExportError(format!("Bad CoT address {}: {}", addr, e));
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|e| bad_addr(e.to_string()))?
+            .next()
+            .ok_or_else(|| bad_addr("no addresses".to_string()))?;
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)
+            .and_then(|s| s.connect(addr).map(|_| s))
+            .map_err(Error::Io)?;
+        Ok(CotSender {
+            socket,
+            target: TargetAffiliation::default(),
+            positions: false,
+        })
+    }
+
+    pub fn target_affiliation(mut self, target: TargetAffiliation) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Whether to send every detection. The default is false.
+    pub fn positions(mut self, positions: bool) -> Self {
+        self.positions = positions;
+        self
+    }
+
+    /// Sends the CoT events for a detection, one per datagram.
+    pub fn send(&self, interception: &Interception) -> Result<(), Error> {
+        for cot in interception_cot(interception, self.target) {
+            self.socket
+                .send(format!("{}{}", XML_DECLARATION, cot).as_bytes())
+                .map_err(|e| Error::ExportError(format!("Couldn't send CoT: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for CotSender {
+    fn notify(&self, kind: DetectionEventKind, event: &InterceptionEvent) -> Result<(), Error> {
+        // When an event ends, its markers are left to go stale.
+        if self.positions || kind == DetectionEventKind::InterceptionEnded {
+            return Ok(());
+        }
+        self.send(&event.closest)
+    }
+
+    fn wants_updates(&self) -> bool {
+        !self.positions
+    }
+
+    fn detection(&self, interception: &Interception) -> Result<(), Error> {
+        if self.positions {
+            self.send(interception)?;
+        }
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::tests::event;
+
+    #[test]
+    fn test_interception_cot() {
+        let event = event();
+        let [interceptor, target] = interception_cot(&event.closest, TargetAffiliation::Neutral);
+        assert_eq!(
+            interceptor,
+            concat!(
+                r#"<event version="2.0" uid="ICAO-AE1234" type="a-f-A" how="m-g" time="2021-02-23T19:42:38.000Z" start="2021-02-23T19:42:38.000Z" stale="2021-02-23T19:45:38.000Z">"#,
+                r#"<point lat="34.001000" lon="-118.000000" hae="3048.0" ce="9999999.0" le="9999999.0"/>"#,
+                r#"<detail><contact callsign="RCH123"/>"#,
+                "<remarks>Intercepting a12345, 350 ft lateral, 100 ft vertical separation</remarks></detail></event>",
+            )
+        );
+        assert!(target.contains(r#"type="a-n-A""#));
+        assert!(target.contains(r#"<contact callsign="a12345"/>"#));
+        assert!(target.contains("<remarks>Intercepted by RCH123, "));
+
+        let cot = events_to_cot(&[event], TargetAffiliation::default());
+        assert_eq!(cot.lines().count(), 2);
+        assert!(cot.contains(r#"type="a-u-A""#));
+        assert_eq!(
+            "Neutral".parse::<TargetAffiliation>().unwrap(),
+            TargetAffiliation::Neutral
+        );
+        assert!("hostile".parse::<TargetAffiliation>().is_err());
+    }
+
+    #[test]
+    fn test_cot_sender() {
+        let event = event();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let addr = receiver.local_addr().unwrap().to_string();
+        let recv = || {
+            let mut buf = [0; 2048];
+            let n = receiver.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..n].to_vec()).unwrap()
+        };
+
+        let sender = CotSender::new(&addr).unwrap();
+        assert!(sender.wants_updates());
+        sender.detection(&event.closest).unwrap();
+        sender
+            .notify(DetectionEventKind::InterceptionEnded, &event)
+            .unwrap();
+        sender
+            .notify(DetectionEventKind::InterceptionStarted, &event)
+            .unwrap();
+        // Only the start was sent.
+        let first = recv();
+        assert!(first.starts_with(XML_DECLARATION), "{}", first);
+        assert!(first.contains(r#"type="a-f-A""#), "{}", first);
+        assert!(recv().contains(r#"type="a-u-A""#));
+
+        let sender = CotSender::new(&addr).unwrap().positions(true);
+        assert!(!sender.wants_updates());
+        sender
+            .notify(DetectionEventKind::InterceptionStarted, &event)
+            .unwrap();
+        sender.detection(&event.closest).unwrap();
+        assert!(recv().contains("ICAO-AE1234"));
+        assert!(recv().contains("ICAO-A12345"));
+        receiver.set_nonblocking(true).unwrap();
+        assert!(receiver.recv(&mut [0; 16]).is_err());
+
+        assert!(CotSender::new("localhost").is_err());
+    }
+}
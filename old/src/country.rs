@@ -0,0 +1,268 @@
+//! The country an aircraft is registered in, from the block of ICAO 24-bit
+//! addresses its hex ID is in.
+//!
+//! ICAO allocates address blocks to each state (Annex 10, Volume III). An
+//! aircraft keeps its address for as long as it's on that state's register,
+//! so this is the country of registration, not of the operator.
+
+/// A block of ICAO addresses, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexRange {
+    pub country: &'static str,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl HexRange {
+    pub const fn new(country: &'static str, start: u32, end: u32) -> Self {
+        HexRange {
+            country,
+            start,
+            end,
+        }
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+/// The ICAO address blocks allocated to each country, in address order.
+pub const COUNTRY_HEX_RANGES: &[HexRange] = &[
+    HexRange::new("Zimbabwe", 0x004000, 0x0043ff),
+    HexRange::new("Mozambique", 0x006000, 0x006fff),
+    HexRange::new("South Africa", 0x008000, 0x00ffff),
+    HexRange::new("Egypt", 0x010000, 0x017fff),
+    HexRange::new("Libya", 0x018000, 0x01ffff),
+    HexRange::new("Morocco", 0x020000, 0x027fff),
+    HexRange::new("Tunisia", 0x028000, 0x02ffff),
+    HexRange::new("Botswana", 0x030000, 0x0303ff),
+    HexRange::new("Burundi", 0x032000, 0x032fff),
+    HexRange::new("Cameroon", 0x034000, 0x034fff),
+    HexRange::new("Comoros", 0x035000, 0x0353ff),
+    HexRange::new("Congo", 0x036000, 0x036fff),
+    HexRange::new("Côte d'Ivoire", 0x038000, 0x038fff),
+    HexRange::new("Gabon", 0x03e000, 0x03efff),
+    HexRange::new("Ethiopia", 0x040000, 0x040fff),
+    HexRange::new("Equatorial Guinea", 0x042000, 0x042fff),
+    HexRange::new("Ghana", 0x044000, 0x044fff),
+    HexRange::new("Guinea", 0x046000, 0x046fff),
+    HexRange::new("Guinea-Bissau", 0x048000, 0x0483ff),
+    HexRange::new("Lesotho", 0x04a000, 0x04a3ff),
+    HexRange::new("Kenya", 0x04c000, 0x04cfff),
+    HexRange::new("Liberia", 0x050000, 0x050fff),
+    HexRange::new("Madagascar", 0x054000, 0x054fff),
+    HexRange::new("Malawi", 0x058000, 0x058fff),
+    HexRange::new("Maldives", 0x05a000, 0x05a3ff),
+    HexRange::new("Mali", 0x05c000, 0x05cfff),
+    HexRange::new("Mauritania", 0x05e000, 0x05e3ff),
+    HexRange::new("Mauritius", 0x060000, 0x0603ff),
+    HexRange::new("Niger", 0x062000, 0x062fff),
+    HexRange::new("Nigeria", 0x064000, 0x064fff),
+    HexRange::new("Uganda", 0x068000, 0x068fff),
+    HexRange::new("Qatar", 0x06a000, 0x06a3ff),
+    HexRange::new("Central African Republic", 0x06c000, 0x06cfff),
+    HexRange::new("Rwanda", 0x06e000, 0x06efff),
+    HexRange::new("Senegal", 0x070000, 0x070fff),
+    HexRange::new("Seychelles", 0x074000, 0x0743ff),
+    HexRange::new("Sierra Leone", 0x076000, 0x0763ff),
+    HexRange::new("Somalia", 0x078000, 0x078fff),
+    HexRange::new("Eswatini", 0x07a000, 0x07a3ff),
+    HexRange::new("Sudan", 0x07c000, 0x07cfff),
+    HexRange::new("Tanzania", 0x080000, 0x080fff),
+    HexRange::new("Chad", 0x084000, 0x084fff),
+    HexRange::new("Togo", 0x088000, 0x088fff),
+    HexRange::new("Zambia", 0x08a000, 0x08afff),
+    HexRange::new("Democratic Republic of the Congo", 0x08c000, 0x08cfff),
+    HexRange::new("Angola", 0x090000, 0x090fff),
+    HexRange::new("Benin", 0x094000, 0x0943ff),
+    HexRange::new("Cabo Verde", 0x096000, 0x0963ff),
+    HexRange::new("Djibouti", 0x098000, 0x0983ff),
+    HexRange::new("Gambia", 0x09a000, 0x09afff),
+    HexRange::new("Burkina Faso", 0x09c000, 0x09cfff),
+    HexRange::new("Sao Tome and Principe", 0x09e000, 0x09e3ff),
+    HexRange::new("Algeria", 0x0a0000, 0x0a7fff),
+    HexRange::new("Bahamas", 0x0a8000, 0x0a8fff),
+    HexRange::new("Barbados", 0x0aa000, 0x0aa3ff),
+    HexRange::new("Belize", 0x0ab000, 0x0ab3ff),
+    HexRange::new("Colombia", 0x0ac000, 0x0acfff),
+    HexRange::new("Costa Rica", 0x0ae000, 0x0aefff),
+    HexRange::new("Cuba", 0x0b0000, 0x0b0fff),
+    HexRange::new("El Salvador", 0x0b2000, 0x0b2fff),
+    HexRange::new("Guatemala", 0x0b4000, 0x0b4fff),
+    HexRange::new("Guyana", 0x0b6000, 0x0b6fff),
+    HexRange::new("Haiti", 0x0b8000, 0x0b8fff),
+    HexRange::new("Honduras", 0x0ba000, 0x0bafff),
+    HexRange::new("Saint Vincent and the Grenadines", 0x0bc000, 0x0bc3ff),
+    HexRange::new("Jamaica", 0x0be000, 0x0befff),
+    HexRange::new("Nicaragua", 0x0c0000, 0x0c0fff),
+    HexRange::new("Panama", 0x0c2000, 0x0c2fff),
+    HexRange::new("Dominican Republic", 0x0c4000, 0x0c4fff),
+    HexRange::new("Trinidad and Tobago", 0x0c6000, 0x0c6fff),
+    HexRange::new("Suriname", 0x0c8000, 0x0c8fff),
+    HexRange::new("Antigua and Barbuda", 0x0ca000, 0x0ca3ff),
+    HexRange::new("Grenada", 0x0cc000, 0x0cc3ff),
+    HexRange::new("Mexico", 0x0d0000, 0x0d7fff),
+    HexRange::new("Venezuela", 0x0d8000, 0x0dffff),
+    HexRange::new("Russia", 0x100000, 0x1fffff),
+    HexRange::new("Namibia", 0x201000, 0x2013ff),
+    HexRange::new("Eritrea", 0x202000, 0x2023ff),
+    HexRange::new("Italy", 0x300000, 0x33ffff),
+    HexRange::new("Spain", 0x340000, 0x37ffff),
+    HexRange::new("France", 0x380000, 0x3bffff),
+    HexRange::new("Germany", 0x3c0000, 0x3fffff),
+    HexRange::new("United Kingdom", 0x400000, 0x43ffff),
+    HexRange::new("Austria", 0x440000, 0x447fff),
+    HexRange::new("Belgium", 0x448000, 0x44ffff),
+    HexRange::new("Bulgaria", 0x450000, 0x457fff),
+    HexRange::new("Denmark", 0x458000, 0x45ffff),
+    HexRange::new("Finland", 0x460000, 0x467fff),
+    HexRange::new("Greece", 0x468000, 0x46ffff),
+    HexRange::new("Hungary", 0x470000, 0x477fff),
+    HexRange::new("Norway", 0x478000, 0x47ffff),
+    HexRange::new("Netherlands", 0x480000, 0x487fff),
+    HexRange::new("Poland", 0x488000, 0x48ffff),
+    HexRange::new("Portugal", 0x490000, 0x497fff),
+    HexRange::new("Czechia", 0x498000, 0x49ffff),
+    HexRange::new("Romania", 0x4a0000, 0x4a7fff),
+    HexRange::new("Sweden", 0x4a8000, 0x4affff),
+    HexRange::new("Switzerland", 0x4b0000, 0x4b7fff),
+    HexRange::new("Turkey", 0x4b8000, 0x4bffff),
+    HexRange::new("Serbia", 0x4c0000, 0x4c7fff),
+    HexRange::new("Cyprus", 0x4c8000, 0x4c83ff),
+    HexRange::new("Ireland", 0x4ca000, 0x4cafff),
+    HexRange::new("Iceland", 0x4cc000, 0x4ccfff),
+    HexRange::new("Luxembourg", 0x4d0000, 0x4d03ff),
+    HexRange::new("Malta", 0x4d2000, 0x4d23ff),
+    HexRange::new("Monaco", 0x4d4000, 0x4d43ff),
+    HexRange::new("San Marino", 0x500000, 0x5003ff),
+    HexRange::new("Albania", 0x501000, 0x5013ff),
+    HexRange::new("Croatia", 0x501c00, 0x501fff),
+    HexRange::new("Latvia", 0x502c00, 0x502fff),
+    HexRange::new("Lithuania", 0x503c00, 0x503fff),
+    HexRange::new("Moldova", 0x504c00, 0x504fff),
+    HexRange::new("Slovakia", 0x505c00, 0x505fff),
+    HexRange::new("Slovenia", 0x506c00, 0x506fff),
+    HexRange::new("Uzbekistan", 0x507c00, 0x507fff),
+    HexRange::new("Ukraine", 0x508000, 0x50ffff),
+    HexRange::new("Belarus", 0x510000, 0x5103ff),
+    HexRange::new("Estonia", 0x511000, 0x5113ff),
+    HexRange::new("North Macedonia", 0x512000, 0x5123ff),
+    HexRange::new("Bosnia and Herzegovina", 0x513000, 0x5133ff),
+    HexRange::new("Georgia", 0x514000, 0x5143ff),
+    HexRange::new("Tajikistan", 0x515000, 0x5153ff),
+    HexRange::new("Montenegro", 0x516000, 0x5163ff),
+    HexRange::new("Armenia", 0x600000, 0x6003ff),
+    HexRange::new("Azerbaijan", 0x600800, 0x600bff),
+    HexRange::new("Kyrgyzstan", 0x601000, 0x6013ff),
+    HexRange::new("Turkmenistan", 0x601800, 0x601bff),
+    HexRange::new("Bhutan", 0x680000, 0x6803ff),
+    HexRange::new("Micronesia", 0x681000, 0x6813ff),
+    HexRange::new("Mongolia", 0x682000, 0x6823ff),
+    HexRange::new("Kazakhstan", 0x683000, 0x6833ff),
+    HexRange::new("Palau", 0x684000, 0x6843ff),
+    HexRange::new("Afghanistan", 0x700000, 0x700fff),
+    HexRange::new("Bangladesh", 0x702000, 0x702fff),
+    HexRange::new("Myanmar", 0x704000, 0x704fff),
+    HexRange::new("Kuwait", 0x706000, 0x706fff),
+    HexRange::new("Laos", 0x708000, 0x708fff),
+    HexRange::new("Nepal", 0x70a000, 0x70afff),
+    HexRange::new("Oman", 0x70c000, 0x70c3ff),
+    HexRange::new("Cambodia", 0x70e000, 0x70efff),
+    HexRange::new("Saudi Arabia", 0x710000, 0x717fff),
+    HexRange::new("South Korea", 0x718000, 0x71ffff),
+    HexRange::new("North Korea", 0x720000, 0x727fff),
+    HexRange::new("Iraq", 0x728000, 0x72ffff),
+    HexRange::new("Iran", 0x730000, 0x737fff),
+    HexRange::new("Israel", 0x738000, 0x73ffff),
+    HexRange::new("Jordan", 0x740000, 0x747fff),
+    HexRange::new("Lebanon", 0x748000, 0x74ffff),
+    HexRange::new("Malaysia", 0x750000, 0x757fff),
+    HexRange::new("Philippines", 0x758000, 0x75ffff),
+    HexRange::new("Pakistan", 0x760000, 0x767fff),
+    HexRange::new("Singapore", 0x768000, 0x76ffff),
+    HexRange::new("Sri Lanka", 0x770000, 0x777fff),
+    HexRange::new("Syria", 0x778000, 0x77ffff),
+    HexRange::new("China", 0x780000, 0x7bffff),
+    HexRange::new("Australia", 0x7c0000, 0x7fffff),
+    HexRange::new("India", 0x800000, 0x83ffff),
+    HexRange::new("Japan", 0x840000, 0x87ffff),
+    HexRange::new("Thailand", 0x880000, 0x887fff),
+    HexRange::new("Vietnam", 0x888000, 0x88ffff),
+    HexRange::new("Yemen", 0x890000, 0x890fff),
+    HexRange::new("Bahrain", 0x894000, 0x894fff),
+    HexRange::new("Brunei", 0x895000, 0x8953ff),
+    HexRange::new("United Arab Emirates", 0x896000, 0x896fff),
+    HexRange::new("Solomon Islands", 0x897000, 0x8973ff),
+    HexRange::new("Papua New Guinea", 0x898000, 0x898fff),
+    HexRange::new("Taiwan", 0x899000, 0x8993ff),
+    HexRange::new("Indonesia", 0x8a0000, 0x8a7fff),
+    HexRange::new("Marshall Islands", 0x900000, 0x9003ff),
+    HexRange::new("Cook Islands", 0x901000, 0x9013ff),
+    HexRange::new("Samoa", 0x902000, 0x9023ff),
+    HexRange::new("United States", 0xa00000, 0xafffff),
+    HexRange::new("Canada", 0xc00000, 0xc3ffff),
+    HexRange::new("New Zealand", 0xc80000, 0xc87fff),
+    HexRange::new("Fiji", 0xc88000, 0xc88fff),
+    HexRange::new("Nauru", 0xc8a000, 0xc8a3ff),
+    HexRange::new("Saint Lucia", 0xc8c000, 0xc8c3ff),
+    HexRange::new("Tonga", 0xc8d000, 0xc8d3ff),
+    HexRange::new("Kiribati", 0xc8e000, 0xc8e3ff),
+    HexRange::new("Vanuatu", 0xc90000, 0xc903ff),
+    HexRange::new("Argentina", 0xe00000, 0xe3ffff),
+    HexRange::new("Brazil", 0xe40000, 0xe7ffff),
+    HexRange::new("Chile", 0xe80000, 0xe80fff),
+    HexRange::new("Ecuador", 0xe84000, 0xe84fff),
+    HexRange::new("Paraguay", 0xe88000, 0xe88fff),
+    HexRange::new("Peru", 0xe8c000, 0xe8cfff),
+    HexRange::new("Uruguay", 0xe90000, 0xe90fff),
+    HexRange::new("Bolivia", 0xe94000, 0xe94fff),
+];
+
+/// Parses a hex ID like "ae1234". Returns None for anything that isn't six
+/// hex digits, like the non-ICAO addresses ADS-B Exchange prefixes with "~".
+pub fn icao_address(hex: &str) -> Option<u32> {
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// Returns the country whose block a hex ID is in, e.g. "United States" for
+/// "a12345".
+pub fn hex_to_country(hex: &str) -> Option<&'static str> {
+    let address = icao_address(hex)?;
+    let i = COUNTRY_HEX_RANGES.partition_point(|r| r.end < address);
+    COUNTRY_HEX_RANGES
+        .get(i)
+        .filter(|r| r.contains(address))
+        .map(|r| r.country)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_to_country() {
+        assert_eq!(hex_to_country("a12345"), Some("United States"));
+        assert_eq!(hex_to_country("AE1234"), Some("United States"));
+        assert_eq!(hex_to_country("0d0abc"), Some("Mexico"));
+        assert_eq!(hex_to_country("c00001"), Some("Canada"));
+        assert_eq!(hex_to_country("43c5f1"), Some("United Kingdom"));
+        // The first and last addresses in a block.
+        assert_eq!(hex_to_country("780000"), Some("China"));
+        assert_eq!(hex_to_country("7bffff"), Some("China"));
+        // Unallocated.
+        assert_eq!(hex_to_country("f00000"), None);
+        assert_eq!(hex_to_country("000001"), None);
+        // Non-ICAO addresses, and junk.
+        assert_eq!(hex_to_country("~2d1a48"), None);
+        assert_eq!(hex_to_country("+a1234"), None);
+        assert_eq!(hex_to_country(""), None);
+        for w in COUNTRY_HEX_RANGES.windows(2) {
+            assert!(w[0].end < w[1].start, "{:?}", w);
+        }
+    }
+}
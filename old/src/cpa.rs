@@ -0,0 +1,285 @@
+//! Closest point of approach (CPA) between two aircraft.
+//!
+//! The separation recorded with a detection is whatever it happened to be in
+//! that snapshot. This interpolates both tracks (with Ac::position_at) onto a
+//! common time base and finds the true minimum 3D separation, assuming each
+//! aircraft flies in a straight line at constant speed between fixes.
+
+use chrono::{prelude::*, Duration};
+use serde::{Deserialize, Serialize};
+
+use crate::{geo::lon_difference, interception::Ac};
+
+/// Fixes further apart than this aren't interpolated between; a CPA that might
+/// be hidden in such a gap is flagged as low-confidence.
+pub const MAX_INTERPOLATION_GAP_SECS: f64 = 60.0;
+
+const FEET_PER_NM: f64 = 6076.12;
+
+/// The closest point of approach of two aircraft.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Cpa {
+    pub time: DateTime<Utc>,
+    pub lateral_ft: f64,
+    pub vertical_ft: f64,
+    /// The speed of the aircraft relative to each other at the CPA, in knots.
+    pub closing_speed_kts: f64,
+    /// True if a track had a gap longer than MAX_INTERPOLATION_GAP_SECS where
+    /// the aircraft might have come closer, or there wasn't enough data to
+    /// interpolate.
+    pub low_confidence: bool,
+}
+
+/// An aircraft's track, with its fix times in seconds since the origin.
+struct Track<'a> {
+    ac: &'a Ac,
+    times: Vec<f64>,
+}
+
+impl<'a> Track<'a> {
+    fn new(ac: &'a Ac, origin: DateTime<Utc>) -> Self {
+        let times = ac
+            .coords
+            .iter()
+            .map(|(t, _)| (*t - origin).num_milliseconds() as f64 / 1000.0)
+            .collect();
+        Track { ac, times }
+    }
+
+    /// Returns the interpolated position at time t, in a local flat
+    /// projection ([x ft, y ft, alt ft]), and whether the fixes either side of
+    /// t are too far apart to trust.
+    fn position(&self, origin: (DateTime<Utc>, [f64; 2]), t: f64) -> Option<([f64; 3], bool)> {
+        let (t0, [lon0, lat0]) = origin;
+        let time = t0 + Duration::milliseconds((t * 1000.0).round() as i64);
+        let [lon, lat] = self.ac.position_at(time)?;
+        let alt = self.ac.alt_at(time)?;
+        let p = [
+            lon_difference(lon0, lon) * lat0.to_radians().cos() * 60.0 * FEET_PER_NM,
+            (lat - lat0) * 60.0 * FEET_PER_NM,
+            alt,
+        ];
+        let i = self.times.partition_point(|ft| *ft < t);
+        let gap = i > 0
+            && i < self.times.len()
+            && self.times[i] != t
+            && self.times[i] - self.times[i - 1] > MAX_INTERPOLATION_GAP_SECS;
+        Some((p, gap))
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// The closest approach within one segment: (time, relative position,
+/// relative velocity in ft/s).
+fn segment_cpa(t0: f64, r0: [f64; 3], t1: f64, r1: [f64; 3]) -> (f64, [f64; 3], [f64; 3]) {
+    let dt = t1 - t0;
+    let v = [
+        (r1[0] - r0[0]) / dt,
+        (r1[1] - r0[1]) / dt,
+        (r1[2] - r0[2]) / dt,
+    ];
+    let vv = dot(v, v);
+    let s = if vv > 0.0 {
+        (-dot(r0, v) / vv).clamp(0.0, dt)
+    } else {
+        0.0
+    };
+    let r = [r0[0] + v[0] * s, r0[1] + v[1] * s, r0[2] + v[2] * s];
+    (t0 + s, r, v)
+}
+
+/// The position of one aircraft relative to another at time t (seconds
+/// since the origin), and whether the segment since the previous sample spans
+/// a gap in either track.
+struct Sample {
+    t: f64,
+    r: [f64; 3],
+    gap_before: bool,
+}
+
+/// Interpolates b's position relative to a at every time either of them has a
+/// fix, while both were tracked. Returns the origin time and the samples, or
+/// None if the tracks don't overlap in time.
+fn relative_track(a: &Ac, b: &Ac) -> Option<(DateTime<Utc>, Vec<Sample>)> {
+    let origin = *a.coords.front()?;
+    let track_a = Track::new(a, origin.0);
+    let track_b = Track::new(b, origin.0);
+    let start = track_a.times.first()?.max(*track_b.times.first()?);
+    let end = track_a.times.last()?.min(*track_b.times.last()?);
+    if start > end {
+        return None;
+    }
+    let mut times: Vec<f64> = track_a
+        .times
+        .iter()
+        .chain(track_b.times.iter())
+        .copied()
+        .filter(|t| *t >= start && *t <= end)
+        .collect();
+    times.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    times.dedup();
+
+    let relative = |t: f64| {
+        let (pa, gap_a) = track_a.position(origin, t)?;
+        let (pb, gap_b) = track_b.position(origin, t)?;
+        Some((sub(pb, pa), gap_a || gap_b))
+    };
+    let mut samples = Vec::with_capacity(times.len());
+    for (i, t) in times.iter().enumerate() {
+        let (r, _) = relative(*t)?;
+        // Neither track has a fix between two samples, so the midpoint tells
+        // us whether the segment spans a gap.
+        let gap_before = match i {
+            0 => false,
+            _ => relative((times[i - 1] + t) / 2.0)?.1,
+        };
+        samples.push(Sample {
+            t: *t,
+            r,
+            gap_before,
+        });
+    }
+    Some((origin.0, samples))
+}
+
+/// Returns the lateral and vertical separation of two aircraft, in feet, at
+/// every time either of them has a fix while both were tracked, interpolated
+/// between fixes. Empty if the tracks don't overlap in time.
+pub fn separation_series(a: &Ac, b: &Ac) -> Vec<(DateTime<Utc>, f64, i32)> {
+    let Some((origin, samples)) = relative_track(a, b) else {
+        return vec![];
+    };
+    samples
+        .iter()
+        .map(|s| {
+            (
+                origin + Duration::milliseconds((s.t * 1000.0).round() as i64),
+                s.r[0].hypot(s.r[1]),
+                s.r[2].abs().round() as i32,
+            )
+        })
+        .collect()
+}
+
+/// Computes the closest point of approach of two aircraft from their recorded
+/// tracks. Returns None if the tracks don't overlap in time.
+pub fn compute_cpa(a: &Ac, b: &Ac) -> Option<Cpa> {
+    let (origin, samples) = relative_track(a, b)?;
+    let to_cpa = |t: f64, r: [f64; 3], v: [f64; 3], low_confidence: bool| Cpa {
+        time: origin + Duration::milliseconds((t * 1000.0).round() as i64),
+        lateral_ft: r[0].hypot(r[1]),
+        vertical_ft: r[2].abs(),
+        closing_speed_kts: dot(v, v).sqrt() * 3600.0 / FEET_PER_NM,
+        low_confidence,
+    };
+    if let [only] = samples.as_slice() {
+        return Some(to_cpa(only.t, only.r, [0.0; 3], true));
+    }
+
+    // The best (distance, cpa) in segments we trust, and in segments where a
+    // track has a gap.
+    let mut best: Option<(f64, Cpa)> = None;
+    let mut best_in_gap: Option<(f64, Cpa)> = None;
+    for w in samples.windows(2) {
+        let gap = w[1].gap_before;
+        let (t, r, v) = segment_cpa(w[0].t, w[0].r, w[1].t, w[1].r);
+        let dist = dot(r, r).sqrt();
+        let slot = if gap { &mut best_in_gap } else { &mut best };
+        if slot.as_ref().is_none_or(|(d, _)| dist < *d) {
+            *slot = Some((dist, to_cpa(t, r, v, gap)));
+        }
+    }
+    match (best, best_in_gap) {
+        (Some((dist, mut cpa)), gap) => {
+            if gap.is_some_and(|(gap_dist, _)| gap_dist < dist) {
+                cpa.low_confidence = true;
+            }
+            Some(cpa)
+        }
+        (None, gap) => gap.map(|(_, mut cpa)| {
+            cpa.low_confidence = true;
+            cpa
+        }),
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_snapshot_filename_time;
+
+    fn ac(fixes: &[(i64, [f64; 2], i32)]) -> Ac {
+        let start = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        let coords: Vec<_> = fixes
+            .iter()
+            .map(|(s, c, _)| (start + Duration::seconds(*s), *c))
+            .collect();
+        Ac {
+            alts: fixes.iter().map(|(_, _, alt)| *alt).collect(),
+            max_speed: 0.0,
+            cur_speed: 0.0,
+            ..Ac::for_test("ae1234", &coords, fixes.last().unwrap().2)
+        }
+    }
+
+    #[test]
+    fn test_cpa_between_fixes() {
+        // The interceptor flies north past a stationary target 0.001 degrees
+        // of longitude to the east, and is closest halfway between fixes.
+        let interceptor = ac(&[(0, [-118.0, 33.99], 10000), (30, [-118.0, 34.01], 10200)]);
+        let target = ac(&[(0, [-117.999, 34.0], 10100), (30, [-117.999, 34.0], 10100)]);
+        let cpa = compute_cpa(&interceptor, &target).unwrap();
+        assert_eq!(cpa.time, interceptor.coords[0].0 + Duration::seconds(15));
+        // 0.001 degrees of longitude at 34 N.
+        assert!((cpa.lateral_ft - 302.3).abs() < 1.0, "{:?}", cpa);
+        assert!(cpa.vertical_ft < 1.0);
+        // 0.02 degrees of latitude in 30 seconds is 144 knots.
+        assert!((cpa.closing_speed_kts - 144.0).abs() < 1.0, "{:?}", cpa);
+        assert!(!cpa.low_confidence);
+    }
+
+    #[test]
+    fn test_separation_series() {
+        let interceptor = ac(&[(0, [-118.0, 33.99], 10000), (30, [-118.0, 34.01], 10200)]);
+        let target = ac(&[
+            (0, [-117.999, 34.0], 10100),
+            (15, [-117.999, 34.0], 10100),
+            (30, [-117.999, 34.0], 10100),
+            (45, [-117.999, 34.0], 10100),
+        ]);
+        let series = separation_series(&interceptor, &target);
+        // Only while both were tracked.
+        assert_eq!(series.len(), 3);
+        assert_eq!(series[1].0, interceptor.coords[0].0 + Duration::seconds(15));
+        assert!((series[1].1 - 302.3).abs() < 1.0, "{:?}", series);
+        assert_eq!(series[1].2, 0);
+        assert_eq!(series[2].2, 100);
+        assert!(series[0].1 > 3000.0);
+        let later = ac(&[(200, [-118.0, 34.0], 10000)]);
+        assert!(separation_series(&interceptor, &later).is_empty());
+    }
+
+    #[test]
+    fn test_cpa_gap_is_low_confidence() {
+        // Same geometry, but the interceptor's fixes are two minutes apart.
+        let interceptor = ac(&[(0, [-118.0, 33.99], 10000), (120, [-118.0, 34.01], 10000)]);
+        let target = ac(&[
+            (0, [-117.999, 34.0], 10000),
+            (30, [-117.999, 34.0], 10000),
+            (120, [-117.999, 34.0], 10000),
+        ]);
+        let cpa = compute_cpa(&interceptor, &target).unwrap();
+        assert!(cpa.low_confidence);
+
+        let later = ac(&[(200, [-118.0, 34.0], 10000), (215, [-118.0, 34.0], 10000)]);
+        assert_eq!(compute_cpa(&interceptor, &later), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! CSV output of interceptions, one row per interception, for quick triage in
+//! a spreadsheet.
+
+use std::{fs::File, io::Write};
+
+use serde::Serialize;
+
+use crate::{error::Error, hex_id::HexId, interception::Interception, tracker::InterceptionGroup};
+
+/// The column names, in order.
+pub const CSV_HEADER: &[&str] = &[
+    "event_id",
+    "time",
+    "interceptor_hex",
+    "interceptor_callsign",
+    "target_hex",
+    "target_callsign",
+    "lat",
+    "lon",
+    "lateral_separation_ft",
+    "vertical_separation_ft",
+    "interceptor_speed",
+    "target_speed",
+    "interceptor_alt",
+    "target_alt",
+    "interceptor_vertical_rate_fpm",
+    "target_vertical_rate_fpm",
+    "target_emergency",
+    "interceptor_source",
+    "target_source",
+];
+
+#[derive(Serialize)]
+struct Row<'a> {
+    event_id: &'a str,
+    time: String,
+    interceptor_hex: HexId,
+    interceptor_callsign: &'a str,
+    target_hex: HexId,
+    target_callsign: &'a str,
+    lat: f64,
+    lon: f64,
+    lateral_separation_ft: f64,
+    vertical_separation_ft: i32,
+    interceptor_speed: f64,
+    target_speed: f64,
+    interceptor_alt: Option<i32>,
+    target_alt: Option<i32>,
+    interceptor_vertical_rate_fpm: Option<f64>,
+    target_vertical_rate_fpm: Option<f64>,
+    target_emergency: &'a str,
+    interceptor_source: &'a str,
+    target_source: &'a str,
+}
+
+fn csv_error(e: impl std::fmt::Display) -> Error {
+    Error::ExportError(e.to_string())
+}
+
+/// Writes interceptions as CSV. Each row is flushed as soon as it's written,
+/// so long-running jobs can write interceptions as they're found.
+pub struct InterceptionCsvWriter<W: Write> {
+    writer: ::csv::Writer<W>,
+}
+
+impl InterceptionCsvWriter<File> {
+    /// Creates (or truncates) a CSV file.
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|e| csv_error(format!("{}: {}", path, e)))?;
+        InterceptionCsvWriter::new(file)
+    }
+
+    /// Appends to a CSV file, creating it if it doesn't exist. The header is
+    /// only written if the file is empty.
+    pub fn append(path: &str) -> Result<Self, Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| csv_error(format!("{}: {}", path, e)))?;
+        let is_empty = file
+            .metadata()
+            .map_err(|e| csv_error(format!("{}: {}", path, e)))?
+            .len()
+            == 0;
+        if is_empty {
+            InterceptionCsvWriter::new(file)
+        } else {
+            Ok(InterceptionCsvWriter::without_header(file))
+        }
+    }
+}
+
+impl<W: Write> InterceptionCsvWriter<W> {
+    /// Creates a writer and writes the header row.
+    pub fn new(writer: W) -> Result<Self, Error> {
+        let mut writer = InterceptionCsvWriter::without_header(writer);
+        writer.writer.write_record(CSV_HEADER).map_err(csv_error)?;
+        writer.writer.flush().map_err(csv_error)?;
+        Ok(writer)
+    }
+
+    /// Creates a writer that doesn't write a header row.
+    pub fn without_header(writer: W) -> Self {
+        InterceptionCsvWriter {
+            writer: ::csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(writer),
+        }
+    }
+
+    /// Writes a row for an interception, with no event_id.
+    pub fn write(&mut self, interception: &Interception) -> Result<(), Error> {
+        self.write_row("", interception)
+    }
+
+    /// Writes a row for each interceptor in a group, from its closest
+    /// detection, all with the group's event_id.
+    pub fn write_group(&mut self, group: &InterceptionGroup) -> Result<(), Error> {
+        for event in &group.interceptors {
+            self.write_row(&group.event_id, &event.closest)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, event_id: &str, interception: &Interception) -> Result<(), Error> {
+        let interceptor = &interception.interceptor;
+        let target = &interception.target;
+        let [lon, lat] = interception.location();
+        self.writer
+            .serialize(Row {
+                event_id,
+                time: interception.time.to_rfc3339(),
+                interceptor_hex: interceptor.hex,
+                interceptor_callsign: interceptor.callsign.as_deref().unwrap_or(""),
+                target_hex: target.hex,
+                target_callsign: target.callsign.as_deref().unwrap_or(""),
+                lat,
+                lon,
+                lateral_separation_ft: interception.lateral_separation_ft,
+                vertical_separation_ft: interception.vertical_separation_ft,
+                interceptor_speed: interceptor.cur_speed,
+                target_speed: target.cur_speed,
+                interceptor_alt: interceptor.known_alt(),
+                target_alt: target.known_alt(),
+                interceptor_vertical_rate_fpm: interceptor.cur_vertical_rate_fpm,
+                target_vertical_rate_fpm: target.cur_vertical_rate_fpm,
+                target_emergency: interception.emergency.map_or("", |e| e.code()),
+                interceptor_source: interceptor.source.as_deref().unwrap_or(""),
+                target_source: target.source.as_deref().unwrap_or(""),
+            })
+            .map_err(csv_error)?;
+        self.writer.flush().map_err(csv_error)
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.writer.into_inner().map_err(csv_error)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::parse_snapshot_filename_time,
+        interception::{Ac, EmergencySquawk},
+        tracker::{group_events, InterceptionTracker},
+    };
+    use chrono::Duration;
+
+    fn interception() -> Interception {
+        let time = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        let ac = |hex: &str, callsign: Option<&str>, lon: f64, speed: f64| Ac {
+            callsign: callsign.map(|c| c.to_string()),
+            max_speed: speed,
+            cur_speed: speed,
+            ..Ac::for_test(hex, &[(time, [lon, 34.0])], 10000)
+        };
+        let mut interceptor = ac("ae1234", Some("VV100,A"), -118.0, 450.0);
+        interceptor.cur_vertical_rate_fpm = Some(-6000.0);
+        interceptor.source = Some("adsb_icao".to_string());
+        let mut target = ac("a12345", None, -118.002, 320.0);
+        target.source = Some("mlat".to_string());
+        Interception {
+            emergency: Some(EmergencySquawk::Hijack),
+            ..Interception::for_test(interceptor, target, time)
+        }
+    }
+
+    #[test]
+    fn test_csv_writer() {
+        let mut writer = InterceptionCsvWriter::new(vec![]).unwrap();
+        writer.write(&interception()).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "event_id,time,interceptor_hex,interceptor_callsign,target_hex,target_callsign,lat,lon,lateral_separation_ft,vertical_separation_ft,interceptor_speed,target_speed,interceptor_alt,target_alt,interceptor_vertical_rate_fpm,target_vertical_rate_fpm,target_emergency,interceptor_source,target_source\n\
+             ,2021-02-23T19:38:53+00:00,ae1234,\"VV100,A\",a12345,,34.0,-118.001,600.0,0,450.0,320.0,10000,10000,-6000.0,,7500,adsb_icao,mlat\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_write_group() {
+        let mut wingman = interception();
+        wingman.interceptor.hex = "ae5678".parse().unwrap();
+        let events =
+            InterceptionTracker::merge_all(&[interception(), wingman], Duration::minutes(10));
+        let groups = group_events(&events, Some(Duration::zero()));
+        assert_eq!(groups.len(), 1);
+        let mut writer = InterceptionCsvWriter::new(vec![]).unwrap();
+        writer.write_group(&groups[0]).unwrap();
+        let csv = writer.into_inner().unwrap();
+        let mut reader = ::csv::Reader::from_reader(csv.as_slice());
+        let rows: Vec<_> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        for (row, hex) in rows.iter().zip(["ae1234", "ae5678"]) {
+            assert_eq!(&row[0], "a12345-20210223T193853Z");
+            assert_eq!(&row[2], hex);
+        }
+    }
+
+    #[test]
+    fn test_csv_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("append.csv");
+        let path = path.to_str().unwrap();
+        for _ in 0..2 {
+            let mut writer = InterceptionCsvWriter::append(path).unwrap();
+            writer.write(&interception()).unwrap();
+        }
+        let mut reader = ::csv::Reader::from_path(path).unwrap();
+        assert_eq!(reader.headers().unwrap(), CSV_HEADER);
+        assert_eq!(reader.records().count(), 2);
+    }
+}
@@ -0,0 +1,238 @@
+//! Why aircraft records were dropped instead of updating the state.
+//!
+//! A record that's missing its position or speed can't be used, and neither
+//! can one whose position jumped implausibly far (see Ac::is_plausible_fix)
+//! or that the prefilter rejected. DropStats counts them by reason, so it's
+//! possible to tell whether a feed is losing 2% of its records or 40%. A
+//! record for an aircraft that's already being tracked is only dropped if
+//! none of it could be used; otherwise it's counted as partly used.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Why an aircraft record was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DropReason {
+    MissingPosition,
+    MissingSpeed,
+    /// Neither a geometric nor a barometric altitude, for an aircraft that
+    /// isn't being tracked yet. Tracked aircraft keep their last altitude.
+    MissingAltitude,
+    MissingSeenPos,
+    /// Rejected by the prefilter. See AircraftStateMap::set_prefilter.
+    FilteredOut,
+    /// The position was rejected as implausible. See Ac::is_plausible_fix.
+    Outlier,
+    /// The hex ID wasn't valid. See HexId.
+    InvalidHexId,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 7] = [
+        DropReason::MissingPosition,
+        DropReason::MissingSpeed,
+        DropReason::MissingAltitude,
+        DropReason::MissingSeenPos,
+        DropReason::FilteredOut,
+        DropReason::Outlier,
+        DropReason::InvalidHexId,
+    ];
+
+    /// Returns the reason for an error from AircraftStateMap::update or
+    /// Ac::new, or None if it isn't one that drops a record.
+    pub fn from_error(e: &Error) -> Option<DropReason> {
+        match e {
+            Error::MissingField { field, .. } => match *field {
+                "position" => Some(DropReason::MissingPosition),
+                "ground speed" => Some(DropReason::MissingSpeed),
+                "altitude" => Some(DropReason::MissingAltitude),
+                "seen_pos" => Some(DropReason::MissingSeenPos),
+                _ => None,
+            },
+            Error::ImplausiblePosition { .. } => Some(DropReason::Outlier),
+            Error::InvalidHexId(_) => Some(DropReason::InvalidHexId),
+            _ => None,
+        }
+    }
+
+    /// Returns a short description, e.g. "missing position".
+    pub fn description(self) -> &'static str {
+        match self {
+            DropReason::MissingPosition => "missing position",
+            DropReason::MissingSpeed => "missing speed",
+            DropReason::MissingAltitude => "missing altitude",
+            DropReason::MissingSeenPos => "missing seen_pos",
+            DropReason::FilteredOut => "filtered out",
+            DropReason::Outlier => "outlier",
+            DropReason::InvalidHexId => "invalid hex ID",
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// Counts of dropped records by reason, along with the number of records
+/// looked at, so the counts can be put in proportion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DropStats {
+    /// The number of records looked at, dropped or not.
+    pub records: usize,
+    pub missing_position: usize,
+    pub missing_speed: usize,
+    pub missing_altitude: usize,
+    pub missing_seen_pos: usize,
+    pub filtered_out: usize,
+    pub outlier: usize,
+    pub invalid_hex_id: usize,
+    /// The number of records that weren't dropped, though they were missing
+    /// something or their position was rejected, because their aircraft was
+    /// already being tracked and was updated with the rest. They aren't in
+    /// total.
+    #[serde(default)]
+    pub partial: usize,
+}
+
+impl DropStats {
+    pub fn add(&mut self, reason: DropReason) {
+        *self.count_mut(reason) += 1;
+    }
+
+    fn count_mut(&mut self, reason: DropReason) -> &mut usize {
+        match reason {
+            DropReason::MissingPosition => &mut self.missing_position,
+            DropReason::MissingSpeed => &mut self.missing_speed,
+            DropReason::MissingAltitude => &mut self.missing_altitude,
+            DropReason::MissingSeenPos => &mut self.missing_seen_pos,
+            DropReason::FilteredOut => &mut self.filtered_out,
+            DropReason::Outlier => &mut self.outlier,
+            DropReason::InvalidHexId => &mut self.invalid_hex_id,
+        }
+    }
+
+    /// Returns the number of records dropped for a reason.
+    pub fn get(&self, reason: DropReason) -> usize {
+        match reason {
+            DropReason::MissingPosition => self.missing_position,
+            DropReason::MissingSpeed => self.missing_speed,
+            DropReason::MissingAltitude => self.missing_altitude,
+            DropReason::MissingSeenPos => self.missing_seen_pos,
+            DropReason::FilteredOut => self.filtered_out,
+            DropReason::Outlier => self.outlier,
+            DropReason::InvalidHexId => self.invalid_hex_id,
+        }
+    }
+
+    /// Returns the number of records dropped for any reason.
+    pub fn total(&self) -> usize {
+        DropReason::ALL.iter().map(|r| self.get(*r)).sum()
+    }
+
+    /// Returns the fraction of records that were dropped, or 0 if there
+    /// weren't any.
+    pub fn fraction_dropped(&self) -> f64 {
+        if self.records == 0 {
+            0.0
+        } else {
+            self.total() as f64 / self.records as f64
+        }
+    }
+}
+
+impl std::ops::AddAssign for DropStats {
+    fn add_assign(&mut self, other: Self) {
+        self.records += other.records;
+        self.partial += other.partial;
+        for reason in DropReason::ALL {
+            *self.count_mut(reason) += other.get(reason);
+        }
+    }
+}
+
+/// E.g. "dropped 12 of 1000 records (1.2%): 10 missing position, 2 outlier;
+/// 3 partly used".
+impl fmt::Display for DropStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dropped {} of {} records ({:.1}%)",
+            self.total(),
+            self.records,
+            self.fraction_dropped() * 100.0
+        )?;
+        let mut sep = ": ";
+        for reason in DropReason::ALL {
+            let count = self.get(reason);
+            if count > 0 {
+                write!(f, "{}{} {}", sep, count, reason)?;
+                sep = ", ";
+            }
+        }
+        if self.partial > 0 {
+            write!(f, "; {} partly used", self.partial)?;
+        }
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_stats() {
+        let mut stats = DropStats {
+            records: 1000,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            stats.add(DropReason::MissingPosition);
+        }
+        stats.add(DropReason::Outlier);
+        stats.add(DropReason::Outlier);
+        stats.partial = 3;
+        assert_eq!(stats.total(), 12);
+        assert_eq!(
+            stats.to_string(),
+            "dropped 12 of 1000 records (1.2%): 10 missing position, 2 outlier; 3 partly used"
+        );
+        let mut sum = stats;
+        sum += stats;
+        assert_eq!(sum.records, 2000);
+        assert_eq!(sum.partial, 6);
+        assert_eq!(sum.get(DropReason::MissingPosition), 20);
+        assert_eq!(
+            DropStats::default().to_string(),
+            "dropped 0 of 0 records (0.0%)"
+        );
+    }
+
+    #[test]
+    fn test_from_error() {
+        let missing = |field| Error::MissingField {
+            hex: "a12345".to_string(),
+            field,
+        };
+        assert_eq!(
+            DropReason::from_error(&missing("ground speed")),
+            Some(DropReason::MissingSpeed)
+        );
+        assert_eq!(
+            DropReason::from_error(&Error::ImplausiblePosition {
+                hex: "a12345".to_string()
+            }),
+            Some(DropReason::Outlier)
+        );
+        assert_eq!(
+            DropReason::from_error(&Error::WorkerPanic("oops".to_string())),
+            None
+        );
+    }
+}
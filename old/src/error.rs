@@ -1,14 +1,147 @@
+use std::io;
+
 use thiserror::Error;
 
+use crate::Compression;
+
 /// The adsbx_browser error type.
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum Error {
-    /// Tried to use an Aircraft that didn't have the required data
+    /// Tried to use an Aircraft that didn't have the required data.
+    #[error("Aircraft {hex} is missing {field}")]
+    MissingField { hex: String, field: &'static str },
+    /// An aircraft's position was rejected as implausible (see
+    /// Ac::rejected_fix).
+    #[error("Aircraft {hex} has an implausible position")]
+    ImplausiblePosition { hex: String },
+    /// An aircraft's hex ID wasn't six hex digits, optionally prefixed with
+    /// "~".
+    #[error("{0}")]
+    InvalidHexId(String),
+    /// A file or stream couldn't be read.
+    #[error("{0}")]
+    Io(#[source] io::Error),
+    /// Compressed input was corrupt or truncated.
+    #[error("Couldn't decompress {compression:?} data: {source}")]
+    Decompress {
+        compression: Compression,
+        source: io::Error,
+    },
+    /// A response wasn't valid JSON, or didn't match the schema. line and
+    /// column are where the parser gave up, if it knows.
+    #[error("{source}")]
+    Parse {
+        line: Option<usize>,
+        column: Option<usize>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A config file had an unknown key, a bad value, or settings that
+    /// contradict each other. key is the offending key, e.g.
+    /// "detection.search_radius_nm", and line and column are where it is in
+    /// the file, if known.
+    #[error("{}{key}: {message}", .line.map(|l| format!("line {}: ", l)).unwrap_or_default())]
+    Config {
+        key: String,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// A thread pool couldn't be started.
+    #[error("Couldn't start thread pool: {0}")]
+    ThreadPool(#[source] rayon::ThreadPoolBuildError),
+    /// A worker thread panicked.
+    #[error("Worker thread panicked: {0}")]
+    WorkerPanic(String),
+    /// An input path or pattern couldn't be expanded.
+    #[error("{0}")]
+    InputPathError(String),
+    /// A checkpoint couldn't be read or written.
+    #[error("{0}")]
+    CheckpointError(String),
+    /// A response was older than the response before it.
+    #[error("Input is out of order: {current} comes after {previous}")]
+    OutOfOrderInput {
+        previous: chrono::DateTime<chrono::Utc>,
+        current: chrono::DateTime<chrono::Utc>,
+    },
+    /// A database operation failed.
+    #[error("{0}")]
+    DatabaseError(String),
+    /// Results couldn't be written.
+    #[error("{0}")]
+    ExportError(String),
+    /// Airport data couldn't be loaded.
     #[error("{0}")]
-    AircraftMissingData(String),
+    AirportDataError(String),
+    /// Regions of interest couldn't be loaded.
     #[error("{0}")]
-    JsonLoadError(String),
+    RegionError(String),
+    /// The aircraft database couldn't be loaded.
     #[error("{0}")]
-    ParallelMapError(String),
+    AircraftDbError(String),
+    /// A live API couldn't be polled.
+    #[error("{0}")]
+    LiveError(String),
+    /// A ctrl-c handler couldn't be installed.
+    #[error("{0}")]
+    SignalError(String),
+    /// Saved detector state couldn't be read or written.
+    #[error("{0}")]
+    StateError(String),
+    /// A replay file couldn't be read or written.
+    #[error("{0}")]
+    ReplayError(String),
+    /// A state file was saved by a different version of the state format.
+    #[error("{path} has state version {found}, but only version {expected} can be loaded")]
+    StateVersionMismatch {
+        path: String,
+        found: u16,
+        expected: u16,
+    },
+    /// An error that occurred while loading a particular input file.
+    #[error("Error loading {path}: {source}")]
+    FileError { path: String, source: Box<Error> },
+}
+
+impl Error {
+    /// Converts a serde_json error, keeping errors that came from the reader
+    /// apart from ones in the JSON itself.
+    pub(crate) fn from_json(e: serde_json::Error) -> Error {
+        if e.is_io() {
+            return Error::from_io(e.into());
+        }
+        // serde_json reports line 0 for errors that aren't at any position,
+        // like those from from_value.
+        let (line, column) = match e.line() {
+            0 => (None, None),
+            line => (Some(line), Some(e.column())),
+        };
+        Error::Parse {
+            line,
+            column,
+            source: Box::new(e),
+        }
+    }
+
+    /// Converts an io error, unwrapping the Error inside it if it came from
+    /// one of the readers returned by open_adsbx_json_file.
+    pub(crate) fn from_io(e: io::Error) -> Error {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *e.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+        Error::Io(e)
+    }
+
+    /// Wraps this error in a FileError naming path, unless it already is
+    /// one.
+    pub(crate) fn in_file(self, path: &str) -> Error {
+        match self {
+            Error::FileError { .. } => self,
+            e => Error::FileError {
+                path: path.to_string(),
+                source: Box::new(e),
+            },
+        }
+    }
 }
@@ -0,0 +1,121 @@
+//! Interception events, for following the detector's findings as a stream.
+//!
+//! Events are written as JSON Lines, one object per line:
+//!
+//! ```json
+//! {"event":"interception_started","interceptor_hex":"ae1234","target_hex":"a12345","time":"2021-02-23T19:42:38Z","lateral_separation_ft":350.2,"vertical_separation_ft":100}
+//! ```
+
+use std::io::Write;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, interception::Interception, tracker::InterceptionEvent};
+
+/// What happened to an interception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionEventKind {
+    /// The interceptor and target came close.
+    InterceptionStarted,
+    /// The aircraft are closer than they've been so far.
+    InterceptionUpdated,
+    /// The aircraft haven't been close for a while. The time is the last time
+    /// they were, and the separations are the smallest of the interception.
+    InterceptionEnded,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectionEvent {
+    pub event: DetectionEventKind,
+    pub interceptor_hex: String,
+    pub target_hex: String,
+    pub time: DateTime<Utc>,
+    pub lateral_separation_ft: f64,
+    pub vertical_separation_ft: i32,
+}
+
+impl DetectionEvent {
+    pub fn new(event: DetectionEventKind, interception: &Interception) -> Self {
+        DetectionEvent {
+            event,
+            interceptor_hex: interception.interceptor.hex.to_string(),
+            target_hex: interception.target.hex.to_string(),
+            time: interception.time,
+            lateral_separation_ft: interception.lateral_separation_ft,
+            vertical_separation_ft: interception.vertical_separation_ft,
+        }
+    }
+
+    /// The InterceptionEnded event for a finished InterceptionEvent.
+    pub fn ended(event: &InterceptionEvent) -> Self {
+        DetectionEvent {
+            event: DetectionEventKind::InterceptionEnded,
+            interceptor_hex: event.interceptor_hex.clone(),
+            target_hex: event.target_hex.clone(),
+            time: event.end,
+            lateral_separation_ft: event.min_lateral_separation_ft,
+            vertical_separation_ft: event.min_vertical_separation_ft,
+        }
+    }
+}
+
+/// Writes events as JSON Lines, flushing after each one so the output can be
+/// tailed.
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesWriter { writer }
+    }
+
+    /// Writes an event, or anything else that can be serialized, like an
+    /// InterceptionGroup.
+    pub fn write<T: Serialize>(&mut self, event: &T) -> Result<(), Error> {
+        let export_error = |e: std::io::Error| Error::ExportError(e.to_string());
+        serde_json::to_writer(&mut self.writer, event)
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+        self.writer.write_all(b"\n").map_err(export_error)?;
+        self.writer.flush().map_err(export_error)
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_lines_writer() {
+        let event = DetectionEvent {
+            event: DetectionEventKind::InterceptionStarted,
+            interceptor_hex: "ae1234".to_string(),
+            target_hex: "a12345".to_string(),
+            time: Utc.with_ymd_and_hms(2021, 2, 23, 19, 42, 38).unwrap(),
+            lateral_separation_ft: 350.5,
+            vertical_separation_ft: 100,
+        };
+        let mut writer = JsonLinesWriter::new(vec![]);
+        writer.write(&event).unwrap();
+        writer.write(&event).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"{"event":"interception_started","interceptor_hex":"ae1234","target_hex":"a12345","time":"2021-02-23T19:42:38Z","lateral_separation_ft":350.5,"vertical_separation_ft":100}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DetectionEvent>(lines[1]).unwrap(),
+            event
+        );
+    }
+}
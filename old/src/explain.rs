@@ -0,0 +1,129 @@
+//! Explain mode: a record of why each fast mover was or wasn't counted as
+//! intercepting the potential target nearest to it, for tuning thresholds.
+//!
+//! When it's on (see AircraftStateMap::set_explain), every snapshot checks
+//! each fast mover against its nearest potential target, and against any
+//! target it's close to, on every criterion instead of stopping at the first
+//! one that fails. The CandidateChecks for the latest snapshot can be
+//! streamed (e.g. with JsonLinesWriter), and they're summed up per pair into
+//! CandidateReports. An Interception found in explain mode carries the check
+//! that promoted it in its criteria field. Shadows, which are found
+//! separately, aren't explained.
+//!
+//! It's off by default, since checking every criterion, and keeping a report
+//! for every pair, is much slower than the short-circuiting checks.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{confidence::Confidence, hex_id::HexId, interception::Rejection};
+
+/// How a fast mover and a potential target measured up in one snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CandidateCheck {
+    pub time: DateTime<Utc>,
+    pub interceptor_hex: HexId,
+    pub target_hex: HexId,
+    pub lateral_separation_m: f64,
+    /// None if either doesn't have an altitude.
+    pub vertical_separation_ft: Option<i32>,
+    pub speed_difference_kts: f64,
+    /// How far the interceptor's heading is from the bearing to the target,
+    /// in degrees, if it has a heading.
+    pub heading_offset_deg: Option<f64>,
+    /// The criteria the pair failed, in the order they're checked. Empty if
+    /// it was close, and had been for long enough to count.
+    pub failed: Vec<Rejection>,
+    /// If the pair was close, the number of snapshots in a row it had been,
+    /// and since when.
+    pub close_frames: usize,
+    pub close_since: Option<DateTime<Utc>>,
+    /// How the pair scored. See the confidence module.
+    #[serde(default)]
+    pub confidence: Option<Confidence>,
+}
+
+impl CandidateCheck {
+    pub fn passed(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Every check of one fast mover against one potential target, summed up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CandidateReport {
+    pub interceptor_hex: HexId,
+    pub target_hex: HexId,
+    pub num_checks: usize,
+    pub num_passed: usize,
+    /// How many checks failed each criterion.
+    pub failures: BTreeMap<Rejection, usize>,
+    /// The check where they were closest laterally.
+    pub closest: CandidateCheck,
+    pub first: CandidateCheck,
+    pub latest: CandidateCheck,
+}
+
+impl CandidateReport {
+    fn new(check: CandidateCheck) -> Self {
+        let mut report = CandidateReport {
+            interceptor_hex: check.interceptor_hex,
+            target_hex: check.target_hex,
+            num_checks: 0,
+            num_passed: 0,
+            failures: BTreeMap::new(),
+            closest: check.clone(),
+            first: check.clone(),
+            latest: check.clone(),
+        };
+        report.add(check);
+        report
+    }
+
+    fn add(&mut self, check: CandidateCheck) {
+        self.num_checks += 1;
+        if check.passed() {
+            self.num_passed += 1;
+        }
+        for reason in &check.failed {
+            *self.failures.entry(*reason).or_default() += 1;
+        }
+        if check.lateral_separation_m < self.closest.lateral_separation_m {
+            self.closest = check.clone();
+        }
+        self.latest = check;
+    }
+}
+
+/// The explain mode bookkeeping kept by AircraftStateMap.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Explainer {
+    reports: HashMap<(HexId, HexId), CandidateReport>,
+    pub(crate) latest: Vec<CandidateCheck>,
+}
+
+impl Explainer {
+    /// Replaces the latest snapshot's checks, adding them to the reports.
+    pub(crate) fn record(&mut self, checks: Vec<CandidateCheck>) {
+        for check in &checks {
+            let key = (check.interceptor_hex, check.target_hex);
+            match self.reports.get_mut(&key) {
+                Some(report) => report.add(check.clone()),
+                None => {
+                    self.reports
+                        .insert(key, CandidateReport::new(check.clone()));
+                }
+            }
+        }
+        self.latest = checks;
+    }
+
+    /// Returns the reports, ordered by interceptor and target.
+    pub(crate) fn reports(&self) -> Vec<CandidateReport> {
+        let mut reports: Vec<CandidateReport> = self.reports.values().cloned().collect();
+        reports.sort_by_key(|r| (r.interceptor_hex, r.target_hex));
+        reports
+    }
+}
@@ -0,0 +1,200 @@
+//! Converting saved detections (the JSON Lines file of InterceptionGroups
+//! written by JsonLinesWriter) to the other output formats, and writing
+//! groups in any of them.
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader},
+    str::FromStr,
+};
+
+use crate::{
+    cot::{events_to_cot, TargetAffiliation},
+    csv::InterceptionCsvWriter,
+    error::Error,
+    geojson::interception_groups_to_geojson,
+    interception::Interception,
+    kml::{interceptions_to_kml, write_interceptions_kmz},
+    report::write_report,
+    tracker::{InterceptionEvent, InterceptionGroup},
+};
+
+/// The formats detections can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    GeoJson,
+    /// KML, or KMZ if the output path ends in .kmz.
+    Kml,
+    Csv,
+    /// A report with a table of the events; see the report module.
+    Html,
+    /// Cursor-on-Target events, one per line; see the cot module.
+    Cot,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "geojson" => Ok(ExportFormat::GeoJson),
+            "kml" | "kmz" => Ok(ExportFormat::Kml),
+            "csv" => Ok(ExportFormat::Csv),
+            "html" => Ok(ExportFormat::Html),
+            "cot" => Ok(ExportFormat::Cot),
+            _ => Err(Error::ExportError(format!(
+                "Unknown export format {}; expected geojson, kml, csv, html, or cot",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::Kml => "kml",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Html => "html",
+            ExportFormat::Cot => "cot",
+        })
+    }
+}
+
+/// Reads InterceptionGroups, one JSON object per line. Blank lines are
+/// skipped.
+pub fn read_groups<R: BufRead>(r: R) -> Result<Vec<InterceptionGroup>, Error> {
+    let mut groups = vec![];
+    for (i, line) in r.lines().enumerate() {
+        let line = line.map_err(Error::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let group = serde_json::from_str(&line).map_err(|e| Error::Parse {
+            line: Some(i + 1),
+            column: Some(e.column()),
+            source: Box::new(e),
+        })?;
+        groups.push(group);
+    }
+    Ok(groups)
+}
+
+/// Loads InterceptionGroups from a JSON Lines file, as written by
+/// `tracon-interception detect --json`.
+pub fn load_groups(path: &str) -> Result<Vec<InterceptionGroup>, Error> {
+    std::fs::File::open(path)
+        .map_err(Error::Io)
+        .and_then(|f| read_groups(BufReader::new(f)))
+        .map_err(|e| e.in_file(path))
+}
+
+/// Writes groups to path in a format. CSV and HTML have a row per
+/// interceptor; KML and CoT have the closest detection of each interceptor's
+/// event.
+pub fn write_groups(
+    groups: &[InterceptionGroup],
+    format: ExportFormat,
+    path: &str,
+) -> Result<(), Error> {
+    let closest = || -> Vec<Interception> {
+        groups
+            .iter()
+            .flat_map(|g| g.interceptors.iter().map(|e| e.closest.clone()))
+            .collect()
+    };
+    let events = || -> Vec<InterceptionEvent> {
+        groups.iter().flat_map(|g| g.interceptors.clone()).collect()
+    };
+    let result = match format {
+        ExportFormat::GeoJson => {
+            std::fs::write(path, interception_groups_to_geojson(groups)).map_err(Error::Io)
+        }
+        ExportFormat::Kml if path.ends_with(".kmz") => std::fs::File::create(path)
+            .map_err(Error::Io)
+            .and_then(|f| write_interceptions_kmz(&closest(), f)),
+        ExportFormat::Kml => {
+            std::fs::write(path, interceptions_to_kml(&closest())).map_err(Error::Io)
+        }
+        ExportFormat::Csv => InterceptionCsvWriter::create(path).and_then(|mut writer| {
+            groups.iter().try_for_each(|g| writer.write_group(g))?;
+            writer.into_inner().map(|_| ())
+        }),
+        ExportFormat::Html => return write_report(&events(), path),
+        ExportFormat::Cot => {
+            std::fs::write(path, events_to_cot(&events(), TargetAffiliation::default()))
+                .map_err(Error::Io)
+        }
+    };
+    result.map_err(|e| e.in_file(path))
+}
+
+/// Converts a JSON Lines file of saved groups to another format. Returns
+/// the number of groups.
+pub fn export_file(input: &str, format: ExportFormat, output: &str) -> Result<usize, Error> {
+    let groups = load_groups(input)?;
+    write_groups(&groups, format, output)?;
+    Ok(groups.len())
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events::JsonLinesWriter, testutil::standard_intercept_state};
+
+    fn groups() -> Vec<InterceptionGroup> {
+        standard_intercept_state().groups()
+    }
+
+    #[test]
+    fn test_export_file() {
+        let groups = groups();
+        assert_eq!(groups.len(), 1);
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("results.json");
+        let input = input.to_str().unwrap();
+        let mut writer = JsonLinesWriter::new(std::fs::File::create(input).unwrap());
+        writer.write(&groups[0]).unwrap();
+        drop(writer);
+        let loaded = load_groups(input).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].event_id, groups[0].event_id);
+        assert_eq!(loaded[0].interceptor_hexes(), vec!["ae1234"]);
+        for (format, name) in [
+            (ExportFormat::GeoJson, "export.geojson"),
+            (ExportFormat::Kml, "export.kml"),
+            (ExportFormat::Csv, "export.csv"),
+            (ExportFormat::Html, "export.html"),
+            (ExportFormat::Cot, "export.cot"),
+        ] {
+            let output = dir.path().join(name);
+            let output = output.to_str().unwrap();
+            assert_eq!(export_file(input, format, output).unwrap(), 1);
+            let exported = std::fs::read_to_string(output).unwrap();
+            // CoT has the IDs in upper case.
+            assert!(
+                exported.to_ascii_lowercase().contains("a12345"),
+                "{}: {}",
+                format,
+                exported
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_errors() {
+        assert_eq!(
+            "GeoJSON".parse::<ExportFormat>().unwrap(),
+            ExportFormat::GeoJson
+        );
+        assert!("shapefile".parse::<ExportFormat>().is_err());
+        let err = read_groups(&b"\n{\"event_id\": 1}\n"[..]).unwrap_err();
+        assert!(
+            matches!(err, Error::Parse { line: Some(2), .. }),
+            "{:?}",
+            err
+        );
+    }
+}
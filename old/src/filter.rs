@@ -0,0 +1,136 @@
+//! Filters that decide which aircraft records the detector looks at at all.
+//! See AircraftStateMap::set_prefilter.
+//!
+//! Records that don't match are dropped before they update the state, so
+//! they cost neither time nor memory. An aircraft that's already being
+//! tracked and stops matching isn't forgotten straight away, though: it just
+//! stops being updated, and expires after stale_aircraft_mins like any other
+//! aircraft that's gone quiet, so ongoing interceptions still end normally.
+
+use std::{fmt, sync::Arc};
+
+use adsbx_json::v2::Aircraft;
+
+use crate::alt_number;
+
+/// Decides whether an aircraft record should be used. Closures that take an
+/// &Aircraft and return a bool are filters too.
+pub trait AircraftFilter: Send + Sync {
+    fn matches(&self, aircraft: &Aircraft) -> bool;
+}
+
+impl<F: Fn(&Aircraft) -> bool + Send + Sync> AircraftFilter for F {
+    fn matches(&self, aircraft: &Aircraft) -> bool {
+        self(aircraft)
+    }
+}
+
+/// A filter that can be shared by clones of the state, and debug printed
+/// even though closures can't be.
+#[derive(Clone)]
+pub(crate) struct SharedFilter(pub(crate) Arc<dyn AircraftFilter>);
+
+impl fmt::Debug for SharedFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedFilter")
+    }
+}
+
+/// Matches aircraft inside a box of longitudes and latitudes, inclusive. If
+/// min_lon is greater than max_lon, the box crosses the antimeridian.
+/// Records without a position don't match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBoxFilter {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl AircraftFilter for BoundingBoxFilter {
+    fn matches(&self, aircraft: &Aircraft) -> bool {
+        let (Some(lon), Some(lat)) = (aircraft.lon, aircraft.lat) else {
+            return false;
+        };
+        let lon_matches = if self.min_lon <= self.max_lon {
+            (self.min_lon..=self.max_lon).contains(&lon)
+        } else {
+            lon >= self.min_lon || lon <= self.max_lon
+        };
+        lon_matches && (self.min_lat..=self.max_lat).contains(&lat)
+    }
+}
+
+/// Matches aircraft at or below an altitude in feet, e.g. 20,000 for FL200.
+/// The barometric altitude is used if there is one, otherwise the geometric
+/// altitude. Aircraft on the ground match, and records without an altitude
+/// don't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxAltitudeFilter(pub i32);
+
+impl AircraftFilter for MaxAltitudeFilter {
+    fn matches(&self, aircraft: &Aircraft) -> bool {
+        aircraft
+            .barometric_altitude
+            .clone()
+            .map(alt_number)
+            .or(aircraft.geometric_altitude)
+            .is_some_and(|alt| alt <= self.0)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn aircraft(lon: f64, lat: f64, alt_baro: serde_json::Value) -> Aircraft {
+        let mut value = json!({
+            "hex": "a12345", "type": "adsb_icao", "messages": 100, "rssi": -20.0, "seen": 0.0,
+            "lat": lat, "lon": lon, "alt_geom": 30000,
+        });
+        if !alt_baro.is_null() {
+            value["alt_baro"] = alt_baro;
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let socal = BoundingBoxFilter {
+            min_lon: -121.0,
+            min_lat: 32.0,
+            max_lon: -114.0,
+            max_lat: 35.0,
+        };
+        assert!(socal.matches(&aircraft(-118.0, 34.0, json!(10000))));
+        assert!(socal.matches(&aircraft(-121.0, 35.0, json!(10000))));
+        assert!(!socal.matches(&aircraft(-118.0, 36.0, json!(10000))));
+        assert!(!socal.matches(&aircraft(-122.0, 34.0, json!(10000))));
+        let pacific = BoundingBoxFilter {
+            min_lon: 170.0,
+            min_lat: -20.0,
+            max_lon: -170.0,
+            max_lat: 20.0,
+        };
+        assert!(pacific.matches(&aircraft(175.0, 0.0, json!(10000))));
+        assert!(pacific.matches(&aircraft(-175.0, 0.0, json!(10000))));
+        assert!(!pacific.matches(&aircraft(0.0, 0.0, json!(10000))));
+        let mut no_position = aircraft(-118.0, 34.0, json!(10000));
+        no_position.lat = None;
+        assert!(!socal.matches(&no_position));
+    }
+
+    #[test]
+    fn test_max_altitude() {
+        let filter = MaxAltitudeFilter(20_000);
+        assert!(filter.matches(&aircraft(0.0, 0.0, json!(20000))));
+        assert!(filter.matches(&aircraft(0.0, 0.0, json!("ground"))));
+        assert!(!filter.matches(&aircraft(0.0, 0.0, json!(37000))));
+        // Falls back to geometric altitude, which is 30,000 ft here.
+        assert!(!filter.matches(&aircraft(0.0, 0.0, serde_json::Value::Null)));
+        let closure = |a: &Aircraft| a.lon.is_some_and(|lon| lon < 0.0);
+        assert!(closure.matches(&aircraft(-1.0, 0.0, json!(0))));
+    }
+}
@@ -0,0 +1,201 @@
+//! Great-circle distance and bearing between [lon, lat] positions, and the
+//! bounding boxes used to query the r-trees of positions.
+//!
+//! The r-trees index raw [lon, lat] degrees, but a degree of longitude
+//! shrinks toward the poles (it's about 60 nm at the equator and 30 nm at
+//! 60°N), so a fixed radius in degrees is a different distance depending on
+//! where you are. Proximity queries use a bounding box that's widened by the
+//! latitude, and then filter on haversine distance.
+//!
+//! Longitudes are treated modulo 360, so positions on either side of the
+//! antimeridian (e.g. 179.9 and -179.9) are close together.
+
+use rstar::{primitives::GeomWithData, RTree, AABB};
+
+/// The mean radius of the Earth, in meters.
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// The length of a degree of latitude, in meters.
+const METERS_PER_DEGREE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+
+/// Returns how far east `to` is from `from`, in degrees of longitude from -180
+/// to 180.
+pub fn lon_difference(from: f64, to: f64) -> f64 {
+    (to - from + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Wraps a longitude into [-180, 180).
+pub fn normalize_lon(lon: f64) -> f64 {
+    (lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Returns the great-circle distance between two [lon, lat] positions, in
+/// meters.
+pub fn haversine_distance_m(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (lat1, lat2) = (a[1].to_radians(), b[1].to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = lon_difference(a[0], b[0]).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().min(1.0).asin()
+}
+
+/// Returns the great-circle distance between two [lon, lat] positions, in
+/// feet.
+pub fn haversine_distance_ft(a: [f64; 2], b: [f64; 2]) -> f64 {
+    haversine_distance_m(a, b) * 3.28084
+}
+
+/// Returns the initial great-circle bearing from one [lon, lat] position to
+/// another, in degrees from 0 to 360.
+pub fn initial_bearing_deg(from: [f64; 2], to: [f64; 2]) -> f64 {
+    let (lat1, lat2) = (from[1].to_radians(), to[1].to_radians());
+    let dlon = lon_difference(from[0], to[0]).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+/// Returns the turn from heading `from` to heading `to`, in degrees from
+/// -180 to 180; positive is a right turn. 359° to 1° is a 2° right turn.
+pub fn heading_difference(from: f64, to: f64) -> f64 {
+    let d = (to - from).rem_euclid(360.0);
+    if d > 180.0 {
+        d - 360.0
+    } else {
+        d
+    }
+}
+
+/// Returns the total turn through a sequence of headings, in degrees;
+/// positive is to the right. Turns in opposite directions cancel out, so
+/// weaving back and forth adds up to about zero.
+pub fn total_turn_deg(headings: impl IntoIterator<Item = f64>) -> f64 {
+    let mut headings = headings.into_iter();
+    let Some(mut prev) = headings.next() else {
+        return 0.0;
+    };
+    headings
+        .map(|h| heading_difference(std::mem::replace(&mut prev, h), h))
+        .sum()
+}
+
+/// Returns a [lon, lat] box that contains every position within radius_m of
+/// center. Close to the poles the box covers every longitude. Near the
+/// antimeridian the box can extend past ±180; see query_boxes.
+pub fn bounding_box(center: [f64; 2], radius_m: f64) -> AABB<[f64; 2]> {
+    let dlat = radius_m / METERS_PER_DEGREE;
+    let min_lat = (center[1] - dlat).max(-90.0);
+    let max_lat = (center[1] + dlat).min(90.0);
+    // The box is widest at whichever edge is closest to a pole.
+    let widest_lat = min_lat.abs().max(max_lat.abs());
+    if widest_lat >= 90.0 || dlat / widest_lat.to_radians().cos() >= 180.0 {
+        return AABB::from_corners([-180.0, min_lat], [180.0, max_lat]);
+    }
+    let dlon = dlat / widest_lat.to_radians().cos();
+    AABB::from_corners([center[0] - dlon, min_lat], [center[0] + dlon, max_lat])
+}
+
+/// Returns the boxes to search for positions within radius_m of center: the
+/// bounding_box, and if it goes past the antimeridian, the same box shifted
+/// by 360° so it covers the positions on the other side.
+pub fn query_boxes(center: [f64; 2], radius_m: f64) -> Vec<AABB<[f64; 2]>> {
+    let bbox = bounding_box(center, radius_m);
+    let [min_lon, min_lat] = bbox.lower();
+    let [max_lon, max_lat] = bbox.upper();
+    let mut boxes = vec![bbox];
+    if min_lon < -180.0 {
+        boxes.push(AABB::from_corners(
+            [min_lon + 360.0, min_lat],
+            [max_lon + 360.0, max_lat],
+        ));
+    }
+    if max_lon > 180.0 {
+        boxes.push(AABB::from_corners(
+            [min_lon - 360.0, min_lat],
+            [max_lon - 360.0, max_lat],
+        ));
+    }
+    boxes
+}
+
+/// Returns the items in an r-tree of [lon, lat] positions that might be
+/// within radius_m of center. Callers should check the haversine distance.
+pub fn locate_near<T>(
+    tree: &RTree<GeomWithData<[f64; 2], T>>,
+    center: [f64; 2],
+    radius_m: f64,
+) -> impl Iterator<Item = &GeomWithData<[f64; 2], T>> {
+    query_boxes(center, radius_m)
+        .into_iter()
+        .flat_map(move |bbox| tree.locate_in_envelope(&bbox))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstar::Envelope;
+
+    #[test]
+    fn test_haversine_distance() {
+        // A degree of latitude is 60 nm anywhere, but a degree of longitude at
+        // 60°N is only 30.
+        let nm = |a, b| haversine_distance_m(a, b) / 1852.0;
+        assert!((nm([-118.0, 34.0], [-118.0, 35.0]) - 60.0).abs() < 0.1);
+        assert!((nm([10.0, 60.0], [11.0, 60.0]) - 30.0).abs() < 0.1);
+        // Straddling the antimeridian is 0.08° of longitude, not 359.92°.
+        let ft = haversine_distance_ft([179.96, -17.0], [-179.96, -17.0]);
+        assert!((ft - 27_910.0).abs() < 10.0, "{}", ft);
+        assert_eq!(
+            initial_bearing_deg([179.96, -17.0], [-179.96, -17.0]).round(),
+            90.0
+        );
+        assert_eq!(initial_bearing_deg([-118.0, 34.0], [-118.0, 33.0]), 180.0);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        // 1 nm at 60°N is about 1/60° of latitude and 1/30° of longitude.
+        let bbox = bounding_box([10.0, 60.0], 1852.0);
+        let [min_lon, min_lat] = bbox.lower();
+        let [max_lon, max_lat] = bbox.upper();
+        assert!((max_lat - min_lat - 2.0 / 60.0).abs() < 0.001);
+        assert!((max_lon - min_lon - 2.0 / 30.0).abs() < 0.002);
+        // A point 0.9 nm east is inside it.
+        let east = [10.0 + 0.9 / 30.0, 60.0];
+        assert!(bbox.contains_point(&east));
+        assert!(haversine_distance_m([10.0, 60.0], east) < 1852.0);
+        let polar = bounding_box([0.0, 89.99], 1852.0);
+        assert_eq!(polar.lower()[0], -180.0);
+    }
+
+    #[test]
+    fn test_heading_difference() {
+        assert_eq!(heading_difference(350.0, 10.0), 20.0);
+        assert_eq!(heading_difference(10.0, 350.0), -20.0);
+        assert_eq!(heading_difference(359.0, 1.0), 2.0);
+        assert_eq!(total_turn_deg([]), 0.0);
+        assert_eq!(total_turn_deg([350.0, 10.0, 350.0, 5.0]), 15.0);
+        assert_eq!(total_turn_deg([0.0, 90.0, 180.0, 270.0, 0.0, 90.0]), 450.0);
+    }
+
+    #[test]
+    fn test_antimeridian() {
+        assert!((lon_difference(179.95, -179.97) - 0.08).abs() < 1e-9);
+        assert!((lon_difference(-179.97, 179.95) + 0.08).abs() < 1e-9);
+        assert_eq!(normalize_lon(180.5), -179.5);
+        assert_eq!(query_boxes([-118.0, 34.0], 1852.0).len(), 1);
+        // A box east of 179.99 wraps around to the western hemisphere.
+        let boxes = query_boxes([179.99, 52.0], 1852.0);
+        assert_eq!(boxes.len(), 2);
+        assert!(boxes[1].lower()[0] < -180.0 && boxes[1].upper()[0] > -180.0);
+        let tree = RTree::bulk_load(vec![
+            GeomWithData::new([-179.995, 52.0], "east"),
+            GeomWithData::new([179.0, 52.0], "far"),
+        ]);
+        let near: Vec<_> = locate_near(&tree, [179.99, 52.0], 1852.0)
+            .map(|p| p.data)
+            .collect();
+        assert_eq!(near, vec!["east"]);
+    }
+}
@@ -0,0 +1,212 @@
+//! GeoJSON export of interceptions, for looking at them on a map.
+//!
+//! Each interception becomes three features: a LineString for the
+//! interceptor's track, one for the target's track, and a Point where they met.
+//! Track features have a "times" property with the RFC 3339 timestamp of each
+//! position, which tools like kepler.gl can use to animate them.
+//!
+//! Groups of interceptions against the same target have the target's track
+//! once, and a track and a Point for each interceptor, with the group's
+//! event_id in their properties.
+
+use serde_json::{json, Value};
+
+use crate::{
+    interception::{Ac, Interception},
+    tracker::InterceptionGroup,
+};
+
+fn track_feature(ac: &Ac, role: &str) -> Value {
+    let coordinates: Vec<[f64; 2]> = ac.coords.iter().map(|(_, c)| *c).collect();
+    let times: Vec<String> = ac.coords.iter().map(|(t, _)| t.to_rfc3339()).collect();
+    // A LineString needs at least two positions.
+    let geometry = if coordinates.len() == 1 {
+        json!({"type": "Point", "coordinates": coordinates[0]})
+    } else {
+        json!({"type": "LineString", "coordinates": coordinates})
+    };
+    json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": {
+            "role": role,
+            "hex": ac.hex,
+            "times": times,
+        },
+    })
+}
+
+fn interception_point_feature(interception: &Interception) -> Value {
+    json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Point",
+            "coordinates": interception.location(),
+        },
+        "properties": {
+            "role": "interception",
+            "interceptor_hex": interception.interceptor.hex,
+            "interceptor_military": interception.interceptor.military,
+            "interceptor_country": interception.interceptor.country,
+            "interceptor_source": interception.interceptor.source,
+            "target_hex": interception.target.hex,
+            "target_country": interception.target.country,
+            "target_source": interception.target.source,
+            "time": interception.time.to_rfc3339(),
+            "lateral_separation_ft": interception.lateral_separation_ft,
+            "vertical_separation_ft": interception.vertical_separation_ft,
+            "pattern": interception.pattern,
+            "target_mode": interception.target_mode,
+            "interceptor_profile": interception.interceptor_profile,
+            "departure_airfield": interception.departure_airfield,
+            "confidence": interception.confidence,
+            "interceptor_vertical_rate_fpm": interception.interceptor.cur_vertical_rate_fpm,
+            "target_vertical_rate_fpm": interception.target.cur_vertical_rate_fpm,
+            "emergency": interception.emergency,
+        },
+    })
+}
+
+/// Converts interceptions into a GeoJSON FeatureCollection.
+pub fn interceptions_to_geojson(interceptions: &[Interception]) -> String {
+    let features: Vec<Value> = interceptions
+        .iter()
+        .flat_map(|i| {
+            [
+                track_feature(&i.interceptor, "interceptor"),
+                track_feature(&i.target, "target"),
+                interception_point_feature(i),
+            ]
+        })
+        .collect();
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+    .to_string()
+}
+
+/// Converts groups of interceptions into a GeoJSON FeatureCollection, using
+/// each interceptor's closest detection.
+pub fn interception_groups_to_geojson(groups: &[InterceptionGroup]) -> String {
+    let mut features = vec![];
+    for group in groups {
+        let with_event_id = |mut feature: Value| {
+            feature["properties"]["event_id"] = json!(group.event_id);
+            feature["properties"]["num_interceptors"] = json!(group.interceptors.len());
+            feature
+        };
+        // The latest detection has the most of the target's track.
+        if let Some(latest) = group.interceptors.iter().max_by_key(|e| e.closest.time) {
+            features.push(with_event_id(track_feature(
+                &latest.closest.target,
+                "target",
+            )));
+        }
+        for event in &group.interceptors {
+            features.push(with_event_id(track_feature(
+                &event.closest.interceptor,
+                "interceptor",
+            )));
+            features.push(with_event_id(interception_point_feature(&event.closest)));
+        }
+    }
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+    .to_string()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::parse_snapshot_filename_time,
+        interception::Ac,
+        tracker::{group_events, InterceptionTracker},
+    };
+
+    fn ac(hex: &str, coords: &[[f64; 2]]) -> Ac {
+        let start = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        let coords: Vec<_> = coords
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (start + chrono::Duration::seconds(15 * i as i64), *c))
+            .collect();
+        Ac::for_test(hex, &coords, 10000)
+    }
+
+    #[test]
+    fn test_interceptions_to_geojson() {
+        let interceptor = ac("ae1234", &[[-118.0, 34.2], [-118.0, 34.1], [-118.0, 34.0]]);
+        let target = ac("a12345", &[[-118.1, 34.0], [-118.002, 34.0]]);
+        let time = interceptor.cur_coords().0;
+        let interception = Interception::for_test(interceptor, target, time);
+        let geojson: ::geojson::GeoJson =
+            interceptions_to_geojson(&[interception]).parse().unwrap();
+        let collection = match geojson {
+            ::geojson::GeoJson::FeatureCollection(collection) => collection,
+            other => panic!("Expected a FeatureCollection, got {:?}", other),
+        };
+        assert_eq!(collection.features.len(), 3);
+        let track = &collection.features[0];
+        assert_eq!(
+            track.geometry.as_ref().unwrap().value,
+            ::geojson::Value::LineString(vec![
+                vec![-118.0, 34.2],
+                vec![-118.0, 34.1],
+                vec![-118.0, 34.0]
+            ])
+        );
+        assert_eq!(
+            track.property("times").unwrap()[2],
+            json!("2021-02-23T19:39:23+00:00")
+        );
+        let point = &collection.features[2];
+        assert_eq!(
+            point.geometry.as_ref().unwrap().value,
+            ::geojson::Value::Point(vec![-118.001, 34.0])
+        );
+        assert_eq!(point.property("target_hex"), Some(&json!("a12345")));
+        assert_eq!(point.property("vertical_separation_ft"), Some(&json!(0)));
+    }
+
+    #[test]
+    fn test_interception_groups_to_geojson() {
+        let target = ac("a12345", &[[-118.1, 34.0], [-118.002, 34.0]]);
+        let interception = |hex: &str| {
+            let interceptor = ac(hex, &[[-118.0, 34.1], [-118.0, 34.0]]);
+            let time = interceptor.cur_coords().0;
+            Interception::for_test(interceptor, target.clone(), time)
+        };
+        let events = InterceptionTracker::merge_all(
+            &[interception("ae1234"), interception("ae5678")],
+            chrono::Duration::minutes(10),
+        );
+        let groups = group_events(&events, Some(chrono::Duration::zero()));
+        let geojson: Value =
+            serde_json::from_str(&interception_groups_to_geojson(&groups)).unwrap();
+        let features = geojson["features"].as_array().unwrap();
+        // The target's track, then each interceptor's track and point.
+        let roles: Vec<&str> = features
+            .iter()
+            .map(|f| f["properties"]["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            roles,
+            [
+                "target",
+                "interceptor",
+                "interception",
+                "interceptor",
+                "interception"
+            ]
+        );
+        for feature in features {
+            assert_eq!(feature["properties"]["event_id"], json!(groups[0].event_id));
+            assert_eq!(feature["properties"]["num_interceptors"], json!(2));
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Where interceptions and fast movers are, in aggregate: a grid of lat/lon
+//! cells counting the interception events and the hours of fast-mover
+//! flying in each, over everything that was processed.
+//!
+//! Set State::heatmap and process_adsbx_response feeds it. Cells are
+//! cell_deg on a side, counted from -90° latitude and -180° longitude, so a
+//! cell's key is the same wherever it's computed from: longitudes are
+//! wrapped first, so 180° and -180° land in the same cell, and the poles
+//! are in the top and bottom rows. If cell_deg doesn't divide 180, the last
+//! row and column are narrower.
+//!
+//! write_heatmap writes the cells that have anything in them, as CSV
+//! (cell_lat, cell_lon, event_count, fast_mover_hours, with each cell's
+//! south-west corner) or as a GeoJSON FeatureCollection of cell polygons
+//! with the same properties, for choropleth maps.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::Error, geo::normalize_lon, interception::AircraftStateMap, tracker::InterceptionEvent,
+};
+
+/// The cell size used by default, in degrees.
+pub const DEFAULT_CELL_DEG: f64 = 0.5;
+
+/// A fast mover's time since its previous position is counted as flying in
+/// its current cell, up to this many seconds, so coverage gaps don't add
+/// hours.
+pub const MAX_FIX_GAP_SECS: i64 = 120;
+
+/// What happened in one cell.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeatmapCell {
+    /// Interception events whose closest approach was in the cell.
+    pub event_count: usize,
+    pub fast_mover_secs: f64,
+}
+
+impl HeatmapCell {
+    pub fn fast_mover_hours(&self) -> f64 {
+        self.fast_mover_secs / 3600.0
+    }
+}
+
+/// Counts of interception events and fast-mover activity per cell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapGrid {
+    cell_deg: f64,
+    num_rows: i32,
+    num_cols: i32,
+    /// Keyed by (row, column), counted from the south-west.
+    cells: BTreeMap<(i32, i32), HeatmapCell>,
+}
+
+impl HeatmapGrid {
+    /// Creates an empty grid of cells cell_deg on a side, which has to be
+    /// more than 0 and at most 180.
+    pub fn new(cell_deg: f64) -> Result<Self, Error> {
+        if !(cell_deg > 0.0 && cell_deg <= 180.0) {
+            return Err(Error::ExportError(format!(
+                "Heatmap cell size must be more than 0 and at most 180 degrees, got {}",
+                cell_deg
+            )));
+        }
+        Ok(HeatmapGrid {
+            cell_deg,
+            num_rows: (180.0 / cell_deg).ceil() as i32,
+            num_cols: (360.0 / cell_deg).ceil() as i32,
+            cells: BTreeMap::new(),
+        })
+    }
+
+    pub fn cell_deg(&self) -> f64 {
+        self.cell_deg
+    }
+
+    /// Returns the (row, column) of the cell a [lon, lat] position is in.
+    pub fn cell_at(&self, [lon, lat]: [f64; 2]) -> (i32, i32) {
+        let row = ((lat.clamp(-90.0, 90.0) + 90.0) / self.cell_deg).floor() as i32;
+        let col = ((normalize_lon(lon) + 180.0) / self.cell_deg).floor() as i32;
+        (
+            row.clamp(0, self.num_rows - 1),
+            col.clamp(0, self.num_cols - 1),
+        )
+    }
+
+    /// Returns the [lon, lat] of a cell's south-west corner.
+    pub fn cell_origin(&self, (row, col): (i32, i32)) -> [f64; 2] {
+        [
+            -180.0 + col as f64 * self.cell_deg,
+            -90.0 + row as f64 * self.cell_deg,
+        ]
+    }
+
+    /// Returns the cells that have anything in them, with their keys,
+    /// south to north and then west to east.
+    pub fn cells(&self) -> impl Iterator<Item = ((i32, i32), &HeatmapCell)> {
+        self.cells.iter().map(|(key, cell)| (*key, cell))
+    }
+
+    pub fn get(&self, key: (i32, i32)) -> Option<&HeatmapCell> {
+        self.cells.get(&key)
+    }
+
+    /// Counts the fast movers of the snapshot at now, the last one
+    /// classified: each one that has a position at now adds the time since
+    /// its previous position (at most MAX_FIX_GAP_SECS) to its cell.
+    pub fn record_snapshot(&mut self, state: &AircraftStateMap, now: DateTime<Utc>) {
+        for hex in &state.latest_snapshot().fast_movers {
+            let ac = match state.get(*hex) {
+                Some(ac) => ac,
+                None => continue,
+            };
+            let n = ac.coords.len();
+            let (time, position) = ac.coords[n - 1];
+            if time != now || n < 2 {
+                continue;
+            }
+            let secs = (time - ac.coords[n - 2].0).num_milliseconds() as f64 / 1000.0;
+            let key = self.cell_at(position);
+            self.cells.entry(key).or_default().fast_mover_secs += secs.min(MAX_FIX_GAP_SECS as f64);
+        }
+    }
+
+    /// Counts an event in the cell where it was closest.
+    pub fn record_event(&mut self, event: &InterceptionEvent) {
+        let key = self.cell_at(event.closest.location());
+        self.cells.entry(key).or_default().event_count += 1;
+    }
+}
+
+/// The formats a heatmap can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapFormat {
+    Csv,
+    GeoJson,
+}
+
+impl HeatmapFormat {
+    /// GeoJSON if the path ends in .geojson or .json, otherwise CSV.
+    pub fn from_path(path: &str) -> HeatmapFormat {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".geojson") || path.ends_with(".json") {
+            HeatmapFormat::GeoJson
+        } else {
+            HeatmapFormat::Csv
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Row {
+    cell_lat: f64,
+    cell_lon: f64,
+    event_count: usize,
+    fast_mover_hours: f64,
+}
+
+fn rows(grid: &HeatmapGrid) -> impl Iterator<Item = Row> + '_ {
+    grid.cells().map(|(key, cell)| {
+        let [lon, lat] = grid.cell_origin(key);
+        Row {
+            cell_lat: lat,
+            cell_lon: lon,
+            event_count: cell.event_count,
+            fast_mover_hours: cell.fast_mover_hours(),
+        }
+    })
+}
+
+/// Converts a heatmap to CSV, one row per cell.
+pub fn heatmap_to_csv(grid: &HeatmapGrid) -> Result<String, Error> {
+    let mut writer = ::csv::Writer::from_writer(vec![]);
+    for row in rows(grid) {
+        writer
+            .serialize(row)
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    Ok(String::from_utf8(csv).unwrap())
+}
+
+/// Converts a heatmap to a GeoJSON FeatureCollection with a Polygon for
+/// each cell.
+pub fn heatmap_to_geojson(grid: &HeatmapGrid) -> String {
+    let features: Vec<Value> = rows(grid)
+        .map(|row| {
+            let (west, south) = (row.cell_lon, row.cell_lat);
+            let east = (west + grid.cell_deg).min(180.0);
+            let north = (south + grid.cell_deg).min(90.0);
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        [west, south], [east, south], [east, north], [west, north], [west, south],
+                    ]],
+                },
+                "properties": row,
+            })
+        })
+        .collect();
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+    .to_string()
+}
+
+/// Writes a heatmap to a file, in the format its name calls for (see
+/// HeatmapFormat::from_path).
+pub fn write_heatmap(grid: &HeatmapGrid, path: &str) -> Result<(), Error> {
+    let contents = match HeatmapFormat::from_path(path) {
+        HeatmapFormat::Csv => heatmap_to_csv(grid)?,
+        HeatmapFormat::GeoJson => heatmap_to_geojson(grid),
+    };
+    std::fs::write(path, contents).map_err(|e| Error::ExportError(format!("{}: {}", path, e)))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interception::{process_adsbx_response, State},
+        notify::tests::event,
+        testutil::standard_intercept,
+    };
+
+    #[test]
+    fn test_cell_keys() {
+        let grid = HeatmapGrid::new(0.5).unwrap();
+        assert_eq!(grid.cell_at([-118.2, 34.1]), (248, 123));
+        assert_eq!(grid.cell_origin((248, 123)), [-118.5, 34.0]);
+        // Both sides of the antimeridian.
+        assert_eq!(grid.cell_at([180.0, 0.0]), grid.cell_at([-180.0, 0.0]));
+        assert_eq!(grid.cell_at([-180.0, 0.0]), (180, 0));
+        assert_eq!(grid.cell_at([179.9, 0.0]), (180, 719));
+        assert_eq!(grid.cell_at([539.9, 0.0]), (180, 719));
+        // The poles are in the top and bottom rows.
+        assert_eq!(grid.cell_at([10.0, 90.0]), (359, 380));
+        assert_eq!(grid.cell_at([10.0, -90.0]), (0, 380));
+        // An uneven cell size leaves a narrower last column.
+        let grid = HeatmapGrid::new(7.0).unwrap();
+        assert_eq!(grid.cell_at([179.9, 89.9]), (25, 51));
+        assert!(HeatmapGrid::new(0.0).is_err());
+        assert!(HeatmapGrid::new(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let responses = standard_intercept();
+        let mut state = State {
+            heatmap: Some(HeatmapGrid::new(DEFAULT_CELL_DEG).unwrap()),
+            ..Default::default()
+        };
+        let bar = indicatif::ProgressBar::hidden();
+        for response in responses {
+            process_adsbx_response(&mut state, response, &bar).unwrap();
+        }
+        state.finish();
+        assert_eq!(state.events.len(), 1);
+        let grid = state.heatmap.as_ref().unwrap();
+        let cell = grid.cell_at(state.events[0].closest.location());
+        assert_eq!(grid.get(cell).unwrap().event_count, 1);
+        let total_events: usize = grid.cells().map(|(_, c)| c.event_count).sum();
+        assert_eq!(total_events, 1);
+        // The interceptor was a fast mover for most of the ten minutes.
+        let hours: f64 = grid.cells().map(|(_, c)| c.fast_mover_hours()).sum();
+        assert!(hours > 5.0 / 60.0 && hours <= 10.0 / 60.0, "{}", hours);
+    }
+
+    #[test]
+    fn test_write_heatmap() {
+        let mut grid = HeatmapGrid::new(DEFAULT_CELL_DEG).unwrap();
+        grid.record_event(&event());
+        grid.cells
+            .entry(grid.cell_at([179.8, 89.9]))
+            .or_default()
+            .fast_mover_secs = 5400.0;
+        let csv = heatmap_to_csv(&grid).unwrap();
+        assert_eq!(
+            csv,
+            "cell_lat,cell_lon,event_count,fast_mover_hours\n34.0,-118.0,1,0.0\n89.5,179.5,0,1.5\n"
+        );
+        let geojson: Value = serde_json::from_str(&heatmap_to_geojson(&grid)).unwrap();
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(
+            features[1]["geometry"]["coordinates"][0],
+            json!([
+                [179.5, 89.5],
+                [180.0, 89.5],
+                [180.0, 90.0],
+                [179.5, 90.0],
+                [179.5, 89.5]
+            ])
+        );
+        assert_eq!(features[1]["properties"]["fast_mover_hours"], json!(1.5));
+        assert_eq!(
+            HeatmapFormat::from_path("grid.GeoJSON"),
+            HeatmapFormat::GeoJson
+        );
+        assert_eq!(HeatmapFormat::from_path("grid.csv"), HeatmapFormat::Csv);
+    }
+}
@@ -0,0 +1,130 @@
+//! A compact aircraft ID, so that the detector doesn't allocate a String for
+//! every aircraft in every snapshot just to look it up.
+//!
+//! ADS-B Exchange identifies aircraft by their 24-bit ICAO address, as six
+//! hex digits like "ae1234", or by a non-ICAO address (e.g. a TIS-B track)
+//! prefixed with "~", like "~1a2b3c". Both fit in a u32, with a flag bit for
+//! the "~". IDs are serialized as the hex string.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{country::icao_address, error::Error};
+
+const NON_ICAO: u32 = 1 << 24;
+
+/// An aircraft's hex ID. IDs order the same way as their (lowercase) hex
+/// strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HexId(u32);
+
+impl HexId {
+    /// Returns the ICAO address, or None if this is a non-ICAO address.
+    pub fn icao_address(&self) -> Option<u32> {
+        if self.is_icao() {
+            Some(self.0)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_icao(&self) -> bool {
+        self.0 & NON_ICAO == 0
+    }
+
+    /// The packed ID, e.g. for hashing.
+    pub(crate) fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for HexId {
+    type Err = Error;
+
+    /// Parses "ae1234" or "~1a2b3c". Upper case digits are accepted, but
+    /// render as lower case.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let id = match s.strip_prefix('~') {
+            Some(hex) => icao_address(hex).map(|a| a | NON_ICAO),
+            None => icao_address(s),
+        };
+        id.map(HexId)
+            .ok_or_else(|| Error::InvalidHexId(format!("Invalid hex ID: {:?}", s)))
+    }
+}
+
+impl fmt::Display for HexId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_icao() {
+            write!(f, "~")?;
+        }
+        write!(f, "{:06x}", self.0 & !NON_ICAO)
+    }
+}
+
+impl PartialEq<&str> for HexId {
+    fn eq(&self, other: &&str) -> bool {
+        other.parse::<HexId>().is_ok_and(|other| *self == other)
+    }
+}
+
+impl Serialize for HexId {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HexId {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display() {
+        for hex in ["ae1234", "000000", "ffffff", "~1a2b3c"] {
+            let id: HexId = hex.parse().unwrap();
+            assert_eq!(id.to_string(), hex);
+            assert_eq!(id, hex);
+        }
+        assert_eq!("AE1234".parse::<HexId>().unwrap().to_string(), "ae1234");
+        assert_eq!(
+            "ae1234".parse::<HexId>().unwrap().icao_address(),
+            Some(0xae1234)
+        );
+        assert_eq!("~ae1234".parse::<HexId>().unwrap().icao_address(), None);
+        for hex in ["", "ae123", "ae12345", "ae123g", "~", "~~ae1234", "+ae123"] {
+            assert!(
+                matches!(hex.parse::<HexId>(), Err(Error::InvalidHexId(_))),
+                "{:?}",
+                hex
+            );
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let mut hexes = vec!["~000001", "ae1234", "a12345", "0abcde", "~ffffff"];
+        let mut ids: Vec<HexId> = hexes.iter().map(|h| h.parse().unwrap()).collect();
+        hexes.sort();
+        ids.sort();
+        let ids: Vec<String> = ids.iter().map(HexId::to_string).collect();
+        assert_eq!(ids, hexes);
+    }
+
+    #[test]
+    fn test_serde() {
+        let id: HexId = "~1a2b3c".parse().unwrap();
+        assert_eq!(serde_json::to_string(&id).unwrap(), r#""~1a2b3c""#);
+        assert_eq!(serde_json::from_str::<HexId>(r#""~1a2b3c""#).unwrap(), id);
+        assert!(serde_json::from_str::<HexId>(r#""nope""#).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::path::Path;
+
+use chrono::prelude::*;
+use tracing::debug;
+
+use crate::error::Error;
+
+/// File extensions that we consider to be ADS-B Exchange API responses when
+/// expanding directories and glob patterns.
+pub const INPUT_EXTENSIONS: &[&str] = &[".json", ".json.bz2", ".json.gz", ".json.zst"];
+
+/// The layouts of timestamps in snapshot filenames, after any prefix ending
+/// in "_" is removed: ADS-B Exchange's "2023-01-02-153405Z", readsb's
+/// "2023-01-02T15:34:05Z", and the same without colons, which some tools use
+/// for filesystems that don't allow them.
+const FILENAME_TIME_FORMATS: &[&str] =
+    &["%Y-%m-%d-%H%M%SZ", "%Y-%m-%dT%H:%M:%SZ", "%Y-%m-%dT%H%M%SZ"];
+
+/// Parses the timestamp embedded in a snapshot filename. Directory
+/// components and extensions (".json.bz2", ".json.zst", etc.) are ignored.
+/// Understands:
+///
+/// - ADS-B Exchange's "2023-01-02-153405Z.json.bz2".
+/// - readsb's "aircraft_2023-01-02T15:34:05Z.json", with any prefix ending in
+///   "_", with or without the colons, or with a UTC offset instead of "Z".
+/// - Seconds since the epoch, e.g. "1672673645.json". Only 9 or 10 digits
+///   count, so that "1.json" isn't taken to be from 1970.
+///
+/// Returns None for anything else, so callers can fall back to the time in
+/// the response itself.
+pub fn parse_snapshot_filename_time(path: &str) -> Option<DateTime<Utc>> {
+    let name = Path::new(path).file_name()?.to_str()?;
+    let stem = name.split('.').next()?;
+    let stem = stem.rsplit('_').next()?;
+    if (9..=10).contains(&stem.len()) && stem.bytes().all(|b| b.is_ascii_digit()) {
+        return Utc.timestamp_opt(stem.parse().ok()?, 0).single();
+    }
+    FILENAME_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(stem, format).ok())
+        .map(|t| Utc.from_utc_datetime(&t))
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(stem)
+                .ok()
+                .map(|t| t.with_timezone(&Utc))
+        })
+}
+
+/// A half-open range of time, [start, end).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl TimeRange {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        TimeRange { start, end }
+    }
+
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        time >= self.start && time < self.end
+    }
+}
+
+/// Removes paths whose filename timestamp is outside the time range, without
+/// opening them. Paths without a recognizable timestamp are kept, so the
+/// caller should still check each response's timestamp after parsing.
+pub fn filter_paths_by_time(paths: &[String], range: &TimeRange) -> Vec<String> {
+    paths
+        .iter()
+        .filter(|p| match parse_snapshot_filename_time(p) {
+            Some(time) => range.contains(time),
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+pub(crate) fn is_input_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    if name.starts_with('.') {
+        debug!("Skipping hidden file {}", path.display());
+        return false;
+    }
+    if !INPUT_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+        debug!("Skipping {}, which doesn't look like JSON", path.display());
+        return false;
+    }
+    true
+}
+
+fn collect_dir(dir: &Path, paths: &mut Vec<String>) -> Result<(), Error> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::InputPathError(format!("{}: {}", dir.display(), e)))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::InputPathError(format!("{}: {}", dir.display(), e)))?
+            .path();
+        if path.is_dir() {
+            collect_dir(&path, paths)?;
+        } else if is_input_file(&path) {
+            paths.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Expands a list of files, directories, and glob patterns into a list of input
+/// files.
+///
+/// Directories are searched recursively for files with one of the
+/// INPUT_EXTENSIONS. Files named explicitly are always included. The result is
+/// sorted chronologically by the timestamp embedded in each filename (files
+/// without a recognizable timestamp sort last, by name), since the interception
+/// detector needs to see snapshots in time order.
+pub fn expand_input_paths(patterns: &[String]) -> Result<Vec<String>, Error> {
+    let mut paths = vec![];
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        if path.is_dir() {
+            collect_dir(path, &mut paths)?;
+        } else if path.exists() {
+            paths.push(pattern.clone());
+        } else {
+            let matches = glob::glob(pattern)
+                .map_err(|e| Error::InputPathError(format!("{}: {}", pattern, e)))?;
+            let mut num_matches = 0;
+            for m in matches {
+                let m = m.map_err(|e| Error::InputPathError(e.to_string()))?;
+                num_matches += 1;
+                if m.is_dir() {
+                    collect_dir(&m, &mut paths)?;
+                } else if is_input_file(&m) {
+                    paths.push(m.to_string_lossy().into_owned());
+                }
+            }
+            if num_matches == 0 {
+                return Err(Error::InputPathError(format!(
+                    "{}: No such file or directory",
+                    pattern
+                )));
+            }
+        }
+    }
+    paths.sort_by_cached_key(|p| {
+        let time = parse_snapshot_filename_time(p);
+        (time.is_none(), time, p.clone())
+    });
+    paths.dedup();
+    Ok(paths)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_snapshot_filename_time() {
+        assert_eq!(
+            parse_snapshot_filename_time("/data/2023/01/02/2023-01-02-153405Z.json.bz2"),
+            Some(Utc.with_ymd_and_hms(2023, 1, 2, 15, 34, 5).unwrap())
+        );
+        assert_eq!(parse_snapshot_filename_time("aircraft.json"), None);
+        let time = Some(Utc.with_ymd_and_hms(2023, 4, 7, 12, 1, 5).unwrap());
+        for path in [
+            "2023-04-07-120105Z.json",
+            "2023-04-07-120105Z.json.gz",
+            "/archive/2023-04-07-120105Z.json.zst",
+            "aircraft_2023-04-07T12:01:05Z.json",
+            "history/aircraft_2023-04-07T12:01:05Z.json.gz",
+            "aircraft_2023-04-07T120105Z.json",
+            "aircraft_2023-04-07T14:01:05+02:00.json",
+            "1680868865.json",
+            "1680868865.json.bz2",
+        ] {
+            assert_eq!(parse_snapshot_filename_time(path), time, "{}", path);
+        }
+        for path in [
+            "1.json",
+            "2023-04-07.json",
+            "2023-13-07-120105Z.json",
+            "aircraft_latest.json",
+            "/data/2023-04-07-120105Z/aircraft.json",
+            "",
+        ] {
+            assert_eq!(parse_snapshot_filename_time(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_filter_paths_by_time() {
+        let paths: Vec<String> = [
+            "2023-01-02-115955Z.json",
+            "2023-01-02-120000Z.json.bz2",
+            "2023-01-02-135955Z.json",
+            "2023-01-02-140000Z.json",
+            "aircraft.json",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let range = TimeRange::new(
+            Utc.with_ymd_and_hms(2023, 1, 2, 12, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 1, 2, 14, 0, 0).unwrap(),
+        );
+        assert_eq!(
+            filter_paths_by_time(&paths, &range),
+            vec![
+                "2023-01-02-120000Z.json.bz2",
+                "2023-01-02-135955Z.json",
+                "aircraft.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_input_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in [
+            "a/2023-01-02-000000Z.json.bz2",
+            "b/2023-01-01-000005Z.json",
+            "b/c/2023-01-01-000000Z.json.gz",
+            "b/.2023-01-01-000010Z.json",
+            "b/notes.txt",
+            "other.json",
+        ] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let dir_str = dir.to_str().unwrap().to_string();
+        let names = |paths: Vec<String>| -> Vec<String> {
+            paths
+                .iter()
+                .map(|p| p.strip_prefix(&dir_str).unwrap()[1..].to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(expand_input_paths(std::slice::from_ref(&dir_str)).unwrap()),
+            vec![
+                "b/c/2023-01-01-000000Z.json.gz",
+                "b/2023-01-01-000005Z.json",
+                "a/2023-01-02-000000Z.json.bz2",
+                "other.json",
+            ]
+        );
+        assert_eq!(
+            names(expand_input_paths(&[format!("{}/*/*.json", dir_str)]).unwrap()),
+            vec!["b/2023-01-01-000005Z.json"]
+        );
+        assert!(expand_input_paths(&[format!("{}/nope", dir_str)]).is_err());
+    }
+}
@@ -1,40 +1,108 @@
 use std::cmp::max;
 
-use adsbx_json::v2::Aircraft;
+use adsbx_json::v2::{Aircraft, AltitudeOrGround};
 use chrono::{prelude::*, Duration};
-use geo::{point, HaversineDistance};
 use indicatif::ProgressBar;
-use rstar::{primitives::GeomWithData, RTree};
-use std::collections::HashMap;
+use rayon::prelude::*;
+use rstar::primitives::GeomWithData;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+use tracing::{debug, info, info_span, warn};
 
-use crate::{aircraft_is_on_ground, alt_number, error::Error};
+use crate::{
+    aircraft_is_on_ground,
+    airports::{departure_airfield, is_airport_traffic, AirportDb},
+    alt_number,
+    confidence::{Confidence, ConfidenceWeights, SCORED_CRITERIA},
+    country::hex_to_country,
+    drops::{DropReason, DropStats},
+    error::Error,
+    events::{DetectionEvent, DetectionEventKind},
+    explain::{CandidateCheck, CandidateReport, Explainer},
+    filter::{AircraftFilter, SharedFilter},
+    geo::{
+        haversine_distance_m, initial_bearing_deg, lon_difference, normalize_lon, total_turn_deg,
+        EARTH_RADIUS_M,
+    },
+    heatmap::HeatmapGrid,
+    hex_id::HexId,
+    metrics::Metrics,
+    military::is_likely_military,
+    notify::Notifier,
+    pattern::{count_passes, is_orbiting, InterceptPattern},
+    persist,
+    quality::{quality_score, source_name},
+    recording::{PairTracks, Recorder},
+    region::Regions,
+    shards::{shard_index, AircraftShards},
+    spatial::{RTreeIndex, SpatialIndex, SpatialIndexKind},
+    tracker::{
+        group_events, link_escort_chains, EscortChain, InterceptionEvent, InterceptionGroup,
+        InterceptionTracker, DEFAULT_HANDOFF_GAP_MINS, DEFAULT_MERGE_GAP_MINS,
+    },
+};
 
-pub struct InterceptionConfig {
-    pub interceptor_min_spd_kts: u32,
-    pub target_max_spd_kts: u32,
-    pub target_min_spd_kts: u32,
-    pub interceptor_timeout: Duration,
-}
-
-pub struct InterceptionDetector {
-    pub config: InterceptionConfig,
-}
-
-/// The speed threshold to be considered an interceptor.
+/// The default speed threshold to be considered an interceptor.
 pub const INTERCEPTOR_MIN_SPEED_KTS: f64 = 400.0;
 
-/// The maximum speed of a potential target.
+/// The default maximum speed of a potential target.
 pub const TARGET_MAX_SPEED_KTS: f64 = 350.0;
 
-/// The minimum speed of a potential target.
+/// The default minimum speed of a potential target.
 pub const TARGET_MIN_SPEED_KTS: f64 = 80.0;
 
-/// The length of time an interceptor must travel below INTERCEPTOR_SPEED_KTS to
-/// lose interceptor status.
+/// The default speed below which targets count as slow, in slow target mode.
+/// See TargetCriteria::slow_mode.
+pub const SLOW_TARGET_MAX_SPEED_KTS: f64 = 30.0;
+
+/// The default length of time an interceptor must travel below
+/// INTERCEPTOR_MIN_SPEED_KTS to lose interceptor status.
 pub const INTERCEPTOR_TIMEOUT_MINS: i64 = 3;
 
-/// The different classifications of aircraft.
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// The name of the interceptor profile set by DetectionParams'
+/// interceptor_* fields.
+pub const FAST_JET_PROFILE: &str = "fast_jet";
+
+/// How long after an aircraft's latest fix query_radius and query_nearest
+/// still dead-reckon where it is.
+pub const QUERY_HORIZON_SECS: i64 = 60;
+
+// Half the circumference of the Earth, the furthest apart two places can be.
+const EARTH_HALF_CIRCUMFERENCE_M: f64 = std::f64::consts::PI * EARTH_RADIUS_M;
+
+/// The default ICAO type designators that are treated as airliners.
+pub const AIRLINER_TYPES: &[&str] = &[
+    "A306", "A310", "A318", "A319", "A320", "A321", "A19N", "A20N", "A21N", "A332", "A333", "A338",
+    "A339", "A342", "A343", "A345", "A346", "A359", "A35K", "A388", "B712", "B733", "B734", "B735",
+    "B736", "B737", "B738", "B739", "B37M", "B38M", "B39M", "B3XM", "B744", "B748", "B752", "B753",
+    "B762", "B763", "B764", "B772", "B773", "B77L", "B77W", "B788", "B789", "B78X", "BCS1", "BCS3",
+    "CRJ2", "CRJ7", "CRJ9", "CRJX", "E170", "E175", "E190", "E195", "E290", "E295", "MD11", "MD82",
+    "MD83", "MD88", "MD90",
+];
+
+/// The default ADS-B emitter categories that are treated as airliners: large
+/// (A3), high vortex large (A4), and heavy (A5).
+pub const AIRLINER_CATEGORIES: &[&str] = &["A3", "A4", "A5"];
+
+/// How far back Ac::update looks in the altitude history to work out the
+/// vertical rate of aircraft that don't report one.
+const DERIVED_VERTICAL_RATE_WINDOW_SECS: i64 = 60;
+
+/// An altitude this much older than the aircraft's latest position is
+/// treated as unknown. See Ac::known_alt.
+pub const MAX_ALT_AGE_SECS: i64 = 60;
+
+/// How much each new position's update interval counts toward
+/// Ac::update_interval_secs.
+const UPDATE_INTERVAL_SMOOTHING: f64 = 0.25;
+
+/// The different classifications of aircraft. Serialized as "interceptor",
+/// "target", or "other".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Class {
     /// Possible interceptor.
     Interceptor,
@@ -44,304 +112,3170 @@ pub enum Class {
     Other,
 }
 
+// Returns a string field with padding removed, or None if it's missing or
+// blank.
+fn trimmed(field: &Option<String>) -> Option<String> {
+    field
+        .as_ref()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// Returns the time a position was received, given how long before now it was.
+// Ages so large they can't be represented are treated as the distant past,
+// rather than panicking.
+fn time_before(now: DateTime<Utc>, age: std::time::Duration) -> DateTime<Utc> {
+    Duration::from_std(age)
+        .ok()
+        .and_then(|age| now.checked_sub_signed(age))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+// Returns the aircraft's (geometric, barometric) altitudes. Aircraft on the
+// ground are at 0 ft in both, whatever their geometric altitude says.
+fn altitudes(aircraft: &Aircraft) -> (Option<i32>, Option<i32>) {
+    match &aircraft.barometric_altitude {
+        Some(AltitudeOrGround::OnGround) => (Some(0), Some(0)),
+        baro => (aircraft.geometric_altitude, baro.clone().map(alt_number)),
+    }
+}
+
+// Returns the aircraft's reported vertical rate in feet per minute, preferring
+// geometric to barometric.
+fn reported_vertical_rate(aircraft: &Aircraft) -> Option<f64> {
+    aircraft
+        .geometric_vertical_rate
+        .map(f64::from)
+        .or_else(|| aircraft.barometric_vertical_rate.map(f64::from))
+}
+
+/// An aircraft's fast status under one interceptor profile. See
+/// FastStatus::update.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FastStatus {
+    /// The last time the aircraft was seen moving faster than the profile's
+    /// minimum speed.
+    pub time_seen_fast: Option<DateTime<Utc>>,
+    /// The number of fast updates since fast_run_start, or since the
+    /// aircraft got fast status.
+    pub fast_count: u32,
+    /// Whether the aircraft has fast status, i.e. it's had enough fast
+    /// updates and hasn't been slow for long enough to lose it.
+    #[serde(default)]
+    pub fast_status: bool,
+    /// When the current run of fast updates, before getting fast status,
+    /// started.
+    #[serde(default)]
+    pub fast_run_start: Option<DateTime<Utc>>,
+    /// When the aircraft last lost fast status.
+    #[serde(default)]
+    pub fast_status_ended: Option<DateTime<Utc>>,
+}
+
+impl FastStatus {
+    /// Updates fast status after an update that was fast (faster than the
+    /// profile's minimum speed) or not.
+    ///
+    /// - Without fast status, each fast update is counted, and a slow one
+    ///   starts the count over. So does a fast update more than
+    ///   entry_window_mins after the first one in the run, since that means
+    ///   the aircraft wasn't seen in between. Reaching min_fast_count fast
+    ///   updates gives the aircraft fast status.
+    /// - With fast status, slow updates don't change anything until it's
+    ///   been timeout_mins since the last fast one. Then the aircraft loses
+    ///   fast status, and its count starts over from zero.
+    ///
+    /// Aircraft that stop being seen lose fast status at the same time they
+    /// would have if they'd been slow; see Ac::is_fast_mover. That holds
+    /// for an update after the gap too: a fast one doesn't keep the old fast
+    /// status, but starts a new run.
+    fn update(&mut self, now: DateTime<Utc>, fast: bool, rule: FastRule) {
+        let timeout = Duration::minutes(rule.timeout_mins);
+        if self.fast_status {
+            let last_fast = self.time_seen_fast.unwrap_or(now);
+            if now - last_fast >= timeout {
+                self.fast_status = false;
+                self.fast_status_ended = Some(last_fast + timeout);
+                self.fast_count = 0;
+            } else if fast {
+                self.fast_count += 1;
+            }
+        }
+        if !self.fast_status {
+            if !fast {
+                self.fast_count = 0;
+                self.fast_run_start = None;
+            } else {
+                let window = Duration::minutes(rule.entry_window_mins);
+                match self.fast_run_start {
+                    Some(start) if now - start <= window => self.fast_count += 1,
+                    _ => {
+                        self.fast_run_start = Some(now);
+                        self.fast_count = 1;
+                    }
+                }
+                if self.fast_count >= rule.min_fast_count {
+                    self.fast_status = true;
+                    self.fast_run_start = None;
+                }
+            }
+        }
+        if fast {
+            self.time_seen_fast = Some(now);
+        }
+    }
+
+    // When the current fast status ends (or ended, if the aircraft hasn't
+    // been updated since), or when it last lost fast status.
+    fn end(&self, rule: FastRule) -> Option<DateTime<Utc>> {
+        match (self.fast_status, self.time_seen_fast) {
+            (true, Some(last_fast)) => Some(last_fast + Duration::minutes(rule.timeout_mins)),
+            _ => self.fast_status_ended,
+        }
+    }
+
+    fn is_active(&self, now: DateTime<Utc>, rule: FastRule) -> bool {
+        self.fast_status && self.end(rule).is_some_and(|end| now < end)
+    }
+}
+
 /// State we keep track of for each aircraft.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Ac {
-    pub hex: String,
-    pub coords: Vec<(DateTime<Utc>, [f64; 2])>,
+    pub hex: HexId,
+    /// The callsign (the "flight" field), with padding removed.
+    #[serde(default)]
+    pub callsign: Option<String>,
+    /// The registration (the "r" field), e.g. "N1234".
+    #[serde(default)]
+    pub registration: Option<String>,
+    /// The ICAO aircraft type designator (the "t" field), e.g. "F16".
+    #[serde(default)]
+    pub aircraft_type: Option<String>,
+    /// The ADS-B emitter category (the "category" field), e.g. "A3".
+    #[serde(default)]
+    pub emitter_category: Option<String>,
+    /// The latest squawk code, e.g. "1200".
+    #[serde(default)]
+    pub squawk: Option<String>,
+    /// Whether the aircraft is likely to be military. See is_likely_military.
+    #[serde(default)]
+    pub military: bool,
+    /// The country of registration, from the hex ID. See hex_to_country.
+    #[serde(default)]
+    pub country: Option<String>,
+    /// The owner or operator. This isn't in the ADS-B data, so it's only
+    /// set by an aircraft database (see the aircraft_db feature).
+    #[serde(default)]
+    pub operator: Option<String>,
+    /// Recent positions, oldest first, as (time, [lon, lat]). How many are
+    /// kept is set by history_max_points and history_max_secs in
+    /// DetectionParams.
+    pub coords: VecDeque<(DateTime<Utc>, [f64; 2])>,
+    /// The altitude in feet (cur_alt) at each of coords, so its history is
+    /// kept for as long as the positions are.
+    #[serde(default)]
+    pub alts: VecDeque<i32>,
+    /// The reported track (true heading over the ground, in degrees) at each
+    /// of coords, if there was one.
+    #[serde(default)]
+    pub tracks: VecDeque<Option<f64>>,
+    /// The ground speed in knots at each of coords.
+    #[serde(default)]
+    pub speeds: VecDeque<f64>,
+    /// The latest geometric and barometric altitudes, in feet, if the
+    /// aircraft reported them.
+    #[serde(default)]
+    pub geom_alt: Option<i32>,
+    #[serde(default)]
+    pub baro_alt: Option<i32>,
+    /// The vertical rate in feet per minute: geom_rate or baro_rate if the
+    /// aircraft reported one, otherwise derived from the altitude history.
+    #[serde(default)]
+    pub cur_vertical_rate_fpm: Option<f64>,
     pub max_speed: f64,
     pub cur_speed: f64,
+    /// The latest known altitude: geometric if there is one, otherwise
+    /// barometric. If an update doesn't have either, this is left as it was,
+    /// so check alt_time (or use known_alt) to see how old it is.
     pub cur_alt: i32,
+    /// When cur_alt was last reported. None in state saved before this was
+    /// recorded.
+    #[serde(default)]
+    pub alt_time: Option<DateTime<Utc>>,
+    /// Whether the aircraft is on the ground, as of the last update that had
+    /// an altitude. See aircraft_is_on_ground.
     pub is_on_ground: bool,
-    /// The last time the aircraft was seen moving faster than
-    /// INTERCEPTOR_MIN_SPEED_KTS.
-    pub time_seen_fast: Option<DateTime<Utc>>,
-    /// The number of updates where the aircraft was moving faster than
-    /// INTERCEPTOR_SPEED_KTS.
-    pub fast_count: u32,
+    /// Fast status under the fast-jet profile, the one set by
+    /// DetectionParams' interceptor_* fields.
+    #[serde(flatten)]
+    pub fast: FastStatus,
+    /// Fast status under each of DetectionParams' interceptor_profiles the
+    /// aircraft has been fast under, by profile name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile_status: BTreeMap<String, FastStatus>,
+    /// The number of positions rejected as implausible. See
+    /// Ac::is_plausible_fix.
+    #[serde(default)]
+    pub rejected_fixes: usize,
+    /// The latest position, if it was rejected.
+    #[serde(default)]
+    pub rejected_fix: Option<(DateTime<Utc>, [f64; 2])>,
+    /// The number of positions accepted.
+    #[serde(default)]
+    pub num_fixes: usize,
+    /// The first position the aircraft was seen at, which unlike coords is
+    /// never trimmed. None in state saved before this was recorded.
+    #[serde(default)]
+    pub first_fix: Option<(DateTime<Utc>, [f64; 2])>,
+    /// Where the latest position came from (the feed's "type" field), e.g.
+    /// "adsb_icao", "mlat", or "tisb_other".
+    #[serde(default)]
+    pub source: Option<String>,
+    /// The latest navigation integrity category and navigation accuracy
+    /// category for position, if the aircraft reported them.
+    #[serde(default)]
+    pub nic: Option<u8>,
+    #[serde(default)]
+    pub nac_p: Option<u8>,
+    /// A moving average of the time between new positions, in seconds.
+    #[serde(default)]
+    pub update_interval_secs: Option<f64>,
     /// When was the aircraft last seen.
     pub seen: DateTime<Utc>,
 }
 
 impl Ac {
-    pub fn new(now: DateTime<Utc>, aircraft: &Aircraft) -> Result<Self, Error> {
+    pub fn new(
+        now: DateTime<Utc>,
+        aircraft: &Aircraft,
+        params: &DetectionParams,
+    ) -> Result<Self, Error> {
+        let hex = aircraft.hex.parse()?;
         let (lon, lat) = match (aircraft.lon, aircraft.lat) {
             (Some(lon), Some(lat)) => (lon, lat),
             _ => {
-                return Err(Error::AircraftMissingData(format!(
-                    "Aircraft {} is missing position data",
-                    aircraft.hex
-                )))
+                return Err(Error::MissingField {
+                    hex: aircraft.hex.clone(),
+                    field: "position",
+                })
             }
         };
         let spd = match aircraft.ground_speed_knots {
             Some(spd) => spd,
             _ => {
-                return Err(Error::AircraftMissingData(format!(
-                    "Aircraft {} is missing ground speed data",
-                    aircraft.hex
-                )))
+                return Err(Error::MissingField {
+                    hex: aircraft.hex.clone(),
+                    field: "ground speed",
+                })
             }
         };
-        let alt = match aircraft.geometric_altitude {
+        let (geom_alt, baro_alt) = altitudes(aircraft);
+        let alt = match geom_alt.or(baro_alt) {
             Some(alt) => alt,
             _ => {
-                return Err(Error::AircraftMissingData(format!(
-                    "Aircraft {} is missing geometric altitude",
-                    aircraft.hex
-                )))
+                return Err(Error::MissingField {
+                    hex: aircraft.hex.clone(),
+                    field: "altitude",
+                })
             }
         };
         let seen_pos = match aircraft.seen_pos {
             Some(seen_pos) => seen_pos,
             _ => {
-                return Err(Error::AircraftMissingData(format!(
-                    "Aircraft {} is missing seen_pos",
-                    aircraft.hex
-                )))
+                return Err(Error::MissingField {
+                    hex: aircraft.hex.clone(),
+                    field: "seen_pos",
+                })
             }
         };
-        let is_fast = spd > INTERCEPTOR_MIN_SPEED_KTS;
-        Ok(Ac {
-            hex: aircraft.hex.clone(),
-            coords: vec![(now, [lon, lat])],
+        let mut ac = Ac {
+            hex,
+            callsign: trimmed(&aircraft.call_sign),
+            registration: trimmed(&aircraft.registration),
+            aircraft_type: trimmed(&aircraft.aircraft_type),
+            emitter_category: trimmed(&aircraft.emitter_category),
+            squawk: trimmed(&aircraft.squawk),
+            military: is_likely_military(aircraft),
+            country: hex_to_country(&aircraft.hex).map(String::from),
+            operator: None,
+            coords: VecDeque::from([(now, [lon, lat])]),
+            alts: VecDeque::from([alt]),
+            tracks: VecDeque::from([aircraft.track]),
+            speeds: VecDeque::from([spd]),
+            geom_alt,
+            baro_alt,
+            cur_vertical_rate_fpm: reported_vertical_rate(aircraft),
             max_speed: spd,
             cur_speed: spd,
             cur_alt: alt,
-            is_on_ground: aircraft_is_on_ground(aircraft),
-            time_seen_fast: if is_fast {
-                Some(now - Duration::from_std(seen_pos).unwrap())
-            } else {
-                None
-            },
-            fast_count: if is_fast { 1 } else { 0 },
-            seen: now - Duration::from_std(aircraft.seen_pos.unwrap()).unwrap(),
-        })
+            alt_time: Some(now),
+            is_on_ground: aircraft_is_on_ground(aircraft, params.on_ground_max_geom_alt_ft)
+                .unwrap_or(false),
+            fast: FastStatus::default(),
+            profile_status: BTreeMap::new(),
+            rejected_fixes: 0,
+            rejected_fix: None,
+            num_fixes: 1,
+            first_fix: Some((now, [lon, lat])),
+            source: Some(source_name(&aircraft.message_type).to_string()),
+            nic: aircraft.nic,
+            nac_p: aircraft.nac_p,
+            update_interval_secs: None,
+            seen: time_before(now, seen_pos),
+        };
+        ac.update_fast_status(now, params, |_, min_speed_kts| spd > min_speed_kts);
+        Ok(ac)
+    }
+
+    /// Updates fast status under every interceptor profile, given whether
+    /// the update was fast for a profile's minimum speed. Profiles the
+    /// aircraft isn't allowed under see only slow updates. See
+    /// FastStatus::update.
+    fn update_fast_status(
+        &mut self,
+        now: DateTime<Utc>,
+        params: &DetectionParams,
+        is_fast: impl Fn(&Ac, f64) -> bool,
+    ) {
+        let fast = is_fast(self, params.interceptor_min_speed_kts);
+        self.fast.update(now, fast, params.fast_jet_rule());
+        for profile in &params.interceptor_profiles {
+            let fast = profile.allows(self) && is_fast(self, profile.min_speed_kts);
+            match self.profile_status.get_mut(&profile.name) {
+                Some(status) => status.update(now, fast, profile.rule()),
+                // Most aircraft are never fast under most profiles, so they
+                // don't get a status until they are.
+                None if fast => {
+                    let mut status = FastStatus::default();
+                    status.update(now, fast, profile.rule());
+                    self.profile_status.insert(profile.name.clone(), status);
+                }
+                None => {}
+            }
+        }
     }
 
     // Updates aircraft state based on latest API response for that aircraft.
-    pub fn update(&mut self, now: DateTime<Utc>, aircraft: &Aircraft) {
+    // Whatever the response is missing is left as it was; in particular, if
+    // it doesn't have a position, or the position is implausible, no
+    // position is added to the history. Responses older than the latest
+    // position are ignored, since the history has to stay in time order.
+    pub fn update(&mut self, now: DateTime<Utc>, aircraft: &Aircraft, params: &DetectionParams) {
+        if now < self.cur_coords().0 {
+            return;
+        }
         if let Some(spd) = aircraft.ground_speed_knots {
             self.cur_speed = spd;
             self.max_speed = self.max_speed.max(spd);
-            if self.cur_speed > INTERCEPTOR_MIN_SPEED_KTS {
-                self.time_seen_fast = Some(now);
-                self.fast_count += 1;
+        }
+        let (geom_alt, baro_alt) = altitudes(aircraft);
+        if let Some(alt) = geom_alt.or(baro_alt) {
+            (self.geom_alt, self.baro_alt) = (geom_alt, baro_alt);
+            self.cur_alt = alt;
+            self.alt_time = Some(now);
+        }
+        if let Some(is_on_ground) =
+            aircraft_is_on_ground(aircraft, params.on_ground_max_geom_alt_ft)
+        {
+            self.is_on_ground = is_on_ground;
+        }
+        // These can show up late, or change, but a missing value in one
+        // snapshot doesn't mean it's gone.
+        if let Some(callsign) = trimmed(&aircraft.call_sign) {
+            self.callsign = Some(callsign);
+        }
+        if let Some(registration) = trimmed(&aircraft.registration) {
+            self.registration = Some(registration);
+        }
+        if let Some(aircraft_type) = trimmed(&aircraft.aircraft_type) {
+            self.aircraft_type = Some(aircraft_type);
+        }
+        if let Some(emitter_category) = trimmed(&aircraft.emitter_category) {
+            self.emitter_category = Some(emitter_category);
+        }
+        if let Some(squawk) = trimmed(&aircraft.squawk) {
+            self.squawk = Some(squawk);
+        }
+        self.military |= aircraft.database_flags.is_military();
+        if aircraft.nic.is_some() {
+            self.nic = aircraft.nic;
+        }
+        if aircraft.nac_p.is_some() {
+            self.nac_p = aircraft.nac_p;
+        }
+        if let (Some(lon), Some(lat)) = (aircraft.lon, aircraft.lat) {
+            if self.is_plausible_fix((now, [lon, lat]), params) {
+                self.rejected_fix = None;
+                self.num_fixes += 1;
+                self.source = Some(source_name(&aircraft.message_type).to_string());
+                if let Some(seen_pos) = aircraft.seen_pos {
+                    let seen = time_before(now, seen_pos);
+                    if seen > self.seen {
+                        let interval = (seen - self.seen).num_milliseconds() as f64 / 1000.0;
+                        self.update_interval_secs = Some(match self.update_interval_secs {
+                            Some(avg) => avg + UPDATE_INTERVAL_SMOOTHING * (interval - avg),
+                            None => interval,
+                        });
+                    }
+                    self.seen = seen;
+                }
+                self.coords.push_back((now, [lon, lat]));
+                self.alts.push_back(self.cur_alt);
+                self.tracks.push_back(aircraft.track);
+                self.speeds.push_back(self.cur_speed);
+                self.trim_history(params);
+            } else {
+                self.rejected_fixes += 1;
+                self.rejected_fix = Some((now, [lon, lat]));
             }
         }
-        self.cur_alt = aircraft.geometric_altitude.unwrap_or_else(|| {
-            aircraft
-                .barometric_altitude
-                .clone()
-                .map(alt_number)
-                .unwrap_or(0)
+        self.cur_vertical_rate_fpm = reported_vertical_rate(aircraft).or_else(|| {
+            self.vertical_rate_fpm(Duration::seconds(DERIVED_VERTICAL_RATE_WINDOW_SECS))
         });
-        self.is_on_ground = aircraft_is_on_ground(aircraft);
-        self.seen = now - Duration::from_std(aircraft.seen_pos.unwrap()).unwrap();
-        self.coords
-            .push((now, [aircraft.lon.unwrap(), aircraft.lat.unwrap()]));
-        // Keep the last 40 positions (about 10 minutes worth).
-        if self.coords.len() > 40 {
-            self.coords.remove(0);
+        // After the position, so the cross-check can use it.
+        if let Some(spd) = aircraft.ground_speed_knots {
+            self.update_fast_status(now, params, |ac, min_speed_kts| {
+                spd > min_speed_kts && ac.is_speed_consistent(now, min_speed_kts, params)
+            });
+        }
+    }
+
+    // Whether the average speed over the last speed_cross_check_secs backs up
+    // the reported ground speed: it has to be at least speed_cross_check_ratio
+    // times a profile's min_speed_kts. Reports are trusted if the check is
+    // off or the history doesn't go back that far.
+    fn is_speed_consistent(
+        &self,
+        now: DateTime<Utc>,
+        min_speed_kts: f64,
+        params: &DetectionParams,
+    ) -> bool {
+        if params.speed_cross_check_secs <= 0 {
+            return true;
+        }
+        self.average_speed_kts(Duration::seconds(params.speed_cross_check_secs), now)
+            .is_none_or(|avg| avg >= params.speed_cross_check_ratio * min_speed_kts)
+    }
+
+    /// Checks whether a new position is plausible: whether getting there from
+    /// the previous one means flying no faster than max_implied_speed_kts, or
+    /// max_implied_speed_ratio times the reported ground speed. Moves shorter
+    /// than outlier_min_distance_nm are always plausible, to allow for noisy
+    /// positions. If the previous position was rejected, a new one that's
+    /// plausible from that one is accepted too, since two positions that
+    /// agree mean the aircraft really is there (and it was the one before
+    /// that that was wrong).
+    fn is_plausible_fix(&self, fix: (DateTime<Utc>, [f64; 2]), params: &DetectionParams) -> bool {
+        let max_speed_kts = match params.max_implied_speed_ratio {
+            Some(ratio) => params.max_implied_speed_kts.min(ratio * self.cur_speed),
+            None => params.max_implied_speed_kts,
+        };
+        let is_consistent = |(time, coords): (DateTime<Utc>, [f64; 2])| {
+            let dist_nm = haversine_distance_m(coords, fix.1) / 1852.0;
+            let hours = (fix.0 - time).num_milliseconds() as f64 / 3_600_000.0;
+            dist_nm <= params.outlier_min_distance_nm
+                || (hours > 0.0 && dist_nm / hours <= max_speed_kts)
+        };
+        is_consistent(*self.cur_coords()) || self.rejected_fix.is_some_and(is_consistent)
+    }
+
+    /// Returns cur_alt, or None if the aircraft's latest position is more
+    /// than MAX_ALT_AGE_SECS newer than it, i.e. it's stopped reporting its
+    /// altitude.
+    pub fn known_alt(&self) -> Option<i32> {
+        match self.alt_time {
+            Some(t) if self.cur_coords().0 - t > Duration::seconds(MAX_ALT_AGE_SECS) => None,
+            _ => Some(self.cur_alt),
+        }
+    }
+
+    /// Returns the vertical separation between two aircraft, in feet, or None
+    /// if either altitude is unknown (see known_alt). Uses geometric altitude
+    /// if both aircraft have it and barometric if both have that, since
+    /// mixing the two can be off by hundreds of feet.
+    pub fn vertical_separation_ft(&self, other: &Ac) -> Option<i32> {
+        let (alt, other_alt) = (self.known_alt()?, other.known_alt()?);
+        Some(
+            match (self.geom_alt, other.geom_alt, self.baro_alt, other.baro_alt) {
+                (Some(a), Some(b), _, _) | (_, _, Some(a), Some(b)) => (a - b).abs(),
+                _ => (alt - other_alt).abs(),
+            },
+        )
+    }
+
+    // Drops the oldest positions (and the altitudes, tracks and speeds that go
+    // with them) until the history is within the configured limits.
+    fn trim_history(&mut self, params: &DetectionParams) {
+        let latest = self.cur_coords().0;
+        while self.coords.len() > 1 {
+            let too_many = params
+                .history_max_points
+                .is_some_and(|max| self.coords.len() > max);
+            let too_old = params
+                .history_max_secs
+                .is_some_and(|max| latest - self.coords[0].0 > Duration::seconds(max));
+            if !too_many && !too_old {
+                break;
+            }
+            self.coords.pop_front();
+            self.alts.pop_front();
+            self.tracks.pop_front();
+            self.speeds.pop_front();
+        }
+    }
+
+    // Returns the index of the oldest position that's within window of the
+    // latest one.
+    fn window_start(&self, window: Duration) -> usize {
+        let latest = self.cur_coords().0;
+        self.coords.partition_point(|(t, _)| latest - *t > window)
+    }
+
+    /// Returns the average vertical rate over the last `window`, in feet per
+    /// minute (negative when descending), or None if there aren't at least two
+    /// altitudes in that time.
+    pub fn vertical_rate_fpm(&self, window: Duration) -> Option<f64> {
+        let start = self.window_start(window);
+        let elapsed = self.cur_coords().0 - self.coords.get(start)?.0;
+        if elapsed <= Duration::zero() || self.alts.len() != self.coords.len() {
+            return None;
+        }
+        let climb = self.alts[self.alts.len() - 1] - self.alts[start];
+        Some(climb as f64 / (elapsed.num_milliseconds() as f64 / 60_000.0))
+    }
+
+    /// Returns how far the aircraft flew in the `window` before now, in
+    /// nautical miles, along its track: the great-circle distances between
+    /// successive positions, interpolated at the start of the window. Rejected
+    /// positions (see is_plausible_fix) aren't in the track, so they're
+    /// skipped over. Returns None if the track doesn't cover the whole
+    /// window.
+    pub fn distance_traveled_nm(&self, window: Duration, now: DateTime<Utc>) -> Option<f64> {
+        let start = now - window;
+        let first = self.position_at(start)?;
+        let last = self.position_at(now)?;
+        let i = self.coords.partition_point(|(t, _)| *t <= start);
+        let j = self.coords.partition_point(|(t, _)| *t < now);
+        let points = std::iter::once(first)
+            .chain(self.coords.range(i..j).map(|(_, p)| *p))
+            .chain(std::iter::once(last));
+        let dist_m: f64 = points
+            .clone()
+            .zip(points.skip(1))
+            .map(|(a, b)| haversine_distance_m(a, b))
+            .sum();
+        Some(dist_m / 1852.0)
+    }
+
+    /// Returns the aircraft's average ground speed in knots over the
+    /// `window` before now, from distance_traveled_nm. Unlike cur_speed, it
+    /// doesn't depend on the reported ground speed, which is occasionally
+    /// garbage.
+    pub fn average_speed_kts(&self, window: Duration, now: DateTime<Utc>) -> Option<f64> {
+        if window <= Duration::zero() {
+            return None;
         }
+        let dist_nm = self.distance_traveled_nm(window, now)?;
+        Some(dist_nm / (window.num_milliseconds() as f64 / 3_600_000.0))
     }
 
     /// Returns the aircraft's most recent coordinates.
     pub fn cur_coords(&self) -> &(DateTime<Utc>, [f64; 2]) {
-        self.coords.last().unwrap()
+        self.coords.back().unwrap()
+    }
+
+    // Returns the indexes of the fixes either side of time t, and how far t is
+    // from the first to the second (0 to 1), or None if t is outside the
+    // track.
+    fn bracketing_fixes(&self, t: DateTime<Utc>) -> Option<(usize, usize, f64)> {
+        let i = self.coords.partition_point(|(ft, _)| *ft < t);
+        let t1 = self.coords.get(i)?.0;
+        if t1 == t {
+            return Some((i, i, 0.0));
+        }
+        let t0 = self.coords.get(i.checked_sub(1)?)?.0;
+        let f = (t - t0).num_milliseconds() as f64 / (t1 - t0).num_milliseconds() as f64;
+        Some((i - 1, i, f))
+    }
+
+    /// Returns the aircraft's position at time t, interpolated linearly
+    /// between the fixes before and after it, or None if t is outside its
+    /// track.
+    pub fn position_at(&self, t: DateTime<Utc>) -> Option<[f64; 2]> {
+        let (i, j, f) = self.bracketing_fixes(t)?;
+        let ([lon0, lat0], [lon1, lat1]) = (self.coords[i].1, self.coords[j].1);
+        Some([
+            normalize_lon(lon0 + lon_difference(lon0, lon1) * f),
+            lat0 + (lat1 - lat0) * f,
+        ])
+    }
+
+    /// Like position_at, but after the latest fix it dead-reckons forward
+    /// from it, at the latest speed and heading, for up to horizon. Returns
+    /// None beyond that.
+    pub fn estimated_position_at(&self, t: DateTime<Utc>, horizon: Duration) -> Option<[f64; 2]> {
+        let (latest, [lon, lat]) = *self.cur_coords();
+        if t <= latest {
+            return self.position_at(t);
+        }
+        if t - latest > horizon {
+            return None;
+        }
+        let heading = self.heading_at(self.coords.len() - 1)?.to_radians();
+        let speed = self.speeds.back().copied().unwrap_or(self.cur_speed);
+        let dist_nm = speed * (t - latest).num_milliseconds() as f64 / 3_600_000.0;
+        let dlat = dist_nm * heading.cos() / 60.0;
+        let dlon = dist_nm * heading.sin() / 60.0 / lat.to_radians().cos().max(0.01);
+        Some([normalize_lon(lon + dlon), (lat + dlat).clamp(-90.0, 90.0)])
+    }
+
+    /// Returns the aircraft's altitude at time t, interpolated like
+    /// position_at.
+    pub fn alt_at(&self, t: DateTime<Utc>) -> Option<f64> {
+        let (i, j, f) = self.bracketing_fixes(t)?;
+        let alt = |k: usize| self.alts.get(k).copied().unwrap_or(self.cur_alt) as f64;
+        Some(alt(i) + (alt(j) - alt(i)) * f)
+    }
+
+    /// Returns the aircraft's heading at coords[i]: the reported track if
+    /// there is one, otherwise the bearing between successive positions.
+    pub fn heading_at(&self, i: usize) -> Option<f64> {
+        if let Some(Some(track)) = self.tracks.get(i) {
+            return Some(*track);
+        }
+        let (from, to) = if i > 0 {
+            (self.coords[i - 1].1, self.coords[i].1)
+        } else {
+            (self.coords[0].1, self.coords.get(1)?.1)
+        };
+        if from == to {
+            return None;
+        }
+        Some(initial_bearing_deg(from, to))
+    }
+
+    // Returns the times and headings (see heading_at) of the positions within
+    // window of the latest one, skipping any without a heading.
+    fn headings_within(&self, window: Duration) -> Vec<(DateTime<Utc>, f64)> {
+        (self.window_start(window)..self.coords.len())
+            .filter_map(|i| Some((self.coords[i].0, self.heading_at(i)?)))
+            .collect()
+    }
+
+    /// Returns how far the aircraft has turned over the last `window`, in
+    /// degrees: the sum of the turns between successive headings, positive to
+    /// the right, so a full circle is 360 and weaving back and forth is about
+    /// zero. Turns across north are handled, so 359° to 1° is 2°. Returns None
+    /// if there aren't at least two headings in that time.
+    pub fn total_heading_change_deg(&self, window: Duration) -> Option<f64> {
+        let headings = self.headings_within(window);
+        if headings.len() < 2 {
+            return None;
+        }
+        Some(total_turn_deg(headings.into_iter().map(|(_, h)| h)))
+    }
+
+    /// Returns the aircraft's average rate of turn over the last `window`, in
+    /// degrees per second, positive to the right. See
+    /// total_heading_change_deg.
+    pub fn turn_rate_deg_per_sec(&self, window: Duration) -> Option<f64> {
+        let headings = self.headings_within(window);
+        let elapsed = headings.last()?.0 - headings.first()?.0;
+        if elapsed <= Duration::zero() {
+            return None;
+        }
+        let turn = total_turn_deg(headings.into_iter().map(|(_, h)| h));
+        Some(turn / (elapsed.num_milliseconds() as f64 / 1000.0))
     }
 
     /// Returns the aircraft's oldest coordinates (usually from about 10 minutes
     /// ago).
     pub fn oldest_coords(&self) -> &(DateTime<Utc>, [f64; 2]) {
-        self.coords.first().unwrap()
+        self.coords.front().unwrap()
     }
 
-    pub fn class(&self, now: DateTime<Utc>) -> Class {
-        if let Some(time_seen_fast) = self.time_seen_fast {
-            let elapsed = now.signed_duration_since(time_seen_fast);
-            if elapsed.num_minutes() < INTERCEPTOR_TIMEOUT_MINS
-                && self.fast_count > 10
-                && !self.is_on_ground
-            {
-                return Class::Interceptor;
-            }
+    /// Returns how much to trust the aircraft's positions, from 0 to 1. See
+    /// quality::quality_score.
+    pub fn quality_score(&self) -> f64 {
+        quality_score(self)
+    }
+
+    /// Returns the emergency the aircraft is squawking, if any.
+    pub fn emergency(&self) -> Option<EmergencySquawk> {
+        self.squawk
+            .as_deref()
+            .and_then(EmergencySquawk::from_squawk)
+    }
+
+    /// Roughly how many bytes of memory the aircraft's state takes up,
+    /// including its history. See AircraftStateMap::estimate_memory_bytes.
+    pub fn estimate_memory_bytes(&self) -> usize {
+        let strings: usize = [
+            &self.callsign,
+            &self.registration,
+            &self.aircraft_type,
+            &self.emitter_category,
+            &self.squawk,
+            &self.country,
+            &self.operator,
+            &self.source,
+        ]
+        .into_iter()
+        .filter_map(|s| s.as_ref().map(String::capacity))
+        .sum();
+        size_of::<Ac>()
+            + strings
+            + self.coords.capacity() * size_of::<(DateTime<Utc>, [f64; 2])>()
+            + self.alts.capacity() * size_of::<i32>()
+            + self.tracks.capacity() * size_of::<Option<f64>>()
+            + self.speeds.capacity() * size_of::<f64>()
+    }
+
+    /// Returns the hex ID followed by whichever of the callsign, registration
+    /// and type are known, e.g. "ae1234 (DOLBY11, 01-7050, F16)".
+    pub fn description(&self) -> String {
+        let known: Vec<&str> = [&self.callsign, &self.registration, &self.aircraft_type]
+            .into_iter()
+            .filter_map(|s| s.as_deref())
+            .collect();
+        if known.is_empty() {
+            self.hex.to_string()
+        } else {
+            format!("{} ({})", self.hex, known.join(", "))
         }
-        if self.cur_speed > TARGET_MIN_SPEED_KTS
-            && self.cur_speed < TARGET_MAX_SPEED_KTS
-            && !self.is_on_ground
-        {
-            return Class::Target;
+    }
+
+    // The aircraft's fast status under each interceptor profile, with the
+    // profile's name and rule, the fast-jet profile first.
+    fn fast_statuses<'a>(
+        &'a self,
+        params: &'a DetectionParams,
+    ) -> impl Iterator<Item = (&'a str, &'a FastStatus, FastRule)> {
+        std::iter::once((FAST_JET_PROFILE, &self.fast, params.fast_jet_rule())).chain(
+            params.interceptor_profiles.iter().filter_map(|profile| {
+                self.profile_status
+                    .get(&profile.name)
+                    .map(|status| (profile.name.as_str(), status, profile.rule()))
+            }),
+        )
+    }
+
+    /// The name of the first interceptor profile the aircraft has fast
+    /// status under (see FastStatus::update), trying the fast-jet one first.
+    /// None if it hasn't any, or it's on the ground.
+    pub fn interceptor_profile<'a>(
+        &'a self,
+        now: DateTime<Utc>,
+        params: &'a DetectionParams,
+    ) -> Option<&'a str> {
+        if self.is_on_ground {
+            return None;
         }
+        self.fast_statuses(params)
+            .find(|(_, status, rule)| status.is_active(now, *rule))
+            .map(|(name, _, _)| name)
+    }
+
+    /// Whether the aircraft has fast status under some interceptor profile
+    /// and isn't on the ground, making it a possible interceptor.
+    pub fn is_fast_mover(&self, now: DateTime<Utc>, params: &DetectionParams) -> bool {
+        self.interceptor_profile(now, params).is_some()
+    }
+
+    /// Whether the aircraft has been a fast mover at some point in the last
+    /// `within`, even if it has slowed down since.
+    pub fn was_fast_mover(
+        &self,
+        now: DateTime<Utc>,
+        within: Duration,
+        params: &DetectionParams,
+    ) -> bool {
+        self.fast_statuses(params)
+            .any(|(_, status, rule)| status.end(rule).is_some_and(|end| now < end + within))
+    }
+
+    /// Whether the aircraft is a possible target of interest: it meets
+    /// params.target, and isn't on the ground.
+    pub fn is_potential_toi(&self, params: &DetectionParams) -> bool {
+        params.target.matches(self)
+    }
+
+    /// Whether the aircraft's type or emitter category is one of the airliner
+    /// ones in params. Aircraft that are likely military never are, since
+    /// tankers and transports report the same categories.
+    pub fn is_airliner(&self, params: &DetectionParams) -> bool {
+        !self.military
+            && (self
+                .aircraft_type
+                .as_ref()
+                .is_some_and(|t| params.airliner_types.contains(t))
+                || self
+                    .emitter_category
+                    .as_ref()
+                    .is_some_and(|c| params.airliner_categories.contains(c)))
+    }
+
+    /// Whether the aircraft is allowed to be an interceptor or escort at all,
+    /// whatever its speed: it has to be military if
+    /// require_military_interceptor is set, and not an airliner unless
+    /// allow_airliner_interceptors is set.
+    pub fn can_intercept(&self, params: &DetectionParams) -> bool {
+        (self.military || !params.require_military_interceptor)
+            && (params.allow_airliner_interceptors || !self.is_airliner(params))
+    }
+}
+
+/// Classifies an aircraft as a possible interceptor, a possible target, or
+/// neither. This is where the detector decides which aircraft to look at.
+///
+/// An interceptor has been faster than an interceptor profile's minimum
+/// speed often enough and recently enough (see Ac::is_fast_mover), isn't on
+/// the ground, and is allowed to intercept (see Ac::can_intercept). A target
+/// meets the TargetCriteria. An aircraft that has slowed down to target
+/// speeds but is still a fast mover is an interceptor, not a target.
+pub fn classify(ac: &Ac, now: DateTime<Utc>, params: &DetectionParams) -> Class {
+    if ac.is_fast_mover(now, params) && ac.can_intercept(params) {
+        Class::Interceptor
+    } else if ac.is_potential_toi(params) {
+        Class::Target
+    } else {
         Class::Other
     }
 }
 
+/// The emergency squawk codes. Serialized as "hijack", "radio_failure", or
+/// "emergency".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencySquawk {
+    /// 7500.
+    Hijack,
+    /// 7600.
+    RadioFailure,
+    /// 7700.
+    Emergency,
+}
+
+impl EmergencySquawk {
+    pub fn from_squawk(squawk: &str) -> Option<Self> {
+        match squawk {
+            "7500" => Some(EmergencySquawk::Hijack),
+            "7600" => Some(EmergencySquawk::RadioFailure),
+            "7700" => Some(EmergencySquawk::Emergency),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            EmergencySquawk::Hijack => "7500",
+            EmergencySquawk::RadioFailure => "7600",
+            EmergencySquawk::Emergency => "7700",
+        }
+    }
+}
+
+#[allow(clippy::empty_line_after_doc_comments)]
 /// This is the type that we put in the spatial index (r-tree) to find
-/// slow-movers near fast-movers.
+/// slow-movers near fast-movers: a target's position, and its hex ID.
 
-pub type TargetLocation = GeomWithData<[f64; 2], Ac>;
+pub type TargetLocation = GeomWithData<[f64; 2], HexId>;
 
-#[derive(Debug)]
+/// A detected interception.
+///
+/// Interceptions (and the Ac records inside them) serialize to JSON like this,
+/// and the shape is stable: fields may be added, but existing ones won't be
+/// renamed or removed without a major version bump.
+///
+/// ```json
+/// {
+///   "interceptor": {
+///     "hex": "ae1234",
+///     "callsign": "VV100",
+///     "registration": "01-7050",
+///     "aircraft_type": "F16",
+///     "emitter_category": "A6",
+///     "squawk": "4401",
+///     "military": true,
+///     "country": "United States",
+///     "operator": "United States Air Force",
+///     "coords": [["2021-02-23T19:38:53Z", [-118.0, 34.1]], ...],
+///     "alts": [10000, ...],
+///     "tracks": [180.0, ...],
+///     "speeds": [450.0, ...],
+///     "geom_alt": 10000,
+///     "baro_alt": 9850,
+///     "cur_vertical_rate_fpm": -6000.0,
+///     "max_speed": 450.0,
+///     "cur_speed": 450.0,
+///     "cur_alt": 10000,
+///     "alt_time": "2021-02-23T19:38:53Z",
+///     "is_on_ground": false,
+///     "time_seen_fast": "2021-02-23T19:38:53Z",
+///     "fast_count": 12,
+///     "fast_status": true,
+///     "fast_run_start": null,
+///     "fast_status_ended": null,
+///     "seen": "2021-02-23T19:38:53Z"
+///   },
+///   "target": { ... },
+///   "time": "2021-02-23T19:38:53Z",
+///   "proximity_start": "2021-02-23T19:37:53Z",
+///   "lateral_separation_ft": 120.5,
+///   "vertical_separation_ft": 100,
+///   "pattern": "shadow",
+///   "emergency": "hijack"
+/// }
+/// ```
+///
+/// Timestamps are RFC 3339 strings in UTC, and coordinates are [lon, lat]
+/// pairs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Interception {
     pub interceptor: Ac,
     pub target: Ac,
     pub time: DateTime<Utc>,
+    /// When the aircraft first came close, in the run of consecutive
+    /// snapshots that led to this detection.
+    pub proximity_start: DateTime<Utc>,
     pub lateral_separation_ft: f64,
     pub vertical_separation_ft: i32,
+    /// How the interceptor was flying relative to the target.
+    #[serde(default)]
+    pub pattern: InterceptPattern,
+    /// Which of the TargetCriteria the target met.
+    #[serde(default)]
+    pub target_mode: TargetMode,
+    /// The interceptor profile the interceptor was a fast mover under, e.g.
+    /// "fast_jet". None for an escort that has slowed down, and in records
+    /// from before there were profiles.
+    #[serde(default)]
+    pub interceptor_profile: Option<String>,
+    /// If the interceptor seems to have just taken off from a military
+    /// airfield instead of intercepting anything, the airfield's ident. See
+    /// departure_airfield.
+    #[serde(default)]
+    pub departure_airfield: Option<String>,
+    /// How much the detection looks like an interception, from 0 to 100.
+    /// None for shadows, which aren't scored. See the confidence module.
+    #[serde(default)]
+    pub confidence: Option<f64>,
+    /// The emergency the target was squawking at the time, if any.
+    #[serde(default)]
+    pub emergency: Option<EmergencySquawk>,
+    /// In explain mode, how the pair measured up when it was promoted to an
+    /// interception (or None, for a shadow). See the explain module.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub criteria: Option<CandidateCheck>,
+    /// With record_event_tracks, the positions of both aircraft recorded
+    /// since the pair's previous detection (or since they were first close).
+    /// See the recording module.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracks: Option<PairTracks>,
 }
 
-/// This is the state that is kept across ADS-B Exchange API responses.
+impl Interception {
+    /// Where the interception happened, as [lon, lat]: the midpoint of the
+    /// aircraft's positions at the time.
+    pub fn location(&self) -> [f64; 2] {
+        let i = self.interceptor.cur_coords().1;
+        let t = self.target.cur_coords().1;
+        [
+            normalize_lon(i[0] + lon_difference(i[0], t[0]) / 2.0),
+            (i[1] + t[1]) / 2.0,
+        ]
+    }
+}
 
-#[derive(Debug, Default)]
-pub struct State {
-    pub aircraft: HashMap<String, Ac>,
-    pub num_ac_indexed: usize,
-    pub num_ac_processed: usize,
-    pub interceptions: Vec<Interception>,
+/// Which aircraft are potential targets. By default, anything in the air
+/// flying faster than TARGET_MIN_SPEED_KTS and slower than
+/// TARGET_MAX_SPEED_KTS.
+///
+/// In DetectionParams (and config files), each field's key has a "target_"
+/// prefix, e.g. target_min_speed_kts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetCriteria {
+    /// Targets fly faster than this, in knots...
+    #[serde(rename = "target_min_speed_kts")]
+    pub min_speed_kts: f64,
+    /// ...and slower than this. Both limits are exclusive.
+    #[serde(rename = "target_max_speed_kts")]
+    pub max_speed_kts: f64,
+    /// If set, targets fly at or above this altitude, in feet...
+    #[serde(rename = "target_min_alt_ft")]
+    pub min_alt_ft: Option<i32>,
+    /// ...and at or below this one. Aircraft whose altitude isn't known
+    /// (see Ac::known_alt) aren't held to either limit.
+    #[serde(rename = "target_max_alt_ft")]
+    pub max_alt_ft: Option<i32>,
+    /// Aircraft in these emitter categories are targets at any speed, e.g.
+    /// B2 for balloons, or A1 for light aircraft that fly slower than
+    /// min_speed_kts.
+    #[serde(rename = "target_categories")]
+    pub categories: BTreeSet<String>,
+    /// Whether aircraft squawking an emergency code are targets at any
+    /// speed, so a bizjet that's being escorted isn't missed for being too
+    /// fast.
+    #[serde(rename = "target_emergency_any_speed")]
+    pub emergency_any_speed: bool,
+    /// Slow target mode, for balloons and hovering drones: aircraft slower
+    /// than slow_max_speed_kts are targets too. No interceptor can fly
+    /// alongside them, so the usual checks of speed, heading, and how long
+    /// they're close are replaced by a stricter one: the interceptor has to
+    /// orbit the target (see orbit_radius_nm and orbit_min_turn_deg), or
+    /// pass within orbit_radius_nm of it at least slow_min_passes times in
+    /// its recent track. Within orbit_radius_nm counts as close.
+    #[serde(rename = "target_slow_mode")]
+    pub slow_mode: bool,
+    #[serde(rename = "target_slow_max_speed_kts")]
+    pub slow_max_speed_kts: f64,
+    #[serde(rename = "target_slow_min_passes")]
+    pub slow_min_passes: usize,
 }
 
-pub fn process_adsbx_response(
-    state: &mut State,
-    response: adsbx_json::v2::Response,
-    bar: &ProgressBar,
-) -> Result<(), Error> {
-    let now = response.now;
+impl Default for TargetCriteria {
+    fn default() -> Self {
+        TargetCriteria {
+            min_speed_kts: TARGET_MIN_SPEED_KTS,
+            max_speed_kts: TARGET_MAX_SPEED_KTS,
+            min_alt_ft: None,
+            max_alt_ft: None,
+            categories: BTreeSet::new(),
+            emergency_any_speed: false,
+            slow_mode: false,
+            slow_max_speed_kts: SLOW_TARGET_MAX_SPEED_KTS,
+            slow_min_passes: 2,
+        }
+    }
+}
 
-    // First classify each aircraft as a fast mover/interceptor, a slow
-    // mover/target, or neither (which we don't care about).
-    let mut fast_movers = vec![];
-    let mut potential_tois: Vec<GeomWithData<[f64; 2], Ac>> = vec![];
-    for aircraft in &response.aircraft {
-        if let (Some(_), Some(_), Some(_), Some(_)) = (
-            aircraft.lat,
-            aircraft.lon,
-            aircraft.ground_speed_knots,
-            aircraft.geometric_altitude,
-        ) {
-            let hex = &aircraft.hex;
-            // Insert or update the aircraft into the state.
-            if let Some(ac) = state.aircraft.get_mut(&aircraft.hex) {
-                ac.update(now, aircraft);
-            } else {
-                state
-                    .aircraft
-                    .insert(aircraft.hex.clone(), Ac::new(now, aircraft).unwrap());
-            }
-            let ac = state.aircraft.get(hex).unwrap();
-            match ac.class(now) {
-                Class::Interceptor => {
-                    fast_movers.push(ac.clone());
-                }
-                Class::Target => {
-                    potential_tois.push(TargetLocation::new(ac.cur_coords().1, ac.clone()));
-                }
-                _ => {}
-            }
+impl TargetCriteria {
+    pub fn speed_in_band(&self, speed_kts: f64) -> bool {
+        speed_kts > self.min_speed_kts && speed_kts < self.max_speed_kts
+    }
+
+    /// Whether an aircraft flying at this speed is a slow target. Always
+    /// false unless slow_mode is set.
+    pub fn is_slow(&self, speed_kts: f64) -> bool {
+        self.slow_mode && speed_kts < self.slow_max_speed_kts
+    }
+
+    /// Whether an aircraft with this emitter category and emergency is a
+    /// target whatever its speed.
+    pub fn ignores_speed(
+        &self,
+        emitter_category: Option<&str>,
+        emergency: Option<EmergencySquawk>,
+    ) -> bool {
+        emitter_category.is_some_and(|c| self.categories.contains(c))
+            || (self.emergency_any_speed && emergency.is_some())
+    }
+
+    pub fn alt_in_band(&self, alt_ft: Option<i32>) -> bool {
+        alt_ft.is_none_or(|alt| {
+            self.min_alt_ft.is_none_or(|min| alt >= min)
+                && self.max_alt_ft.is_none_or(|max| alt <= max)
+        })
+    }
+
+    /// Returns which of the criteria an aircraft meets, or None if it isn't
+    /// a target. Aircraft on the ground never are.
+    pub fn mode(&self, ac: &Ac) -> Option<TargetMode> {
+        if ac.is_on_ground || !self.alt_in_band(ac.known_alt()) {
+            None
+        } else if self.speed_in_band(ac.cur_speed) {
+            Some(TargetMode::Speed)
+        } else if self.is_slow(ac.cur_speed) {
+            Some(TargetMode::Slow)
+        } else if ac
+            .emitter_category
+            .as_ref()
+            .is_some_and(|c| self.categories.contains(c))
+        {
+            Some(TargetMode::Category)
+        } else if self.emergency_any_speed && ac.emergency().is_some() {
+            Some(TargetMode::Emergency)
+        } else {
+            None
         }
     }
-    // Now remove stale aircraft.
-    state
-        .aircraft
-        .retain(|_, ac| (now - ac.seen) < Duration::minutes(10));
-
-    if fast_movers.is_empty() {
-        return Ok(());
-    }
-    // The r-tree treats coordinates as cartesian, but they're geospatial
-    // (spherical). So we use the fact that one degree (of latitude, anyway)
-    // is 60 nautical miles and use the r-tree index to look up any
-    // potential targets kinda-close to each fast-mover, then do a more
-    // precise filtering using Haversine distance.  Of course one degree in
-    // longitude/the X-axis represents a variable distance depending on
-    // where it is on Earth, but we're not usually looking at planes flying
-    // over a pole.
-    //
-    // An alternative might be to use H3?
-    state.num_ac_indexed += &potential_tois.len();
-    let spatial_index = RTree::bulk_load(potential_tois);
-    const MAX_DIST_NM: f64 = 0.5;
-    let max_dist_deg_2 = (MAX_DIST_NM / 60.0).powi(2);
 
-    // For each fast mover, find any potential targets that are close enough.
-    for fast_mover in fast_movers {
-        let fast_mover_coords = fast_mover.cur_coords().1;
-        let targets = spatial_index.locate_within_distance(fast_mover_coords, max_dist_deg_2);
-        for target in targets {
-            let target_coords = target.data.cur_coords().1;
-            state.num_ac_processed += 1;
-            let target_pt = point!(x: target_coords[0], y: target_coords[1]);
-            let fast_mover_pt = point!(x: fast_mover_coords[0], y: fast_mover_coords[1]);
-            let dist = target_pt.haversine_distance(&fast_mover_pt);
-            let alt_diff = (target.data.cur_alt - fast_mover.cur_alt).abs();
-            if dist < 500.0
-                && (target.data.cur_speed - fast_mover.cur_speed).abs() < 150.0
-                && alt_diff < 500
-                && ((now - target.data.seen) < Duration::minutes(1))
-                && started_far_apart(&fast_mover, &target.data)
-            {
-                // Consider this a duplicate interception if the same
-                // fast_mover intercepted the same target within the
-                // past 10 minutes.
-                if state.interceptions.iter().any(|i| {
-                    i.interceptor.hex == fast_mover.hex
-                        && i.target.hex == target.data.hex
-                        && i.time > now - Duration::minutes(10)
-                }) {
-                    continue;
-                }
+    /// Whether an aircraft meets the criteria and isn't on the ground.
+    pub fn matches(&self, ac: &Ac) -> bool {
+        self.mode(ac).is_some()
+    }
+}
+
+/// Which of the TargetCriteria a target met, checked in this order.
+/// Serialized as "speed", "slow", "category", or "emergency".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetMode {
+    /// It was in the speed band.
+    #[default]
+    Speed,
+    /// It was slower than slow_max_speed_kts, in slow target mode.
+    Slow,
+    /// It was in one of the categories.
+    Category,
+    /// It was squawking an emergency, with emergency_any_speed.
+    Emergency,
+}
+
+// The parts of an interceptor profile that fast status depends on.
+#[derive(Debug, Clone, Copy)]
+struct FastRule {
+    timeout_mins: i64,
+    min_fast_count: u32,
+    entry_window_mins: i64,
+}
+
+/// A kind of interceptor, with its own idea of fast. The fast-jet profile is
+/// set by DetectionParams' interceptor_* fields; others go in its
+/// interceptor_profiles, e.g. for law-enforcement and Coast Guard
+/// helicopters:
+///
+/// ```toml
+/// [[detection.interceptor_profiles]]
+/// name = "helicopter"
+/// min_speed_kts = 60.0
+/// min_fast_count = 20
+/// categories = ["A7"]
+/// type_prefixes = ["H"]
+/// ```
+///
+/// The fields mean the same as the interceptor_* ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InterceptorProfile {
+    /// Recorded on detections as Interception::interceptor_profile.
+    pub name: String,
+    pub min_speed_kts: f64,
+    pub timeout_mins: i64,
+    pub min_fast_count: u32,
+    pub entry_window_mins: i64,
+    /// If either of these is set, only aircraft with one of these emitter
+    /// categories...
+    pub categories: BTreeSet<String>,
+    /// ...or a type designator starting with one of these can be
+    /// interceptors under the profile.
+    pub type_prefixes: Vec<String>,
+}
+
+impl Default for InterceptorProfile {
+    fn default() -> Self {
+        InterceptorProfile {
+            name: String::new(),
+            min_speed_kts: INTERCEPTOR_MIN_SPEED_KTS,
+            timeout_mins: INTERCEPTOR_TIMEOUT_MINS,
+            min_fast_count: 10,
+            entry_window_mins: 5,
+            categories: BTreeSet::new(),
+            type_prefixes: vec![],
+        }
+    }
+}
+
+impl InterceptorProfile {
+    /// Whether the aircraft's category or type lets it be an interceptor
+    /// under this profile.
+    pub fn allows(&self, ac: &Ac) -> bool {
+        (self.categories.is_empty() && self.type_prefixes.is_empty())
+            || ac
+                .emitter_category
+                .as_ref()
+                .is_some_and(|c| self.categories.contains(c))
+            || ac
+                .aircraft_type
+                .as_ref()
+                .is_some_and(|t| self.type_prefixes.iter().any(|p| t.starts_with(p.as_str())))
+    }
+
+    fn rule(&self) -> FastRule {
+        FastRule {
+            timeout_mins: self.timeout_mins,
+            min_fast_count: self.min_fast_count,
+            entry_window_mins: self.entry_window_mins,
+        }
+    }
+}
+
+/// Thresholds used to classify aircraft and to decide whether an interceptor
+/// and a target are close enough to count as an interception.
+///
+/// This can be loaded from a config file; any fields that are left out get
+/// their default values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectionParams {
+    /// The speed threshold to be considered an interceptor, in knots.
+    pub interceptor_min_speed_kts: f64,
+    /// Which aircraft can be targets. Its fields are set with these keys:
+    /// target_min_speed_kts, target_max_speed_kts, and so on.
+    #[serde(flatten)]
+    pub target: TargetCriteria,
+    /// The length of time an interceptor must travel below
+    /// interceptor_min_speed_kts to lose interceptor status.
+    pub interceptor_timeout_mins: i64,
+    /// The number of consecutive fast updates an aircraft needs before it's
+    /// considered an interceptor...
+    pub interceptor_min_fast_count: u32,
+    /// ...all within this many minutes.
+    pub interceptor_entry_window_mins: i64,
+    /// Interceptor profiles besides the fast-jet one set by the fields
+    /// above, each evaluated on its own. None by default.
+    pub interceptor_profiles: Vec<InterceptorProfile>,
+    /// Only consider aircraft that are likely to be military as interceptors
+    /// (and escorts). Otherwise military status is just recorded on the Ac.
+    pub require_military_interceptor: bool,
+    /// Aircraft whose type is in airliner_types, or whose emitter category is
+    /// in airliner_categories, can't be interceptors unless this is set, so
+    /// airliners descending past slower traffic aren't reported.
+    pub allow_airliner_interceptors: bool,
+    pub airliner_types: BTreeSet<String>,
+    pub airliner_categories: BTreeSet<String>,
+    /// How far around each interceptor to look for targets, in nautical miles.
+    pub search_radius_nm: f64,
+    /// The kind of index the targets are looked up in. See the spatial
+    /// module.
+    pub spatial_index: SpatialIndexKind,
+    /// The maximum lateral distance between the aircraft, in meters.
+    pub max_lateral_separation_m: f64,
+    /// Pairs that are further apart vertically than this, in feet, aren't
+    /// considered at all.
+    pub vertical_gate_ft: i32,
+    /// The maximum vertical distance between the aircraft, in feet.
+    pub max_vertical_separation_ft: i32,
+    /// The maximum difference in ground speed, in knots.
+    pub max_speed_difference_kts: f64,
+    /// Targets whose position is older than this are ignored.
+    pub max_target_age_secs: i64,
+    /// How far apart the aircraft must have been at the start of their
+    /// tracks, in statute miles.
+    pub min_initial_separation_mi: f64,
+    /// The interceptor's heading must be within this many degrees of the
+    /// bearing to the target...
+    pub convergence_cone_deg: f64,
+    /// ...for this many of its positions before they got close. Zero disables
+    /// the check.
+    pub convergence_frames: usize,
+    /// The aircraft must stay close for this many seconds...
+    pub min_proximity_secs: i64,
+    /// ...or this many consecutive snapshots, whichever comes first, before
+    /// it counts as an interception. Setting either to zero reports pairs the
+    /// first time they're close.
+    pub min_proximity_frames: usize,
+    /// A pair that's been close doesn't start over when one of them is
+    /// missing from a snapshot, as long as dead-reckoning it forward (for up
+    /// to this many seconds since its latest position) keeps it close. Zero
+    /// means any snapshot where a pair isn't close resets it.
+    pub dead_reckoning_horizon_secs: i64,
+    /// If set, pairs where the target is squawking 7500 only have to be close
+    /// for this many snapshots (or min_proximity_secs), so hijackings are
+    /// reported sooner.
+    pub hijack_min_proximity_frames: Option<usize>,
+    /// Whether the interceptor has to match the target's speed, by slowing
+    /// down to within speed_match_ratio times the target's speed or to within
+    /// max_relative_speed_kts of its velocity.
+    pub require_speed_match: bool,
+    pub speed_match_ratio: f64,
+    pub max_relative_speed_kts: f64,
+    /// An interceptor that turns through orbit_min_turn_deg while staying
+    /// within orbit_radius_nm of the target is orbiting it.
+    pub orbit_radius_nm: f64,
+    pub orbit_min_turn_deg: f64,
+    /// Whether to look for escorts: aircraft that were fast movers in the
+    /// last shadow_fast_window_mins and are now flying in formation with a
+    /// target (within the lateral and vertical separation limits, and within
+    /// these heading and speed differences).
+    pub detect_shadows: bool,
+    pub shadow_fast_window_mins: i64,
+    pub shadow_max_heading_difference_deg: f64,
+    pub shadow_max_speed_difference_kts: f64,
+    /// How long the pair has to stay in formation before it counts.
+    pub shadow_min_duration_secs: i64,
+    /// Gaps in formation (or in either aircraft's updates) up to this long
+    /// don't reset shadow_min_duration_secs.
+    pub shadow_max_gap_secs: i64,
+    /// Whether the interceptor has to have descended toward the target: over
+    /// the last descent_window_secs it has to have started above the target
+    /// (by more than max_vertical_separation_ft) and come down at
+    /// min_descent_rate_fpm or faster.
+    pub require_descent: bool,
+    pub descent_window_secs: i64,
+    pub min_descent_rate_fpm: f64,
+    /// If set, only aircraft within region_buffer_nm of one of these regions
+    /// are considered, and aircraft more than region_margin_nm away aren't
+    /// tracked at all. The margin lets aircraft build up some history before
+    /// they enter a region. Regions can't be set in a config file; load them
+    /// with Regions::load.
+    #[serde(skip)]
+    pub regions: Option<Regions>,
+    pub region_buffer_nm: f64,
+    pub region_margin_nm: f64,
+    /// When State has an airport database, interceptions where both aircraft
+    /// are within airport_radius_nm of an airport and less than
+    /// airport_max_alt_ft above it are ignored.
+    pub airport_radius_nm: f64,
+    pub airport_max_alt_ft: i32,
+    /// When State has military airfields, interceptions where the
+    /// interceptor looks like it just took off from one are likely
+    /// departures: its first fix was within departure_radius_nm of the
+    /// airfield, the aircraft came close within departure_window_mins of that
+    /// fix, and the interceptor is climbing at departure_min_climb_fpm or
+    /// more. They're tagged with Interception::departure_airfield, or
+    /// ignored if suppress_departures is set.
+    pub departure_radius_nm: f64,
+    pub departure_window_mins: i64,
+    pub departure_min_climb_fpm: f64,
+    pub suppress_departures: bool,
+    /// If set, the NotConverging, SpeedNotMatched, NotDescending, and
+    /// LowQuality criteria are scored instead of pass/fail, and pairs are
+    /// only detected if their confidence is at least this, from 0 to 100.
+    /// See the confidence module.
+    pub min_confidence: Option<f64>,
+    pub confidence_weights: ConfidenceWeights,
+    /// Pairs get the full proximity_duration weight once they've been close
+    /// for this long.
+    pub confidence_full_duration_secs: i64,
+    /// An interception ends once the interceptor and target haven't been close
+    /// for more than this many minutes. Until then, the same pair isn't
+    /// reported again. It's the gap State's InterceptionTracker merges
+    /// detections with.
+    pub duplicate_window_mins: i64,
+    /// Aircraft that haven't been seen for this many minutes are forgotten.
+    pub stale_aircraft_mins: i64,
+    /// Limits on how many aircraft are tracked, and roughly how much memory
+    /// the state can use, for long runs on small machines. When they're
+    /// exceeded, the least recently seen aircraft are forgotten early. See
+    /// AircraftStateMap::evict. Both are off by default. history_max_points
+    /// and history_max_secs limit each aircraft's share.
+    pub max_tracked_aircraft: Option<usize>,
+    pub memory_budget_mb: Option<usize>,
+    /// How much of each aircraft's history to keep: at most
+    /// history_max_points positions, and none older than history_max_secs
+    /// before the latest one, whichever is reached first. The latest position
+    /// is always kept. The default is the last 40 positions; to keep 30
+    /// minutes of track instead, set history_max_points to null and
+    /// history_max_secs to 1800.
+    pub history_max_points: Option<usize>,
+    pub history_max_secs: Option<i64>,
+    /// Positions that would mean the aircraft flew faster than
+    /// max_implied_speed_kts (or max_implied_speed_ratio times its reported
+    /// ground speed) since its previous one are rejected, unless they're
+    /// within outlier_min_distance_nm of it. See Ac::is_plausible_fix.
+    pub max_implied_speed_kts: f64,
+    pub max_implied_speed_ratio: Option<f64>,
+    pub outlier_min_distance_nm: f64,
+    /// If set above zero, a ground speed report only counts toward fast
+    /// status if the aircraft's average speed over the last this many
+    /// seconds (see Ac::average_speed_kts) is at least speed_cross_check_ratio
+    /// times interceptor_min_speed_kts, since the reported speed is
+    /// occasionally garbage. Off by default.
+    pub speed_cross_check_secs: i64,
+    pub speed_cross_check_ratio: f64,
+    /// Whether to record the full tracks of both aircraft of each
+    /// interception, from when they're first close until the interception
+    /// ends, for at most event_track_max_points positions per aircraft. The
+    /// tracks end up in InterceptionEvent::tracks. Off by default. See the
+    /// recording module.
+    pub record_event_tracks: bool,
+    pub event_track_max_points: usize,
+    /// At least one aircraft in a pair must have a quality_score of at least
+    /// this. Around 0.8 means one of them needs ADS-B positions. Zero
+    /// disables the check.
+    pub min_quality_score: f64,
+    /// Whether to group events where several interceptors went after the
+    /// same target at the same time, and how far apart (in seconds) their
+    /// events can be and still count as the same time. See group_events.
+    pub group_interceptors: bool,
+    pub group_window_secs: i64,
+    /// A target's events are linked into an escort chain if each one starts
+    /// at most this many minutes after the previous one ended. See
+    /// link_escort_chains.
+    pub escort_handoff_gap_mins: i64,
+    /// Aircraft whose geometric altitude is below this many feet count as on
+    /// the ground, as well as those reporting a barometric altitude of
+    /// "ground". It's above sea level, not the ground, so it's off by
+    /// default. See aircraft_is_on_ground.
+    pub on_ground_max_geom_alt_ft: Option<i32>,
+}
+
+impl Default for DetectionParams {
+    fn default() -> Self {
+        DetectionParams {
+            interceptor_min_speed_kts: INTERCEPTOR_MIN_SPEED_KTS,
+            target: TargetCriteria::default(),
+            interceptor_timeout_mins: INTERCEPTOR_TIMEOUT_MINS,
+            interceptor_min_fast_count: 10,
+            interceptor_entry_window_mins: 5,
+            interceptor_profiles: vec![],
+            require_military_interceptor: false,
+            allow_airliner_interceptors: false,
+            airliner_types: AIRLINER_TYPES.iter().map(|t| t.to_string()).collect(),
+            airliner_categories: AIRLINER_CATEGORIES.iter().map(|c| c.to_string()).collect(),
+            search_radius_nm: 0.5,
+            spatial_index: SpatialIndexKind::Rtree,
+            max_lateral_separation_m: 500.0,
+            vertical_gate_ft: 4000,
+            max_vertical_separation_ft: 500,
+            max_speed_difference_kts: 150.0,
+            max_target_age_secs: 60,
+            min_initial_separation_mi: 10.0,
+            convergence_cone_deg: 45.0,
+            convergence_frames: 3,
+            min_proximity_secs: 60,
+            min_proximity_frames: 5,
+            dead_reckoning_horizon_secs: 60,
+            hijack_min_proximity_frames: None,
+            require_speed_match: true,
+            speed_match_ratio: 1.3,
+            max_relative_speed_kts: 70.0,
+            orbit_radius_nm: 3.0,
+            orbit_min_turn_deg: 300.0,
+            detect_shadows: true,
+            shadow_fast_window_mins: 10,
+            shadow_max_heading_difference_deg: 20.0,
+            shadow_max_speed_difference_kts: 30.0,
+            shadow_min_duration_secs: 120,
+            shadow_max_gap_secs: 60,
+            require_descent: false,
+            descent_window_secs: 180,
+            min_descent_rate_fpm: 1000.0,
+            regions: None,
+            region_buffer_nm: 0.0,
+            region_margin_nm: 50.0,
+            airport_radius_nm: 5.0,
+            airport_max_alt_ft: 5000,
+            departure_radius_nm: 5.0,
+            departure_window_mins: 5,
+            departure_min_climb_fpm: 1000.0,
+            suppress_departures: false,
+            min_confidence: None,
+            confidence_weights: ConfidenceWeights::default(),
+            confidence_full_duration_secs: 300,
+            duplicate_window_mins: DEFAULT_MERGE_GAP_MINS,
+            stale_aircraft_mins: 10,
+            max_tracked_aircraft: None,
+            memory_budget_mb: None,
+            history_max_points: Some(40),
+            history_max_secs: None,
+            max_implied_speed_kts: 1200.0,
+            max_implied_speed_ratio: Some(3.0),
+            outlier_min_distance_nm: 1.0,
+            speed_cross_check_secs: 0,
+            speed_cross_check_ratio: 0.8,
+            record_event_tracks: false,
+            event_track_max_points: 20_000,
+            min_quality_score: 0.0,
+            group_interceptors: true,
+            group_window_secs: 0,
+            escort_handoff_gap_mins: DEFAULT_HANDOFF_GAP_MINS,
+            on_ground_max_geom_alt_ft: None,
+        }
+    }
+}
+
+impl DetectionParams {
+    /// How far apart an interceptor and a target can be and still be close:
+    /// max_lateral_separation_m, or in slow target mode, orbit_radius_nm if
+    /// that's further.
+    pub fn close_radius_m(&self) -> f64 {
+        if self.target.slow_mode {
+            self.max_lateral_separation_m
+                .max(self.orbit_radius_nm * 1852.0)
+        } else {
+            self.max_lateral_separation_m
+        }
+    }
+
+    /// The lowest minimum speed of any interceptor profile, in knots.
+    pub fn min_interceptor_speed_kts(&self) -> f64 {
+        self.interceptor_profiles
+            .iter()
+            .map(|p| p.min_speed_kts)
+            .fold(self.interceptor_min_speed_kts, f64::min)
+    }
+
+    /// The longest any interceptor profile keeps fast status after the last
+    /// fast update, in minutes.
+    pub fn max_interceptor_timeout_mins(&self) -> i64 {
+        self.interceptor_profiles
+            .iter()
+            .map(|p| p.timeout_mins)
+            .fold(self.interceptor_timeout_mins, i64::max)
+    }
+
+    fn fast_jet_rule(&self) -> FastRule {
+        FastRule {
+            timeout_mins: self.interceptor_timeout_mins,
+            min_fast_count: self.interceptor_min_fast_count,
+            entry_window_mins: self.interceptor_entry_window_mins,
+        }
+    }
+}
+
+/// A pair that has been close in consecutive snapshots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingInterception {
+    since: DateTime<Utc>,
+    num_frames: usize,
+}
+
+/// A pair that has been flying in formation, with gaps no longer than
+/// shadow_max_gap_secs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingShadow {
+    since: DateTime<Utc>,
+    last: DateTime<Utc>,
+}
+
+// What AircraftStateMap::update needs besides the map, so that shards of the
+// map can be updated in parallel.
+struct Updater<'a> {
+    now: DateTime<Utc>,
+    params: &'a DetectionParams,
+    #[cfg(feature = "aircraft_db")]
+    aircraft_db: Option<&'a crate::aircraft_db::SharedAircraftDb>,
+}
 
-                let interception = Interception {
-                    interceptor: fast_mover.clone(),
-                    target: target.data.clone(),
-                    lateral_separation_ft: dist * 3.28084,
-                    vertical_separation_ft: alt_diff,
-                    time: now,
+impl Updater<'_> {
+    // Also returns whether the update was partial: the record was missing
+    // something, or its position was rejected, but the aircraft was still
+    // updated with the rest of it. A record that nothing was kept from is an
+    // error.
+    fn update<'m>(
+        &self,
+        entry: Entry<'m, HexId, Ac>,
+        aircraft: &Aircraft,
+    ) -> Result<(&'m Ac, bool), Error> {
+        let (now, params) = (self.now, self.params);
+        match entry {
+            Entry::Occupied(entry) => {
+                let ac = entry.into_mut();
+                // Ac::update ignores records older than the latest position.
+                let stale = now < ac.cur_coords().0;
+                ac.update(now, aircraft, params);
+                // A missing altitude is fine, since we keep the last one
+                // (see Ac::known_alt).
+                let has_position = aircraft.lat.is_some() && aircraft.lon.is_some();
+                let missing = if !has_position {
+                    Some("position")
+                } else if aircraft.ground_speed_knots.is_none() {
+                    Some("ground speed")
+                } else if aircraft.seen_pos.is_none() {
+                    Some("seen_pos")
+                } else {
+                    None
                 };
-                state.interceptions.push(interception);
-                eprintln!(
-                    "\n{} might have intercepted {} at {}",
-                    fast_mover.hex, target.data.hex, now,
-                );
+                let rejected = has_position && ac.rejected_fix.is_some();
+                let err = match missing {
+                    Some(field) => Error::MissingField {
+                        hex: aircraft.hex.clone(),
+                        field,
+                    },
+                    None if rejected => Error::ImplausiblePosition {
+                        hex: aircraft.hex.clone(),
+                    },
+                    None => return Ok((ac, false)),
+                };
+                let (geom_alt, baro_alt) = altitudes(aircraft);
+                let kept = !stale
+                    && ((has_position && !rejected)
+                        || aircraft.ground_speed_knots.is_some()
+                        || geom_alt.or(baro_alt).is_some());
+                if kept {
+                    Ok((ac, true))
+                } else {
+                    Err(err)
+                }
+            }
+            Entry::Vacant(entry) => {
+                #[allow(unused_mut)]
+                let mut ac = Ac::new(now, aircraft, params)?;
+                #[cfg(feature = "aircraft_db")]
+                if let Some(Ok(db)) = self.aircraft_db.map(|db| db.get()) {
+                    db.enrich(&mut ac);
+                }
+                Ok((entry.insert(ac), false))
             }
         }
     }
-
-    bar.set_message(format!(
-        "[ {} interceptions found ]",
-        state.interceptions.len()
-    ));
-    Ok(())
 }
 
-// Function that checks whether the two aircraft were more than 10 miles apart
-// in the past.
-//
-// First we look at the oldest position for each aircraft, then find the most
-// recent timestamp on those positions to be our time of comparison. Then we
-// find the timestamped position for the other aircraft that is closest in time
-// to the time of comparison.
-fn started_far_apart(fast_mover: &Ac, target: &Ac) -> bool {
-    let oldest_fm_ts = fast_mover.oldest_coords().0;
-    let oldest_t_ts = target.coords[0].0;
-    let comparison_ts = max(oldest_fm_ts, oldest_t_ts);
-    let mut temp_fast_mover_coords = fast_mover.coords.clone();
-    let mut temp_target_coords = target.coords.clone();
-    temp_fast_mover_coords.sort_by_key(|c| (c.0 - comparison_ts).num_seconds().abs());
-    temp_target_coords.sort_by_key(|c| (c.0 - comparison_ts).num_seconds().abs());
-    let dist = point!(x: temp_fast_mover_coords[0].1[0], y: temp_fast_mover_coords[0].1[1])
-        .haversine_distance(&point!(x: temp_target_coords[0].1[0], y: temp_target_coords[0].1[1]));
-    dist > 10.0 * 1609.34
+/// The aircraft being tracked, keyed by hex ID, along with the pairs that
+/// are close. Which interceptions are ongoing is up to the
+/// InterceptionTracker; see State.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AircraftStateMap {
+    aircraft: AircraftShards,
+    /// Pairs that are close in the current snapshot, and since when. Pairs
+    /// stay here after they're promoted to interceptions, until they
+    /// separate.
+    pending: HashMap<(HexId, HexId), PendingInterception>,
+    /// Like pending, but for escorts flying in formation.
+    pending_shadows: HashMap<(HexId, HexId), PendingShadow>,
+    /// Hex IDs of aircraft that aren't expired however long ago they were
+    /// seen, e.g. because they're part of an open InterceptionTracker event.
+    pinned: HashSet<HexId>,
+    /// The number of potential targets put in the spatial index.
+    pub num_ac_indexed: usize,
+    /// The number of interceptor/target pairs that were compared.
+    pub num_ac_processed: usize,
+    /// The number of aircraft records skipped because they were missing data
+    /// we need (position, speed, altitude), or their position was rejected as
+    /// implausible.
+    pub num_ac_missing_data: usize,
+    /// The number of aircraft forgotten because they hadn't been seen for
+    /// stale_aircraft_mins.
+    pub num_ac_expired: usize,
+    /// The number of aircraft forgotten to stay within max_tracked_aircraft
+    /// or memory_budget_mb. See evict.
+    #[serde(default)]
+    pub num_ac_evicted: usize,
+    /// The number of aircraft records dropped by the prefilter. See
+    /// set_prefilter.
+    #[serde(default)]
+    pub num_ac_filtered: usize,
+    /// Why aircraft records were dropped, over all the snapshots so far.
+    /// This breaks down num_ac_missing_data and num_ac_filtered.
+    #[serde(default)]
+    pub drops: DropStats,
+    /// Why aircraft records were dropped from the latest snapshot.
+    #[serde(default)]
+    pub snapshot_drops: DropStats,
+    /// The number of snapshots classified.
+    #[serde(default)]
+    pub num_snapshots: usize,
+    /// If set, used to fill in the registration, type, and operator of new
+    /// aircraft, before they're classified. If the database can't be loaded,
+    /// aircraft are left as they are. It isn't saved by save_state.
+    #[cfg(feature = "aircraft_db")]
+    #[serde(skip)]
+    pub aircraft_db: Option<crate::aircraft_db::SharedAircraftDb>,
+    /// The last snapshot classified by detect_frame_interceptions, kept so
+    /// its buffers can be reused for the next one.
+    #[serde(skip)]
+    snapshot: ClassifiedSnapshot,
+    /// The threads used by detect_frame_interceptions, if there's more than
+    /// one. See set_num_threads.
+    #[serde(skip)]
+    pool: Option<Arc<rayon::ThreadPool>>,
+    /// See set_prefilter.
+    #[serde(skip)]
+    prefilter: Option<SharedFilter>,
+    /// See set_log_drops_every.
+    #[serde(skip)]
+    log_drops_every: Option<usize>,
+    /// See set_explain.
+    #[serde(skip)]
+    explain: Option<Explainer>,
+    /// The tracks of pending and ongoing pairs, with record_event_tracks.
+    #[serde(default)]
+    recorder: Recorder,
 }
 
-/// Generates an ADS-B Exchange URL for an interception.
+impl AircraftStateMap {
+    pub fn new() -> Self {
+        AircraftStateMap::default()
+    }
+
+    pub fn get(&self, hex: HexId) -> Option<&Ac> {
+        self.aircraft.get(&hex)
+    }
+
+    pub fn len(&self) -> usize {
+        self.aircraft.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aircraft.is_empty()
+    }
+
+    /// Returns the number of pairs that are close in the current snapshot,
+    /// including escorts.
+    pub fn num_pending(&self) -> usize {
+        self.pending.len() + self.pending_shadows.len()
+    }
+
+    /// Returns the number of pairs whose tracks are being recorded. See
+    /// DetectionParams::record_event_tracks.
+    pub fn num_recording(&self) -> usize {
+        self.recorder.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Ac> {
+        self.aircraft.values()
+    }
+
+    /// Returns how the aircraft in the last snapshot were classified by
+    /// detect_frame_interceptions.
+    pub fn latest_snapshot(&self) -> &ClassifiedSnapshot {
+        &self.snapshot
+    }
+
+    // Indexes where each aircraft was at now (see query_radius).
+    fn index_at(&self, now: DateTime<Utc>) -> RTreeIndex<(HexId, [f64; 2])> {
+        let horizon = Duration::seconds(QUERY_HORIZON_SECS);
+        RTreeIndex::new(
+            self.iter()
+                .filter_map(|ac| {
+                    let position = ac.estimated_position_at(now, horizon)?;
+                    Some(GeomWithData::new(position, (ac.hex, position)))
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the aircraft within radius_nm of center at now that match
+    /// filter, nearest first. Distances are great-circle distances, and work
+    /// across the antimeridian and near the poles. Each aircraft's position
+    /// is interpolated from its track, or dead-reckoned from its latest fix
+    /// for up to QUERY_HORIZON_SECS; aircraft that weren't being tracked at
+    /// now are left out.
+    pub fn query_radius(
+        &self,
+        center: [f64; 2],
+        radius_nm: f64,
+        now: DateTime<Utc>,
+        filter: impl Fn(&Ac) -> bool,
+    ) -> Vec<&Ac> {
+        let index = self.index_at(now);
+        let found = index.locate_within(center, radius_nm * 1852.0).copied();
+        self.nearest_first(center, found, &filter)
+    }
+
+    /// Returns the k aircraft nearest center at now, nearest first. Aircraft
+    /// positions are the same as for query_radius.
+    pub fn query_nearest(&self, center: [f64; 2], k: usize, now: DateTime<Utc>) -> Vec<&Ac> {
+        let index = self.index_at(now);
+        // Look further and further out until there are at least k. Nothing's
+        // further away than half way around the world.
+        let mut radius_m: f64 = 10.0 * 1852.0;
+        loop {
+            let found: Vec<_> = index.locate_within(center, radius_m).copied().collect();
+            if found.len() >= k || radius_m > EARTH_HALF_CIRCUMFERENCE_M {
+                let mut found = self.nearest_first(center, found.into_iter(), &|_| true);
+                found.truncate(k);
+                return found;
+            }
+            radius_m *= 4.0;
+        }
+    }
+
+    // Looks up aircraft that were found at positions, sorted by distance from
+    // center.
+    fn nearest_first(
+        &self,
+        center: [f64; 2],
+        found: impl Iterator<Item = (HexId, [f64; 2])>,
+        filter: &dyn Fn(&Ac) -> bool,
+    ) -> Vec<&Ac> {
+        let mut found: Vec<(f64, &Ac)> = found
+            .filter_map(|(hex, position)| {
+                let ac = self.get(hex).filter(|ac| filter(ac))?;
+                Some((haversine_distance_m(center, position), ac))
+            })
+            .collect();
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.hex.cmp(&b.1.hex)));
+        found.into_iter().map(|(_, ac)| ac).collect()
+    }
+
+    /// Inserts a new aircraft or updates an existing one, and returns its
+    /// state. Records missing the data we need are rejected with
+    /// Error::MissingField, and ones with an implausible position (see
+    /// Ac::is_plausible_fix) with Error::ImplausiblePosition, unless they're
+    /// for an existing aircraft and it could still be updated with some of
+    /// what they do have (a position, speed, or altitude), in which case its
+    /// state is returned. Records with an invalid hex ID are rejected with
+    /// Error::InvalidHexId.
+    pub fn update(
+        &mut self,
+        now: DateTime<Utc>,
+        aircraft: &Aircraft,
+        params: &DetectionParams,
+    ) -> Result<&Ac, Error> {
+        let entry = self.aircraft.entry(aircraft.hex.parse()?);
+        Updater {
+            now,
+            params,
+            #[cfg(feature = "aircraft_db")]
+            aircraft_db: self.aircraft_db.as_ref(),
+        }
+        .update(entry, aircraft)
+        .map(|(ac, _partial)| ac)
+    }
+
+    /// Sets the number of threads detect_frame_interceptions uses to update
+    /// the aircraft and to search for close pairs. The default is one. The
+    /// detections are the same however many there are.
+    pub fn set_num_threads(&mut self, num_threads: usize) -> Result<(), Error> {
+        self.pool = if num_threads > 1 {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .map_err(Error::ThreadPool)?;
+            Some(Arc::new(pool))
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// Sets a filter that classify_snapshot (and so detect_frame_interceptions)
+    /// checks each aircraft record against before doing anything else with
+    /// it, or None to use every record. Records that don't match are counted
+    /// in num_ac_filtered and otherwise ignored. Aircraft that are already
+    /// being tracked aren't forgotten when they stop matching; they expire
+    /// once they've gone stale_aircraft_mins without a matching record. See
+    /// the filter module for some built-in filters.
+    pub fn set_prefilter<F: AircraftFilter + 'static>(&mut self, filter: Option<F>) {
+        self.prefilter = filter.map(|f| SharedFilter(Arc::new(f)));
+    }
+
+    /// Logs snapshot_drops and drops at info level after every n snapshots,
+    /// or never if n is None or zero.
+    pub fn set_log_drops_every(&mut self, n: Option<usize>) {
+        self.log_drops_every = n.filter(|n| *n > 0);
+    }
+
+    /// Turns explain mode on or off. Turning it off discards the reports.
+    /// See the explain module.
+    pub fn set_explain(&mut self, explain: bool) {
+        if explain != self.explain.is_some() {
+            self.explain = explain.then(Explainer::default);
+        }
+    }
+
+    /// In explain mode, returns the checks from the latest snapshot.
+    pub fn candidate_checks(&self) -> &[CandidateCheck] {
+        self.explain.as_ref().map_or(&[], |e| e.latest.as_slice())
+    }
+
+    /// In explain mode, returns a report for each pair that's been checked,
+    /// ordered by interceptor and target.
+    pub fn candidate_reports(&self) -> Vec<CandidateReport> {
+        self.explain
+            .as_ref()
+            .map(Explainer::reports)
+            .unwrap_or_default()
+    }
+
+    /// Keeps an aircraft from being expired until it's unpinned.
+    pub fn pin(&mut self, hex: HexId) {
+        self.pinned.insert(hex);
+    }
+
+    pub fn unpin(&mut self, hex: HexId) {
+        self.pinned.remove(&hex);
+    }
+
+    /// Replaces the set of pinned aircraft.
+    pub fn set_pinned<I: IntoIterator<Item = HexId>>(&mut self, hexes: I) {
+        self.pinned = hexes.into_iter().collect();
+    }
+
+    /// Forgets aircraft that haven't been seen for max_age, unless they're
+    /// pinned.
+    pub fn expire(&mut self, now: DateTime<Utc>, max_age: Duration) {
+        let before = self.aircraft.len();
+        self.aircraft
+            .retain(|hex, ac| (now - ac.seen) < max_age || self.pinned.contains(hex));
+        self.num_ac_expired += before - self.aircraft.len();
+    }
+
+    /// Roughly how many bytes of memory the state takes up: the aircraft and
+    /// their histories, and the close pairs.
+    pub fn estimate_memory_bytes(&self) -> usize {
+        // HashMap entries have a byte of control data too.
+        let entry = |key: usize, value: usize| key + value + 1;
+        let aircraft: usize = self
+            .aircraft
+            .values()
+            .map(|ac| entry(size_of::<HexId>(), ac.estimate_memory_bytes()))
+            .sum();
+        aircraft
+            + self.pending.len()
+                * entry(
+                    size_of::<(HexId, HexId)>(),
+                    size_of::<PendingInterception>(),
+                )
+            + self.pending_shadows.len()
+                * entry(size_of::<(HexId, HexId)>(), size_of::<PendingShadow>())
+            + self.pinned.len() * entry(size_of::<HexId>(), 0)
+    }
+
+    /// Forgets the least recently seen aircraft until there are at most
+    /// max_tracked_aircraft, and estimate_memory_bytes is at most
+    /// memory_budget_mb, if either is set. Pinned aircraft aren't evicted,
+    /// even if that means staying over the limits.
+    pub fn evict(&mut self, params: &DetectionParams) {
+        let max_aircraft = params.max_tracked_aircraft.unwrap_or(usize::MAX);
+        let max_bytes = params
+            .memory_budget_mb
+            .map_or(usize::MAX, |mb| mb.saturating_mul(1024 * 1024));
+        let mut num_aircraft = self.aircraft.len();
+        let mut bytes = match params.memory_budget_mb {
+            Some(_) => self.estimate_memory_bytes(),
+            None => 0,
+        };
+        if num_aircraft <= max_aircraft && bytes <= max_bytes {
+            return;
+        }
+        let mut candidates: Vec<(DateTime<Utc>, HexId, usize)> = self
+            .aircraft
+            .values()
+            .filter(|ac| !self.pinned.contains(&ac.hex))
+            .map(|ac| {
+                let size = size_of::<HexId>() + ac.estimate_memory_bytes() + 1;
+                (ac.seen, ac.hex, size)
+            })
+            .collect();
+        candidates.sort_unstable();
+        let mut evicted = HashSet::new();
+        for (_, hex, size) in candidates {
+            if num_aircraft <= max_aircraft && bytes <= max_bytes {
+                break;
+            }
+            evicted.insert(hex);
+            num_aircraft -= 1;
+            bytes = bytes.saturating_sub(size);
+        }
+        self.aircraft.retain(|hex, _| !evicted.contains(hex));
+        self.num_ac_evicted += evicted.len();
+        debug!(
+            "Evicted {} aircraft, leaving {} (about {} MB)",
+            evicted.len(),
+            num_aircraft,
+            bytes / (1024 * 1024)
+        );
+    }
+
+    /// Saves the aircraft and the close pairs to a file. See the persist module.
+    pub fn save_state(&self, path: &str) -> Result<(), Error> {
+        persist::save(path, self)
+    }
+
+    /// Loads state saved by save_state, forgetting the aircraft that hadn't
+    /// been seen for max_age as of now (pinned or not), along with the
+    /// interceptions and close pairs they were part of. The aircraft
+    /// database and the number of threads aren't saved, so they need setting
+    /// again.
+    pub fn load_state(path: &str, now: DateTime<Utc>, max_age: Duration) -> Result<Self, Error> {
+        let mut state: AircraftStateMap = persist::load(path)?;
+        state.discard_stale(now, max_age);
+        Ok(state)
+    }
+
+    fn discard_stale(&mut self, now: DateTime<Utc>, max_age: Duration) {
+        let before = self.aircraft.len();
+        self.aircraft.retain(|_, ac| (now - ac.seen) < max_age);
+        self.num_ac_expired += before - self.aircraft.len();
+        let aircraft = &self.aircraft;
+        let tracked = |(interceptor, target): &(HexId, HexId)| {
+            aircraft.contains_key(interceptor) && aircraft.contains_key(target)
+        };
+        self.pending.retain(|pair, _| tracked(pair));
+        self.pending_shadows.retain(|pair, _| tracked(pair));
+        self.recorder.retain(tracked);
+    }
+
+    /// Keeps track of how long each pair has been close, and returns the ones
+    /// that have been close for long enough, with their proximity_start set.
+    /// Pairs that aren't close in this snapshot start over, unless one of
+    /// them is missing from it and it's still close when dead-reckoned
+    /// forward.
+    fn sustained_interceptions(
+        &mut self,
+        now: DateTime<Utc>,
+        close_pairs: Vec<Interception>,
+        params: &DetectionParams,
+    ) -> Vec<Interception> {
+        let mut pending = HashMap::new();
+        let mut sustained = vec![];
+        for mut interception in close_pairs {
+            let key = (interception.interceptor.hex, interception.target.hex);
+            let mut entry = self.pending.remove(&key).unwrap_or(PendingInterception {
+                since: now,
+                num_frames: 0,
+            });
+            entry.num_frames += 1;
+            let min_frames = match (interception.emergency, params.hijack_min_proximity_frames) {
+                (Some(EmergencySquawk::Hijack), Some(frames)) => frames,
+                _ => params.min_proximity_frames,
+            };
+            // The orbit or passes slow targets need take longer than this.
+            let long_enough = entry.num_frames >= min_frames
+                || now - entry.since >= Duration::seconds(params.min_proximity_secs)
+                || interception.target_mode == TargetMode::Slow;
+            let confidence = Confidence::score(
+                &interception.interceptor,
+                &interception.target,
+                interception.lateral_separation_ft / 3.28084,
+                (now - entry.since).num_seconds(),
+                now,
+                params,
+            )
+            .score;
+            interception.confidence = Some(confidence);
+            if long_enough && params.min_confidence.is_some_and(|min| confidence < min) {
+                debug!(
+                    interceptor = %key.0,
+                    target = %key.1,
+                    confidence,
+                    reason = %Rejection::LowConfidence,
+                    "Rejected pair"
+                );
+            } else if long_enough {
+                interception.proximity_start = entry.since;
+                sustained.push(interception);
+            } else {
+                debug!(
+                    interceptor = %key.0,
+                    target = %key.1,
+                    close_frames = entry.num_frames,
+                    close_since = %entry.since,
+                    reason = %Rejection::TooBrief,
+                    "Rejected pair"
+                );
+            }
+            pending.insert(key, entry);
+        }
+        let horizon = Duration::seconds(params.dead_reckoning_horizon_secs);
+        for (key, entry) in std::mem::take(&mut self.pending) {
+            if self.aircraft_in_gap(&key, now, horizon, params) {
+                pending.insert(key, entry);
+            }
+        }
+        self.pending = pending;
+        sustained
+    }
+
+    // Whether at least one of a pair of aircraft doesn't have a position from
+    // this snapshot, but dead-reckoning puts them within
+    // max_lateral_separation_m of each other.
+    fn aircraft_in_gap(
+        &self,
+        (interceptor_hex, target_hex): &(HexId, HexId),
+        now: DateTime<Utc>,
+        horizon: Duration,
+        params: &DetectionParams,
+    ) -> bool {
+        let (Some(interceptor), Some(target)) = (
+            self.aircraft.get(interceptor_hex),
+            self.aircraft.get(target_hex),
+        ) else {
+            return false;
+        };
+        if interceptor.cur_coords().0 >= now && target.cur_coords().0 >= now {
+            return false;
+        }
+        match (
+            interceptor.estimated_position_at(now, horizon),
+            target.estimated_position_at(now, horizon),
+        ) {
+            (Some(a), Some(b)) => haversine_distance_m(a, b) < params.max_lateral_separation_m,
+            _ => false,
+        }
+    }
+
+    // Scores each pair, fills in how long each pair that was close has been,
+    // marks the ones that haven't been for long enough (or scored too low),
+    // attaches the checks to the pairs that are now interceptions, and adds
+    // them to the reports.
+    fn record_checks(
+        &mut self,
+        mut checks: Vec<CandidateCheck>,
+        sustained: &mut [Interception],
+        params: &DetectionParams,
+    ) {
+        for check in &mut checks {
+            let key = (check.interceptor_hex, check.target_hex);
+            if let Some(pending) = self.pending.get(&key).filter(|_| check.passed()) {
+                check.close_frames = pending.num_frames;
+                check.close_since = Some(pending.since);
+            }
+            if let (Some(interceptor), Some(target)) =
+                (self.aircraft.get(&key.0), self.aircraft.get(&key.1))
+            {
+                let close_secs = check
+                    .close_since
+                    .map_or(0, |t| (check.time - t).num_seconds());
+                check.confidence = Some(Confidence::score(
+                    interceptor,
+                    target,
+                    check.lateral_separation_m,
+                    close_secs,
+                    check.time,
+                    params,
+                ));
+            }
+        }
+        for check in checks.iter_mut().filter(|c| c.passed()) {
+            let key = (check.interceptor_hex, check.target_hex);
+            let low_confidence = params
+                .min_confidence
+                .is_some_and(|min| check.confidence.as_ref().is_some_and(|c| c.score < min));
+            match sustained
+                .iter_mut()
+                .find(|i| (i.interceptor.hex, i.target.hex) == key)
+            {
+                Some(interception) => interception.criteria = Some(check.clone()),
+                None if low_confidence => check.failed.push(Rejection::LowConfidence),
+                None => check.failed.push(Rejection::TooBrief),
+            }
+        }
+        if let Some(explainer) = &mut self.explain {
+            explainer.record(checks);
+        }
+    }
+
+    // With record_event_tracks, starts recording the pairs that have just
+    // become pending, forgets the ones that are neither pending nor pinned
+    // any more, records the latest positions of the rest, and hands the new
+    // points to the pairs that are now interceptions.
+    fn record_tracks(&mut self, sustained: &mut [Interception], params: &DetectionParams) {
+        if !params.record_event_tracks {
+            return;
+        }
+        for (key, pending) in &self.pending {
+            self.recorder.start(*key, pending.since);
+        }
+        for (key, pending) in &self.pending_shadows {
+            self.recorder.start(*key, pending.since);
+        }
+        let (pending, pending_shadows, pinned) =
+            (&self.pending, &self.pending_shadows, &self.pinned);
+        self.recorder.retain(|key @ (interceptor, target)| {
+            pending.contains_key(key)
+                || pending_shadows.contains_key(key)
+                || (pinned.contains(interceptor) && pinned.contains(target))
+        });
+        let aircraft = &self.aircraft;
+        self.recorder
+            .record(|hex| aircraft.get(hex), params.event_track_max_points);
+        for interception in sustained {
+            let key = (interception.interceptor.hex, interception.target.hex);
+            interception.tracks = self.recorder.take(&key);
+        }
+    }
+
+    /// Keeps track of how long each escort has been flying in formation with
+    /// its target, and returns the ones that have been for long enough.
+    fn sustained_shadows(
+        &mut self,
+        now: DateTime<Utc>,
+        shadow_pairs: Vec<Interception>,
+        params: &DetectionParams,
+    ) -> Vec<Interception> {
+        let mut sustained = vec![];
+        for mut interception in shadow_pairs {
+            let key = (interception.interceptor.hex, interception.target.hex);
+            let entry = self.pending_shadows.entry(key).or_insert(PendingShadow {
+                since: now,
+                last: now,
+            });
+            entry.last = now;
+            if now - entry.since >= Duration::seconds(params.shadow_min_duration_secs) {
+                interception.proximity_start = entry.since;
+                sustained.push(interception);
+            }
+        }
+        let max_gap = Duration::seconds(params.shadow_max_gap_secs);
+        self.pending_shadows
+            .retain(|_, pending| now - pending.last <= max_gap);
+        sustained
+    }
+}
+
+/// Updates the aircraft state and returns every interceptor/target pair that
+/// is close in this snapshot and has been for at least min_proximity_secs or
+/// min_proximity_frames, along with escorts that have been shadowing a target
+/// for shadow_min_duration_secs. Use InterceptionTracker to merge these
+/// per-snapshot records into events.
+pub fn detect_frame_interceptions(
+    state: &mut AircraftStateMap,
+    response: &adsbx_json::v2::Response,
+    params: &DetectionParams,
+) -> Vec<Interception> {
+    // Everything logged while detecting is about this snapshot.
+    let _span = info_span!("snapshot", now = %response.now).entered();
+    // Reuse the last snapshot's buffers.
+    let mut snapshot = std::mem::take(&mut state.snapshot);
+    classify_snapshot(state, response, params, &mut snapshot);
+    debug!(
+        fast_movers = snapshot.fast_movers.len(),
+        escorts = snapshot.escorts.len(),
+        targets = snapshot.targets.len(),
+        "Classified snapshot"
+    );
+    let close_pairs = find_close_pairs(state, response.now, &snapshot, params);
+    state.snapshot = snapshot;
+    close_pairs
+}
+
+/// The aircraft in one snapshot that matter for detection, once they've
+/// been classified. See classify_snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct ClassifiedSnapshot {
+    /// Potential interceptors.
+    pub fast_movers: Vec<HexId>,
+    /// Aircraft that were fast movers recently, and might be flying in
+    /// formation with a target.
+    pub escorts: Vec<HexId>,
+    /// Potential targets, with their current positions.
+    pub targets: Vec<TargetLocation>,
+}
+
+// Maps f over items, on the thread pool if there is one, keeping the order.
+fn par_map<T: Send, R: Send>(
+    pool: Option<&rayon::ThreadPool>,
+    items: Vec<T>,
+    f: impl Fn(T) -> R + Sync + Send,
+) -> Vec<R> {
+    match pool {
+        Some(pool) => pool.install(|| items.into_par_iter().map(f).collect()),
+        None => items.into_iter().map(f).collect(),
+    }
+}
+
+// What classify_snapshot found out about one aircraft record.
+enum Classified {
+    // Outside the regions of interest.
+    Skipped,
+    Dropped(DropReason),
+    Aircraft {
+        coords: [f64; 2],
+        class: Class,
+        escort: bool,
+        // See Updater::update.
+        partial: bool,
+    },
+}
+
+/// The first half of detect_frame_interceptions: updates the aircraft state
+/// with a response, and classifies the aircraft in the response as fast
+/// movers, escorts, and targets (or none of those, which we don't care
+/// about). The snapshot is cleared first, so its buffers can be reused.
+///
+/// The records are divided up by the shard of the state map their aircraft
+/// is in, and each shard is updated by one thread, in the order the records
+/// are in the response. Then the results are put back in that order, so the
+/// snapshot is the same however many threads there are.
+pub fn classify_snapshot(
+    state: &mut AircraftStateMap,
+    response: &adsbx_json::v2::Response,
+    params: &DetectionParams,
+    snapshot: &mut ClassifiedSnapshot,
+) {
+    let now = response.now;
+    snapshot.fast_movers.clear();
+    snapshot.escorts.clear();
+    snapshot.targets.clear();
+    state.snapshot_drops = DropStats {
+        records: response.aircraft.len(),
+        ..Default::default()
+    };
+    let mut work: Vec<Vec<(usize, HexId)>> = vec![vec![]; state.aircraft.shards_mut().len()];
+    for (i, aircraft) in response.aircraft.iter().enumerate() {
+        if let (Some(regions), Some(lon), Some(lat)) = (&params.regions, aircraft.lon, aircraft.lat)
+        {
+            if !regions.contains([lon, lat], params.region_margin_nm * 1852.0) {
+                continue;
+            }
+        }
+        if let Some(SharedFilter(filter)) = &state.prefilter {
+            if !filter.matches(aircraft) {
+                state.num_ac_filtered += 1;
+                state.snapshot_drops.add(DropReason::FilteredOut);
+                continue;
+            }
+        }
+        match aircraft.hex.parse() {
+            Ok(hex) => work[shard_index(hex)].push((i, hex)),
+            Err(_) => {
+                state.num_ac_missing_data += 1;
+                state.snapshot_drops.add(DropReason::InvalidHexId);
+            }
+        }
+    }
+    let updater = Updater {
+        now,
+        params,
+        #[cfg(feature = "aircraft_db")]
+        aircraft_db: state.aircraft_db.as_ref(),
+    };
+    let classify_one = |shard: &mut HashMap<HexId, Ac>, aircraft: &Aircraft, hex: HexId| {
+        let (ac, partial) = match updater.update(shard.entry(hex), aircraft) {
+            Ok(updated) => updated,
+            Err(e) => {
+                return DropReason::from_error(&e).map_or(Classified::Skipped, Classified::Dropped)
+            }
+        };
+        if let Some(regions) = &params.regions {
+            if !regions.contains(ac.cur_coords().1, params.region_buffer_nm * 1852.0) {
+                return Classified::Skipped;
+            }
+        }
+        let class = classify(ac, now, params);
+        let escort = class != Class::Interceptor
+            && ac.can_intercept(params)
+            && params.detect_shadows
+            && ac.was_fast_mover(
+                now,
+                Duration::minutes(params.shadow_fast_window_mins),
+                params,
+            );
+        Classified::Aircraft {
+            coords: ac.cur_coords().1,
+            class,
+            escort,
+            partial,
+        }
+    };
+    let shards: Vec<_> = state.aircraft.shards_mut().iter_mut().zip(work).collect();
+    let results = par_map(state.pool.as_deref(), shards, |(shard, records)| {
+        records
+            .into_iter()
+            .map(|(i, hex)| (i, hex, classify_one(shard, &response.aircraft[i], hex)))
+            .collect::<Vec<_>>()
+    });
+    let mut results: Vec<_> = results.into_iter().flatten().collect();
+    results.sort_unstable_by_key(|(i, _, _)| *i);
+    for (_, hex, classified) in results {
+        match classified {
+            Classified::Dropped(reason) => {
+                state.num_ac_missing_data += 1;
+                state.snapshot_drops.add(reason);
+            }
+            Classified::Skipped => {}
+            Classified::Aircraft {
+                coords,
+                class,
+                escort,
+                partial,
+            } => {
+                if partial {
+                    state.snapshot_drops.partial += 1;
+                }
+                if escort {
+                    snapshot.escorts.push(hex);
+                }
+                match class {
+                    Class::Interceptor => snapshot.fast_movers.push(hex),
+                    Class::Target => snapshot.targets.push(TargetLocation::new(coords, hex)),
+                    _ => {}
+                }
+            }
+        }
+    }
+    state.num_snapshots += 1;
+    state.drops += state.snapshot_drops;
+    if let Some(n) = state.log_drops_every {
+        if state.num_snapshots.is_multiple_of(n) {
+            info!("{}; {} in total", state.snapshot_drops, state.drops);
+        }
+    }
+}
+
+/// The second half of detect_frame_interceptions: indexes the targets in
+/// an r-tree, looks up the ones close to each fast mover and escort, forgets
+/// stale aircraft, and returns the pairs that have been close for long
+/// enough. The lookups are spread over the thread pool, if there is one, but
+/// the pairs are returned in the same order either way.
+pub fn find_close_pairs(
+    state: &mut AircraftStateMap,
+    now: DateTime<Utc>,
+    snapshot: &ClassifiedSnapshot,
+    params: &DetectionParams,
+) -> Vec<Interception> {
+    if snapshot.fast_movers.is_empty() && snapshot.escorts.is_empty() {
+        state.expire(now, Duration::minutes(params.stale_aircraft_mins));
+        state.evict(params);
+        let mut sustained = state.sustained_interceptions(now, vec![], params);
+        sustained.extend(state.sustained_shadows(now, vec![], params));
+        state.record_tracks(&mut sustained, params);
+        return sustained;
+    }
+    // Index the potential targets, so each fast-mover and escort only looks at
+    // the ones within search_radius_nm (see the spatial module).
+    state.num_ac_indexed += snapshot.targets.len();
+    // The indexes take ownership of the points, so this copies the targets,
+    // but they're only a position and an ID.
+    let search_radius_m = params
+        .close_radius_m()
+        .max(params.search_radius_nm * 1852.0);
+    let spatial_index = params
+        .spatial_index
+        .build(snapshot.targets.clone(), search_radius_m);
+    let aircraft = &state.aircraft;
+    let lookup = |hex: &HexId| aircraft.get(hex);
+    let pool = state.pool.as_deref();
+    let explain = state.explain.is_some();
+
+    // For each fast mover, find any potential targets that are close enough.
+    let fast_movers: Vec<&Ac> = snapshot.fast_movers.iter().filter_map(lookup).collect();
+    let found = par_map(pool, fast_movers, |fast_mover| {
+        let mut interceptions = vec![];
+        let mut num_processed = 0;
+        // In explain mode, the nearest target and the close ones.
+        let mut nearest: Option<(f64, &Ac)> = None;
+        let mut close = vec![];
+        let fast_mover_coords = fast_mover.cur_coords().1;
+        let targets = spatial_index.locate_within(fast_mover_coords, search_radius_m);
+        for target in targets.filter_map(lookup) {
+            if explain {
+                let dist = haversine_distance_m(target.cur_coords().1, fast_mover_coords);
+                if nearest.is_none_or(|(d, _)| dist < d) {
+                    nearest = Some((dist, target));
+                }
+            }
+            let alt_diff = match fast_mover.vertical_separation_ft(target) {
+                Some(alt_diff) if alt_diff < params.vertical_gate_ft => alt_diff,
+                alt_diff => {
+                    let reason = match alt_diff {
+                        Some(_) => Rejection::TooFarVertically,
+                        None => Rejection::NoAltitude,
+                    };
+                    log_rejection(fast_mover, target, reason);
+                    continue;
+                }
+            };
+            let target_coords = target.cur_coords().1;
+            num_processed += 1;
+            let dist = haversine_distance_m(target_coords, fast_mover_coords);
+            match check_pair(fast_mover, target, dist, Some(alt_diff), now, params) {
+                Err(reason) => log_rejection(fast_mover, target, reason),
+                Ok(()) => {
+                    if explain {
+                        close.push(target);
+                    }
+                    interceptions.push(Interception {
+                        interceptor: fast_mover.clone(),
+                        target: target.clone(),
+                        lateral_separation_ft: dist * 3.28084,
+                        vertical_separation_ft: alt_diff,
+                        time: now,
+                        proximity_start: now,
+                        pattern: InterceptPattern::classify(fast_mover, target, params),
+                        target_mode: params.target.mode(target).unwrap_or_default(),
+                        interceptor_profile: fast_mover
+                            .interceptor_profile(now, params)
+                            .map(String::from),
+                        departure_airfield: None,
+                        confidence: None,
+                        emergency: target.emergency(),
+                        criteria: None,
+                        tracks: None,
+                    })
+                }
+            }
+        }
+        if let Some((_, target)) = nearest.filter(|(_, t)| !close.iter().any(|c| c.hex == t.hex)) {
+            close.push(target);
+        }
+        let checks: Vec<CandidateCheck> = close
+            .into_iter()
+            .map(|target| explain_pair(fast_mover, target, now, params))
+            .collect();
+        (interceptions, num_processed, checks)
+    });
+
+    // Then look for escorts flying in formation with a target.
+    let escorts: Vec<&Ac> = snapshot.escorts.iter().filter_map(lookup).collect();
+    let found_shadows = par_map(pool, escorts, |escort| {
+        let mut shadows = vec![];
+        let mut num_processed = 0;
+        let escort_coords = escort.cur_coords().1;
+        let targets = spatial_index.locate_within(escort_coords, search_radius_m);
+        for target in targets.filter_map(lookup) {
+            if target.hex == escort.hex
+                || escort
+                    .vertical_separation_ft(target)
+                    .is_none_or(|alt_diff| alt_diff >= params.vertical_gate_ft)
+            {
+                continue;
+            }
+            num_processed += 1;
+            if let Some(interception) = shadow(escort, target, now, params) {
+                shadows.push(interception);
+            }
+        }
+        (shadows, num_processed)
+    });
+
+    let mut interceptions = vec![];
+    let mut shadows = vec![];
+    let mut checks = vec![];
+    for (found, num_processed, found_checks) in found {
+        interceptions.extend(found);
+        state.num_ac_processed += num_processed;
+        checks.extend(found_checks);
+    }
+    for (found, num_processed) in found_shadows {
+        shadows.extend(found);
+        state.num_ac_processed += num_processed;
+    }
+    // Expire stale aircraft only now, since the search looks them up by hex.
+    state.expire(now, Duration::minutes(params.stale_aircraft_mins));
+    state.evict(params);
+    let mut sustained = state.sustained_interceptions(now, interceptions, params);
+    if explain {
+        state.record_checks(checks, &mut sustained, params);
+    }
+    sustained.extend(state.sustained_shadows(now, shadows, params));
+    state.record_tracks(&mut sustained, params);
+    sustained
+}
+
+/// Why a fast mover and a potential target near it weren't counted as close.
+/// They're logged at debug level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Rejection {
+    /// Further apart than max_lateral_separation_m.
+    TooFar,
+    /// Further apart than max_vertical_separation_ft (or vertical_gate_ft).
+    TooFarVertically,
+    /// One of them doesn't have an altitude.
+    NoAltitude,
+    /// Ground speeds differ by max_speed_difference_kts or more.
+    SpeedDifference,
+    /// The target's position is older than max_target_age_secs.
+    StaleTarget,
+    /// They weren't min_initial_separation_mi apart to begin with.
+    StartedClose,
+    /// The interceptor wasn't heading toward the target. See
+    /// convergence_cone_deg.
+    NotConverging,
+    /// The interceptor didn't slow down to the target's speed. See
+    /// require_speed_match.
+    SpeedNotMatched,
+    /// The interceptor didn't come down to the target. See require_descent.
+    NotDescending,
+    /// Neither has positions of at least min_quality_score.
+    LowQuality,
+    /// The target is slow (see TargetCriteria::slow_mode), and the
+    /// interceptor hasn't orbited it or passed it enough times.
+    NoSlowTargetPattern,
+    /// They were close, but not yet for min_proximity_secs or
+    /// min_proximity_frames.
+    TooBrief,
+    /// They were close for long enough, but scored under min_confidence.
+    LowConfidence,
+}
+
+impl Rejection {
+    /// Returns a short description, e.g. "too far".
+    pub fn description(self) -> &'static str {
+        match self {
+            Rejection::TooFar => "too far",
+            Rejection::TooFarVertically => "too far vertically",
+            Rejection::NoAltitude => "no altitude",
+            Rejection::SpeedDifference => "speed difference",
+            Rejection::StaleTarget => "stale target",
+            Rejection::StartedClose => "started close",
+            Rejection::NotConverging => "wrong heading",
+            Rejection::SpeedNotMatched => "speed not matched",
+            Rejection::NotDescending => "not descending",
+            Rejection::LowQuality => "quality gate",
+            Rejection::NoSlowTargetPattern => "no orbit or passes of slow target",
+            Rejection::TooBrief => "not close for long enough",
+            Rejection::LowConfidence => "low confidence",
+        }
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+// The criteria check_pair checks, in order.
+const CRITERIA: [Rejection; 11] = [
+    Rejection::TooFar,
+    Rejection::SpeedDifference,
+    Rejection::NoAltitude,
+    Rejection::TooFarVertically,
+    Rejection::StaleTarget,
+    Rejection::StartedClose,
+    Rejection::NotConverging,
+    Rejection::SpeedNotMatched,
+    Rejection::NotDescending,
+    Rejection::LowQuality,
+    Rejection::NoSlowTargetPattern,
+];
+
+// Checks whether a fast mover and a potential target that are dist meters and
+// alt_diff feet apart meet one of the criteria. TooBrief and LowConfidence
+// aren't checked here; see sustained_interceptions.
+pub(crate) fn meets_criterion(
+    criterion: Rejection,
+    fast_mover: &Ac,
+    target: &Ac,
+    dist: f64,
+    alt_diff: Option<i32>,
+    now: DateTime<Utc>,
+    params: &DetectionParams,
+) -> bool {
+    let slow = params.target.mode(target) == Some(TargetMode::Slow);
+    match criterion {
+        Rejection::TooFar if slow => dist < params.orbit_radius_nm * 1852.0,
+        Rejection::TooFar => {
+            dist < params.max_lateral_separation_m && dist <= params.search_radius_nm * 1852.0
+        }
+        Rejection::SpeedDifference | Rejection::NotConverging | Rejection::SpeedNotMatched
+            if slow =>
+        {
+            true
+        }
+        Rejection::SpeedDifference => {
+            (target.cur_speed - fast_mover.cur_speed).abs() < params.max_speed_difference_kts
+        }
+        Rejection::NoAltitude => alt_diff.is_some(),
+        Rejection::TooFarVertically => alt_diff
+            .is_some_and(|d| d < params.max_vertical_separation_ft && d < params.vertical_gate_ft),
+        Rejection::StaleTarget => now - target.seen < Duration::seconds(params.max_target_age_secs),
+        Rejection::StartedClose => {
+            started_far_apart(fast_mover, target, params.min_initial_separation_mi)
+        }
+        Rejection::NotConverging => is_converging(fast_mover, target, params),
+        Rejection::SpeedNotMatched => is_speed_matched(fast_mover, target, params),
+        Rejection::NotDescending => is_descending_toward(fast_mover, target, params),
+        Rejection::LowQuality => is_good_enough_quality(fast_mover, target, params),
+        Rejection::NoSlowTargetPattern => {
+            !slow || is_slow_target_pattern(fast_mover, target, params)
+        }
+        Rejection::TooBrief | Rejection::LowConfidence => true,
+    }
+}
+
+// Checks whether an interceptor has orbited a slow target, or passed close
+// to it at least slow_min_passes times.
+fn is_slow_target_pattern(fast_mover: &Ac, target: &Ac, params: &DetectionParams) -> bool {
+    let radius_m = params.orbit_radius_nm * 1852.0;
+    is_orbiting(fast_mover, target, radius_m, params.orbit_min_turn_deg)
+        || count_passes(fast_mover, target, radius_m) >= params.target.slow_min_passes
+}
+
+// The criteria pairs have to pass: all of them, or with min_confidence set,
+// the ones that aren't scored instead.
+fn pass_fail_criteria(params: &DetectionParams) -> impl Iterator<Item = Rejection> {
+    let scored = params.min_confidence.is_some();
+    CRITERIA
+        .into_iter()
+        .filter(move |c| !scored || !SCORED_CRITERIA.contains(c))
+}
+
+// Checks a pair against the criteria, in order of how cheap they are, and
+// returns the first one it fails.
+fn check_pair(
+    fast_mover: &Ac,
+    target: &Ac,
+    dist: f64,
+    alt_diff: Option<i32>,
+    now: DateTime<Utc>,
+    params: &DetectionParams,
+) -> Result<(), Rejection> {
+    match pass_fail_criteria(params)
+        .find(|c| !meets_criterion(*c, fast_mover, target, dist, alt_diff, now, params))
+    {
+        Some(reason) => Err(reason),
+        None => Ok(()),
+    }
+}
+
+// Checks a pair against every criterion, for explain mode.
+fn explain_pair(
+    fast_mover: &Ac,
+    target: &Ac,
+    now: DateTime<Utc>,
+    params: &DetectionParams,
+) -> CandidateCheck {
+    let (fast_mover_coords, target_coords) = (fast_mover.cur_coords().1, target.cur_coords().1);
+    let dist = haversine_distance_m(target_coords, fast_mover_coords);
+    let alt_diff = fast_mover.vertical_separation_ft(target);
+    CandidateCheck {
+        time: now,
+        interceptor_hex: fast_mover.hex,
+        target_hex: target.hex,
+        lateral_separation_m: dist,
+        vertical_separation_ft: alt_diff,
+        speed_difference_kts: (target.cur_speed - fast_mover.cur_speed).abs(),
+        heading_offset_deg: fast_mover
+            .heading_at(fast_mover.coords.len() - 1)
+            .filter(|_| fast_mover_coords != target_coords)
+            .map(|h| angle_difference(h, initial_bearing_deg(fast_mover_coords, target_coords))),
+        failed: pass_fail_criteria(params)
+            .filter(|c| !meets_criterion(*c, fast_mover, target, dist, alt_diff, now, params))
+            .collect(),
+        close_frames: 0,
+        close_since: None,
+        confidence: None,
+    }
+}
+
+fn log_rejection(fast_mover: &Ac, target: &Ac, reason: Rejection) {
+    debug!(
+        interceptor = %fast_mover.hex,
+        target = %target.hex,
+        reason = %reason,
+        "Rejected pair"
+    );
+}
+
+// Checks whether at least one of a pair has positions of at least
+// min_quality_score.
+fn is_good_enough_quality(a: &Ac, b: &Ac, params: &DetectionParams) -> bool {
+    a.quality_score().max(b.quality_score()) >= params.min_quality_score
+}
+
+// Checks whether an escort is flying in formation with a target: close, at
+// about the same speed, and in the same direction.
+fn shadow(
+    escort: &Ac,
+    target: &Ac,
+    now: DateTime<Utc>,
+    params: &DetectionParams,
+) -> Option<Interception> {
+    let escort_coords = escort.cur_coords().1;
+    let target_coords = target.cur_coords().1;
+    let dist = haversine_distance_m(target_coords, escort_coords);
+    let alt_diff = escort.vertical_separation_ft(target)?;
+    let escort_heading = escort.heading_at(escort.coords.len() - 1)?;
+    let target_heading = target.heading_at(target.coords.len() - 1)?;
+    if dist < params.max_lateral_separation_m
+        && alt_diff < params.max_vertical_separation_ft
+        && (now - target.seen) < Duration::seconds(params.max_target_age_secs)
+        && (target.cur_speed - escort.cur_speed).abs() < params.shadow_max_speed_difference_kts
+        && angle_difference(escort_heading, target_heading)
+            < params.shadow_max_heading_difference_deg
+        && is_good_enough_quality(escort, target, params)
+    {
+        Some(Interception {
+            interceptor: escort.clone(),
+            target: target.clone(),
+            time: now,
+            proximity_start: now,
+            lateral_separation_ft: dist * 3.28084,
+            vertical_separation_ft: alt_diff,
+            pattern: InterceptPattern::Shadow,
+            target_mode: params.target.mode(target).unwrap_or_default(),
+            interceptor_profile: escort.interceptor_profile(now, params).map(String::from),
+            departure_airfield: None,
+            confidence: None,
+            emergency: target.emergency(),
+            criteria: None,
+            tracks: None,
+        })
+    } else {
+        None
+    }
+}
+
+/// This is the state that is kept across ADS-B Exchange API responses.
+
+#[derive(Debug, Default)]
+pub struct State {
+    pub aircraft: AircraftStateMap,
+    pub params: DetectionParams,
+    /// The first detection of each interception.
+    pub interceptions: Vec<Interception>,
+    pub tracker: InterceptionTracker,
+    /// Finished interception events, merged from every detection.
+    pub events: Vec<InterceptionEvent>,
+    /// The events linked into escort chains, once finish has been called.
+    pub escort_chains: Vec<EscortChain>,
+    /// If set, interceptions that look like airport traffic are ignored. See
+    /// is_airport_traffic.
+    pub airports: Option<AirportDb>,
+    /// If set, interceptions that look like an interceptor taking off from
+    /// one of these are tagged or ignored. See departure_airfield and
+    /// load_military_airfields.
+    pub military_airfields: Option<AirportDb>,
+    /// Told when each event starts and ends (and, if they want, when it
+    /// gets closer). See Notifier.
+    pub notifiers: Vec<Box<dyn Notifier>>,
+    /// Updated by process_adsbx_response. Share it with serve_metrics, or
+    /// with LivePoller or DirectoryWatcher to count their errors too.
+    pub metrics: Arc<Metrics>,
+    /// If set, counts the events and fast movers in each lat/lon cell. See
+    /// the heatmap module.
+    pub heatmap: Option<HeatmapGrid>,
+}
+
+impl State {
+    // Tells the notifiers about an event, logging any errors.
+    fn notify(&self, kind: DetectionEventKind, event: &InterceptionEvent) {
+        let is_update = kind == DetectionEventKind::InterceptionUpdated;
+        for notifier in self
+            .notifiers
+            .iter()
+            .filter(|n| !is_update || n.wants_updates())
+        {
+            if let Err(e) = notifier.notify(kind, event) {
+                warn!("Couldn't send notification: {}", e);
+            }
+        }
+    }
+
+    // Tells the notifiers about a detection, logging any errors.
+    fn notify_detection(&self, interception: &Interception) {
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.detection(interception) {
+                warn!("Couldn't send notification: {}", e);
+            }
+        }
+    }
+
+    // Adds finished events, and returns an InterceptionEnded event for each.
+    fn end_events(&mut self, events: Vec<InterceptionEvent>) -> Vec<DetectionEvent> {
+        let mut ended = vec![];
+        for event in &events {
+            info!(
+                interceptor = %event.interceptor_hex,
+                target = %event.target_hex,
+                end = %event.end,
+                cpa_lateral_ft = event.cpa_lateral_ft.round(),
+                cpa_vertical_ft = event.cpa_vertical_ft.round(),
+                "Interception ended"
+            );
+            Metrics::inc(&self.metrics.interceptions_ended);
+            self.notify(DetectionEventKind::InterceptionEnded, event);
+            if let Some(heatmap) = &mut self.heatmap {
+                heatmap.record_event(event);
+            }
+            ended.push(DetectionEvent::ended(event));
+        }
+        self.events.extend(events);
+        ended
+    }
+
+    /// Finishes any interception events that are still open, and links them
+    /// into escort chains. Call this after the last response. Returns an
+    /// InterceptionEnded event for each of the events it finished.
+    pub fn finish(&mut self) -> Vec<DetectionEvent> {
+        let events = self.tracker.finish();
+        let ended = self.end_events(events);
+        self.escort_chains = link_escort_chains(
+            &mut self.events,
+            Duration::minutes(self.params.escort_handoff_gap_mins),
+        );
+        ended
+    }
+
+    /// Saves the aircraft and the open interception events to a file, so a
+    /// restarted run can carry on with load_state. The finished events,
+    /// notifiers, etc. aren't saved.
+    pub fn save_state(&self, path: &str) -> Result<(), Error> {
+        persist::save(path, &(&self.aircraft, &self.tracker))
+    }
+
+    /// Loads the aircraft and open events saved by save_state, dropping the
+    /// aircraft that hadn't been seen for stale_aircraft_mins as of now, and
+    /// the events that would have ended by now. Everything else, like the
+    /// params, the aircraft database, the number of threads, the prefilter,
+    /// how often drops are logged, and explain mode, is left as it is.
+    pub fn load_state(&mut self, path: &str, now: DateTime<Utc>) -> Result<(), Error> {
+        let (mut aircraft, mut tracker): (AircraftStateMap, InterceptionTracker) =
+            persist::load(path)?;
+        aircraft.discard_stale(now, Duration::minutes(self.params.stale_aircraft_mins));
+        tracker.discard_stale(now);
+        self.restore(aircraft, tracker);
+        Ok(())
+    }
+
+    // Replaces the aircraft and tracker with loaded ones, keeping the
+    // settings that aren't saved.
+    fn restore(&mut self, mut aircraft: AircraftStateMap, tracker: InterceptionTracker) {
+        #[cfg(feature = "aircraft_db")]
+        {
+            aircraft.aircraft_db = self.aircraft.aircraft_db.take();
+        }
+        aircraft.pool = self.aircraft.pool.take();
+        aircraft.prefilter = self.aircraft.prefilter.take();
+        aircraft.log_drops_every = self.aircraft.log_drops_every;
+        aircraft.explain = self.aircraft.explain.take();
+        aircraft.set_pinned(tracker.open_aircraft());
+        self.aircraft = aircraft;
+        self.tracker = tracker;
+    }
+
+    /// Encodes what save_state saves, along with the interceptions and
+    /// finished events, for a pipeline checkpoint. See CheckpointState.
+    pub(crate) fn checkpoint_bytes(&self) -> Result<Vec<u8>, Error> {
+        persist::encode(
+            "checkpoint",
+            &(
+                &self.aircraft,
+                &self.tracker,
+                &self.interceptions,
+                &self.events,
+            ),
+        )
+    }
+
+    /// Restores a checkpoint encoded by checkpoint_bytes. Like load_state,
+    /// it leaves the settings that aren't saved as they are.
+    pub(crate) fn restore_checkpoint_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let (aircraft, tracker, interceptions, events) = persist::decode("checkpoint", data)?;
+        self.restore(aircraft, tracker);
+        self.interceptions = interceptions;
+        self.events = events;
+        Ok(())
+    }
+
+    /// Returns the finished events, grouped by target if group_interceptors
+    /// is set.
+    pub fn groups(&self) -> Vec<InterceptionGroup> {
+        let window = self
+            .params
+            .group_interceptors
+            .then(|| Duration::seconds(self.params.group_window_secs));
+        group_events(&self.events, window)
+    }
+}
+
+/// Runs the detector on a response, and collects the interceptions and
+/// interception events in the state. This has the signature
+/// for_each_adsbx_json and AdsbxPipeline expect.
+pub fn process_adsbx_response(
+    state: &mut State,
+    response: adsbx_json::v2::Response,
+    bar: &ProgressBar,
+) -> Result<(), Error> {
+    let events = detect_interception_events(state, &response)?;
+    if events
+        .iter()
+        .any(|e| e.event == DetectionEventKind::InterceptionStarted)
+    {
+        bar.set_message(format!(
+            "[ {} interceptions found ]",
+            state.interceptions.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Like process_adsbx_response, but returns an event when an interception
+/// starts, when the separation shrinks, and when it ends. The events for the
+/// interceptions still going after the last response come from
+/// State::finish.
+pub fn detect_interception_events(
+    state: &mut State,
+    response: &adsbx_json::v2::Response,
+) -> Result<Vec<DetectionEvent>, Error> {
+    let now = response.now;
+    // Load the aircraft database now, so that if it can't be, the error is
+    // reported.
+    #[cfg(feature = "aircraft_db")]
+    if let Some(db) = &state.aircraft.aircraft_db {
+        db.get()?;
+    }
+    let mut frame = detect_frame_interceptions(&mut state.aircraft, response, &state.params);
+    if let Some(airports) = &state.airports {
+        frame.retain(|i| !is_airport_traffic(airports, i, &state.params));
+    }
+    if let Some(airfields) = &state.military_airfields {
+        for interception in &mut frame {
+            interception.departure_airfield =
+                departure_airfield(airfields, interception, &state.params)
+                    .map(|airfield| airfield.ident.clone());
+        }
+        if state.params.suppress_departures {
+            frame.retain(|i| i.departure_airfield.is_none());
+        }
+    }
+    state
+        .tracker
+        .set_gap(Duration::minutes(state.params.duplicate_window_mins));
+    let closed = state.tracker.close_stale(now);
+    let mut events = state.end_events(closed);
+    for interception in frame {
+        let (interceptor_hex, target_hex) = (interception.interceptor.hex, interception.target.hex);
+        let previous_min = state
+            .tracker
+            .get(interceptor_hex, target_hex)
+            .map(|e| e.min_lateral_separation_ft);
+        let ended = state.tracker.add(&interception);
+        let kind = match (previous_min, &ended) {
+            (None, _) | (_, Some(_)) => Some(DetectionEventKind::InterceptionStarted),
+            (Some(min), None) if interception.lateral_separation_ft < min => {
+                Some(DetectionEventKind::InterceptionUpdated)
+            }
+            _ => None,
+        };
+        if let Some(event) = ended {
+            events.extend(state.end_events(vec![event]));
+        }
+        if let (Some(kind), Some(event)) = (kind, state.tracker.get(interceptor_hex, target_hex)) {
+            if kind == DetectionEventKind::InterceptionStarted {
+                info!(
+                    interceptor = %event.interceptor_hex,
+                    target = %event.target_hex,
+                    start = %event.start,
+                    "Interception started"
+                );
+                Metrics::inc(&state.metrics.interceptions_started);
+            }
+            state.notify(kind, event);
+            events.push(DetectionEvent::new(kind, &interception));
+        }
+        state.notify_detection(&interception);
+        if kind == Some(DetectionEventKind::InterceptionStarted) {
+            state.interceptions.push(interception);
+        }
+    }
+    state.aircraft.set_pinned(state.tracker.open_aircraft());
+    state.metrics.record_snapshot(state, now);
+    if let Some(heatmap) = &mut state.heatmap {
+        heatmap.record_snapshot(&state.aircraft, now);
+    }
+    Ok(events)
+}
+
+/// Like detect_interception_events, but returns just the interceptions that
+/// started.
+pub fn detect_interceptions(
+    state: &mut State,
+    response: &adsbx_json::v2::Response,
+) -> Result<Vec<Interception>, Error> {
+    let before = state.interceptions.len();
+    detect_interception_events(state, response)?;
+    Ok(state.interceptions[before..].to_vec())
+}
+
+// Function that checks whether the two aircraft were more than min_miles
+// apart in the past.
+//
+// First we look at the oldest position for each aircraft, then find the most
+// recent timestamp on those positions to be our time of comparison. Then we
+// find the timestamped position for the other aircraft that is closest in time
+// to the time of comparison.
+fn started_far_apart(fast_mover: &Ac, target: &Ac, min_miles: f64) -> bool {
+    let oldest_fm_ts = fast_mover.oldest_coords().0;
+    let oldest_t_ts = target.coords[0].0;
+    let comparison_ts = max(oldest_fm_ts, oldest_t_ts);
+    let mut temp_fast_mover_coords = Vec::from(fast_mover.coords.clone());
+    let mut temp_target_coords = Vec::from(target.coords.clone());
+    temp_fast_mover_coords.sort_by_key(|c| (c.0 - comparison_ts).num_seconds().abs());
+    temp_target_coords.sort_by_key(|c| (c.0 - comparison_ts).num_seconds().abs());
+    let dist = haversine_distance_m(temp_fast_mover_coords[0].1, temp_target_coords[0].1);
+    dist > min_miles * 1609.34
+}
+
+/// Returns the difference between two headings, from 0 to 180 degrees.
+fn angle_difference(a: f64, b: f64) -> f64 {
+    let d = (a - b).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+// Checks whether the interceptor was heading toward the target for its last
+// convergence_frames positions. Positions where the aircraft were already
+// within max_lateral_separation_m are skipped, since the bearing between them
+// doesn't mean much at that point.
+fn is_converging(fast_mover: &Ac, target: &Ac, params: &DetectionParams) -> bool {
+    if params.convergence_frames == 0 {
+        return true;
+    }
+    let mut num_frames = 0;
+    for i in (0..fast_mover.coords.len()).rev() {
+        let (time, fast_mover_coords) = fast_mover.coords[i];
+        // Interpolated, so that a gap in the target's track doesn't compare
+        // the interceptor to where the target was a while ago.
+        let target_coords = target.position_at(time).unwrap_or_else(|| {
+            target
+                .coords
+                .iter()
+                .min_by_key(|(t, _)| (*t - time).num_milliseconds().abs())
+                .unwrap()
+                .1
+        });
+        let dist = haversine_distance_m(fast_mover_coords, target_coords);
+        if dist < params.max_lateral_separation_m {
+            continue;
+        }
+        let heading = match fast_mover.heading_at(i) {
+            Some(heading) => heading,
+            None => return false,
+        };
+        if angle_difference(
+            heading,
+            initial_bearing_deg(fast_mover_coords, target_coords),
+        ) > params.convergence_cone_deg
+        {
+            return false;
+        }
+        num_frames += 1;
+        if num_frames >= params.convergence_frames {
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the speed of one aircraft relative to the other, in knots. If we
+/// don't know which way they're going, it's just the difference in ground
+/// speed.
+pub(crate) fn relative_speed_kts(a: &Ac, b: &Ac) -> f64 {
+    let headings = (
+        a.heading_at(a.coords.len() - 1),
+        b.heading_at(b.coords.len() - 1),
+    );
+    match headings {
+        (Some(a_heading), Some(b_heading)) => {
+            let (a_heading, b_heading) = (a_heading.to_radians(), b_heading.to_radians());
+            let dx = a.cur_speed * a_heading.sin() - b.cur_speed * b_heading.sin();
+            let dy = a.cur_speed * a_heading.cos() - b.cur_speed * b_heading.cos();
+            dx.hypot(dy)
+        }
+        _ => (a.cur_speed - b.cur_speed).abs(),
+    }
+}
+
+// Checks whether the interceptor has slowed down to fly alongside the target:
+// either it was faster than speed_match_ratio times the target's speed and now
+// isn't, or their velocities are within max_relative_speed_kts.
+fn is_speed_matched(fast_mover: &Ac, target: &Ac, params: &DetectionParams) -> bool {
+    if !params.require_speed_match {
+        return true;
+    }
+    let limit = params.speed_match_ratio * target.cur_speed;
+    let slowed_down = fast_mover.cur_speed <= limit && fast_mover.speeds.iter().any(|s| *s > limit);
+    slowed_down || relative_speed_kts(fast_mover, target) <= params.max_relative_speed_kts
+}
+
+// Checks whether the interceptor came down to the target's altitude, if
+// params.require_descent is set.
+fn is_descending_toward(fast_mover: &Ac, target: &Ac, params: &DetectionParams) -> bool {
+    if !params.require_descent {
+        return true;
+    }
+    let window = Duration::seconds(params.descent_window_secs);
+    let started_above = match (
+        fast_mover.alts.get(fast_mover.window_start(window)),
+        target.known_alt(),
+    ) {
+        (Some(alt), Some(target_alt)) => alt - target_alt > params.max_vertical_separation_ft,
+        _ => false,
+    };
+    match fast_mover.vertical_rate_fpm(window) {
+        Some(rate) => started_above && rate <= -params.min_descent_rate_fpm,
+        None => false,
+    }
+}
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// Generates an ADS-B Exchange URL for an interception.
 
 pub fn url(fast_mover: &Ac, target: &Ac, now: DateTime<Utc>) -> String {
     let mut url = String::new();
     url.push_str("https://globe.adsbexchange.com/?icao=");
-    url.push_str(&fast_mover.hex);
+    url.push_str(&fast_mover.hex.to_string());
     url.push(',');
-    url.push_str(&target.hex);
+    url.push_str(&target.hex.to_string());
     url.push_str("&showTrace=");
     url.push_str(&now.format("%Y-%m-%d").to_string());
     let fast_mover_coords = fast_mover.coords.iter().last().unwrap().1;
@@ -353,3 +3287,2066 @@ pub fn url(fast_mover: &Ac, target: &Ac, now: DateTime<Utc>) -> String {
     url.push_str(format!("&endTime={}", end_time.format("%H:%M:%S")).as_str());
     url
 }
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adsbx_json::v2::Response;
+    use serde_json::{json, Value};
+    use tracing_test::traced_test;
+
+    fn id(hex: &str) -> HexId {
+        hex.parse().unwrap()
+    }
+
+    fn aircraft(hex: &str, lat: f64, lon: f64, gs: f64) -> Value {
+        json!({
+            "hex": hex, "type": "adsb_icao", "messages": 100, "rssi": -20.0, "seen": 0.0,
+            "seen_pos": 0.0, "lat": lat, "lon": lon, "gs": gs,
+            "alt_baro": 10000, "alt_geom": 10000, "flight": format!("{:<8}", hex.to_uppercase()),
+        })
+    }
+
+    fn response(secs: i64, aircraft: Vec<Value>) -> Response {
+        let ms = 1614109133000 + secs * 1000;
+        serde_json::from_value(json!({
+            "now": ms, "ctime": ms, "ptime": 10, "total": aircraft.len(), "ac": aircraft,
+        }))
+        .unwrap()
+    }
+
+    // detect_interceptions with params.
+    fn detect(
+        state: &mut State,
+        response: &Response,
+        params: &DetectionParams,
+    ) -> Vec<Interception> {
+        state.params = params.clone();
+        detect_interceptions(state, response).unwrap()
+    }
+
+    // The interceptor's speed: fast on the way in, then slowing to fly
+    // alongside the target.
+    fn intercept_speed(i: i64) -> f64 {
+        if i < 15 {
+            450.0
+        } else {
+            380.0
+        }
+    }
+
+    // An interceptor flies at a target from about 20 miles away, and ends up
+    // right next to it.
+    fn intercept_track() -> Vec<Response> {
+        (0..20)
+            .map(|i| {
+                let lat = 34.0 + 0.3 * (15 - i.min(15)) as f64 / 15.0;
+                response(
+                    i * 15,
+                    vec![
+                        aircraft("ae1234", lat, -118.0, intercept_speed(i)),
+                        aircraft("a12345", 34.0, -118.0, 320.0),
+                    ],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_interceptions() {
+        let mut state = State::default();
+        let params = DetectionParams::default();
+        let mut found = vec![];
+        for response in intercept_track() {
+            found.extend(detect(&mut state, &response, &params));
+        }
+        // Reported once, not again for each later snapshot.
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].interceptor.hex, "ae1234");
+        assert_eq!(found[0].target.hex, "a12345");
+        // They're close from the 15th snapshot on, and it takes five of them.
+        assert_eq!(found[0].time, response(19 * 15, vec![]).now);
+        assert_eq!(found[0].proximity_start, response(15 * 15, vec![]).now);
+        assert_eq!(found[0].vertical_separation_ft, 0);
+        assert_eq!(found[0].pattern, InterceptPattern::Shadow);
+        assert_eq!(found[0].interceptor.callsign.as_deref(), Some("AE1234"));
+        assert_eq!(
+            found[0].interceptor.country.as_deref(),
+            Some("United States")
+        );
+        assert_eq!(state.aircraft.len(), 2);
+    }
+
+    #[test]
+    fn test_detect_interception_events() {
+        // The interceptor closes to about 1000 ft and stays there for a
+        // minute, then moves right alongside for a minute, and leaves.
+        let responses = (0..70).map(|i| {
+            let lat = match i {
+                0..=14 => 34.0 + 0.3 * (15 - i) as f64 / 15.0,
+                15..=19 => 34.003,
+                _ => 34.0,
+            };
+            let mut ac = vec![aircraft("a12345", 34.0, -118.0, 320.0)];
+            if i < 25 {
+                ac.push(aircraft("ae1234", lat, -118.0, intercept_speed(i)));
+            }
+            response(i * 15, ac)
+        });
+        let start = response(0, vec![]).now;
+        let mut state = State::default();
+        let mut events = vec![];
+        for response in responses {
+            for event in detect_interception_events(&mut state, &response).unwrap() {
+                events.push((
+                    event.event,
+                    (event.time - start).num_seconds(),
+                    (response.now - start).num_seconds(),
+                ));
+            }
+        }
+        assert_eq!(
+            events,
+            vec![
+                (DetectionEventKind::InterceptionStarted, 285, 285),
+                (DetectionEventKind::InterceptionUpdated, 300, 300),
+                // More than ten minutes after they were last close.
+                (DetectionEventKind::InterceptionEnded, 360, 975),
+            ]
+        );
+        assert!(state.finish().is_empty());
+    }
+
+    #[test]
+    fn test_position_at() {
+        let params = DetectionParams::default();
+        let r = intercept_track();
+        let mut state = State::default();
+        for r in &r[..3] {
+            detect(&mut state, r, &params);
+        }
+        let ac = state.aircraft.aircraft.get(&id("ae1234")).unwrap();
+        // Halfway between the first two fixes.
+        let [lon, lat] = ac
+            .position_at(r[0].now + Duration::milliseconds(7500))
+            .unwrap();
+        assert!((lon + 118.0).abs() < 1e-9);
+        assert!((lat - 34.29).abs() < 1e-9, "{}", lat);
+        assert_eq!(ac.position_at(r[2].now), Some([-118.0, 34.26]));
+        assert_eq!(ac.alt_at(r[1].now), Some(10000.0));
+        assert_eq!(ac.position_at(r[0].now - Duration::seconds(1)), None);
+        assert_eq!(ac.position_at(r[3].now), None);
+        // Dead-reckoning south at 450 knots for 16 seconds is 2 nm.
+        let later = r[2].now + Duration::seconds(16);
+        let [_, lat] = ac
+            .estimated_position_at(later, Duration::seconds(60))
+            .unwrap();
+        assert!((lat - (34.26 - 2.0 / 60.0)).abs() < 1e-6, "{}", lat);
+        assert_eq!(ac.estimated_position_at(later, Duration::seconds(10)), None);
+    }
+
+    #[test]
+    fn test_distance_traveled() {
+        let mut state = AircraftStateMap::new();
+        let update = |state: &mut AircraftStateMap, secs, lat, gs, params: &DetectionParams| {
+            let r = response(secs, vec![aircraft("a12345", lat, -118.0, gs)]);
+            state.update(r.now, &r.aircraft[0], params).ok();
+            r.now
+        };
+        let params = DetectionParams::default();
+        // 1.2 nm north every 10 seconds is 432 knots, with a bad position in
+        // the middle.
+        let mut now = response(0, vec![]).now;
+        for i in 0..12 {
+            now = update(&mut state, i * 10, 34.0 + 0.02 * i as f64, 450.0, &params);
+            if i == 5 {
+                update(&mut state, i * 10 + 5, 39.0, 450.0, &params);
+            }
+        }
+        let ac = state.aircraft.get(&id("a12345")).unwrap();
+        assert_eq!(ac.rejected_fixes, 1);
+        let nm_per_deg = EARTH_RADIUS_M.to_radians() / 1852.0;
+        let dist = ac.distance_traveled_nm(Duration::seconds(60), now).unwrap();
+        assert!((dist - 0.12 * nm_per_deg).abs() < 1e-6, "{}", dist);
+        // The start of the window is interpolated between positions.
+        let dist = ac.distance_traveled_nm(Duration::seconds(45), now).unwrap();
+        assert!((dist - 0.09 * nm_per_deg).abs() < 1e-6, "{}", dist);
+        let avg = ac.average_speed_kts(Duration::seconds(60), now).unwrap();
+        assert!((avg - 432.0).abs() < 1.0, "{}", avg);
+        assert_eq!(ac.distance_traveled_nm(Duration::seconds(120), now), None);
+        assert_eq!(
+            ac.average_speed_kts(Duration::seconds(60), now + Duration::seconds(1)),
+            None
+        );
+
+        // Reporting 450 knots while really doing 43 is fast, unless the
+        // speed is cross-checked.
+        let fast_status = |params: &DetectionParams| {
+            let mut state = AircraftStateMap::new();
+            for i in 0..20 {
+                update(&mut state, i * 10, 34.0 + 0.002 * i as f64, 450.0, params);
+            }
+            state.aircraft.get(&id("a12345")).unwrap().fast.fast_status
+        };
+        assert!(fast_status(&params));
+        assert!(!fast_status(&DetectionParams {
+            speed_cross_check_secs: 60,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_heading_change() {
+        let params = DetectionParams::default();
+        let mut state = AircraftStateMap::new();
+        // Weaving either side of north.
+        for (i, track) in [350.0, 10.0, 350.0, 10.0, 0.0].into_iter().enumerate() {
+            let mut a = aircraft("a12345", 34.0 + 0.01 * i as f64, -118.0, 250.0);
+            a["track"] = json!(track);
+            let r = response(i as i64 * 10, vec![a]);
+            state.update(r.now, &r.aircraft[0], &params).unwrap();
+        }
+        let ac = state.aircraft.get(&id("a12345")).unwrap();
+        let window = Duration::seconds(60);
+        let turn = ac.total_heading_change_deg(window).unwrap();
+        assert!((turn - 10.0).abs() < 1e-9, "{}", turn);
+        let rate = ac.turn_rate_deg_per_sec(window).unwrap();
+        assert!((rate - 0.25).abs() < 1e-9, "{}", rate);
+        // Just the last turn, from 10° left to 0°.
+        let turn = ac.total_heading_change_deg(Duration::seconds(10)).unwrap();
+        assert!((turn + 10.0).abs() < 1e-9, "{}", turn);
+        assert_eq!(ac.total_heading_change_deg(Duration::zero()), None);
+
+        // Without tracks, the headings come from the positions: north, east,
+        // south, west, and north again is a full turn to the right.
+        let mut state = AircraftStateMap::new();
+        let square = [
+            [0.0, 0.0],
+            [0.0, 0.01],
+            [0.01, 0.01],
+            [0.01, 0.0],
+            [0.0, 0.0],
+            [0.0, 0.01],
+        ];
+        for (i, [lon, lat]) in square.into_iter().enumerate() {
+            let r = response(i as i64 * 10, vec![aircraft("a12345", lat, lon, 250.0)]);
+            state.update(r.now, &r.aircraft[0], &params).unwrap();
+        }
+        let ac = state.aircraft.get(&id("a12345")).unwrap();
+        let turn = ac.total_heading_change_deg(window).unwrap();
+        assert!((turn - 360.0).abs() < 1e-3, "{}", turn);
+        let rate = ac.turn_rate_deg_per_sec(window).unwrap();
+        assert!((rate - 360.0 / 50.0).abs() < 1e-3, "{}", rate);
+    }
+
+    #[test]
+    fn test_min_quality_score() {
+        let params = DetectionParams {
+            min_quality_score: 0.8,
+            ..Default::default()
+        };
+        let found = |interceptor_source: &str, target_source: &str| {
+            let mut state = State::default();
+            let mut found = vec![];
+            for mut r in intercept_track() {
+                r.aircraft[0].message_type =
+                    serde_json::from_value(json!(interceptor_source)).unwrap();
+                r.aircraft[1].message_type = serde_json::from_value(json!(target_source)).unwrap();
+                found.extend(detect(&mut state, &r, &params));
+            }
+            found
+        };
+        // One of them having ADS-B positions is enough.
+        let detected = found("adsb_icao", "mlat");
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].interceptor.source.as_deref(), Some("adsb_icao"));
+        assert_eq!(detected[0].target.source.as_deref(), Some("mlat"));
+        assert_eq!(found("mlat", "mlat").len(), 0);
+    }
+
+    #[test]
+    fn test_teleports() {
+        let params = DetectionParams::default();
+        let mut state = AircraftStateMap::new();
+        // Whether the position was kept. The speed and altitude always are.
+        let update = |state: &mut AircraftStateMap, secs, lat, gs| {
+            let r = response(secs, vec![aircraft("a12345", lat, -118.0, gs)]);
+            let ac = state.update(r.now, &r.aircraft[0], &params).unwrap();
+            ac.rejected_fix.is_none()
+        };
+        assert!(update(&mut state, 0, 34.0, 450.0));
+        assert!(update(&mut state, 5, 34.01, 450.0));
+        // 300 nm in 5 seconds.
+        assert!(!update(&mut state, 10, 39.0, 450.0));
+        // And back again. It was a bad position, not a relocation.
+        assert!(update(&mut state, 15, 34.02, 450.0));
+        let ac = state.aircraft.get(&id("a12345")).unwrap();
+        assert_eq!(ac.rejected_fixes, 1);
+        assert_eq!(ac.coords.len(), 3);
+        assert_eq!(ac.cur_coords().1, [-118.0, 34.02]);
+        // Two positions that agree with each other are a relocation.
+        assert!(!update(&mut state, 20, 39.0, 450.0));
+        assert!(update(&mut state, 25, 39.01, 450.0));
+        assert!(update(&mut state, 30, 39.02, 450.0));
+        let ac = state.aircraft.get(&id("a12345")).unwrap();
+        assert_eq!(ac.rejected_fixes, 2);
+        assert_eq!(ac.cur_coords().1, [-118.0, 39.02]);
+        assert_eq!(ac.coords.len(), 5);
+        // 3 nm in 15 seconds is 720 knots, which is plausible for a fighter
+        // but not for something reporting 100 knots.
+        assert!(update(&mut state, 45, 39.07, 450.0));
+        assert!(!update(&mut state, 60, 39.12, 100.0));
+        // Small jumps are always allowed.
+        assert!(update(&mut state, 65, 39.08, 100.0));
+        let ac = state.aircraft.get(&id("a12345")).unwrap();
+        assert_eq!(ac.rejected_fixes, 3);
+    }
+
+    #[test]
+    fn test_coverage_gap() {
+        // Both aircraft drop out of coverage for two snapshots just after they
+        // get close.
+        let detect = |horizon_secs| {
+            let params = DetectionParams {
+                dead_reckoning_horizon_secs: horizon_secs,
+                ..Default::default()
+            };
+            let mut state = State::default();
+            let mut found = vec![];
+            for i in 0..24 {
+                if i == 16 || i == 17 {
+                    found.extend(detect(&mut state, &response(i * 15, vec![]), &params));
+                    continue;
+                }
+                let lat = 34.0 + 0.3 * (15 - i.min(15)) as f64 / 15.0;
+                let speed = if i < 15 { 450.0 } else { 320.0 };
+                let mut interceptor = aircraft("ae1234", lat, -118.0, speed);
+                let mut target = aircraft("a12345", 34.0, -118.0, 320.0);
+                interceptor["track"] = json!(180.0);
+                target["track"] = json!(180.0);
+                found.extend(detect(
+                    &mut state,
+                    &response(i * 15, vec![interceptor, target]),
+                    &params,
+                ));
+            }
+            found
+        };
+        // Dead-reckoned, they stay together through the gap, so the gap
+        // doesn't reset how long they've been close.
+        let found = detect(60);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].proximity_start, response(15 * 15, vec![]).now);
+        assert_eq!(found[0].time, response(19 * 15, vec![]).now);
+        // Without dead-reckoning, they start over after the gap.
+        let found = detect(0);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].proximity_start, response(18 * 15, vec![]).now);
+    }
+
+    #[test]
+    fn test_sustained_proximity() {
+        // The interceptor only stays close for three snapshots.
+        let params = DetectionParams::default();
+        let mut state = State::default();
+        let found = intercept_track()
+            .iter()
+            .take(18)
+            .flat_map(|r| detect(&mut state, r, &params))
+            .count();
+        assert_eq!(found, 0);
+        // Three is enough if that's all we ask for.
+        let params = DetectionParams {
+            min_proximity_frames: 3,
+            ..params
+        };
+        let mut state = State::default();
+        let found: Vec<Interception> = intercept_track()
+            .iter()
+            .take(18)
+            .flat_map(|r| detect(&mut state, r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].time - found[0].proximity_start,
+            Duration::seconds(30)
+        );
+    }
+
+    #[test]
+    fn test_emergency_squawk() {
+        // The target is squawking 7500.
+        let hijack_track = || {
+            intercept_track().into_iter().map(|r| {
+                let mut json = serde_json::to_value(&r).unwrap();
+                json["ac"][1]["squawk"] = json!("7500");
+                serde_json::from_value::<Response>(json).unwrap()
+            })
+        };
+        let params = DetectionParams::default();
+        let mut state = State::default();
+        let found: Vec<Interception> = hijack_track()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].emergency, Some(EmergencySquawk::Hijack));
+        assert_eq!(found[0].target.emergency(), Some(EmergencySquawk::Hijack));
+        assert_eq!(found[0].interceptor.emergency(), None);
+        assert_eq!(
+            found[0].time - found[0].proximity_start,
+            Duration::seconds(60)
+        );
+        // Report it the first time they're close.
+        let params = DetectionParams {
+            hijack_min_proximity_frames: Some(1),
+            ..params
+        };
+        let mut state = State::default();
+        let found: Vec<Interception> = hijack_track()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].time, found[0].proximity_start);
+        // It's only for hijackings.
+        let mut state = State::default();
+        let found: Vec<Interception> = intercept_track()
+            .iter()
+            .flat_map(|r| detect(&mut state, r, &params))
+            .collect();
+        assert_eq!(found[0].emergency, None);
+        assert_eq!(
+            found[0].time - found[0].proximity_start,
+            Duration::seconds(60)
+        );
+        assert_eq!(
+            EmergencySquawk::from_squawk("7700"),
+            Some(EmergencySquawk::Emergency)
+        );
+        assert_eq!(EmergencySquawk::from_squawk("1200"), None);
+        assert_eq!(EmergencySquawk::RadioFailure.code(), "7600");
+    }
+
+    #[test]
+    fn test_require_military_interceptor() {
+        let params = DetectionParams {
+            require_military_interceptor: true,
+            ..Default::default()
+        };
+        // ae1234 is in the US military block.
+        let mut state = State::default();
+        let found: Vec<Interception> = intercept_track()
+            .iter()
+            .flat_map(|r| detect(&mut state, r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].interceptor.military);
+        assert!(!found[0].target.military);
+        // A civilian doing the same thing is ignored.
+        let civilian_track = || {
+            intercept_track().into_iter().map(|r| {
+                let mut json = serde_json::to_value(&r).unwrap();
+                json["ac"][0]["hex"] = json!("a00001");
+                serde_json::from_value::<Response>(json).unwrap()
+            })
+        };
+        let mut state = State::default();
+        let found = civilian_track()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .count();
+        assert_eq!(found, 0);
+        // Unless the database says it's military.
+        let mut state = State::default();
+        let found = civilian_track()
+            .map(|r| {
+                let mut json = serde_json::to_value(&r).unwrap();
+                json["ac"][0]["dbFlags"] = json!(1);
+                serde_json::from_value::<Response>(json).unwrap()
+            })
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .count();
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn test_airliners() {
+        let count = |fields: Value, params: &DetectionParams| {
+            let mut state = State::default();
+            intercept_track()
+                .into_iter()
+                .map(|r| {
+                    let mut json = serde_json::to_value(&r).unwrap();
+                    json["ac"][0]["hex"] = json!("a00001");
+                    for (k, v) in fields.as_object().unwrap() {
+                        json["ac"][0][k] = v.clone();
+                    }
+                    serde_json::from_value::<Response>(json).unwrap()
+                })
+                .flat_map(|r| detect(&mut state, &r, params))
+                .count()
+        };
+        let params = DetectionParams::default();
+        assert_eq!(count(json!({}), &params), 1);
+        assert_eq!(count(json!({"t": "B738"}), &params), 0);
+        assert_eq!(count(json!({"category": "A5"}), &params), 0);
+        assert_eq!(count(json!({"t": "F16", "category": "A3"}), &params), 0);
+        // Unless it's military.
+        assert_eq!(count(json!({"category": "A3", "dbFlags": 1}), &params), 1);
+        let allowed = DetectionParams {
+            allow_airliner_interceptors: true,
+            ..params.clone()
+        };
+        assert_eq!(count(json!({"t": "B738"}), &allowed), 1);
+        // The list can be extended.
+        let mut params = params;
+        params.airliner_types.insert("C25B".to_string());
+        assert_eq!(count(json!({"t": "C25B"}), &params), 0);
+    }
+
+    #[test]
+    fn test_speed_matching() {
+        // The interceptor blows past at full speed.
+        let fly_by = || {
+            intercept_track().into_iter().map(|r| {
+                let mut json = serde_json::to_value(&r).unwrap();
+                json["ac"][0]["gs"] = json!(450.0);
+                serde_json::from_value::<Response>(json).unwrap()
+            })
+        };
+        let params = DetectionParams::default();
+        let mut state = State::default();
+        let found = fly_by()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .count();
+        assert_eq!(found, 0);
+        let params = DetectionParams {
+            require_speed_match: false,
+            ..params
+        };
+        let mut state = State::default();
+        let found = fly_by()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .count();
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn test_shadow() {
+        // An escort flies north at full speed well off to the side, then
+        // slows down and joins up with a target that's also heading north, and
+        // flies alongside it long after it stops being a fast mover. It drops
+        // out of a couple of snapshots along the way.
+        let responses = || {
+            (0..45).map(|i| {
+                let target_lat = 34.0 + 0.0208 * i as f64;
+                let mut target = aircraft("a12345", target_lat, -118.0, 300.0);
+                target["track"] = json!(0.0);
+                let mut escort = if i < 15 {
+                    aircraft("ae1234", 34.0 + 0.03 * i as f64, -117.5, 450.0)
+                } else {
+                    aircraft("ae1234", target_lat, -117.997, 300.0)
+                };
+                escort["track"] = json!(0.0);
+                let ac = if i == 30 || i == 31 {
+                    vec![target]
+                } else {
+                    vec![target, escort]
+                };
+                response(i * 15, ac)
+            })
+        };
+        let start = response(0, vec![]).now;
+        let mut state = State::default();
+        let params = DetectionParams::default();
+        let found: Vec<Interception> = responses()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].pattern, InterceptPattern::Shadow);
+        assert_eq!(found[0].interceptor.hex, "ae1234");
+        // It stops being a fast mover three minutes after it slows down.
+        assert_eq!((found[0].proximity_start - start).num_seconds(), 26 * 15);
+        assert_eq!((found[0].time - start).num_seconds(), 34 * 15);
+
+        let params = DetectionParams {
+            detect_shadows: false,
+            ..params
+        };
+        let mut state = State::default();
+        let found = responses()
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .count();
+        assert_eq!(found, 0);
+    }
+
+    #[test]
+    fn test_vertical_separation() {
+        let params = DetectionParams::default();
+        let now = response(0, vec![]).now;
+        let ac = |alt_geom: Option<i32>, alt_baro: Value| {
+            let mut json = aircraft("ae1234", 34.0, -118.0, 450.0);
+            json["alt_geom"] = json!(alt_geom);
+            json["alt_baro"] = alt_baro;
+            let response = response(0, vec![json]);
+            Ac::new(now, &response.aircraft[0], &params).unwrap()
+        };
+        let fighter = ac(Some(10300), json!(10000));
+        // Geometric when both have it.
+        assert_eq!(
+            fighter.vertical_separation_ft(&ac(Some(10000), json!(10000))),
+            Some(300)
+        );
+        // Otherwise barometric.
+        assert_eq!(
+            fighter.vertical_separation_ft(&ac(None, json!(9800))),
+            Some(200)
+        );
+        // On the ground is 0 ft, whatever the GPS says.
+        let on_ground = ac(Some(150), json!("ground"));
+        assert_eq!(on_ground.cur_alt, 0);
+        assert_eq!(fighter.vertical_separation_ft(&on_ground), Some(10300));
+
+        // Pairs thousands of feet apart aren't even compared.
+        let mut state = State::default();
+        for response in intercept_track() {
+            let mut json = serde_json::to_value(&response).unwrap();
+            json["ac"][0]["alt_geom"] = json!(35000);
+            let response: Response = serde_json::from_value(json).unwrap();
+            assert!(detect(&mut state, &response, &params).is_empty());
+        }
+        assert_eq!(state.aircraft.num_ac_processed, 0);
+    }
+
+    #[test]
+    fn test_notifiers() {
+        type Notifications = std::rc::Rc<std::cell::RefCell<Vec<(DetectionEventKind, String)>>>;
+        type Detections = std::rc::Rc<std::cell::Cell<usize>>;
+        #[derive(Debug, Default)]
+        struct Recorder(Notifications, bool, Detections);
+        impl Notifier for Recorder {
+            fn notify(
+                &self,
+                kind: DetectionEventKind,
+                event: &InterceptionEvent,
+            ) -> Result<(), Error> {
+                self.0
+                    .borrow_mut()
+                    .push((kind, event.interceptor_hex.clone()));
+                Err(Error::ExportError("Errors are only logged".to_string()))
+            }
+
+            fn wants_updates(&self) -> bool {
+                self.1
+            }
+
+            fn detection(&self, _interception: &Interception) -> Result<(), Error> {
+                self.2.set(self.2.get() + 1);
+                Ok(())
+            }
+        }
+        let (notifications, updates) = (Notifications::default(), Notifications::default());
+        let detections = Detections::default();
+        let mut state = State::default();
+        state.notifiers.push(Box::new(Recorder(
+            notifications.clone(),
+            false,
+            detections.clone(),
+        )));
+        state.notifiers.push(Box::new(Recorder(
+            updates.clone(),
+            true,
+            Detections::default(),
+        )));
+        let bar = ProgressBar::hidden();
+        // The target is about 370 m east of the interceptor's track, and then
+        // closes in.
+        let track = (0..25).map(|i: i64| {
+            let lat = 34.0 + 0.3 * (15 - i.min(15)) as f64 / 15.0;
+            let target_lon = -118.0 + 0.004 * (24 - i.max(20)) as f64 / 4.0;
+            response(
+                i * 15,
+                vec![
+                    aircraft("ae1234", lat, -118.0, intercept_speed(i)),
+                    aircraft("a12345", 34.0, target_lon, 320.0),
+                ],
+            )
+        });
+        for response in track {
+            process_adsbx_response(&mut state, response, &bar).unwrap();
+        }
+        // Once when it starts, not for each detection.
+        assert_eq!(
+            *notifications.borrow(),
+            vec![(
+                DetectionEventKind::InterceptionStarted,
+                "ae1234".to_string()
+            )]
+        );
+        // And, for the notifier that wants them, each time it gets closer.
+        let kinds: Vec<DetectionEventKind> = updates.borrow().iter().map(|n| n.0).collect();
+        assert_eq!(kinds[0], DetectionEventKind::InterceptionStarted);
+        assert_eq!(kinds.len(), 5);
+        assert!(kinds[1..]
+            .iter()
+            .all(|k| *k == DetectionEventKind::InterceptionUpdated));
+        state.finish();
+        assert_eq!(notifications.borrow().len(), 2);
+        // Every detection, whether or not it's an update.
+        assert_eq!(detections.get(), state.events[0].num_detections);
+        assert!(detections.get() > 5);
+        let count = |counter: &std::sync::atomic::AtomicU64| {
+            counter.load(std::sync::atomic::Ordering::Relaxed)
+        };
+        assert_eq!(count(&state.metrics.snapshots_processed), 25);
+        assert_eq!(count(&state.metrics.interceptions_started), 1);
+        assert_eq!(count(&state.metrics.interceptions_ended), 1);
+        assert_eq!(count(&state.metrics.aircraft_tracked), 2);
+        assert_eq!(
+            notifications.borrow()[1].0,
+            DetectionEventKind::InterceptionEnded
+        );
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("detector.state");
+        let path = path.to_str().unwrap();
+        let bar = ProgressBar::hidden();
+        // Like intercept_track, but flying alongside for longer.
+        let mut track: Vec<Response> = (0..30)
+            .map(|i| {
+                let lat = 34.0 + 0.3 * (15 - i.min(15)) as f64 / 15.0;
+                response(
+                    i * 15,
+                    vec![
+                        aircraft("ae1234", lat, -118.0, intercept_speed(i)),
+                        aircraft("a12345", 34.0, -118.0, 320.0),
+                    ],
+                )
+            })
+            .collect();
+        let rest = track.split_off(25);
+        let mut state = State::default();
+        for response in track {
+            process_adsbx_response(&mut state, response, &bar).unwrap();
+        }
+        assert_eq!(state.tracker.num_open(), 1);
+        state.save_state(path).unwrap();
+
+        // A restarted run carries on with the same event, rather than
+        // starting a new one.
+        let now = rest[0].now;
+        let mut restarted = State::default();
+        restarted.load_state(path, now).unwrap();
+        assert_eq!(restarted.aircraft.len(), 2);
+        assert_eq!(
+            restarted.aircraft.get(id("ae1234")),
+            state.aircraft.get(id("ae1234"))
+        );
+        for response in rest {
+            process_adsbx_response(&mut restarted, response, &bar).unwrap();
+        }
+        restarted.finish();
+        assert_eq!(restarted.events.len(), 1);
+        assert_eq!(
+            restarted
+                .metrics
+                .interceptions_started
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+        assert_eq!(
+            restarted.events[0].start,
+            state.tracker.get(id("ae1234"), id("a12345")).unwrap().start
+        );
+
+        // After a long enough gap, everything's stale.
+        let mut restarted = State::default();
+        restarted
+            .load_state(path, now + Duration::minutes(30))
+            .unwrap();
+        assert!(restarted.aircraft.is_empty());
+        assert_eq!(restarted.aircraft.num_pending(), 0);
+        assert_eq!(restarted.tracker.num_open(), 0);
+
+        // Or they can be saved separately.
+        state.aircraft.save_state(path).unwrap();
+        let later = now + Duration::minutes(30);
+        let aircraft = AircraftStateMap::load_state(path, later, Duration::hours(1)).unwrap();
+        assert_eq!(aircraft.len(), 2);
+        assert!(InterceptionTracker::load_state(path, now).is_err());
+        state.tracker.save_state(path).unwrap();
+        let tracker = InterceptionTracker::load_state(path, now).unwrap();
+        assert_eq!(tracker.num_open(), 1);
+        assert_eq!(
+            InterceptionTracker::load_state(path, later)
+                .unwrap()
+                .num_open(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_altitude_dropout() {
+        let params = DetectionParams::default();
+        // The target stops reporting its altitude from snapshot first_missing
+        // on (15 seconds apart).
+        let run = |first_missing: usize, state: &mut State| {
+            let mut found = vec![];
+            for (i, response) in intercept_track().into_iter().enumerate() {
+                let mut json = serde_json::to_value(&response).unwrap();
+                if i >= first_missing {
+                    json["ac"][1]["alt_baro"] = Value::Null;
+                    json["ac"][1]["alt_geom"] = Value::Null;
+                }
+                let response: Response = serde_json::from_value(json).unwrap();
+                found.extend(detect(state, &response, &params));
+            }
+            found
+        };
+        // For the last 45 seconds, when it's close: the last known altitude
+        // is still good enough.
+        let mut state = State::default();
+        let found = run(17, &mut state);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].target.cur_alt, 10000);
+        assert_eq!(found[0].vertical_separation_ft, 0);
+        // But not for more than a minute, and it doesn't become 0 ft.
+        let mut state = State::default();
+        assert!(run(10, &mut state).is_empty());
+        let target = &state.aircraft.aircraft[&id("a12345")];
+        assert_eq!(target.cur_alt, 10000);
+        assert_eq!(target.known_alt(), None);
+        assert!(!target.is_on_ground);
+        assert_eq!(
+            state.aircraft.aircraft[&id("ae1234")].vertical_separation_ft(target),
+            None
+        );
+    }
+
+    #[test]
+    fn test_descent_toward_target() {
+        // The interceptor comes down from 30,000 ft to the target's 10,000 ft
+        // over the three minutes before it gets close.
+        let descending = |i: i64| 10000 + 20000 * (15 - i.clamp(3, 15)) / 12;
+        let track = |alt: &dyn Fn(i64) -> i64| -> Vec<Response> {
+            intercept_track()
+                .into_iter()
+                .enumerate()
+                .map(|(i, r)| {
+                    let mut json = serde_json::to_value(&r).unwrap();
+                    json["ac"][0]["alt_geom"] = json!(alt(i as i64));
+                    serde_json::from_value(json).unwrap()
+                })
+                .collect()
+        };
+        let params = DetectionParams {
+            require_descent: true,
+            ..Default::default()
+        };
+        let detect = |responses: Vec<Response>| {
+            let mut state = State::default();
+            let found: Vec<Interception> = responses
+                .iter()
+                .flat_map(|r| detect(&mut state, r, &params))
+                .collect();
+            found
+        };
+        let found = detect(track(&descending));
+        assert_eq!(found.len(), 1);
+        // 20,000 ft in 3 minutes, but the window at detection time only
+        // catches the end of the descent.
+        let rate = found[0]
+            .interceptor
+            .vertical_rate_fpm(Duration::minutes(3))
+            .unwrap();
+        assert!((rate - -20000.0 * 8.0 / 12.0 / 3.0).abs() < 1.0, "{}", rate);
+        // Level the whole way.
+        assert!(detect(track(&|_| 10000)).is_empty());
+    }
+
+    #[test]
+    fn test_vertical_rate() {
+        let params = DetectionParams::default();
+        let ac = |secs: i64, alt: i32, rates: Value| {
+            let mut json = aircraft("ae1234", 34.0, -118.0, 450.0);
+            json["alt_geom"] = json!(alt);
+            for (k, v) in rates.as_object().unwrap() {
+                json[k] = v.clone();
+            }
+            response(secs, vec![json])
+        };
+        let r = ac(0, 10000, json!({"baro_rate": -5000, "geom_rate": -6000}));
+        let mut state = Ac::new(r.now, &r.aircraft[0], &params).unwrap();
+        assert_eq!(state.cur_vertical_rate_fpm, Some(-6000.0));
+        let r = ac(15, 9000, json!({"baro_rate": -5000}));
+        state.update(r.now, &r.aircraft[0], &params);
+        assert_eq!(state.cur_vertical_rate_fpm, Some(-5000.0));
+        // Nothing reported, so it comes from the last minute of altitudes.
+        let r = ac(30, 8000, json!({}));
+        state.update(r.now, &r.aircraft[0], &params);
+        assert_eq!(state.cur_vertical_rate_fpm, Some(-4000.0));
+    }
+
+    #[test]
+    fn test_identification() {
+        let params = DetectionParams::default();
+        let ac = |secs: i64, fields: Value| {
+            let mut json = aircraft("ae1234", 34.0, -118.0, 450.0);
+            json.as_object_mut().unwrap().remove("flight");
+            for (k, v) in fields.as_object().unwrap() {
+                json[k] = v.clone();
+            }
+            response(secs, vec![json])
+        };
+        // Nothing but the hex, and a blank callsign.
+        let r = ac(0, json!({"flight": "        "}));
+        let mut state = Ac::new(r.now, &r.aircraft[0], &params).unwrap();
+        assert_eq!(state.callsign, None);
+        assert_eq!(state.registration, None);
+        assert_eq!(state.aircraft_type, None);
+        assert_eq!(state.squawk, None);
+        assert_eq!(state.description(), "ae1234");
+        // The callsign shows up later, with trailing spaces.
+        let r = ac(
+            15,
+            json!({"flight": "DOLBY11 ", "r": "01-7050", "t": "F16", "squawk": "4401"}),
+        );
+        state.update(r.now, &r.aircraft[0], &params);
+        assert_eq!(state.callsign.as_deref(), Some("DOLBY11"));
+        assert_eq!(state.registration.as_deref(), Some("01-7050"));
+        assert_eq!(state.aircraft_type.as_deref(), Some("F16"));
+        assert_eq!(state.squawk.as_deref(), Some("4401"));
+        assert_eq!(state.description(), "ae1234 (DOLBY11, 01-7050, F16)");
+        // A snapshot without them doesn't forget them, but a new squawk
+        // replaces the old one.
+        let r = ac(30, json!({"squawk": "7700"}));
+        state.update(r.now, &r.aircraft[0], &params);
+        assert_eq!(state.callsign.as_deref(), Some("DOLBY11"));
+        assert_eq!(state.aircraft_type.as_deref(), Some("F16"));
+        assert_eq!(state.squawk.as_deref(), Some("7700"));
+    }
+
+    #[test]
+    fn test_history_retention() {
+        // 5-second snapshots for 10 minutes.
+        let track = |params: &DetectionParams| {
+            let r = |i: i64| response(i * 5, vec![aircraft("ae1234", 34.0, -118.0, 450.0)]);
+            let first = r(0);
+            let mut ac = Ac::new(first.now, &first.aircraft[0], params).unwrap();
+            for i in 1..120 {
+                let r = r(i);
+                ac.update(r.now, &r.aircraft[0], params);
+            }
+            ac
+        };
+        // By default only the last 40 positions are kept.
+        let ac = track(&DetectionParams::default());
+        assert_eq!(ac.coords.len(), 40);
+        assert_eq!(
+            ac.cur_coords().0 - ac.oldest_coords().0,
+            Duration::seconds(39 * 5)
+        );
+        // Keep 5 minutes instead.
+        let params = DetectionParams {
+            history_max_points: None,
+            history_max_secs: Some(300),
+            ..Default::default()
+        };
+        let ac = track(&params);
+        assert_eq!(ac.coords.len(), 61);
+        assert_eq!(
+            ac.cur_coords().0 - ac.oldest_coords().0,
+            Duration::seconds(300)
+        );
+        assert_eq!(ac.alts.len(), 61);
+        assert_eq!(ac.tracks.len(), 61);
+        assert_eq!(ac.speeds.len(), 61);
+        // Whichever limit is reached first.
+        let params = DetectionParams {
+            history_max_points: Some(20),
+            ..params
+        };
+        assert_eq!(track(&params).coords.len(), 20);
+    }
+
+    #[test]
+    fn test_high_latitude() {
+        // The same intercept in Alaska, with the target about 1500 ft east,
+        // which is more degrees of longitude than the search radius is degrees
+        // of latitude.
+        let mut state = State::default();
+        let params = DetectionParams::default();
+        let found: Vec<Interception> = intercept_track()
+            .into_iter()
+            .map(|r| {
+                let mut json = serde_json::to_value(&r).unwrap();
+                for ac in json["ac"].as_array_mut().unwrap() {
+                    ac["lat"] = json!(ac["lat"].as_f64().unwrap() + 30.0);
+                }
+                json["ac"][1]["lon"] = json!(-118.0 + 0.0095);
+                serde_json::from_value::<Response>(json).unwrap()
+            })
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert!((found[0].lateral_separation_ft - 1520.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn test_antimeridian() {
+        // The same intercept over the Bering Strait, with the target on the
+        // other side of the antimeridian, a couple of miles east.
+        let mut state = State::default();
+        let params = DetectionParams {
+            search_radius_nm: 2.5,
+            max_lateral_separation_m: 4000.0,
+            // It flies straight south, not at the target.
+            convergence_frames: 0,
+            ..Default::default()
+        };
+        let found: Vec<Interception> = intercept_track()
+            .into_iter()
+            .map(|r| {
+                let mut json = serde_json::to_value(&r).unwrap();
+                for ac in json["ac"].as_array_mut().unwrap() {
+                    ac["lat"] = json!(ac["lat"].as_f64().unwrap() + 32.5);
+                }
+                json["ac"][0]["lon"] = json!(179.95);
+                json["ac"][1]["lon"] = json!(-179.97);
+                serde_json::from_value::<Response>(json).unwrap()
+            })
+            .flat_map(|r| detect(&mut state, &r, &params))
+            .collect();
+        assert_eq!(found.len(), 1);
+        let nm = found[0].lateral_separation_ft / 6076.12;
+        assert!((nm - 1.92).abs() < 0.01, "{}", nm);
+        let [lon, _] = found[0].location();
+        assert!((lon - 179.99).abs() < 1e-9, "{}", lon);
+    }
+
+    #[test]
+    fn test_regions() {
+        // A box around where the interception happens, and one that's about
+        // 10 nm south of it.
+        let square = |lat: f64| {
+            format!(
+                r#"{{"type": "Polygon", "coordinates": [[[-118.1, {0}], [-117.9, {0}], [-117.9, {1}], [-118.1, {1}], [-118.1, {0}]]]}}"#,
+                lat - 0.1,
+                lat + 0.1
+            )
+        };
+        let detect = |regions: &str, margin_nm: f64| {
+            let params = DetectionParams {
+                regions: Some(Regions::from_geojson(regions).unwrap()),
+                region_margin_nm: margin_nm,
+                ..Default::default()
+            };
+            let mut state = State::default();
+            let found = intercept_track()
+                .iter()
+                .flat_map(|r| detect(&mut state, r, &params))
+                .count();
+            (found, state.aircraft.len())
+        };
+        // The interceptor starts outside the box, but inside the margin.
+        assert_eq!(detect(&square(34.0), 50.0), (1, 2));
+        assert_eq!(detect(&square(33.7), 50.0), (0, 2));
+        // Without a margin, it doesn't have enough history when it gets
+        // there.
+        assert_eq!(detect(&square(34.0), 0.0), (0, 2));
+        assert_eq!(detect(&square(30.0), 50.0), (0, 0));
+    }
+
+    #[test]
+    fn test_heading_convergence() {
+        // The same track, but the interceptor reports pointing east, so it's
+        // just passing by.
+        let params = DetectionParams::default();
+        let with_track = |track: f64| {
+            let mut state = State::default();
+            intercept_track()
+                .into_iter()
+                .map(|r| {
+                    let mut json = serde_json::to_value(&r).unwrap();
+                    json["ac"][0]["track"] = json!(track);
+                    serde_json::from_value(json).unwrap()
+                })
+                .flat_map(|r| detect(&mut state, &r, &params))
+                .count()
+        };
+        assert_eq!(with_track(90.0), 0);
+        assert_eq!(with_track(170.0), 1);
+        assert_eq!(angle_difference(350.0, 10.0), 20.0);
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_logging() {
+        crate::testutil::standard_intercept_state();
+        assert!(logs_contain(
+            "snapshot{now=2021-02-23 19:41:08 UTC}: tracon::interception: Classified snapshot fast_movers=1 escorts=0 targets=1"
+        ));
+        assert!(logs_contain(
+            "Rejected pair interceptor=ae1234 target=a12345 reason=speed difference"
+        ));
+        assert!(logs_contain(
+            "Rejected pair interceptor=ae1234 target=a12345 close_frames=1"
+        ));
+        assert!(logs_contain(
+            "Interception started interceptor=ae1234 target=a12345 start="
+        ));
+        assert!(logs_contain(
+            "Interception ended interceptor=ae1234 target=a12345 end="
+        ));
+    }
+
+    #[test]
+    fn test_explain() {
+        use crate::testutil::standard_intercept;
+        let responses = standard_intercept();
+        let params = DetectionParams::default();
+        // It's off by default.
+        let mut state = AircraftStateMap::new();
+        let mut found = vec![];
+        for r in &responses {
+            found.extend(detect_frame_interceptions(&mut state, r, &params));
+        }
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|i| i.criteria.is_none()));
+        assert!(state.candidate_reports().is_empty());
+
+        let mut state = AircraftStateMap::new();
+        state.set_explain(true);
+        let mut explained = vec![];
+        let mut checks = vec![];
+        for r in &responses {
+            explained.extend(detect_frame_interceptions(&mut state, r, &params));
+            checks.extend(state.candidate_checks().to_vec());
+        }
+        // Explaining doesn't change what's found.
+        assert_eq!(explained.len(), found.len());
+        // Shadows are found separately, and aren't explained.
+        for interception in explained
+            .iter()
+            .filter(|i| i.pattern != InterceptPattern::Shadow)
+        {
+            let criteria = interception.criteria.as_ref().unwrap();
+            assert!(criteria.passed());
+            assert_eq!(criteria.time, interception.time);
+            assert!(criteria.close_frames >= params.min_proximity_frames);
+            assert_eq!(criteria.close_since, Some(interception.proximity_start));
+            assert!(criteria.heading_offset_deg.is_some());
+        }
+        // Before they're close, the checks say why not, and the first
+        // snapshot they're close is too brief.
+        assert_eq!(
+            checks[0].failed,
+            vec![Rejection::SpeedDifference, Rejection::SpeedNotMatched]
+        );
+        assert!(checks.iter().any(|c| c.failed == vec![Rejection::TooBrief]));
+        let reports = state.candidate_reports();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.interceptor_hex.to_string(), "ae1234");
+        assert_eq!(report.target_hex.to_string(), "a12345");
+        assert_eq!(report.num_checks, checks.len());
+        assert_eq!(
+            report.num_passed,
+            explained.iter().filter(|i| i.criteria.is_some()).count()
+        );
+        assert!(report.failures[&Rejection::SpeedDifference] > 0);
+        assert!(report.closest.lateral_separation_m < 200.0);
+        // Turning it off drops the reports.
+        state.set_explain(false);
+        assert!(state.candidate_reports().is_empty());
+    }
+
+    #[test]
+    fn test_detection_params() {
+        // A helicopter intercepting a slow Cessna.
+        let params: DetectionParams = serde_json::from_str(
+            r#"{"interceptor_min_speed_kts": 100.0, "target_max_speed_kts": 90.0, "target_min_speed_kts": 50.0}"#,
+        )
+        .unwrap();
+        assert_eq!(params.interceptor_timeout_mins, INTERCEPTOR_TIMEOUT_MINS);
+        let now = response(0, vec![]).now;
+        let response = response(0, vec![aircraft("ae1234", 34.0, -118.0, 120.0)]);
+        let mut ac = Ac::new(now, &response.aircraft[0], &params).unwrap();
+        ac.fast.fast_status = true;
+        assert_eq!(classify(&ac, now, &params), Class::Interceptor);
+        // With the default thresholds it's just a potential target.
+        let defaults = DetectionParams::default();
+        let mut ac = Ac::new(now, &response.aircraft[0], &defaults).unwrap();
+        ac.fast.fast_status = true;
+        assert_eq!(classify(&ac, now, &defaults), Class::Target);
+        ac.cur_speed = 60.0;
+        assert!(ac.is_potential_toi(&params));
+    }
+
+    #[test]
+    fn test_target_criteria() {
+        let now = response(0, vec![]).now;
+        let mut balloon = aircraft("a12345", 34.0, -118.0, 40.0);
+        balloon["category"] = json!("B2");
+        balloon["alt_baro"] = json!(60000);
+        balloon["alt_geom"] = json!(60000);
+        let response = response(0, vec![balloon]);
+        let mut params = DetectionParams::default();
+        let mut ac = Ac::new(now, &response.aircraft[0], &params).unwrap();
+        // Too slow by default...
+        assert_eq!(classify(&ac, now, &params), Class::Other);
+        // ...but not with balloons allowed.
+        params.target.categories.insert("B2".to_string());
+        assert_eq!(classify(&ac, now, &params), Class::Target);
+        // Unless it's above the altitude band.
+        params.target.max_alt_ft = Some(45_000);
+        assert!(!ac.is_potential_toi(&params));
+        params.target.max_alt_ft = None;
+        params.target.min_alt_ft = Some(1_000);
+        assert!(ac.is_potential_toi(&params));
+
+        // A bizjet that's too fast, until it squawks 7700.
+        let mut params = DetectionParams::default();
+        params.target.emergency_any_speed = true;
+        ac.emitter_category = Some("A2".to_string());
+        ac.cur_speed = 420.0;
+        assert!(!ac.is_potential_toi(&params));
+        ac.squawk = Some("7700".to_string());
+        assert!(ac.is_potential_toi(&params));
+        ac.is_on_ground = true;
+        assert!(!ac.is_potential_toi(&params));
+
+        // The keys in a config are flat.
+        let params: DetectionParams = serde_json::from_value(json!({
+            "target_min_speed_kts": 30.0, "target_categories": ["A1", "B2"],
+            "target_max_alt_ft": 12_000,
+        }))
+        .unwrap();
+        assert_eq!(params.target.min_speed_kts, 30.0);
+        assert_eq!(params.target.max_speed_kts, TARGET_MAX_SPEED_KTS);
+        assert_eq!(params.target.categories.len(), 2);
+        assert_eq!(params.target.max_alt_ft, Some(12_000));
+        let json = serde_json::to_value(DetectionParams::default()).unwrap();
+        assert_eq!(json["target_min_speed_kts"], json!(TARGET_MIN_SPEED_KTS));
+        assert!(json.get("target").is_none());
+    }
+
+    #[test]
+    fn test_slow_target_mode() {
+        // A balloon drifting at 5 kt, and an interceptor that comes down
+        // from the north, passes over it, turns around and passes again.
+        let mut lats: Vec<f64> = (0..16).map(|i| 34.3 - 0.025 * i as f64).collect();
+        lats.extend((0..8).map(|i| 33.9 + 0.025 * i as f64));
+        lats.extend((0..6).map(|i| 34.1 - 0.025 * i as f64));
+        let responses: Vec<Response> = lats
+            .iter()
+            .enumerate()
+            .map(|(i, lat)| {
+                response(
+                    i as i64 * 15,
+                    vec![
+                        aircraft("ae1234", *lat, -118.0, 450.0),
+                        aircraft("a12345", 34.0, -118.0, 5.0),
+                    ],
+                )
+            })
+            .collect();
+        let detect = |params: &DetectionParams| {
+            let mut state = State::default();
+            let mut found = vec![];
+            for response in &responses {
+                found.extend(detect(&mut state, response, params));
+            }
+            found
+        };
+
+        let mut params = DetectionParams::default();
+        let balloon = Ac::new(responses[0].now, &responses[0].aircraft[1], &params).unwrap();
+        assert_eq!(params.target.mode(&balloon), None);
+        // Too slow to be a target by default.
+        assert!(detect(&params).is_empty());
+
+        params.target.slow_mode = true;
+        assert_eq!(params.target.mode(&balloon), Some(TargetMode::Slow));
+        let found = detect(&params);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].target_mode, TargetMode::Slow);
+        // Detected when it comes back within orbit_radius_nm for the second
+        // pass, 0.025° south of the balloon.
+        assert_eq!(found[0].time, responses[19].now);
+
+        // Or when it comes back from the north for the third.
+        params.target.slow_min_passes = 3;
+        assert_eq!(detect(&params)[0].time, responses[27].now);
+        params.target.slow_min_passes = 4;
+        assert!(detect(&params).is_empty());
+    }
+
+    #[test]
+    fn test_interceptor_profiles() {
+        // A helicopter comes down from about 11 miles north at 120 kt, and
+        // slows to fly alongside a 90 kt target.
+        let responses: Vec<Response> = (0..30)
+            .map(|i| {
+                let lat = 34.0 + 0.008 * (20 - i.min(20)) as f64;
+                let mut helicopter =
+                    aircraft("ae1234", lat, -118.0, if i < 20 { 120.0 } else { 90.0 });
+                helicopter["category"] = json!("A7");
+                helicopter["t"] = json!("H60");
+                response(
+                    i * 15,
+                    vec![helicopter, aircraft("a12345", 34.0, -118.0, 90.0)],
+                )
+            })
+            .collect();
+        let run = |params: &DetectionParams| {
+            let mut state = State::default();
+            let mut found = vec![];
+            for response in &responses {
+                found.extend(detect(&mut state, response, params));
+            }
+            found
+        };
+        let helicopter = InterceptorProfile {
+            name: "helicopter".to_string(),
+            min_speed_kts: 100.0,
+            categories: BTreeSet::from(["A7".to_string()]),
+            ..Default::default()
+        };
+
+        // Never a fast mover as a jet.
+        let mut params = DetectionParams::default();
+        assert!(run(&params).is_empty());
+
+        params.interceptor_profiles.push(helicopter.clone());
+        let found = run(&params);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].interceptor_profile.as_deref(), Some("helicopter"));
+        assert!(found[0].interceptor.profile_status["helicopter"].fast_status);
+        assert!(!found[0].interceptor.fast.fast_status);
+
+        // A type prefix works too, but a restriction it doesn't meet doesn't.
+        params.interceptor_profiles[0].categories.clear();
+        params.interceptor_profiles[0].type_prefixes = vec!["H".to_string()];
+        assert_eq!(run(&params).len(), 1);
+        params.interceptor_profiles[0].type_prefixes = vec!["F".to_string()];
+        assert!(run(&params).is_empty());
+        // Nor does one it isn't fast for long enough under.
+        params.interceptor_profiles[0] = InterceptorProfile {
+            min_fast_count: 25,
+            ..helicopter
+        };
+        assert!(run(&params).is_empty());
+
+        // Jets are still jets.
+        let mut state = State::default();
+        let found: Vec<_> = intercept_track()
+            .iter()
+            .flat_map(|r| detect(&mut state, r, &params))
+            .collect();
+        assert_eq!(
+            found[0].interceptor_profile.as_deref(),
+            Some(FAST_JET_PROFILE)
+        );
+    }
+
+    #[test]
+    fn test_min_confidence() {
+        let detect = |params: &DetectionParams| {
+            let mut state = State::default();
+            let mut found = vec![];
+            for response in intercept_track() {
+                found.extend(detect(&mut state, &response, params));
+            }
+            found
+        };
+        // Scored even without min_confidence.
+        let mut params = DetectionParams::default();
+        let found = detect(&params);
+        assert_eq!(found.len(), 1);
+        assert!(
+            found[0].confidence.unwrap() > 50.0,
+            "{:?}",
+            found[0].confidence
+        );
+
+        params.min_confidence = Some(50.0);
+        let found = detect(&params);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].time, response(19 * 15, vec![]).now);
+
+        // It isn't descending, which is no longer a rejection, but costs
+        // enough points to put it under 50.
+        params.require_descent = true;
+        assert!(detect(&params).is_empty());
+        params.min_confidence = Some(10.0);
+        assert_eq!(detect(&params).len(), 1);
+        params.min_confidence = None;
+        assert!(detect(&params).is_empty());
+    }
+
+    #[test]
+    fn test_fast_status() {
+        let params = DetectionParams::default();
+        let r = |secs: i64, gs: f64| response(secs, vec![aircraft("ae1234", 34.0, -118.0, gs)]);
+        // Updates every 15 seconds, at the given speeds.
+        let fly = |speeds: &[f64]| {
+            let first = r(0, speeds[0]);
+            let mut ac = Ac::new(first.now, &first.aircraft[0], &params).unwrap();
+            for (i, gs) in speeds.iter().enumerate().skip(1) {
+                let r = r(i as i64 * 15, *gs);
+                ac.update(r.now, &r.aircraft[0], &params);
+            }
+            ac
+        };
+        let time = |i: i64| r(i * 15, 0.0).now;
+        // Ten fast updates in a row are enough.
+        let ac = fly(&[450.0; 9]);
+        assert!(!ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 9);
+        let ac = fly(&[450.0; 10]);
+        assert!(ac.fast.fast_status);
+        assert!(ac.is_fast_mover(time(9), &params));
+        // A slow update in the middle starts the count over.
+        let mut speeds = vec![450.0; 9];
+        speeds.extend([300.0]);
+        speeds.extend([450.0; 9]);
+        let ac = fly(&speeds);
+        assert!(!ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 9);
+        // Once it's fast, slowing down doesn't lose fast status until it's
+        // been slow for exactly interceptor_timeout_mins, not a second less.
+        let mut speeds = vec![450.0; 10];
+        speeds.extend([300.0; 11]);
+        let ac = fly(&speeds);
+        assert!(ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 10);
+        // The last fast update was at 135 s, so it's fast until 315 s.
+        assert!(ac.is_fast_mover(time(9) + Duration::seconds(179), &params));
+        assert!(!ac.is_fast_mover(time(9) + Duration::seconds(180), &params));
+        speeds.push(300.0);
+        let ac = fly(&speeds);
+        assert!(!ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 0);
+        assert_eq!(ac.fast.fast_status_ended, Some(time(21)));
+        // It can still be an escort for a while.
+        let within = Duration::minutes(params.shadow_fast_window_mins);
+        assert!(ac.was_fast_mover(time(21) + within - Duration::seconds(1), within, &params));
+        assert!(!ac.was_fast_mover(time(21) + within, within, &params));
+        // And getting fast status again takes another ten fast updates, not
+        // one.
+        speeds.push(450.0);
+        let ac = fly(&speeds);
+        assert!(!ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 1);
+        speeds.extend([450.0; 9]);
+        assert!(fly(&speeds).fast.fast_status);
+        // Likewise after going unseen for longer than the timeout: the first
+        // fast update after the gap doesn't bring the old fast status back.
+        let mut ac = fly(&[450.0; 10]);
+        assert!(ac.fast.fast_status);
+        let after_gap = r(9 * 15 + 4 * 60, 450.0);
+        ac.update(after_gap.now, &after_gap.aircraft[0], &params);
+        assert!(!ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 1);
+        assert_eq!(
+            ac.fast.fast_status_ended,
+            Some(time(9) + Duration::minutes(INTERCEPTOR_TIMEOUT_MINS))
+        );
+        assert!(!ac.is_fast_mover(after_gap.now, &params));
+        // Fast updates that are too far apart don't count as a run.
+        let first = r(0, 450.0);
+        let mut ac = Ac::new(first.now, &first.aircraft[0], &params).unwrap();
+        for secs in [15, 30, 45, 60, 75, 90, 105, 120, 600] {
+            let r = r(secs, 450.0);
+            ac.update(r.now, &r.aircraft[0], &params);
+        }
+        assert!(!ac.fast.fast_status);
+        assert_eq!(ac.fast.fast_count, 1);
+    }
+
+    #[test]
+    fn test_classify() {
+        let params = DetectionParams::default();
+        let r = response(0, vec![aircraft("a12345", 34.0, -118.0, 200.0)]);
+        let now = r.now;
+        let ac = |speed: f64| {
+            let mut ac = Ac::new(now, &r.aircraft[0], &params).unwrap();
+            ac.cur_speed = speed;
+            ac
+        };
+        // The target speed limits are exclusive.
+        assert_eq!(classify(&ac(200.0), now, &params), Class::Target);
+        assert_eq!(classify(&ac(350.0), now, &params), Class::Other);
+        assert_eq!(classify(&ac(349.9), now, &params), Class::Target);
+        assert_eq!(classify(&ac(80.0), now, &params), Class::Other);
+        assert_eq!(classify(&ac(80.1), now, &params), Class::Target);
+        // A fast mover that has slowed to target speeds is still an
+        // interceptor...
+        let mut fast = ac(300.0);
+        fast.fast.time_seen_fast = Some(now - Duration::minutes(1));
+        fast.fast.fast_status = true;
+        assert!(fast.is_potential_toi(&params));
+        assert_eq!(classify(&fast, now, &params), Class::Interceptor);
+        // ...until it's been slow for interceptor_timeout_mins.
+        let later = now + Duration::minutes(INTERCEPTOR_TIMEOUT_MINS - 1);
+        assert_eq!(classify(&fast, later, &params), Class::Target);
+        // It needs fast status, not just a fast update.
+        fast.fast.fast_status = false;
+        assert_eq!(classify(&fast, now, &params), Class::Target);
+        // Fast movers on the ground are neither.
+        let mut taxiing = ac(450.0);
+        taxiing.fast.time_seen_fast = Some(now);
+        taxiing.fast.fast_status = true;
+        taxiing.is_on_ground = true;
+        assert_eq!(classify(&taxiing, now, &params), Class::Other);
+        taxiing.cur_speed = 200.0;
+        assert_eq!(classify(&taxiing, now, &params), Class::Other);
+    }
+
+    #[test]
+    fn test_interception_serde_round_trip() {
+        let mut state = State::default();
+        let params = DetectionParams::default();
+        let interception = intercept_track()
+            .iter()
+            .flat_map(|r| detect(&mut state, r, &params))
+            .next()
+            .unwrap();
+        let json = serde_json::to_value(&interception).unwrap();
+        assert_eq!(json["time"], json!("2021-02-23T19:43:38Z"));
+        assert_eq!(json["proximity_start"], json!("2021-02-23T19:42:38Z"));
+        assert_eq!(
+            json["target"]["coords"][0],
+            json!(["2021-02-23T19:38:53Z", [-118.0, 34.0]])
+        );
+        assert_eq!(
+            serde_json::to_value(Class::Interceptor).unwrap(),
+            json!("interceptor")
+        );
+        let round_tripped: Interception = serde_json::from_value(json).unwrap();
+        assert_eq!(round_tripped, interception);
+        let ac = &interception.interceptor;
+        let round_tripped: Ac = serde_json::from_str(&serde_json::to_string(ac).unwrap()).unwrap();
+        assert_eq!(&round_tripped, ac);
+    }
+
+    #[test]
+    fn test_missing_data_is_counted() {
+        let mut state = State::default();
+        let mut ac = aircraft("ae1234", 34.0, -118.0, 450.0);
+        ac.as_object_mut().unwrap().remove("lat");
+        let found = detect(
+            &mut state,
+            &response(0, vec![ac]),
+            &DetectionParams::default(),
+        );
+        assert!(found.is_empty());
+        assert!(state.aircraft.is_empty());
+        assert_eq!(state.aircraft.num_ac_missing_data, 1);
+        assert_eq!(
+            state
+                .aircraft
+                .snapshot_drops
+                .get(DropReason::MissingPosition),
+            1
+        );
+
+        // An aircraft that only reports barometric altitude is fine, but one
+        // with no altitude at all isn't.
+        let mut baro_only = aircraft("a00001", 34.0, -118.0, 250.0);
+        baro_only.as_object_mut().unwrap().remove("alt_geom");
+        let mut no_alt = aircraft("a00002", 34.0, -118.0, 250.0);
+        no_alt.as_object_mut().unwrap().remove("alt_geom");
+        no_alt.as_object_mut().unwrap().remove("alt_baro");
+        let mut no_speed = aircraft("a00003", 34.0, -118.0, 250.0);
+        no_speed.as_object_mut().unwrap().remove("gs");
+        let bad_hex = aircraft("nope", 34.0, -118.0, 250.0);
+        let r = response(5, vec![baro_only, no_alt, no_speed, bad_hex]);
+        detect(&mut state, &r, &DetectionParams::default());
+        assert_eq!(state.aircraft.get(id("a00001")).unwrap().cur_alt, 10000);
+        assert_eq!(
+            state.aircraft.snapshot_drops,
+            DropStats {
+                records: 4,
+                missing_altitude: 1,
+                missing_speed: 1,
+                invalid_hex_id: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(state.aircraft.drops.records, 5);
+        assert_eq!(state.aircraft.drops.total(), 4);
+        assert_eq!(state.aircraft.num_snapshots, 2);
+
+        // A tracked aircraft is still updated, and classified, when a record
+        // is only missing its position. It's only dropped once there's
+        // nothing in a record to use.
+        let mut no_position = aircraft("a00001", 34.0, -118.0, 300.0);
+        no_position.as_object_mut().unwrap().remove("lat");
+        detect(
+            &mut state,
+            &response(10, vec![no_position.clone()]),
+            &DetectionParams::default(),
+        );
+        assert_eq!(state.aircraft.get(id("a00001")).unwrap().cur_speed, 300.0);
+        assert_eq!(state.aircraft.snapshot_drops.total(), 0);
+        assert_eq!(state.aircraft.snapshot_drops.partial, 1);
+        for field in ["gs", "alt_geom", "alt_baro"] {
+            no_position.as_object_mut().unwrap().remove(field);
+        }
+        detect(
+            &mut state,
+            &response(15, vec![no_position]),
+            &DetectionParams::default(),
+        );
+        assert_eq!(
+            state
+                .aircraft
+                .snapshot_drops
+                .get(DropReason::MissingPosition),
+            1
+        );
+        assert_eq!(state.aircraft.snapshot_drops.partial, 0);
+        assert_eq!(state.aircraft.drops.partial, 1);
+    }
+
+    #[test]
+    fn test_query_radius() {
+        let params = DetectionParams::default();
+        let mut state = AircraftStateMap::new();
+        let r = response(
+            0,
+            vec![
+                aircraft("a00001", 34.0, -118.0, 250.0),
+                // 6 nm north.
+                aircraft("a00002", 34.1, -118.0, 250.0),
+                // 18 nm north, and fast.
+                aircraft("ae0003", 34.3, -118.0, 450.0),
+                // Either side of the antimeridian.
+                aircraft("a00004", 52.0, 179.99, 250.0),
+                aircraft("a00005", 52.0, -179.99, 250.0),
+            ],
+        );
+        for a in &r.aircraft {
+            state.update(r.now, a, &params).unwrap();
+        }
+        let hexes = |found: Vec<&Ac>| {
+            found
+                .iter()
+                .map(|ac| ac.hex.to_string())
+                .collect::<Vec<_>>()
+        };
+        let center = [-118.0, 34.05];
+        assert_eq!(
+            hexes(state.query_radius(center, 10.0, r.now, |_| true)),
+            vec!["a00001", "a00002"]
+        );
+        assert_eq!(
+            hexes(state.query_radius(center, 20.0, r.now, |ac| ac.cur_speed > 400.0)),
+            vec!["ae0003"]
+        );
+        assert_eq!(
+            hexes(state.query_radius([179.999, 52.0], 1.0, r.now, |_| true)),
+            vec!["a00004", "a00005"]
+        );
+        assert_eq!(
+            hexes(state.query_nearest([-118.0, 34.25], 2, r.now)),
+            vec!["ae0003", "a00002"]
+        );
+        // From far away, the nearest are on the other side of the world.
+        assert_eq!(
+            hexes(state.query_nearest([0.0, -60.0], 1, r.now)),
+            vec!["a00001"]
+        );
+        assert_eq!(state.query_nearest(center, 10, r.now).len(), 5);
+        // Once they haven't been seen for QUERY_HORIZON_SECS, they're gone,
+        // and before they were first seen they weren't there yet.
+        let later = r.now + Duration::seconds(QUERY_HORIZON_SECS + 1);
+        assert!(state.query_radius(center, 10.0, later, |_| true).is_empty());
+        let earlier = r.now - Duration::seconds(1);
+        assert!(state.query_nearest(center, 1, earlier).is_empty());
+    }
+
+    #[test]
+    fn test_expire() {
+        let params = DetectionParams::default();
+        let mut state = State::default();
+        for r in intercept_track() {
+            detect(&mut state, &r, &params);
+        }
+        let r = response(
+            0,
+            vec![
+                aircraft("a00001", 36.0, -118.0, 250.0),
+                aircraft("a00002", 36.1, -118.0, 250.0),
+            ],
+        );
+        detect(&mut state, &r, &params);
+        assert_eq!(state.aircraft.len(), 4);
+        state.aircraft.pin(id("a00002"));
+        // The interceptor and target are part of an ongoing interception.
+        state
+            .aircraft
+            .expire(r.now + Duration::hours(1), Duration::minutes(10));
+        assert_eq!(state.aircraft.len(), 3);
+        assert!(state.aircraft.get(id("a00001")).is_none());
+        assert!(state.aircraft.get(id("a00002")).is_some());
+        assert_eq!(state.aircraft.num_ac_expired, 1);
+        // Once the interception ends, they're unpinned and can be expired too.
+        state.finish();
+        state.aircraft.set_pinned(state.tracker.open_aircraft());
+        state
+            .aircraft
+            .expire(r.now + Duration::hours(1), Duration::minutes(10));
+        assert!(state.aircraft.is_empty());
+        assert_eq!(state.aircraft.num_ac_expired, 4);
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut params = DetectionParams::default();
+        let mut state = State::default();
+        for r in intercept_track() {
+            detect(&mut state, &r, &params);
+        }
+        for (i, hex) in ["a00001", "a00002", "a00003"].into_iter().enumerate() {
+            let r = response(
+                300 + i as i64 * 15,
+                vec![aircraft(hex, 36.0, -118.0, 250.0)],
+            );
+            detect(&mut state, &r, &params);
+        }
+        assert_eq!(state.aircraft.len(), 5);
+        state.aircraft.pin(id("a00001"));
+        // Not pinned, or in an interception, and seen least recently.
+        params.max_tracked_aircraft = Some(4);
+        state.aircraft.evict(&params);
+        assert_eq!(state.aircraft.len(), 4);
+        assert!(state.aircraft.get(id("a00002")).is_none());
+        assert_eq!(state.aircraft.num_ac_evicted, 1);
+        // Nothing else can go, however small the budget.
+        let bytes = state.aircraft.estimate_memory_bytes();
+        params.memory_budget_mb = Some(0);
+        state.aircraft.evict(&params);
+        assert_eq!(state.aircraft.len(), 3);
+        assert!(state.aircraft.get(id("a00003")).is_none());
+        assert!(state.aircraft.estimate_memory_bytes() < bytes);
+        assert_eq!(state.aircraft.num_ac_evicted, 2);
+    }
+
+    #[test]
+    fn test_prefilter() {
+        use crate::filter::BoundingBoxFilter;
+        let params = DetectionParams::default();
+        let mut state = AircraftStateMap::new();
+        state.set_prefilter(Some(|a: &Aircraft| a.lon.is_some_and(|lon| lon < -117.0)));
+        let r = response(
+            0,
+            vec![
+                aircraft("a00001", 34.0, -118.0, 250.0),
+                aircraft("a00002", 34.0, -116.0, 250.0),
+            ],
+        );
+        detect_frame_interceptions(&mut state, &r, &params);
+        assert!(state.get(id("a00001")).is_some());
+        assert!(state.get(id("a00002")).is_none());
+        assert_eq!(state.num_ac_filtered, 1);
+        // a00001 leaves the box, and isn't updated, but isn't forgotten until
+        // it's stale.
+        state.set_prefilter(Some(BoundingBoxFilter {
+            min_lon: -118.5,
+            min_lat: 33.0,
+            max_lon: -117.5,
+            max_lat: 35.0,
+        }));
+        let r = response(60, vec![aircraft("a00001", 34.0, -117.0, 250.0)]);
+        detect_frame_interceptions(&mut state, &r, &params);
+        let ac = state.get(id("a00001")).unwrap();
+        assert_eq!(ac.cur_coords().1, [-118.0, 34.0]);
+        assert_eq!(state.num_ac_filtered, 2);
+        let r = response(
+            60 + params.stale_aircraft_mins * 60 + 1,
+            vec![aircraft("a00001", 34.0, -117.0, 250.0)],
+        );
+        detect_frame_interceptions(&mut state, &r, &params);
+        assert!(state.is_empty());
+        assert_eq!(state.num_ac_expired, 1);
+        // Without a filter, everything gets through.
+        state.set_prefilter(None::<BoundingBoxFilter>);
+        detect_frame_interceptions(&mut state, &r, &params);
+        assert_eq!(state.len(), 1);
+    }
+
+    #[test]
+    fn test_missing_fields_dont_panic() {
+        let fields = [
+            "lat", "lon", "gs", "alt_baro", "alt_geom", "seen_pos", "track", "flight",
+        ];
+        let params = DetectionParams::default();
+        let mut state = State::default();
+        for r in intercept_track() {
+            detect(&mut state, &r, &params);
+        }
+        let existing = state.aircraft.iter().next().unwrap().clone();
+        for mask in 0..1 << fields.len() {
+            let mut values = vec![];
+            for hex in ["ae1234", "a12345", "a00001"] {
+                let mut value = aircraft(hex, 34.0, -118.0, 450.0);
+                value["track"] = json!(90.0);
+                for (i, field) in fields.iter().enumerate() {
+                    if mask & 1 << i != 0 {
+                        value.as_object_mut().unwrap().remove(*field);
+                    }
+                }
+                values.push(value);
+            }
+            let r = response(300 + mask, values);
+            for aircraft in &r.aircraft {
+                let _ = Ac::new(r.now, aircraft, &params);
+                let mut ac = existing.clone();
+                ac.update(r.now, aircraft, &params);
+                let _ = state.aircraft.update(r.now, aircraft, &params);
+            }
+            detect(&mut state, &r, &params);
+        }
+        // A position so old that it can't be subtracted from now.
+        let mut value = aircraft("a12345", 34.0, -118.0, 450.0);
+        value["seen_pos"] = json!(1e15);
+        let r = response(600, vec![value]);
+        let ac = Ac::new(r.now, &r.aircraft[0], &params).unwrap();
+        assert_eq!(ac.seen, DateTime::<Utc>::MIN_UTC);
+        detect(&mut state, &r, &params);
+        let mut value = aircraft("a12345", 34.0, -118.0, 450.0);
+        value.as_object_mut().unwrap().remove("gs");
+        let r = response(660, vec![value]);
+        assert!(matches!(
+            Ac::new(r.now, &r.aircraft[0], &params),
+            Err(Error::MissingField {
+                field: "ground speed",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_num_threads_determinism() {
+        use crate::testutil::{Scenario, ScriptedAircraft};
+        // A few intercepts, an escort that stays with its target, and lots of
+        // other traffic, with noise.
+        let mut scenario = Scenario::new()
+            .interval_secs(15)
+            .duration_secs(900)
+            .noise(30.0, 40.0);
+        for i in 0..4 {
+            let target = ScriptedAircraft::straight(
+                &format!("a1234{}", i),
+                [-118.0 + i as f64, 34.0],
+                10_000.0,
+                90.0,
+                300.0,
+            );
+            let interceptor = ScriptedAircraft::intercepting(
+                &format!("ae123{}", i),
+                &target,
+                25.0,
+                0.0,
+                20_000.0,
+                480.0,
+            )
+            .vertical_rate(4000.0);
+            scenario = scenario.aircraft(interceptor).aircraft(target);
+        }
+        for i in 0..500 {
+            let (lon, lat) = (-120.0 + (i % 25) as f64 * 0.2, 33.0 + (i / 25) as f64 * 0.1);
+            scenario = scenario.aircraft(ScriptedAircraft::straight(
+                &format!("c{:05x}", i),
+                [lon, lat],
+                2000.0 + 70.0 * i as f64,
+                (i * 37 % 360) as f64,
+                if i % 20 == 0 { 500.0 } else { 250.0 },
+            ));
+        }
+        let responses = scenario.responses();
+        let params = DetectionParams::default();
+        let run = |num_threads| {
+            let mut state = AircraftStateMap::new();
+            state.set_num_threads(num_threads).unwrap();
+            let found: Vec<Vec<Interception>> = responses
+                .iter()
+                .map(|r| detect_frame_interceptions(&mut state, r, &params))
+                .collect();
+            (found, state)
+        };
+        let (found_1, state_1) = run(1);
+        let (found_8, state_8) = run(8);
+        assert!(found_1.iter().flatten().count() > 4);
+        assert_eq!(found_1, found_8);
+        assert_eq!(state_1.num_ac_processed, state_8.num_ac_processed);
+        assert_eq!(state_1.num_ac_indexed, state_8.num_ac_indexed);
+        assert_eq!(state_1.num_ac_missing_data, state_8.num_ac_missing_data);
+        assert_eq!(state_1.len(), state_8.len());
+    }
+
+    #[test]
+    fn test_record_event_tracks() {
+        use crate::testutil::standard_intercept;
+        let responses = standard_intercept();
+        // The aircraft only keep their last 6 positions, so the checks that
+        // look back further than that are turned off.
+        let params = DetectionParams {
+            record_event_tracks: true,
+            history_max_points: Some(6),
+            min_initial_separation_mi: 0.0,
+            convergence_frames: 0,
+            require_speed_match: false,
+            detect_shadows: false,
+            ..Default::default()
+        };
+        let mut state = State {
+            params: params.clone(),
+            ..Default::default()
+        };
+        for r in &responses {
+            process_adsbx_response(&mut state, r.clone(), &ProgressBar::hidden()).unwrap();
+        }
+        state.finish();
+        assert_eq!(state.events.len(), 1);
+        let event = &state.events[0];
+        assert!(event.closest.tracks.is_none());
+        for track in [&event.tracks.interceptor, &event.tracks.target] {
+            assert!(track.len() > 6, "{}", track.len());
+            assert_eq!(track[0].time, event.start);
+            assert!(track.windows(2).all(|w| w[0].time < w[1].time));
+            assert!(track.last().unwrap().time >= event.end);
+        }
+        let json = serde_json::to_value(event).unwrap();
+        assert!(json["tracks"]["target"][0]["lat"].is_f64());
+        assert!(json["closest"].get("tracks").is_none());
+
+        // A pair that's close, but never for long enough, is only recorded
+        // until it separates. And at most event_track_max_points are kept.
+        let params = DetectionParams {
+            min_proximity_frames: 1000,
+            min_proximity_secs: 100_000,
+            detect_shadows: false,
+            record_event_tracks: true,
+            event_track_max_points: 3,
+            ..Default::default()
+        };
+        let mut state = AircraftStateMap::new();
+        let mut max_recording = 0;
+        let mut recorded = PairTracks::default();
+        for r in &responses {
+            assert!(detect_frame_interceptions(&mut state, r, &params).is_empty());
+            max_recording = max_recording.max(state.num_recording());
+            if let Some(tracks) = state.recorder.take(&(id("ae1234"), id("a12345"))) {
+                recorded.extend(&tracks);
+            }
+        }
+        assert_eq!(max_recording, 1);
+        assert_eq!(state.num_recording(), 0);
+        assert_eq!(recorded.interceptor.len(), 3);
+        assert_eq!(recorded.target.len(), 3);
+        // Without record_event_tracks, nothing is.
+        let mut state = AircraftStateMap::new();
+        for r in &responses {
+            detect_frame_interceptions(&mut state, r, &DetectionParams::default());
+        }
+        assert_eq!(state.num_recording(), 0);
+    }
+
+    // An aircraft record with any combination of fields missing, and values
+    // anywhere from sensible to absurd.
+    fn arb_aircraft() -> impl proptest::strategy::Strategy<Value = Value> {
+        use proptest::{option::of, prelude::*};
+
+        let lat = prop_oneof![-90.0..=90.0, -1000.0..1000.0, Just(90.0), Just(-90.0)];
+        let lon = prop_oneof![-180.0..=180.0, -1000.0..1000.0, Just(179.999), Just(-180.0)];
+        let speed = prop_oneof![0.0..700.0, -1000.0..0.0, Just(1e9)];
+        let alt_baro = prop_oneof![
+            (-2000..60_000).prop_map(|alt| json!(alt)),
+            Just(json!("ground")),
+            any::<i32>().prop_map(|alt| json!(alt)),
+        ];
+        (
+            prop_oneof![Just("a00001"), Just("a00002"), Just("ae1234")],
+            (of(lat), of(lon), of(speed), of(-720.0..720.0)),
+            (of(alt_baro), of(any::<i32>()), of(-20_000..20_000)),
+            // Up to a few days.
+            (of(0.0..300_000.0), of("[0-7]{4}"), of("[A-Z0-9 ]{0,8}")),
+        )
+            .prop_map(
+                |(
+                    hex,
+                    (lat, lon, gs, track),
+                    (alt_baro, alt_geom, baro_rate),
+                    (seen_pos, squawk, flight),
+                )| {
+                    json!({
+                        "hex": hex, "type": "adsb_icao", "messages": 100, "rssi": -20.0,
+                        "seen": 0.0, "seen_pos": seen_pos, "lat": lat, "lon": lon, "gs": gs,
+                        "track": track, "alt_baro": alt_baro, "alt_geom": alt_geom,
+                        "baro_rate": baro_rate, "squawk": squawk, "flight": flight,
+                    })
+                },
+            )
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_ac_updates(
+            first in arb_aircraft(),
+            // Updates at any time from ten minutes before the first to two
+            // days after it, in any order.
+            updates in proptest::collection::vec((-600i64..172_800, arb_aircraft()), 0..60),
+        ) {
+            let params = DetectionParams::default();
+            let start = response(0, vec![]).now;
+            let first: Aircraft = serde_json::from_value(first).unwrap();
+            let Ok(mut ac) = Ac::new(start, &first, &params) else {
+                return Ok(());
+            };
+            let mut latest = start;
+            for (secs, aircraft) in updates {
+                let now = start + Duration::seconds(secs);
+                let aircraft: Aircraft = serde_json::from_value(aircraft).unwrap();
+                let max_speed = ac.max_speed;
+                ac.update(now, &aircraft, &params);
+                latest = latest.max(now);
+                proptest::prop_assert!(ac.coords.len() <= params.history_max_points.unwrap());
+                proptest::prop_assert_eq!(ac.alts.len(), ac.coords.len());
+                proptest::prop_assert_eq!(ac.tracks.len(), ac.coords.len());
+                proptest::prop_assert_eq!(ac.speeds.len(), ac.coords.len());
+                proptest::prop_assert!(ac.coords.iter().zip(ac.coords.iter().skip(1)).all(|(a, b)| a.0 <= b.0));
+                proptest::prop_assert!(ac.max_speed >= max_speed);
+                proptest::prop_assert!(ac.fast.time_seen_fast.is_none_or(|t| t <= latest));
+                // None of these should panic, whatever the history.
+                ac.known_alt();
+                ac.vertical_rate_fpm(Duration::seconds(60));
+                ac.estimated_position_at(now, Duration::seconds(60));
+                ac.alt_at(now - Duration::seconds(30));
+                ac.heading_at(ac.coords.len() - 1);
+                ac.is_fast_mover(now, &params);
+            }
+        }
+
+        #[test]
+        fn prop_detect_interceptions(
+            snapshots in proptest::collection::vec(
+                (-600i64..7200, proptest::collection::vec(arb_aircraft(), 0..4)),
+                0..30,
+            ),
+        ) {
+            let params = DetectionParams::default();
+            let mut state = State::default();
+            for (secs, aircraft) in snapshots {
+                let r = response(secs, aircraft);
+                detect(&mut state, &r, &params);
+                state.aircraft.expire(r.now, Duration::minutes(params.stale_aircraft_mins));
+            }
+        }
+    }
+}
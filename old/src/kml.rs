@@ -0,0 +1,168 @@
+//! KML and KMZ export of interceptions, for looking at them in Google Earth.
+//!
+//! Each interception becomes a folder with a time-stamped gx:Track for the
+//! interceptor and for the target, and a placemark where they met. Altitudes
+//! are written in meters with altitudeMode absolute.
+
+use std::{
+    fmt::Write as _,
+    io::{Seek, Write},
+};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    error::Error,
+    interception::{Ac, Interception},
+};
+
+const METERS_PER_FOOT: f64 = 0.3048;
+
+// KML colors are aabbggrr.
+const STYLES: &str = r#"<Style id="interceptor"><LineStyle><color>ff0000ff</color><width>3</width></LineStyle><IconStyle><color>ff0000ff</color></IconStyle></Style>
+<Style id="target"><LineStyle><color>ffff0000</color><width>3</width></LineStyle><IconStyle><color>ffff0000</color></IconStyle></Style>
+<Style id="interception"><IconStyle><color>ff00ffff</color><scale>1.2</scale><Icon><href>http://maps.google.com/mapfiles/kml/shapes/star.png</href></Icon></IconStyle></Style>
+"#;
+
+fn write_track(kml: &mut String, ac: &Ac, style: &str) {
+    writeln!(
+        kml,
+        "<Placemark><name>{}</name><styleUrl>#{}</styleUrl><gx:Track><altitudeMode>absolute</altitudeMode>",
+        ac.hex,
+        style
+    )
+    .unwrap();
+    for (time, _) in &ac.coords {
+        writeln!(kml, "<when>{}</when>", time.to_rfc3339()).unwrap();
+    }
+    for (i, (_, [lon, lat])) in ac.coords.iter().enumerate() {
+        // Records from before altitudes were kept only have the current one.
+        let alt_ft = ac.alts.get(i).copied().unwrap_or(ac.cur_alt);
+        writeln!(
+            kml,
+            "<gx:coord>{} {} {:.1}</gx:coord>",
+            lon,
+            lat,
+            alt_ft as f64 * METERS_PER_FOOT
+        )
+        .unwrap();
+    }
+    kml.push_str("</gx:Track></Placemark>\n");
+}
+
+fn write_interception(kml: &mut String, interception: &Interception) {
+    let interceptor = &interception.interceptor;
+    let target = &interception.target;
+    let time = interception.time.to_rfc3339();
+    writeln!(
+        kml,
+        "<Folder><name>{} intercepted {} at {}</name>",
+        interceptor.hex, target.hex, time
+    )
+    .unwrap();
+    write_track(kml, interceptor, "interceptor");
+    write_track(kml, target, "target");
+    let [lon, lat] = interception.location();
+    let alt_ft = (interceptor.cur_alt + target.cur_alt) as f64 / 2.0;
+    writeln!(
+        kml,
+        "<Placemark><name>Interception</name><styleUrl>#interception</styleUrl><TimeStamp><when>{}</when></TimeStamp><description>{:.0} ft lateral separation, {} ft vertical separation</description><Point><altitudeMode>absolute</altitudeMode><coordinates>{},{},{:.1}</coordinates></Point></Placemark>",
+        time,
+        interception.lateral_separation_ft,
+        interception.vertical_separation_ft,
+        lon,
+        lat,
+        alt_ft * METERS_PER_FOOT
+    )
+    .unwrap();
+    kml.push_str("</Folder>\n");
+}
+
+/// Converts interceptions into a KML document.
+pub fn interceptions_to_kml(interceptions: &[Interception]) -> String {
+    let mut kml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+<Document>
+"#,
+    );
+    kml.push_str(STYLES);
+    for interception in interceptions {
+        write_interception(&mut kml, interception);
+    }
+    kml.push_str("</Document>\n</kml>\n");
+    kml
+}
+
+/// Writes interceptions as a KMZ file (a zip archive containing doc.kml).
+pub fn write_interceptions_kmz<W: Write + Seek>(
+    interceptions: &[Interception],
+    writer: W,
+) -> Result<(), Error> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("doc.kml", options)
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    zip.write_all(interceptions_to_kml(interceptions).as_bytes())
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    zip.finish()
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    Ok(())
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_snapshot_filename_time;
+    use std::io::Read;
+
+    fn interception() -> Interception {
+        let start = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        let ac = |hex: &str, coords: &[[f64; 2]], alts: &[i32]| {
+            let coords: Vec<_> = coords
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (start + chrono::Duration::seconds(15 * i as i64), *c))
+                .collect();
+            Ac {
+                alts: alts.iter().copied().collect(),
+                ..Ac::for_test(hex, &coords, *alts.last().unwrap())
+            }
+        };
+        Interception::for_test(
+            ac("ae1234", &[[-118.0, 34.1], [-118.0, 34.0]], &[12000, 10000]),
+            ac(
+                "a12345",
+                &[[-118.1, 34.0], [-118.002, 34.0]],
+                &[10000, 10000],
+            ),
+            start + chrono::Duration::seconds(15),
+        )
+    }
+
+    #[test]
+    fn test_interceptions_to_kml() {
+        let kml = interceptions_to_kml(&[interception()]);
+        assert!(kml.contains("<when>2021-02-23T19:39:08+00:00</when>"));
+        // 12000 ft is 3657.6 m.
+        assert!(kml.contains("<gx:coord>-118 34.1 3657.6</gx:coord>"));
+        assert!(kml.contains("<styleUrl>#target</styleUrl>"));
+        assert!(kml.contains("<coordinates>-118.001,34,3048.0</coordinates>"));
+        assert_eq!(kml.matches("<Placemark>").count(), 3);
+    }
+
+    #[test]
+    fn test_write_interceptions_kmz() {
+        let mut kmz = std::io::Cursor::new(vec![]);
+        write_interceptions_kmz(&[interception()], &mut kmz).unwrap();
+        let mut archive = zip::ZipArchive::new(kmz).unwrap();
+        let mut kml = String::new();
+        archive
+            .by_name("doc.kml")
+            .unwrap()
+            .read_to_string(&mut kml)
+            .unwrap();
+        assert_eq!(kml, interceptions_to_kml(&[interception()]));
+    }
+}
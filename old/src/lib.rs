@@ -1,81 +1,406 @@
-use std::{io::Read, str::FromStr};
+use std::io::{BufRead, BufReader, Read};
 
 use adsbx_json::v2::{Aircraft, AltitudeOrGround};
 use error::Error;
 use indicatif::{ProgressBar, ProgressStyle};
-use pariter::IteratorExt;
+use input::TimeRange;
+use order::InputOrder;
+use pipeline::AdsbxPipeline;
+use tracing::warn;
 
+#[cfg(feature = "aircraft_db")]
+pub mod aircraft_db;
+pub mod airports;
+pub mod confidence;
+pub mod config;
+pub mod cot;
+pub mod country;
+pub mod cpa;
+pub mod csv;
+pub mod drops;
 pub mod error;
+pub mod events;
+pub mod explain;
+pub mod export;
+pub mod filter;
+pub mod geo;
+pub mod geojson;
+pub mod heatmap;
+pub mod hex_id;
+pub mod input;
 pub mod interception;
+pub mod kml;
+#[cfg(feature = "live")]
+pub mod live;
+pub mod metrics;
+pub mod military;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod notify;
+pub mod order;
+pub mod pair;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod pattern;
+pub mod persist;
+pub mod pipeline;
+pub mod quality;
+pub mod readsb;
+pub mod recording;
+pub mod region;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod replay;
+pub mod report;
+pub mod screen;
+mod shards;
+pub mod spatial;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testutil;
+pub mod trace;
+pub mod tracker;
+pub mod trim;
+pub mod v1;
+pub mod watch;
 
-/// Loads a JSON file containing an ADS-B Exchange API response and parses it
-/// into a struct.
+/// The compression formats that input files can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Bzip2,
+    Gzip,
+    Zstd,
+}
 
-pub fn load_adsbx_json_file(path: &str) -> Result<adsbx_json::v2::Response, Error> {
-    let mut json_contents = String::new();
-    if path.ends_with(".bz2") {
-        let file = std::fs::File::open(path).map_err(|e| Error::JsonLoadError(e.to_string()))?;
+impl Compression {
+    /// Guesses the compression format from a file's extension. Returns None if
+    /// the extension isn't one we recognize.
+    pub fn from_path(path: &str) -> Option<Compression> {
+        if path.ends_with(".bz2") {
+            Some(Compression::Bzip2)
+        } else if path.ends_with(".gz") {
+            Some(Compression::Gzip)
+        } else if path.ends_with(".zst") || path.ends_with(".zstd") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// The extension from_path recognizes it by, e.g. ".bz2", or "" for
+    /// None.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Bzip2 => ".bz2",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Guesses the compression format from the first few bytes of a file.
+    pub fn from_magic(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// A reader that puts the Error each of its io errors should turn into inside
+/// the io error, so that once they've come out of a decompressor and a JSON
+/// parser, errors reading a file can still be told apart from errors
+/// decompressing it. Errors that are already tagged are passed through. See
+/// Error::from_io.
+struct TaggedReader<R> {
+    inner: R,
+    /// What the data is being decompressed from, or None if these are errors
+    /// reading the file itself.
+    compression: Option<Compression>,
+}
+
+impl<R> TaggedReader<R> {
+    fn new(inner: R, compression: Option<Compression>) -> Self {
+        TaggedReader { inner, compression }
+    }
+
+    fn tag(compression: Option<Compression>, e: std::io::Error) -> std::io::Error {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return e;
+        }
+        let kind = e.kind();
+        let err = match compression {
+            Some(compression) => Error::Decompress {
+                compression,
+                source: e,
+            },
+            None => Error::Io(e),
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+impl<R: Read> Read for TaggedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let compression = self.compression;
+        self.inner.read(buf).map_err(|e| Self::tag(compression, e))
+    }
+}
+
+impl<R: BufRead> BufRead for TaggedReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        let compression = self.compression;
+        self.inner.fill_buf().map_err(|e| Self::tag(compression, e))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+/// Opens a file containing an ADS-B Exchange API response and returns a reader
+/// that yields the decompressed JSON.
+///
+/// The compression format is determined by the file's extension, or if that
+/// isn't recognized, by sniffing the file's magic bytes. Errors opening the
+/// file are returned as an Error::FileError. Errors reading from the reader
+/// are io errors, which load_adsbx_json_reader turns back into an Error::Io
+/// or Error::Decompress.
+pub fn open_adsbx_json_file(path: &str) -> Result<Box<dyn Read>, Error> {
+    open_compressed(path).map_err(|e| e.in_file(path))
+}
+
+fn open_compressed(path: &str) -> Result<Box<dyn Read>, Error> {
+    let file = std::fs::File::open(path).map_err(Error::Io)?;
+    let mut reader = BufReader::new(file);
+    let compression = match Compression::from_path(path) {
+        Some(compression) => compression,
+        None => Compression::from_magic(reader.fill_buf().map_err(Error::Io)?),
+    };
+    let reader = TaggedReader::new(reader, None);
+    let tagged = Some(compression);
+    Ok(match compression {
         // Need to use MultiBZDecoder to decode something compressed with pbzip2.
-        let mut decompressor = bzip2::read::MultiBzDecoder::new(file);
-        decompressor
-            .read_to_string(&mut json_contents)
-            .map_err(|e| Error::JsonLoadError(e.to_string()))?;
-    } else {
-        std::fs::File::open(path)
-            .map_err(|e| Error::JsonLoadError(e.to_string()))?
-            .read_to_string(&mut json_contents)
-            .map_err(|e| Error::JsonLoadError(e.to_string()))?;
+        Compression::Bzip2 => Box::new(TaggedReader::new(
+            bzip2::read::MultiBzDecoder::new(reader),
+            tagged,
+        )),
+        // Likewise, gzip files are sometimes concatenations of multiple members.
+        Compression::Gzip => Box::new(TaggedReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+            tagged,
+        )),
+        // The zstd decoder reads all frames, so files produced by pzstd or
+        // zstd --rsyncable work too.
+        Compression::Zstd => Box::new(TaggedReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader).map_err(|source| {
+                Error::Decompress {
+                    compression,
+                    source,
+                }
+            })?,
+            tagged,
+        )),
+        Compression::None => Box::new(reader),
+    })
+}
+
+/// How many bytes at the start of a response we look at to decide which format
+/// it's in.
+const FORMAT_SNIFF_LEN: u64 = 4096;
+
+/// The JSON formats that we can load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The ADS-B Exchange v2 API.
+    V2,
+    /// The older ADS-B Exchange v1 API ("acList").
+    V1,
+    /// readsb/tar1090 aircraft.json.
+    Readsb,
+}
+
+impl Format {
+    /// Guesses the format of a response from the first few KB of its JSON,
+    /// based on which top-level key holds the aircraft.
+    pub fn sniff(prefix: &[u8]) -> Format {
+        let has_key = |key: &[u8]| prefix.windows(key.len()).any(|w| w == key);
+        if has_key(b"\"acList\"") {
+            Format::V1
+        } else if has_key(b"\"aircraft\"") {
+            Format::Readsb
+        } else {
+            Format::V2
+        }
+    }
+}
+
+/// The JSON parser to use for responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsonBackend {
+    /// serde_json, which can parse JSON as it's read. The default, unless
+    /// the simd_json feature is enabled.
+    #[cfg_attr(not(feature = "simd_json"), default)]
+    SerdeJson,
+    /// simd-json, which needs all of the JSON in a buffer, and overwrites it
+    /// while parsing. The default with the simd_json feature. Whether it's
+    /// faster than serde_json depends on the machine; the parse benchmarks
+    /// compare them.
+    #[cfg(feature = "simd_json")]
+    #[default]
+    SimdJson,
+}
+
+fn from_json_slice<T: serde::de::DeserializeOwned>(
+    json: &mut [u8],
+    backend: JsonBackend,
+) -> Result<T, Error> {
+    match backend {
+        JsonBackend::SerdeJson => serde_json::from_slice(json).map_err(Error::from_json),
+        #[cfg(feature = "simd_json")]
+        JsonBackend::SimdJson => simd_json::serde::from_slice(json).map_err(|e| Error::Parse {
+            line: None,
+            column: None,
+            source: Box::new(e),
+        }),
     }
-    adsbx_json::v2::Response::from_str(&json_contents)
-        .map_err(|e| Error::JsonLoadError(e.to_string()))
+}
+
+/// Parses an ADS-B Exchange API response from a buffer holding all of its
+/// JSON, with the given backend. Like load_adsbx_json_reader, v1 and readsb
+/// responses are converted to v2 (readsb ones always with serde_json). The
+/// simd-json backend leaves garbage in the buffer.
+pub fn parse_adsbx_json(
+    json: &mut [u8],
+    backend: JsonBackend,
+) -> Result<adsbx_json::v2::Response, Error> {
+    let prefix_len = json.len().min(FORMAT_SNIFF_LEN as usize);
+    match Format::sniff(&json[..prefix_len]) {
+        Format::V1 => from_json_slice::<v1::Response>(json, backend)?.to_v2(),
+        Format::Readsb => readsb::load_readsb_json_reader(&json[..]),
+        Format::V2 => from_json_slice(json, backend),
+    }
+}
+
+#[cfg(feature = "simd_json")]
+thread_local! {
+    // The buffer load_adsbx_json_reader reads responses into for simd-json,
+    // kept so that each response doesn't need a new one.
+    static JSON_BUFFER: std::cell::RefCell<Vec<u8>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Parses an ADS-B Exchange API response from a reader that yields JSON.
+///
+/// With the serde_json backend, the JSON is parsed as it's read, so the full
+/// text of the response never has to be held in memory. With simd-json (see
+/// JsonBackend), it's read into a buffer that's reused for each response on
+/// the same thread. Responses in the older v1 format and readsb aircraft.json
+/// snapshots are detected and converted to v2.
+pub fn load_adsbx_json_reader<R: Read>(r: R) -> Result<adsbx_json::v2::Response, Error> {
+    #[cfg(feature = "simd_json")]
+    if JsonBackend::default() == JsonBackend::SimdJson {
+        let mut r = r;
+        return JSON_BUFFER.with(|buf| {
+            let mut buf = buf.borrow_mut();
+            buf.clear();
+            r.read_to_end(&mut buf).map_err(Error::from_io)?;
+            parse_adsbx_json(&mut buf, JsonBackend::SimdJson)
+        });
+    }
+    let mut r = BufReader::new(r);
+    let mut prefix = vec![];
+    (&mut r)
+        .take(FORMAT_SNIFF_LEN)
+        .read_to_end(&mut prefix)
+        .map_err(Error::from_io)?;
+    let format = Format::sniff(&prefix);
+    let r = prefix.as_slice().chain(r);
+    match format {
+        Format::V1 => {
+            let response: v1::Response = serde_json::from_reader(r).map_err(Error::from_json)?;
+            response.to_v2()
+        }
+        Format::Readsb => readsb::load_readsb_json_reader(r),
+        Format::V2 => serde_json::from_reader(r).map_err(Error::from_json),
+    }
+}
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// Loads a JSON file containing an ADS-B Exchange API response and parses it
+/// into a struct. Errors are returned as an Error::FileError naming the file,
+/// with the underlying Error::Io, Error::Decompress, or Error::Parse as its
+/// source.
+
+pub fn load_adsbx_json_file(path: &str) -> Result<adsbx_json::v2::Response, Error> {
+    load_adsbx_json_reader(open_adsbx_json_file(path)?).map_err(|e| e.in_file(path))
 }
 
 // Processes a collection of files containing ADS-B Exchange API responses.
 // Decompresses and parses files in parallel, but calls the callback function
 // serially.
+//
+// If a file can't be loaded and skip_json_errors is false, processing stops
+// and an Error::FileError naming the file is returned. Errors returned by the
+// callback are passed through as-is.
+//
+// This is a shortcut for AdsbxPipeline with the default settings; use the
+// pipeline directly for more control.
 
-pub fn for_each_adsbx_json<F>(
+pub fn for_each_adsbx_json<F>(paths: &[String], skip_json_errors: bool, f: F) -> Result<(), Error>
+where
+    F: FnMut(adsbx_json::v2::Response, &ProgressBar) -> Result<(), Error>,
+{
+    AdsbxPipeline::new(paths)
+        .skip_errors(skip_json_errors)
+        .run(f)
+        .map(|_| ())
+}
+
+// Like for_each_adsbx_json, but checks that responses are in chronological
+// order (or re-sorts them within a small window, depending on order).
+
+pub fn for_each_adsbx_json_ordered<F>(
     paths: &[String],
     skip_json_errors: bool,
-    mut f: F,
+    order: InputOrder,
+    f: F,
 ) -> Result<(), Error>
 where
     F: FnMut(adsbx_json::v2::Response, &ProgressBar) -> Result<(), Error>,
 {
-    let bar = ProgressBar::new(paths.len().try_into().unwrap());
-    bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{wide_bar} {pos}/{len} {eta} {elapsed_precise} {msg}"),
-    );
-    let r = pariter::scope(|scope| {
-        paths
-            .iter()
-            .parallel_map_scoped(scope, |path| match load_adsbx_json_file(path) {
-                Ok(response) => Ok(response),
-                Err(err) => Err((path, err)),
-            })
-            .try_for_each(|result| {
-                let r = match result {
-                    Ok(response) => f(response, &bar),
-                    Err((path, err)) => {
-                        eprintln!("Error reading file {}: {}\n", path, err);
-                        if !skip_json_errors {
-                            Err(err)
-                        } else {
-                            Ok(())
-                        }
-                    }
-                };
-                bar.inc(1);
-                r
-            })
-    })
-    .map_err(|e| Error::ParallelMapError(format!("{:?}", e)));
-    bar.finish();
-    match r {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
-    }
+    AdsbxPipeline::new(paths)
+        .skip_errors(skip_json_errors)
+        .order(order)
+        .run(f)
+        .map(|_| ())
+}
+
+// Like for_each_adsbx_json, but only calls the callback for responses within
+// the time range. Files whose names contain a timestamp outside the range are
+// skipped without being decompressed.
+
+pub fn for_each_adsbx_json_in_range<F>(
+    paths: &[String],
+    skip_json_errors: bool,
+    range: &TimeRange,
+    f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(adsbx_json::v2::Response, &ProgressBar) -> Result<(), Error>,
+{
+    AdsbxPipeline::new(paths)
+        .skip_errors(skip_json_errors)
+        .time_range(*range)
+        .run(f)
+        .map(|_| ())
 }
 
 pub fn try_fold_adsbx_json<B, F, R>(
@@ -87,44 +412,102 @@ pub fn try_fold_adsbx_json<B, F, R>(
 where
     F: FnMut(B, adsbx_json::v2::Response, &ProgressBar) -> Result<B, Error>,
 {
-    let bar = ProgressBar::new(paths.len().try_into().unwrap());
+    let mut acc = Some(init);
+    AdsbxPipeline::new(paths)
+        .skip_errors(skip_json_errors)
+        .run(|response, bar| {
+            acc = Some(f(acc.take().unwrap(), response, bar)?);
+            Ok(())
+        })?;
+    Ok(acc.unwrap())
+}
+
+pub(crate) fn spinner() -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
     bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{wide_bar} {pos}/{len} {eta} {elapsed_precise} {msg}"),
+        ProgressStyle::default_spinner().template("{spinner} {pos} {elapsed_precise} {msg}"),
     );
-    let r = pariter::scope(|scope| {
-        paths
-            .iter()
-            .parallel_map_scoped(scope, |path| match load_adsbx_json_file(path) {
-                Ok(response) => Ok(response),
-                Err(err) => Err((path, err)),
-            })
-            .try_fold(init, |init, result| {
-                let r = match result {
-                    Ok(response) => f(init, response, &bar),
-                    Err((path, err)) => {
-                        eprintln!("Error reading file {}: {}\n", path, err);
-                        if !skip_json_errors {
-                            Err(Error::JsonLoadError("BLAH".to_string()))
-                        } else {
-                            Ok(init)
-                        }
-                    }
-                };
-                bar.inc(1);
-                r
-            })
-    })
-    .map_err(|e| Error::ParallelMapError(format!("{:?}", e)));
+    bar
+}
+
+// Processes a stream of newline-delimited ADS-B Exchange API responses, e.g.
+// on stdin. Each non-blank line must contain one complete response. Since the
+// number of responses isn't known in advance, a spinner is shown instead of a
+// progress bar.
+
+pub fn for_each_adsbx_json_from_reader<R, F>(
+    reader: R,
+    skip_json_errors: bool,
+    mut f: F,
+) -> Result<(), Error>
+where
+    R: BufRead,
+    F: FnMut(adsbx_json::v2::Response, &ProgressBar) -> Result<(), Error>,
+{
+    let bar = spinner();
+    let r = reader.lines().enumerate().try_for_each(|(i, line)| {
+        let line = line.map_err(Error::Io)?;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let r = match load_adsbx_json_reader(line.as_bytes()) {
+            Ok(response) => f(response, &bar),
+            Err(err) => {
+                warn!("Error reading line {}: {}", i + 1, err);
+                if !skip_json_errors {
+                    Err(Error::FileError {
+                        path: format!("line {}", i + 1),
+                        source: Box::new(err),
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        bar.inc(1);
+        r
+    });
     bar.finish();
-    match r {
-        Ok(b) => b,
-        Err(e) => Err(e),
-    }
+    r
 }
 
-/// Turns an altitude into a number (where ground is 0).
+// Processes a sequence of readers that each yield a single ADS-B Exchange API
+// response. Readers are consumed one at a time, in order.
 
+pub fn for_each_adsbx_json_from_readers<I, R, F>(
+    readers: I,
+    skip_json_errors: bool,
+    mut f: F,
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    F: FnMut(adsbx_json::v2::Response, &ProgressBar) -> Result<(), Error>,
+{
+    let bar = spinner();
+    let r = readers.into_iter().enumerate().try_for_each(|(i, reader)| {
+        let r = match load_adsbx_json_reader(reader) {
+            Ok(response) => f(response, &bar),
+            Err(err) => {
+                warn!("Error reading input {}: {}", i + 1, err);
+                if !skip_json_errors {
+                    Err(Error::FileError {
+                        path: format!("input {}", i + 1),
+                        source: Box::new(err),
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        bar.inc(1);
+        r
+    });
+    bar.finish();
+    r
+}
+
+/// Turns an altitude into a number (where ground is 0).
 pub fn alt_number(alt: AltitudeOrGround) -> i32 {
     match alt {
         AltitudeOrGround::OnGround => 0,
@@ -132,10 +515,303 @@ pub fn alt_number(alt: AltitudeOrGround) -> i32 {
     }
 }
 
-// Checks whether an aircraft seems to be on the ground (or very close to it).
+/// Checks whether an aircraft seems to be on the ground: its barometric
+/// altitude is reported as "ground" or, if max_geom_alt_ft is given, its
+/// geometric altitude is below that. Returns None if it didn't report either
+/// altitude, since that says nothing either way.
+///
+/// Geometric altitude is above mean sea level, not above the ground, so
+/// max_geom_alt_ft is only a rough guess: with 500 ft, a floatplane at 300 ft
+/// or anything over Death Valley counts as on the ground, and nothing on the
+/// ground in Denver does.
+pub fn aircraft_is_on_ground(aircraft: &Aircraft, max_geom_alt_ft: Option<i32>) -> Option<bool> {
+    if aircraft.barometric_altitude.is_none() && aircraft.geometric_altitude.is_none() {
+        return None;
+    }
+    Some(
+        aircraft.barometric_altitude == Some(AltitudeOrGround::OnGround)
+            || aircraft
+                .geometric_altitude
+                .zip(max_geom_alt_ft)
+                .is_some_and(|(alt, max_alt)| alt < max_alt),
+    )
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_path(name: &str) -> String {
+        format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn mixed_paths() -> Vec<String> {
+        vec![
+            data_path("2021-02-23-193853Z.json"),
+            data_path("corrupt.json"),
+            data_path("2021-02-23-193858Z.json"),
+        ]
+    }
+
+    #[test]
+    fn test_skip_json_errors() {
+        let mut num_responses = 0;
+        let r = for_each_adsbx_json(&mixed_paths(), true, |_response, _bar| {
+            num_responses += 1;
+            Ok(())
+        });
+        assert!(r.is_ok());
+        assert_eq!(num_responses, 2);
+    }
+
+    #[test]
+    fn test_json_error_is_returned() {
+        let r = for_each_adsbx_json(&mixed_paths(), false, |_response, _bar| Ok(()));
+        match r {
+            Err(Error::FileError { path, .. }) => assert!(path.ends_with("corrupt.json")),
+            other => panic!("Expected FileError, got {:?}", other),
+        }
+        let r = try_fold_adsbx_json::<_, _, ()>(&mixed_paths(), false, 0, |n, _response, _bar| {
+            Ok(n + 1)
+        });
+        assert!(matches!(r, Err(Error::FileError { .. })));
+    }
+
+    #[test]
+    fn test_load_compressed() {
+        let plain = load_adsbx_json_file(&data_path("2021-02-23-193853Z.json")).unwrap();
+        let gzipped = load_adsbx_json_file(&data_path("2021-02-23-193853Z.json.gz")).unwrap();
+        assert_eq!(plain, gzipped);
+        let bzipped = load_adsbx_json_file(&data_path("2021-02-23-193858Z.json.bz2")).unwrap();
+        assert_eq!(bzipped.aircraft.len(), 2);
+        // Misnamed files are detected by their magic bytes.
+        let misnamed = load_adsbx_json_file(&data_path("gzipped-misnamed.json")).unwrap();
+        assert_eq!(misnamed, bzipped);
+        // This one has two zstd frames.
+        let zstd = load_adsbx_json_file(&data_path("2021-02-23-193858Z.json.zst")).unwrap();
+        assert_eq!(zstd, bzipped);
+    }
+
+    #[test]
+    fn test_zstd_decode_error() {
+        // A plain JSON file with a zstd name isn't a valid zstd stream.
+        let path = data_path("2021-02-23-193853Z.json");
+        let dir = tempfile::tempdir().unwrap();
+        let bad_path = dir.path().join("not-really.json.zst");
+        std::fs::copy(&path, &bad_path).unwrap();
+        let bad_path = bad_path.to_str().unwrap();
+        match load_adsbx_json_file(bad_path) {
+            Err(Error::FileError { path, source }) => {
+                assert_eq!(path, bad_path);
+                assert!(matches!(
+                    *source,
+                    Error::Decompress {
+                        compression: Compression::Zstd,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("Expected FileError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_variants() {
+        use std::error::Error as _;
+        fn load_error(path: &str) -> Error {
+            match load_adsbx_json_file(path) {
+                Err(Error::FileError { path: p, source }) => {
+                    assert_eq!(p, path);
+                    *source
+                }
+                other => panic!("Expected FileError, got {:?}", other),
+            }
+        }
+        match load_error(&data_path("no-such-file.json")) {
+            Error::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("Expected Io, got {:?}", other),
+        }
+        match load_error(&data_path("corrupt.json")) {
+            // simd-json doesn't say where errors are.
+            Error::Parse { line, column, .. } => {
+                if JsonBackend::default() == JsonBackend::SerdeJson {
+                    assert_eq!(line, Some(1));
+                    assert!(column.is_some());
+                }
+            }
+            other => panic!("Expected Parse, got {:?}", other),
+        }
+        // A truncated bzip2 file.
+        let bz2 = std::fs::read(data_path("2021-02-23-193858Z.json.bz2")).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let truncated = dir.path().join("truncated.json.bz2");
+        std::fs::write(&truncated, &bz2[..bz2.len() / 2]).unwrap();
+        let err = load_error(truncated.to_str().unwrap());
+        assert!(
+            matches!(
+                err,
+                Error::Decompress {
+                    compression: Compression::Bzip2,
+                    ..
+                }
+            ),
+            "Expected Decompress, got {:?}",
+            err
+        );
+        assert!(err.source().is_some());
+        // Valid JSON that doesn't match the schema.
+        assert!(matches!(
+            load_adsbx_json_reader(&b"{\"now\": \"yesterday\"}"[..]),
+            Err(Error::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_from_reader() {
+        let file = std::fs::File::open(data_path("2021-02-23-193853Z.json")).unwrap();
+        let response = load_adsbx_json_reader(file).unwrap();
+        assert_eq!(response.aircraft.len(), 2);
+        assert!(load_adsbx_json_reader(&b"{\"now\": 16"[..]).is_err());
+    }
+
+    #[test]
+    fn test_json_backends() {
+        for name in [
+            "2021-02-23-193853Z.json",
+            "v1-2019-06-01-120000Z.json",
+            "readsb-aircraft.json",
+        ] {
+            let json = std::fs::read(data_path(name)).unwrap();
+            let expected = load_adsbx_json_reader(json.as_slice()).unwrap();
+            let mut buf = json.clone();
+            assert_eq!(
+                parse_adsbx_json(&mut buf, JsonBackend::SerdeJson).unwrap(),
+                expected
+            );
+            let mut buf = json.clone();
+            assert_eq!(
+                parse_adsbx_json(&mut buf, JsonBackend::default()).unwrap(),
+                expected
+            );
+        }
+        let mut buf = b"{\"now\": 16".to_vec();
+        assert!(parse_adsbx_json(&mut buf, JsonBackend::default()).is_err());
+    }
+
+    #[test]
+    fn test_for_each_from_reader() {
+        let mut input = String::new();
+        for name in ["2021-02-23-193853Z.json", "2021-02-23-193858Z.json"] {
+            let response = load_adsbx_json_file(&data_path(name)).unwrap();
+            input.push_str(&serde_json::to_string(&response).unwrap());
+            input.push_str("\n\n");
+        }
+        let mut nows = vec![];
+        for_each_adsbx_json_from_reader(input.as_bytes(), false, |response, _bar| {
+            nows.push(response.now);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(nows.len(), 2);
+        assert!(nows[0] < nows[1]);
 
-pub fn aircraft_is_on_ground(aircraft: &Aircraft) -> bool {
-    (aircraft.barometric_altitude.is_some()
-        && aircraft.barometric_altitude.as_ref().unwrap() == &AltitudeOrGround::OnGround)
-        || (aircraft.geometric_altitude.is_some() && aircraft.geometric_altitude.unwrap() < 500)
+        input.push_str("{\"now\": 1614\n");
+        let r = for_each_adsbx_json_from_reader(input.as_bytes(), false, |_, _| Ok(()));
+        assert!(matches!(r, Err(Error::FileError { .. })));
+        let r = for_each_adsbx_json_from_reader(input.as_bytes(), true, |_, _| Ok(()));
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    fn test_for_each_from_readers() {
+        let readers = ["2021-02-23-193853Z.json.gz", "2021-02-23-193858Z.json.bz2"]
+            .iter()
+            .map(|name| open_adsbx_json_file(&data_path(name)).unwrap());
+        let mut num_responses = 0;
+        for_each_adsbx_json_from_readers(readers, false, |_response, _bar| {
+            num_responses += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(num_responses, 2);
+    }
+
+    #[test]
+    fn test_for_each_ordered() {
+        let paths = vec![
+            data_path("2021-02-23-193858Z.json"),
+            data_path("2021-02-23-193853Z.json"),
+        ];
+        let r = for_each_adsbx_json_ordered(&paths, false, InputOrder::Strict, |_, _| Ok(()));
+        assert!(matches!(r, Err(Error::OutOfOrderInput { .. })));
+        let mut nows = vec![];
+        for_each_adsbx_json_ordered(&paths, false, InputOrder::Reorder(4), |response, _| {
+            nows.push(response.now);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(nows.len(), 2);
+        assert!(nows[0] < nows[1]);
+    }
+
+    #[test]
+    fn test_for_each_in_range() {
+        use chrono::prelude::*;
+        // gzipped-misnamed.json has no timestamp in its name, so it has to be
+        // parsed to find out that it's out of range.
+        let paths = vec![
+            data_path("2021-02-23-193853Z.json"),
+            data_path("2021-02-23-193858Z.json"),
+            data_path("gzipped-misnamed.json"),
+        ];
+        let range = TimeRange::new(
+            Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 50).unwrap(),
+            Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 55).unwrap(),
+        );
+        let mut nows = vec![];
+        for_each_adsbx_json_in_range(&paths, false, &range, |response, _| {
+            nows.push(response.now);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(nows.len(), 1);
+    }
+
+    #[test]
+    fn test_mixed_compression() {
+        let paths = vec![
+            data_path("2021-02-23-193853Z.json.gz"),
+            data_path("2021-02-23-193858Z.json.bz2"),
+            data_path("2021-02-23-193858Z.json.zst"),
+            data_path("2021-02-23-193858Z.json"),
+        ];
+        let mut num_responses = 0;
+        for_each_adsbx_json(&paths, false, |_response, _bar| {
+            num_responses += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(num_responses, 4);
+    }
+
+    #[test]
+    fn test_aircraft_is_on_ground() {
+        let aircraft = |alt_baro: serde_json::Value, alt_geom: Option<i32>| -> Aircraft {
+            serde_json::from_value(serde_json::json!({
+                "hex": "a12345", "type": "adsb_icao", "messages": 100, "rssi": -20.0,
+                "seen": 0.0, "alt_baro": alt_baro, "alt_geom": alt_geom,
+            }))
+            .unwrap()
+        };
+        let taxiing = aircraft("ground".into(), Some(5400));
+        assert_eq!(aircraft_is_on_ground(&taxiing, None), Some(true));
+        // A floatplane at 300 ft is only on the ground if we guess from
+        // geometric altitude.
+        let floatplane = aircraft(250.into(), Some(300));
+        assert_eq!(aircraft_is_on_ground(&floatplane, None), Some(false));
+        assert_eq!(aircraft_is_on_ground(&floatplane, Some(500)), Some(true));
+        let no_alt = aircraft(serde_json::Value::Null, None);
+        assert_eq!(aircraft_is_on_ground(&no_alt, Some(500)), None);
+    }
 }
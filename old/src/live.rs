@@ -0,0 +1,380 @@
+//! Polling a live ADS-B API, for running detection continuously instead of
+//! over archived snapshots.
+//!
+//! Any endpoint that returns a v2 (or readsb-style) response works: the ADS-B
+//! Exchange API with an API key, or the free adsb.fi and adsb.lol APIs.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use tracon::live::LivePoller;
+//!
+//! LivePoller::new("https://opendata.adsb.fi/api/v2/lat/34.0/lon/-118.0/dist/250")
+//!     .interval(Duration::from_secs(5))
+//!     .stop_on_ctrl_c()
+//!     .unwrap()
+//!     .run(|response, _bar| {
+//!         println!("{} aircraft", response.aircraft.len());
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use adsbx_json::v2::Response;
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
+use tracing::warn;
+
+use crate::{error::Error, load_adsbx_json_reader, metrics::Metrics, spinner};
+
+/// The default time between polls.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The default shortest time between requests. adsb.fi allows one request
+/// per second.
+pub const DEFAULT_MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The default longest time to wait after repeated failures.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// How often to check whether we've been asked to stop while waiting.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+fn live_error(e: impl std::fmt::Display) -> Error {
+    Error::LiveError(e.to_string())
+}
+
+/// Counts from a polling run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveSummary {
+    /// The number of requests made.
+    pub polls: usize,
+    /// The number of requests that failed, including responses that couldn't
+    /// be parsed.
+    pub failed_polls: usize,
+    /// The number of responses passed to the callback.
+    pub responses: usize,
+    /// The number of responses that were skipped because they were no newer
+    /// than the previous one, e.g. when the API served a cached response.
+    pub stale_responses: usize,
+    pub wall_time: Duration,
+}
+
+/// Polls an API endpoint and calls a callback with each new response,
+/// serially, until it's stopped.
+#[derive(Debug, Clone)]
+pub struct LivePoller {
+    url: String,
+    headers: Vec<(String, String)>,
+    interval: Duration,
+    min_request_interval: Duration,
+    max_backoff: Duration,
+    timeout: Duration,
+    max_polls: Option<usize>,
+    stop: Arc<AtomicBool>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+/// Returns how long to wait after num_failures consecutive failures: the
+/// poll interval, doubled for each failure after the first, up to
+/// max_backoff.
+pub fn backoff(interval: Duration, num_failures: u32, max_backoff: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(num_failures.saturating_sub(1));
+    interval
+        .saturating_mul(factor)
+        .min(max_backoff)
+        .max(interval)
+}
+
+// Returns the value of a Retry-After header, if it's a number of seconds.
+fn retry_after(response: &ureq::Response) -> Option<Duration> {
+    let secs = response.header("Retry-After")?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+impl LivePoller {
+    /// Creates a poller for an endpoint URL, e.g.
+    /// https://adsbexchange.com/api/aircraft/v2/lat/34.0/lon/-118.0/dist/250/
+    /// or https://api.adsb.lol/v2/mil.
+    pub fn new(url: &str) -> Self {
+        LivePoller {
+            url: url.to_string(),
+            headers: vec![],
+            interval: DEFAULT_POLL_INTERVAL,
+            min_request_interval: DEFAULT_MIN_REQUEST_INTERVAL,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            timeout: Duration::from_secs(30),
+            max_polls: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            metrics: None,
+        }
+    }
+
+    /// Sends an API key with each request, in the named header. The ADS-B
+    /// Exchange API uses "api-auth".
+    pub fn api_key(self, header: &str, key: &str) -> Self {
+        self.header(header, key)
+    }
+
+    /// Sends a header with each request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the time between the start of one poll and the start of the
+    /// next. The default is DEFAULT_POLL_INTERVAL.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the shortest time between requests, whatever the interval or a
+    /// Retry-After header says. The default is DEFAULT_MIN_REQUEST_INTERVAL.
+    pub fn min_request_interval(mut self, min_request_interval: Duration) -> Self {
+        self.min_request_interval = min_request_interval;
+        self
+    }
+
+    /// Sets the longest time to wait after repeated failures. The default is
+    /// DEFAULT_MAX_BACKOFF.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Sets the timeout for each request. The default is 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Stops after this many requests, successful or not. The default is to
+    /// keep going until stopped.
+    pub fn max_polls(mut self, max_polls: usize) -> Self {
+        self.max_polls = Some(max_polls);
+        self
+    }
+
+    /// Counts failed requests, and responses that couldn't be parsed, in
+    /// metrics, e.g. State::metrics.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Returns a flag that stops the poller when it's set. The poller
+    /// finishes the poll it's in the middle of, if any, and then run returns.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Stops the poller on ctrl-c or SIGTERM, so that run returns normally
+    /// and whatever is tracking interceptions can finish (or save) them. Can
+    /// only be used once per process.
+    pub fn stop_on_ctrl_c(self) -> Result<Self, Error> {
+        let stop = self.stop_handle();
+        ctrlc::set_handler(move || stop.store(true, Ordering::SeqCst))
+            .map_err(|e| Error::SignalError(e.to_string()))?;
+        Ok(self)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    // Sleeps until deadline, or until we're stopped.
+    fn wait_until(&self, deadline: Instant) {
+        while !self.is_stopped() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep((deadline - now).min(STOP_CHECK_INTERVAL));
+        }
+    }
+
+    // Makes one request. On failure, also returns the Retry-After delay the
+    // server asked for, if any.
+    fn poll(&self, agent: &ureq::Agent) -> Result<Response, (Error, Option<Duration>)> {
+        let mut request = agent.get(&self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        match request.call() {
+            Ok(response) => load_adsbx_json_reader(response.into_reader()).map_err(|e| (e, None)),
+            Err(ureq::Error::Status(status, response)) => Err((
+                live_error(format!("{}: HTTP status {}", self.url, status)),
+                retry_after(&response),
+            )),
+            Err(e) => Err((live_error(format!("{}: {}", self.url, e)), None)),
+        }
+    }
+
+    /// Polls until stopped (or max_polls is reached), calling the callback
+    /// with each response that's newer than the one before it.
+    ///
+    /// Failed requests are logged and retried with exponential backoff; see
+    /// backoff. Errors returned by the callback stop polling and are passed
+    /// through as-is.
+    pub fn run<F>(self, mut f: F) -> Result<LiveSummary, Error>
+    where
+        F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+    {
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+        let bar = spinner();
+        let start = Instant::now();
+        let mut summary = LiveSummary::default();
+        let mut num_failures = 0;
+        let mut last_now: Option<DateTime<Utc>> = None;
+        let mut next_poll = Instant::now();
+        while !self.is_stopped() && self.max_polls.is_none_or(|max| summary.polls < max) {
+            self.wait_until(next_poll);
+            if self.is_stopped() {
+                break;
+            }
+            let poll_start = Instant::now();
+            summary.polls += 1;
+            let wait = match self.poll(&agent) {
+                Ok(response) => {
+                    num_failures = 0;
+                    if last_now.is_some_and(|last_now| response.now <= last_now) {
+                        summary.stale_responses += 1;
+                    } else {
+                        last_now = Some(response.now);
+                        summary.responses += 1;
+                        bar.set_message(format!("{} aircraft", response.aircraft.len()));
+                        if let Err(e) = f(response, &bar) {
+                            bar.finish();
+                            return Err(e);
+                        }
+                        bar.inc(1);
+                    }
+                    self.interval
+                }
+                Err((e, retry_after)) => {
+                    num_failures += 1;
+                    summary.failed_polls += 1;
+                    if let Some(metrics) = &self.metrics {
+                        Metrics::inc(match e {
+                            Error::LiveError(_) => &metrics.fetch_errors,
+                            _ => &metrics.parse_errors,
+                        });
+                    }
+                    let wait = retry_after
+                        .unwrap_or_else(|| backoff(self.interval, num_failures, self.max_backoff));
+                    warn!("{}; retrying in {:.0?}", e, wait);
+                    wait
+                }
+            };
+            next_poll = poll_start + wait.max(self.min_request_interval);
+        }
+        bar.finish();
+        summary.wall_time = start.elapsed();
+        Ok(summary)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_backoff() {
+        let interval = Duration::from_secs(10);
+        let max = Duration::from_secs(300);
+        assert_eq!(backoff(interval, 1, max), interval);
+        assert_eq!(backoff(interval, 2, max), Duration::from_secs(20));
+        assert_eq!(backoff(interval, 4, max), Duration::from_secs(80));
+        assert_eq!(backoff(interval, 6, max), max);
+        assert_eq!(backoff(interval, 100, max), max);
+    }
+
+    // Serves each of the (status, body) responses once, in order, on a local
+    // port, and returns the URL and the headers of each request.
+    fn serve(responses: Vec<(u16, String)>) -> (String, std::thread::JoinHandle<Vec<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v2/mil", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let headers: Vec<String> = BufReader::new(&stream)
+                    .lines()
+                    .map(|line| line.unwrap())
+                    .take_while(|line| !line.is_empty())
+                    .collect();
+                requests.push(headers);
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_poll() {
+        let snapshot = |now: i64| {
+            format!(
+                r#"{{"now": {}, "total": 1, "ctime": {}, "ptime": 0, "msg": "No error",
+                "ac": [{{"hex": "a12345", "type": "adsb_icao", "messages": 100, "rssi": -20.0, "seen": 0.0}}]}}"#,
+                now, now
+            )
+        };
+        let (url, server) = serve(vec![
+            (200, snapshot(1614109133000)),
+            (503, "Busy".to_string()),
+            (200, "not json".to_string()),
+            // Cached.
+            (200, snapshot(1614109133000)),
+            (200, snapshot(1614109143000)),
+        ]);
+        let mut nows = vec![];
+        let metrics = Arc::new(Metrics::default());
+        let summary = LivePoller::new(&url)
+            .api_key("api-auth", "secret")
+            .metrics(metrics.clone())
+            .interval(Duration::from_millis(10))
+            .min_request_interval(Duration::ZERO)
+            .max_polls(5)
+            .run(|response, _bar| {
+                nows.push(response.now.timestamp());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(nows, vec![1614109133, 1614109143]);
+        assert_eq!(summary.polls, 5);
+        assert_eq!(summary.failed_polls, 2);
+        assert_eq!(summary.responses, 2);
+        assert_eq!(summary.stale_responses, 1);
+        assert_eq!(metrics.fetch_errors.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.parse_errors.load(Ordering::Relaxed), 1);
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains(&"api-auth: secret".to_string()));
+
+        // Stopping before the first poll.
+        let poller = LivePoller::new(&url);
+        poller.stop_handle().store(true, Ordering::SeqCst);
+        let summary = poller.run(|_, _| Ok(())).unwrap();
+        assert_eq!(summary.polls, 0);
+    }
+}
@@ -0,0 +1,225 @@
+//! Counters and gauges for monitoring a long-running detector.
+//!
+//! Metrics are atomics, so they can be updated from the detector and read
+//! from another thread without locking. With the metrics feature,
+//! serve_metrics serves them in the Prometheus text exposition format:
+//!
+//! ```text
+//! # HELP tracon_snapshots_processed_total Snapshots run through the detector.
+//! # TYPE tracon_snapshots_processed_total counter
+//! tracon_snapshots_processed_total 1234
+//! ```
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+
+use crate::interception::State;
+
+/// The detector's metrics. See State::metrics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub snapshots_processed: AtomicU64,
+    /// Snapshots that couldn't be loaded or parsed.
+    pub parse_errors: AtomicU64,
+    /// Failed requests to a live API.
+    pub fetch_errors: AtomicU64,
+    pub interceptions_started: AtomicU64,
+    pub interceptions_ended: AtomicU64,
+    pub aircraft_tracked: AtomicU64,
+    /// Aircraft that are currently fast movers, i.e. potential interceptors.
+    pub fast_movers_active: AtomicU64,
+    /// Pairs that are close but not (yet) interceptions.
+    pub pending_pairs: AtomicU64,
+    pub interceptions_open: AtomicU64,
+    /// How far the latest snapshot's timestamp was behind the wall clock when
+    /// it was processed, in milliseconds.
+    pub processing_lag_ms: AtomicI64,
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: String) {
+    out.push_str(&format!(
+        "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+    ));
+}
+
+impl Metrics {
+    /// Adds one to a counter.
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Updates the gauges from the detector's state, after it's processed a
+    /// snapshot from time now.
+    pub fn record_snapshot(&self, state: &State, now: DateTime<Utc>) {
+        Metrics::inc(&self.snapshots_processed);
+        let set = |gauge: &AtomicU64, value: usize| gauge.store(value as u64, Ordering::Relaxed);
+        set(&self.aircraft_tracked, state.aircraft.len());
+        set(
+            &self.fast_movers_active,
+            state
+                .aircraft
+                .iter()
+                .filter(|ac| {
+                    ac.fast.fast_status || ac.profile_status.values().any(|s| s.fast_status)
+                })
+                .count(),
+        );
+        set(&self.pending_pairs, state.aircraft.num_pending());
+        set(&self.interceptions_open, state.tracker.num_open());
+        self.processing_lag_ms
+            .store((Utc::now() - now).num_milliseconds(), Ordering::Relaxed);
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let get = |metric: &AtomicU64| metric.load(Ordering::Relaxed).to_string();
+        let mut out = String::new();
+        for (name, kind, help, value) in [
+            (
+                "tracon_snapshots_processed_total",
+                "counter",
+                "Snapshots run through the detector.",
+                get(&self.snapshots_processed),
+            ),
+            (
+                "tracon_parse_errors_total",
+                "counter",
+                "Snapshots that couldn't be loaded or parsed.",
+                get(&self.parse_errors),
+            ),
+            (
+                "tracon_fetch_errors_total",
+                "counter",
+                "Failed requests to a live API.",
+                get(&self.fetch_errors),
+            ),
+            (
+                "tracon_interceptions_started_total",
+                "counter",
+                "Interception events started.",
+                get(&self.interceptions_started),
+            ),
+            (
+                "tracon_interceptions_ended_total",
+                "counter",
+                "Interception events ended.",
+                get(&self.interceptions_ended),
+            ),
+            (
+                "tracon_aircraft_tracked",
+                "gauge",
+                "Aircraft being tracked.",
+                get(&self.aircraft_tracked),
+            ),
+            (
+                "tracon_fast_movers_active",
+                "gauge",
+                "Aircraft that are currently potential interceptors.",
+                get(&self.fast_movers_active),
+            ),
+            (
+                "tracon_pending_pairs",
+                "gauge",
+                "Pairs that are close but not yet interceptions.",
+                get(&self.pending_pairs),
+            ),
+            (
+                "tracon_interceptions_open",
+                "gauge",
+                "Interception events that haven't ended.",
+                get(&self.interceptions_open),
+            ),
+            (
+                "tracon_processing_lag_seconds",
+                "gauge",
+                "How far the latest snapshot was behind the wall clock.",
+                format!(
+                    "{:.3}",
+                    self.processing_lag_ms.load(Ordering::Relaxed) as f64 / 1000.0
+                ),
+            ),
+        ] {
+            write_metric(&mut out, name, kind, help, value);
+        }
+        out
+    }
+}
+
+/// Serves the metrics at http://addr/metrics (or any other path) from a
+/// background thread, e.g. serve_metrics(state.metrics.clone(),
+/// "0.0.0.0:9100"). Returns the address it's listening on, which is useful
+/// if the port was 0.
+#[cfg(feature = "metrics")]
+pub fn serve_metrics(
+    metrics: std::sync::Arc<Metrics>,
+    addr: &str,
+) -> Result<std::net::SocketAddr, crate::error::Error> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let export_error =
+        |e: std::io::Error| crate::error::Error::ExportError(format!("{}: {}", addr, e));
+    let listener = std::net::TcpListener::bind(addr).map_err(export_error)?;
+    let local_addr = listener.local_addr().map_err(export_error)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            // Read the request line and headers, and ignore them.
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line.trim() != "" {
+                line.clear();
+            }
+            let body = metrics.render();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+    Ok(local_addr)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        Metrics::inc(&metrics.snapshots_processed);
+        Metrics::inc(&metrics.snapshots_processed);
+        metrics.processing_lag_ms.store(1500, Ordering::Relaxed);
+        let text = metrics.render();
+        assert!(text.contains(
+            "# HELP tracon_snapshots_processed_total Snapshots run through the detector.\n\
+             # TYPE tracon_snapshots_processed_total counter\n\
+             tracon_snapshots_processed_total 2\n"
+        ));
+        assert!(text.contains("\ntracon_processing_lag_seconds 1.500\n"));
+        assert_eq!(text.lines().count(), 30);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_serve_metrics() {
+        use std::io::{Read, Write};
+
+        let metrics = std::sync::Arc::new(Metrics::default());
+        Metrics::inc(&metrics.parse_errors);
+        let addr = serve_metrics(metrics, "127.0.0.1:0").unwrap();
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\ntracon_parse_errors_total 1\n"));
+    }
+}
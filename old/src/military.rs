@@ -0,0 +1,73 @@
+//! Guessing whether an aircraft is military, from the ADS-B Exchange dbFlags
+//! field or, failing that, from the block of ICAO addresses its hex ID is in.
+//!
+//! Many countries set aside part of their ICAO address allocation for
+//! military aircraft. The table below isn't exhaustive, and some of the
+//! blocks also contain a few government or civil aircraft, so treat a match
+//! as "likely".
+
+use adsbx_json::v2::Aircraft;
+
+use crate::country::{icao_address, HexRange};
+
+/// ICAO address blocks used by military aircraft.
+pub const MILITARY_HEX_RANGES: &[HexRange] = &[
+    HexRange::new("United States", 0xae0000, 0xafffff),
+    HexRange::new("United Kingdom", 0x43c000, 0x43cfff),
+    HexRange::new("France", 0x3a8000, 0x3affff),
+    HexRange::new("France", 0x3b0000, 0x3bffff),
+    HexRange::new("Germany", 0x3ea000, 0x3ebfff),
+    HexRange::new("Germany", 0x3f4000, 0x3fbfff),
+    HexRange::new("Italy", 0x33ff00, 0x33ffff),
+    HexRange::new("Spain", 0x350000, 0x37ffff),
+    HexRange::new("Netherlands", 0x480000, 0x480fff),
+    HexRange::new("Belgium", 0x44f000, 0x44ffff),
+    HexRange::new("Switzerland", 0x4b7000, 0x4b7fff),
+    HexRange::new("Israel", 0x738a00, 0x738aff),
+    HexRange::new("Australia", 0x7cf800, 0x7cfaff),
+];
+
+/// Returns the military block a hex ID is in, if any.
+pub fn military_hex_range(hex: &str) -> Option<&'static HexRange> {
+    let address = icao_address(hex)?;
+    MILITARY_HEX_RANGES.iter().find(|r| r.contains(address))
+}
+
+/// Whether an aircraft is likely to be military: ADS-B Exchange flags it as
+/// military, or its hex ID is in one of the MILITARY_HEX_RANGES.
+pub fn is_likely_military(aircraft: &Aircraft) -> bool {
+    aircraft.database_flags.is_military() || military_hex_range(&aircraft.hex).is_some()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn aircraft(hex: &str, db_flags: u32) -> Aircraft {
+        serde_json::from_value(json!({
+            "hex": hex, "type": "adsb_icao", "messages": 100, "rssi": -20.0, "seen": 0.0,
+            "dbFlags": db_flags,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_is_likely_military() {
+        assert_eq!(
+            military_hex_range("ae1234").map(|r| r.country),
+            Some("United States")
+        );
+        assert_eq!(
+            military_hex_range("43C5F1").map(|r| r.country),
+            Some("United Kingdom")
+        );
+        assert!(military_hex_range("a12345").is_none());
+        assert!(military_hex_range("~2d1a48").is_none());
+        assert!(is_likely_military(&aircraft("ae1234", 0)));
+        assert!(!is_likely_military(&aircraft("a12345", 0)));
+        // The database knows better than the table.
+        assert!(is_likely_military(&aircraft("a12345", 1)));
+    }
+}
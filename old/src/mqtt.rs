@@ -0,0 +1,220 @@
+//! Publishing interception events to an MQTT broker.
+//!
+//! ```no_run
+//! use tracon::{interception::State, mqtt::MqttNotifier};
+//!
+//! let mut state = State::default();
+//! let notifier = MqttNotifier::builder("tracon", "localhost", 1883)
+//!     .topic("interceptions/{target_hex}")
+//!     .retain(true)
+//!     .connect();
+//! state.notifiers.push(Box::new(notifier));
+//! ```
+
+use std::time::Duration;
+
+use rumqttc::{Client, MqttOptions, QoS};
+use tracing::warn;
+
+use crate::{
+    error::Error,
+    events::DetectionEventKind,
+    notify::{event_payload, Notifier},
+    tracker::InterceptionEvent,
+};
+
+/// The default topic template. See MqttNotifier::topic.
+pub const DEFAULT_TOPIC: &str = "interceptions/{interceptor_hex}";
+
+// How long to wait before reconnecting after the connection fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// How many messages can be waiting to be sent. If the broker is down for
+// long enough that it fills up, later events are dropped (and logged).
+const QUEUE_CAPACITY: usize = 100;
+
+/// Fills in a topic template for an event. "{interceptor_hex}",
+/// "{target_hex}", and "{event}" (e.g. "interception_started") are replaced.
+pub fn event_topic(template: &str, kind: DetectionEventKind, event: &InterceptionEvent) -> String {
+    let kind = serde_json::to_value(kind).unwrap();
+    template
+        .replace("{interceptor_hex}", &event.interceptor_hex)
+        .replace("{target_hex}", &event.target_hex)
+        .replace("{event}", kind.as_str().unwrap_or_default())
+}
+
+/// Configures an MqttNotifier.
+#[derive(Debug, Clone)]
+pub struct MqttNotifierBuilder {
+    options: MqttOptions,
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    updates: bool,
+}
+
+/// Publishes event_payloads to an MQTT broker.
+///
+/// Publishing doesn't wait for the broker: messages are queued and sent by a
+/// background thread, which reconnects if the connection drops.
+pub struct MqttNotifier {
+    client: Client,
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    updates: bool,
+}
+
+impl std::fmt::Debug for MqttNotifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttNotifier")
+            .field("topic", &self.topic)
+            .field("qos", &self.qos)
+            .field("retain", &self.retain)
+            .field("updates", &self.updates)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttNotifier {
+    /// Starts configuring a notifier that connects to host:port with a
+    /// client ID.
+    pub fn builder(client_id: &str, host: &str, port: u16) -> MqttNotifierBuilder {
+        MqttNotifierBuilder {
+            options: MqttOptions::new(client_id, host, port),
+            topic: DEFAULT_TOPIC.to_string(),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            updates: true,
+        }
+    }
+
+    /// The topic an event is published to.
+    pub fn topic(&self, kind: DetectionEventKind, event: &InterceptionEvent) -> String {
+        event_topic(&self.topic, kind, event)
+    }
+}
+
+impl MqttNotifierBuilder {
+    /// Sets the topic template; see event_topic. The default is
+    /// DEFAULT_TOPIC.
+    pub fn topic(mut self, topic: &str) -> Self {
+        self.topic = topic.to_string();
+        self
+    }
+
+    /// Sets the QoS level, 0 to 2. The default is 1, at least once.
+    pub fn qos(mut self, qos: u8) -> Result<Self, Error> {
+        self.qos = rumqttc::qos(qos).map_err(|e| Error::ExportError(e.to_string()))?;
+        Ok(self)
+    }
+
+    /// Whether the broker should retain the last message on each topic. The
+    /// default is false.
+    pub fn retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Whether to publish InterceptionUpdated events too. The default is
+    /// true.
+    pub fn updates(mut self, updates: bool) -> Self {
+        self.updates = updates;
+        self
+    }
+
+    /// Sets the username and password.
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        self.options.set_credentials(username, password);
+        self
+    }
+
+    /// Creates the notifier, and starts the background thread that connects
+    /// to the broker.
+    pub fn connect(self) -> MqttNotifier {
+        let (client, mut connection) = Client::new(self.options, QUEUE_CAPACITY);
+        std::thread::spawn(move || {
+            let mut connected = true;
+            for notification in connection.iter() {
+                match notification {
+                    Ok(_) => connected = true,
+                    Err(e) => {
+                        // Only log the first failure of each outage.
+                        if connected {
+                            warn!("MQTT connection failed, reconnecting: {}", e);
+                        }
+                        connected = false;
+                        std::thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+        MqttNotifier {
+            client,
+            topic: self.topic,
+            qos: self.qos,
+            retain: self.retain,
+            updates: self.updates,
+        }
+    }
+}
+
+impl Notifier for MqttNotifier {
+    fn notify(&self, kind: DetectionEventKind, event: &InterceptionEvent) -> Result<(), Error> {
+        self.client
+            .try_publish(
+                self.topic(kind, event),
+                self.qos,
+                self.retain,
+                event_payload(kind, event).to_string(),
+            )
+            .map_err(|e| Error::ExportError(format!("Couldn't publish to MQTT: {}", e)))
+    }
+
+    fn wants_updates(&self) -> bool {
+        self.updates
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::tests::event;
+
+    #[test]
+    fn test_mqtt_notifier() {
+        let event = event();
+        assert_eq!(
+            event_topic(
+                "tracon/{event}/{target_hex}",
+                DetectionEventKind::InterceptionUpdated,
+                &event
+            ),
+            "tracon/interception_updated/a12345"
+        );
+        // Nothing's listening, but messages are queued until there is.
+        let notifier = MqttNotifier::builder("tracon-test", "127.0.0.1", 1)
+            .qos(2)
+            .unwrap()
+            .connect();
+        assert_eq!(
+            notifier.topic(DetectionEventKind::InterceptionStarted, &event),
+            "interceptions/ae1234"
+        );
+        assert!(notifier.wants_updates());
+        notifier
+            .notify(DetectionEventKind::InterceptionStarted, &event)
+            .unwrap();
+        assert!(MqttNotifier::builder("tracon-test", "127.0.0.1", 1)
+            .qos(3)
+            .is_err());
+
+        // The payload is the same as everywhere else.
+        let payload = event_payload(DetectionEventKind::InterceptionStarted, &event);
+        assert_eq!(payload["event"], "interception_started");
+        let parsed: InterceptionEvent =
+            serde_json::from_value(payload["interception"].clone()).unwrap();
+        assert_eq!(parsed, event);
+    }
+}
@@ -0,0 +1,261 @@
+//! Notifications when interception events start and end, e.g. for a push
+//! notification while running on a live feed.
+//!
+//! With the live feature, WebhookNotifier POSTs each event to a URL, either
+//! as the serialized InterceptionEvent or as a one-line summary that Discord
+//! and Slack webhooks can display. With the mqtt feature, mqtt::MqttNotifier
+//! publishes them to an MQTT broker. cot::CotSender sends them to TAK
+//! clients over UDP.
+
+use std::fmt::Debug;
+
+use crate::{
+    error::Error, events::DetectionEventKind, interception::Interception,
+    tracker::InterceptionEvent,
+};
+
+/// Something that wants to know when interception events start and end. See
+/// State::notifiers.
+pub trait Notifier: Debug {
+    /// Called with InterceptionStarted when an event is first detected, and
+    /// InterceptionEnded when it's finished. If wants_updates returns true,
+    /// also called with InterceptionUpdated when the aircraft get closer than
+    /// they've been so far, but not for every detection in between.
+    ///
+    /// Errors are logged, and don't stop detection.
+    fn notify(&self, kind: DetectionEventKind, event: &InterceptionEvent) -> Result<(), Error>;
+
+    fn wants_updates(&self) -> bool {
+        false
+    }
+
+    /// Called with every detection of an ongoing event, after notify, for
+    /// notifiers that follow the aircraft. Errors are logged.
+    fn detection(&self, _interception: &Interception) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Returns the JSON sent for an event: {"event": "interception_started",
+/// "interception": {InterceptionEvent}}.
+pub fn event_payload(kind: DetectionEventKind, event: &InterceptionEvent) -> serde_json::Value {
+    serde_json::json!({"event": kind, "interception": event})
+}
+
+fn name(hex: &str, callsign: Option<&str>) -> String {
+    match callsign {
+        Some(callsign) => format!("{} ({})", hex, callsign.trim()),
+        None => hex.to_string(),
+    }
+}
+
+/// Returns a human-readable summary of an event, like "Interception started:
+/// ae1234 (RCH123) intercepted a12345 (N12345) at 34.0000, -118.0000 with
+/// 350 ft lateral, 100 ft vertical separation".
+pub fn event_summary(kind: DetectionEventKind, event: &InterceptionEvent) -> String {
+    let closest = &event.closest;
+    let [lon, lat] = closest.location();
+    let what = match kind {
+        DetectionEventKind::InterceptionStarted => "Interception started",
+        DetectionEventKind::InterceptionUpdated => "Interception updated",
+        DetectionEventKind::InterceptionEnded => "Interception ended",
+    };
+    let mut summary =
+        format!(
+        "{}: {} intercepted {} at {:.4}, {:.4} with {:.0} ft lateral, {} ft vertical separation",
+        what,
+        name(&event.interceptor_hex, closest.interceptor.callsign.as_deref()),
+        name(&event.target_hex, closest.target.callsign.as_deref()),
+        lat,
+        lon,
+        event.min_lateral_separation_ft,
+        event.min_vertical_separation_ft,
+    );
+    if kind == DetectionEventKind::InterceptionEnded {
+        summary.push_str(&format!(", lasting {}s", event.duration().num_seconds()));
+    }
+    summary
+}
+
+/// What a WebhookNotifier sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WebhookFormat {
+    /// The event_payload.
+    #[default]
+    Json,
+    /// {"content": summary, "text": summary}, with the event_summary as the
+    /// message. Discord reads content and Slack reads text.
+    Chat,
+}
+
+/// POSTs events to a webhook URL. Failed requests are retried once.
+#[cfg(feature = "live")]
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    url: String,
+    format: WebhookFormat,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "live")]
+impl WebhookNotifier {
+    /// Creates a notifier that sends JSON, with a 5 second timeout for each
+    /// request. Since there's one retry, a slow endpoint holds up detection
+    /// for at most twice the timeout.
+    pub fn new(url: &str) -> Self {
+        WebhookNotifier {
+            url: url.to_string(),
+            format: WebhookFormat::Json,
+            agent: ureq::AgentBuilder::new()
+                .timeout(std::time::Duration::from_secs(5))
+                .build(),
+        }
+    }
+
+    pub fn format(mut self, format: WebhookFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        self
+    }
+
+    /// Returns the JSON body to send for an event.
+    pub fn payload(
+        &self,
+        kind: DetectionEventKind,
+        event: &InterceptionEvent,
+    ) -> serde_json::Value {
+        match self.format {
+            WebhookFormat::Json => event_payload(kind, event),
+            WebhookFormat::Chat => {
+                let summary = event_summary(kind, event);
+                serde_json::json!({"content": summary, "text": summary})
+            }
+        }
+    }
+}
+
+#[cfg(feature = "live")]
+impl Notifier for WebhookNotifier {
+    fn notify(&self, kind: DetectionEventKind, event: &InterceptionEvent) -> Result<(), Error> {
+        let payload = self.payload(kind, event).to_string();
+        let send = || {
+            self.agent
+                .post(&self.url)
+                .set("Content-Type", "application/json")
+                .send_string(&payload)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        send()
+            .or_else(|_| send())
+            .map_err(|e| Error::ExportError(format!("{}: {}", self.url, e)))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        interception::{Ac, Interception},
+        tracker::InterceptionTracker,
+    };
+    use chrono::{prelude::*, Duration};
+
+    pub(crate) fn event() -> InterceptionEvent {
+        let time = Utc.with_ymd_and_hms(2021, 2, 23, 19, 42, 38).unwrap();
+        let ac = |hex: &str, lat: f64| Ac {
+            max_speed: 300.0,
+            cur_speed: 300.0,
+            ..Ac::for_test(hex, &[(time, [-118.0, lat])], 10000)
+        };
+        // ae1234 is in the US military's block of hex IDs.
+        let interceptor = Ac {
+            callsign: Some("RCH123".to_string()),
+            military: true,
+            ..ac("ae1234", 34.001)
+        };
+        let interception = Interception {
+            proximity_start: time - Duration::seconds(60),
+            lateral_separation_ft: 350.2,
+            vertical_separation_ft: 100,
+            ..Interception::for_test(interceptor, ac("a12345", 34.0), time)
+        };
+        InterceptionTracker::merge_all(&[interception], Duration::minutes(10)).remove(0)
+    }
+
+    #[test]
+    fn test_event_summary() {
+        let event = event();
+        assert_eq!(
+            event_summary(DetectionEventKind::InterceptionStarted, &event),
+            "Interception started: ae1234 (RCH123) intercepted a12345 at 34.0005, -118.0000 with 350 ft lateral, 100 ft vertical separation"
+        );
+        assert!(
+            event_summary(DetectionEventKind::InterceptionEnded, &event).ends_with(", lasting 60s")
+        );
+    }
+
+    #[cfg(feature = "live")]
+    #[test]
+    fn test_webhook_notifier() {
+        use std::{
+            io::{BufRead, BufReader, Read, Write},
+            net::TcpListener,
+        };
+
+        // Fails the first request and records the body of the second.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut bodies = vec![];
+            for status in [500, 204] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(len) = line.strip_prefix("content-length: ") {
+                        content_length = len.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        let notifier = WebhookNotifier::new(&url).format(WebhookFormat::Chat);
+        notifier
+            .notify(DetectionEventKind::InterceptionStarted, &event())
+            .unwrap();
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 2);
+        let body: serde_json::Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert!(body["content"]
+            .as_str()
+            .unwrap()
+            .starts_with("Interception started: ae1234 (RCH123)"));
+        assert_eq!(body["content"], body["text"]);
+
+        let payload =
+            WebhookNotifier::new(&url).payload(DetectionEventKind::InterceptionEnded, &event());
+        assert_eq!(payload["event"], "interception_ended");
+        assert_eq!(payload["interception"]["target_hex"], "a12345");
+    }
+}
@@ -0,0 +1,132 @@
+use adsbx_json::v2::Response;
+use chrono::prelude::*;
+
+use crate::error::Error;
+
+/// How to handle responses that don't arrive in chronological order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputOrder {
+    /// Don't check the order at all.
+    Unchecked,
+    /// Return an Error::OutOfOrderInput as soon as a response is older than
+    /// the one before it.
+    Strict,
+    /// Buffer up to this many responses and release them in time order. A
+    /// response that is older than one that has already been released is
+    /// still an error.
+    Reorder(usize),
+}
+
+/// Keeps track of response timestamps and, optionally, re-sorts a sliding
+/// window of responses so they come out in chronological order.
+///
+/// Each response can carry a tag (e.g. the path it was loaded from) that stays
+/// with it through the reordering buffer.
+#[derive(Debug)]
+pub struct OrderChecker<T> {
+    order: InputOrder,
+    last_now: Option<DateTime<Utc>>,
+    buffer: Vec<(Response, T)>,
+}
+
+impl<T> OrderChecker<T> {
+    pub fn new(order: InputOrder) -> Self {
+        OrderChecker {
+            order,
+            last_now: None,
+            buffer: vec![],
+        }
+    }
+
+    fn release(&mut self, response: (Response, T)) -> Result<(Response, T), Error> {
+        if let Some(last_now) = self.last_now {
+            if response.0.now < last_now {
+                return Err(Error::OutOfOrderInput {
+                    previous: last_now,
+                    current: response.0.now,
+                });
+            }
+        }
+        self.last_now = Some(response.0.now);
+        Ok(response)
+    }
+
+    /// Adds a response, and returns the responses that are ready to be
+    /// processed (in order).
+    pub fn push(&mut self, response: Response, tag: T) -> Result<Vec<(Response, T)>, Error> {
+        match self.order {
+            InputOrder::Unchecked => Ok(vec![(response, tag)]),
+            InputOrder::Strict => Ok(vec![self.release((response, tag))?]),
+            InputOrder::Reorder(window) => {
+                let i = self.buffer.partition_point(|r| r.0.now <= response.now);
+                self.buffer.insert(i, (response, tag));
+                let mut ready = vec![];
+                while self.buffer.len() > window {
+                    let response = self.buffer.remove(0);
+                    ready.push(self.release(response)?);
+                }
+                Ok(ready)
+            }
+        }
+    }
+
+    /// Returns any responses still in the reordering buffer.
+    pub fn finish(&mut self) -> Vec<(Response, T)> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(secs: i64) -> Response {
+        let ms = 1614109133000 + secs * 1000;
+        serde_json::from_str(&format!(
+            r#"{{"now": {}, "ctime": {}, "ptime": 10, "total": 0, "ac": []}}"#,
+            ms, ms
+        ))
+        .unwrap()
+    }
+
+    fn secs(responses: &[(Response, i64)]) -> Vec<i64> {
+        responses
+            .iter()
+            .map(|(r, tag)| {
+                assert_eq!((r.now - response(0).now).num_seconds(), *tag);
+                *tag
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_strict() {
+        let mut checker = OrderChecker::new(InputOrder::Strict);
+        assert!(checker.push(response(0), ()).is_ok());
+        assert!(checker.push(response(5), ()).is_ok());
+        assert!(checker.push(response(5), ()).is_ok());
+        assert!(matches!(
+            checker.push(response(3), ()),
+            Err(Error::OutOfOrderInput { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut checker = OrderChecker::new(InputOrder::Reorder(2));
+        let mut out = vec![];
+        for s in [0, 10, 5, 15, 20] {
+            out.extend(checker.push(response(s), s).unwrap());
+        }
+        out.extend(checker.finish());
+        assert_eq!(secs(&out), vec![0, 5, 10, 15, 20]);
+
+        // Too far out of order for the window.
+        let mut checker = OrderChecker::new(InputOrder::Reorder(1));
+        for s in [10, 20, 30] {
+            checker.push(response(s), ()).unwrap();
+        }
+        assert!(checker.push(response(0), ()).is_err());
+    }
+}
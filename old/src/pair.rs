@@ -0,0 +1,263 @@
+//! Runs the detector on two tracks that have already been extracted, from
+//! trace files or a database say, instead of on a feed.
+//!
+//! The tracks are replayed as a series of snapshots, one at each time either
+//! track has a fix, with each aircraft's position and altitude interpolated
+//! linearly between its fixes (like Ac::position_at) and its speed, heading,
+//! and vertical rate taken from the fixes either side. The snapshots go
+//! through detect_frame_interceptions and InterceptionTracker, so the CPA,
+//! closure rate, and pattern are worked out exactly as they are for a feed.
+
+use adsbx_json::v2::Response;
+use chrono::{prelude::*, Duration};
+use serde_json::{json, Value};
+
+use crate::{
+    geo::{haversine_distance_m, initial_bearing_deg, lon_difference, normalize_lon},
+    hex_id::HexId,
+    interception::{detect_frame_interceptions, AircraftStateMap, DetectionParams},
+    tracker::{InterceptionEvent, InterceptionTracker, DEFAULT_MERGE_GAP_MINS},
+};
+
+/// A fix: the time, [lon, lat], and altitude in feet.
+pub type TrackPoint = (DateTime<Utc>, [f64; 2], i32);
+
+/// The hex ID analyze_track_pair gives the interceptor. It's flagged as
+/// military, so require_military_interceptor doesn't rule it out.
+pub const INTERCEPTOR_HEX: &str = "~000001";
+
+/// The hex ID analyze_track_pair gives the target.
+pub const TARGET_HEX: &str = "~000002";
+
+/// An aircraft's interpolated state at one time.
+struct Sample {
+    position: [f64; 2],
+    alt: i32,
+    speed_kts: Option<f64>,
+    track_deg: Option<f64>,
+    vertical_rate_fpm: Option<f64>,
+}
+
+// Returns a track's state at time t, or None if t is outside it. Speed,
+// heading, and vertical rate come from the fixes either side of t or, at a
+// fix, the segment leading up to it (or away from it, for the first fix).
+fn sample(track: &[TrackPoint], t: DateTime<Utc>) -> Option<Sample> {
+    let i = track.partition_point(|p| p.0 < t);
+    let at_fix = track.get(i)?.0 == t;
+    let (a, b) = match (at_fix, i) {
+        (true, 0) => (0, 1.min(track.len() - 1)),
+        (true, i) => (i - 1, i),
+        (false, 0) => return None,
+        (false, i) => (i - 1, i),
+    };
+    let ((t0, p0, alt0), (t1, p1, alt1)) = (track[a], track[b]);
+    let (position, alt) = if at_fix {
+        (track[i].1, track[i].2)
+    } else {
+        let f = (t - t0).num_milliseconds() as f64 / (t1 - t0).num_milliseconds() as f64;
+        (
+            [
+                normalize_lon(p0[0] + lon_difference(p0[0], p1[0]) * f),
+                p0[1] + (p1[1] - p0[1]) * f,
+            ],
+            (alt0 as f64 + (alt1 - alt0) as f64 * f).round() as i32,
+        )
+    };
+    let secs = (t1 - t0).num_milliseconds() as f64 / 1000.0;
+    let (speed_kts, track_deg, vertical_rate_fpm) = if a == b || secs <= 0.0 {
+        (None, None, None)
+    } else {
+        (
+            Some(haversine_distance_m(p0, p1) / 1852.0 / (secs / 3600.0)),
+            (p0 != p1).then(|| initial_bearing_deg(p0, p1)),
+            Some((alt1 - alt0) as f64 / (secs / 60.0)),
+        )
+    };
+    Some(Sample {
+        position,
+        alt,
+        speed_kts,
+        track_deg,
+        vertical_rate_fpm,
+    })
+}
+
+// Returns the aircraft record for a sample, in feed JSON.
+fn aircraft_json(hex: &str, sample: &Sample, military: bool) -> Value {
+    json!({
+        "hex": hex, "type": "adsb_icao", "messages": 100, "rssi": -20.0,
+        "seen": 0.0, "seen_pos": 0.0, "dbFlags": u32::from(military),
+        "lon": sample.position[0], "lat": sample.position[1],
+        "alt_baro": sample.alt, "alt_geom": sample.alt,
+        "gs": sample.speed_kts, "track": sample.track_deg,
+        "geom_rate": sample.vertical_rate_fpm.map(|r| r.round().clamp(-32768.0, 32767.0) as i16),
+    })
+}
+
+// Sorts a track by time, dropping extra fixes with the same time.
+fn sorted(track: &[TrackPoint]) -> Vec<TrackPoint> {
+    let mut track = track.to_vec();
+    track.sort_by_key(|p| p.0);
+    track.dedup_by_key(|p| p.0);
+    track
+}
+
+/// Replays two tracks as snapshots, one at each time either has a fix. See
+/// the module documentation.
+pub fn track_pair_responses(interceptor: &[TrackPoint], target: &[TrackPoint]) -> Vec<Response> {
+    let interceptor = sorted(interceptor);
+    let target = sorted(target);
+    let mut times: Vec<DateTime<Utc>> = interceptor.iter().chain(&target).map(|p| p.0).collect();
+    times.sort();
+    times.dedup();
+    times
+        .into_iter()
+        .map(|t| {
+            let ac: Vec<Value> = [
+                (INTERCEPTOR_HEX, &interceptor, true),
+                (TARGET_HEX, &target, false),
+            ]
+            .into_iter()
+            .filter_map(|(hex, track, military)| {
+                sample(track, t).map(|s| aircraft_json(hex, &s, military))
+            })
+            .collect();
+            let ms = t.timestamp_millis();
+            serde_json::from_value(json!({
+                "now": ms, "ctime": ms, "ptime": 0, "total": ac.len(), "ac": ac,
+            }))
+            .expect("track pair responses should be valid")
+        })
+        .collect()
+}
+
+/// Runs the detector on an interceptor's track and a target's, and returns
+/// the interception of the target by the interceptor, if it finds one. If
+/// there's more than one (they separated for longer than the merge gap and
+/// came back), the one with the closest CPA is returned. The event's hex IDs
+/// are INTERCEPTOR_HEX and TARGET_HEX.
+///
+/// Since the tracks don't say how fast the aircraft were going, speeds come
+/// from the distance between fixes, so sparse tracks will look slower on
+/// turns than they were.
+pub fn analyze_track_pair(
+    interceptor: &[TrackPoint],
+    target: &[TrackPoint],
+    params: &DetectionParams,
+) -> Option<InterceptionEvent> {
+    let interceptor_hex: HexId = INTERCEPTOR_HEX.parse().expect("INTERCEPTOR_HEX is valid");
+    let mut state = AircraftStateMap::new();
+    let mut tracker = InterceptionTracker::new(Duration::minutes(DEFAULT_MERGE_GAP_MINS));
+    let mut events = vec![];
+    for response in track_pair_responses(interceptor, target) {
+        events.extend(tracker.close_stale(response.now));
+        for interception in detect_frame_interceptions(&mut state, &response, params) {
+            if interception.interceptor.hex == interceptor_hex {
+                events.extend(tracker.add(&interception));
+            }
+        }
+        state.set_pinned(tracker.open_aircraft());
+    }
+    events.extend(tracker.finish());
+    events.into_iter().min_by(|a, b| {
+        let distance = |e: &InterceptionEvent| e.cpa_lateral_ft.hypot(e.cpa_vertical_ft);
+        distance(a).total_cmp(&distance(b))
+    })
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::standard_intercept;
+
+    // Pulls one aircraft's track out of a series of responses.
+    fn track(responses: &[Response], hex: &str) -> Vec<TrackPoint> {
+        responses
+            .iter()
+            .filter_map(|r| {
+                let ac = r.aircraft.iter().find(|ac| ac.hex == hex)?;
+                Some((
+                    r.now,
+                    [ac.lon?, ac.lat?],
+                    ac.geometric_altitude.unwrap_or(0),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_pipeline() {
+        let responses = standard_intercept();
+        let params = DetectionParams::default();
+        let mut state = AircraftStateMap::new();
+        let mut frames = vec![];
+        for r in &responses {
+            frames.extend(detect_frame_interceptions(&mut state, r, &params));
+        }
+        let expected =
+            InterceptionTracker::merge_all(&frames, Duration::minutes(DEFAULT_MERGE_GAP_MINS));
+        assert_eq!(expected.len(), 1);
+        let expected = &expected[0];
+
+        let event = analyze_track_pair(
+            &track(&responses, "ae1234"),
+            &track(&responses, "a12345"),
+            &params,
+        )
+        .unwrap();
+        assert_eq!(event.interceptor_hex, INTERCEPTOR_HEX);
+        assert_eq!(event.target_hex, TARGET_HEX);
+        assert_eq!(event.start, expected.start);
+        assert_eq!(event.end, expected.end);
+        assert_eq!(event.num_detections, expected.num_detections);
+        assert_eq!(event.closest.pattern, expected.closest.pattern);
+        assert!((event.cpa_lateral_ft - expected.cpa_lateral_ft).abs() < 1.0);
+        assert_eq!(event.cpa_time, expected.cpa_time);
+    }
+
+    #[test]
+    fn test_no_interception() {
+        let responses = standard_intercept();
+        let params = DetectionParams::default();
+        // The target's track, but 2 degrees north.
+        let far_away: Vec<TrackPoint> = track(&responses, "a12345")
+            .into_iter()
+            .map(|(t, [lon, lat], alt)| (t, [lon, lat + 2.0], alt))
+            .collect();
+        assert!(analyze_track_pair(&track(&responses, "ae1234"), &far_away, &params).is_none());
+        // Swapping the roles doesn't find anything either, since the target
+        // never goes fast.
+        assert!(analyze_track_pair(
+            &track(&responses, "a12345"),
+            &track(&responses, "ae1234"),
+            &params
+        )
+        .is_none());
+        assert!(analyze_track_pair(&[], &[], &params).is_none());
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let t0 = Utc.with_ymd_and_hms(2023, 1, 2, 12, 0, 0).unwrap();
+        let interceptor = [
+            (t0, [-118.0, 34.0], 10_000),
+            (t0 + Duration::seconds(60), [-117.9, 34.0], 10_600),
+        ];
+        let target = [(t0 + Duration::seconds(30), [-118.0, 34.1], 5000)];
+        let responses = track_pair_responses(&interceptor, &target);
+        assert_eq!(responses.len(), 3);
+        let middle = &responses[1];
+        assert_eq!(middle.aircraft.len(), 2);
+        let ac = &middle.aircraft[0];
+        assert!((ac.lon.unwrap() + 117.95).abs() < 1e-9);
+        assert_eq!(ac.geometric_altitude, Some(10_300));
+        assert_eq!(ac.geometric_vertical_rate, Some(600));
+        assert!((ac.track.unwrap() - 90.0).abs() < 0.1);
+        // 0.1 degrees of longitude a minute at 34 N is about 298 kts.
+        assert!((ac.ground_speed_knots.unwrap() - 298.0).abs() < 1.0);
+        // A single fix has no speed.
+        assert_eq!(middle.aircraft[1].ground_speed_knots, None);
+        assert_eq!(responses[0].aircraft.len(), 1);
+    }
+}
@@ -0,0 +1,378 @@
+//! Writing the aircraft states of each snapshot to a Parquet file, for
+//! analysis in DuckDB, pandas, etc. It's also a way to see exactly what the
+//! detector saw.
+//!
+//! Each row is one aircraft with a position in one snapshot, as classified
+//! by classify_snapshot:
+//!
+//! | column           | type                          |
+//! |------------------|-------------------------------|
+//! | hex              | string                        |
+//! | time             | timestamp (milliseconds, UTC) |
+//! | lat, lon         | double                        |
+//! | speed_kts        | double                        |
+//! | alt_ft           | int32                         |
+//! | is_fast_mover    | boolean                       |
+//! | is_potential_toi | boolean                       |
+//!
+//! Every column is required, and compressed with zstd by default. The file
+//! is written by the parquet crate's ArrowWriter, a snapshot at a time.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufWriter, Write},
+    sync::Arc,
+};
+
+use ::parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, Int32Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::{error::Error, hex_id::HexId, interception::AircraftStateMap};
+
+/// The most rows a row group holds by default. See
+/// ParquetSink::row_group_rows.
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 100_000;
+
+/// The zstd level used by default.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// One aircraft's state in a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotRow {
+    pub hex: HexId,
+    pub time: DateTime<Utc>,
+    pub lat: f64,
+    pub lon: f64,
+    pub speed_kts: f64,
+    pub alt_ft: i32,
+    pub is_fast_mover: bool,
+    pub is_potential_toi: bool,
+}
+
+/// Returns the states of the aircraft that had a position in the snapshot
+/// at now, the last one classified, sorted by hex ID.
+pub fn snapshot_rows(state: &AircraftStateMap, now: DateTime<Utc>) -> Vec<SnapshotRow> {
+    let snapshot = state.latest_snapshot();
+    let fast_movers: HashSet<HexId> = snapshot.fast_movers.iter().copied().collect();
+    let targets: HashSet<HexId> = snapshot.targets.iter().map(|t| t.data).collect();
+    let mut rows: Vec<SnapshotRow> = state
+        .iter()
+        .filter(|ac| ac.cur_coords().0 == now)
+        .map(|ac| {
+            let (time, [lon, lat]) = *ac.cur_coords();
+            SnapshotRow {
+                hex: ac.hex,
+                time,
+                lat,
+                lon,
+                speed_kts: ac.cur_speed,
+                alt_ft: ac.cur_alt,
+                is_fast_mover: fast_movers.contains(&ac.hex),
+                is_potential_toi: targets.contains(&ac.hex),
+            }
+        })
+        .collect();
+    rows.sort_by_key(|r| r.hex);
+    rows
+}
+
+/// How column chunks are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParquetCompression {
+    Uncompressed,
+    /// zstd, at a level from 1 to 22.
+    Zstd(i32),
+}
+
+impl Default for ParquetCompression {
+    fn default() -> Self {
+        ParquetCompression::Zstd(DEFAULT_ZSTD_LEVEL)
+    }
+}
+
+impl ParquetCompression {
+    fn codec(self) -> Result<Compression, Error> {
+        match self {
+            ParquetCompression::Uncompressed => Ok(Compression::UNCOMPRESSED),
+            ParquetCompression::Zstd(level) => ZstdLevel::try_new(level)
+                .map(Compression::ZSTD)
+                .map_err(from_parquet),
+        }
+    }
+}
+
+fn from_parquet(e: impl std::fmt::Display) -> Error {
+    Error::ExportError(format!("Parquet error: {}", e))
+}
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("hex", DataType::Utf8, false),
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lon", DataType::Float64, false),
+        Field::new("speed_kts", DataType::Float64, false),
+        Field::new("alt_ft", DataType::Int32, false),
+        Field::new("is_fast_mover", DataType::Boolean, false),
+        Field::new("is_potential_toi", DataType::Boolean, false),
+    ]))
+}
+
+fn record_batch(schema: SchemaRef, rows: &[SnapshotRow]) -> Result<RecordBatch, Error> {
+    let doubles = |f: fn(&SnapshotRow) -> f64| -> ArrayRef {
+        Arc::new(rows.iter().map(f).collect::<Float64Array>())
+    };
+    let bools = |f: fn(&SnapshotRow) -> bool| -> ArrayRef {
+        Arc::new(rows.iter().map(|r| Some(f(r))).collect::<BooleanArray>())
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            rows.iter()
+                .map(|r| Some(r.hex.to_string()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                rows.iter().map(|r| r.time.timestamp_millis()),
+            )
+            .with_timezone("UTC"),
+        ),
+        doubles(|r| r.lat),
+        doubles(|r| r.lon),
+        doubles(|r| r.speed_kts),
+        Arc::new(rows.iter().map(|r| r.alt_ft).collect::<Int32Array>()),
+        bools(|r| r.is_fast_mover),
+        bools(|r| r.is_potential_toi),
+    ];
+    RecordBatch::try_new(schema, columns).map_err(from_parquet)
+}
+
+/// Writes snapshots of aircraft states to a Parquet file.
+///
+/// The footer is written by finish, or when the sink is dropped, in which
+/// case errors are logged.
+pub struct ParquetSink<W: Write + Send> {
+    // The writer until the first write, when the ArrowWriter takes it over,
+    // so the settings can be changed until then. Both are None once it's
+    // finished.
+    writer: Option<W>,
+    arrow: Option<ArrowWriter<W>>,
+    schema: SchemaRef,
+    row_group_rows: usize,
+    compression: ParquetCompression,
+}
+
+impl ParquetSink<BufWriter<File>> {
+    /// Creates a Parquet file.
+    pub fn create(path: &str) -> Result<Self, Error> {
+        File::create(path)
+            .map_err(Error::Io)
+            .and_then(|f| ParquetSink::new(BufWriter::new(f)))
+            .map_err(|e| e.in_file(path))
+    }
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(writer: W) -> Result<Self, Error> {
+        Ok(ParquetSink {
+            writer: Some(writer),
+            arrow: None,
+            schema: schema(),
+            row_group_rows: DEFAULT_ROW_GROUP_ROWS,
+            compression: ParquetCompression::default(),
+        })
+    }
+
+    /// Sets the most rows a row group can hold. The default is
+    /// DEFAULT_ROW_GROUP_ROWS.
+    pub fn row_group_rows(mut self, rows: usize) -> Self {
+        self.row_group_rows = rows.max(1);
+        self
+    }
+
+    /// The default is zstd at DEFAULT_ZSTD_LEVEL.
+    pub fn compression(mut self, compression: ParquetCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Adds the states from the snapshot at now, the last one classified.
+    /// See snapshot_rows.
+    pub fn write_snapshot(
+        &mut self,
+        state: &AircraftStateMap,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.write_rows(snapshot_rows(state, now))
+    }
+
+    /// Adds a snapshot's rows.
+    pub fn write_rows(&mut self, rows: Vec<SnapshotRow>) -> Result<(), Error> {
+        let batch = record_batch(self.schema.clone(), &rows)?;
+        self.arrow_writer()?.write(&batch).map_err(from_parquet)
+    }
+
+    fn arrow_writer(&mut self) -> Result<&mut ArrowWriter<W>, Error> {
+        if let Some(writer) = self.writer.take() {
+            let props = WriterProperties::builder()
+                .set_compression(self.compression.codec()?)
+                .set_max_row_group_row_count(Some(self.row_group_rows))
+                .set_created_by(concat!("tracon version ", env!("CARGO_PKG_VERSION")).to_string())
+                .build();
+            let arrow = ArrowWriter::try_new(writer, self.schema.clone(), Some(props))
+                .map_err(from_parquet)?;
+            self.arrow = Some(arrow);
+        }
+        self.arrow
+            .as_mut()
+            .ok_or_else(|| Error::ExportError("Parquet file already finished".to_string()))
+    }
+
+    /// Writes any rows that are left and the footer, and returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.arrow_writer()?;
+        // Taking it means drop doesn't try again.
+        let arrow = self.arrow.take().unwrap();
+        let mut writer = arrow.into_inner().map_err(from_parquet)?;
+        writer.flush().map_err(Error::Io)?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write + Send> Drop for ParquetSink<W> {
+    fn drop(&mut self) {
+        if self.writer.is_none() && self.arrow.is_none() {
+            return;
+        }
+        let result = self.arrow_writer().and_then(|arrow| {
+            arrow.finish().map_err(from_parquet)?;
+            arrow.inner_mut().flush().map_err(Error::Io)
+        });
+        if let Err(e) = result {
+            warn!("Couldn't finish Parquet file: {}", e);
+        }
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interception::{detect_frame_interceptions, DetectionParams},
+        testutil::standard_intercept,
+    };
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int32Type, TimestampMillisecondType},
+    };
+
+    // Reads a Parquet file's row groups back, each as one batch.
+    fn read_row_groups(file: &File) -> (SchemaRef, Vec<RecordBatch>) {
+        let builder = |file: &File| {
+            ParquetRecordBatchReaderBuilder::try_new(file.try_clone().unwrap()).unwrap()
+        };
+        let metadata = builder(file).metadata().clone();
+        let groups = (0..metadata.num_row_groups())
+            .map(|i| {
+                let rows = metadata.row_group(i).num_rows() as usize;
+                let mut reader = builder(file)
+                    .with_row_groups(vec![i])
+                    .with_batch_size(rows)
+                    .build()
+                    .unwrap();
+                reader.next().unwrap().unwrap()
+            })
+            .collect();
+        (builder(file).schema().clone(), groups)
+    }
+
+    #[test]
+    fn test_parquet_sink() {
+        let responses = standard_intercept();
+        let params = DetectionParams::default();
+        let mut state = AircraftStateMap::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshots.parquet");
+        // Each snapshot has 2 rows, so gets its own row group.
+        let mut sink = ParquetSink::create(path.to_str().unwrap())
+            .unwrap()
+            .row_group_rows(2);
+        for response in &responses {
+            detect_frame_interceptions(&mut state, response, &params);
+            sink.write_snapshot(&state, response.now).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let (schema, groups) = read_row_groups(&File::open(&path).unwrap());
+        assert_eq!(schema.fields(), super::schema().fields());
+        assert_eq!(groups.len(), responses.len());
+        assert!(groups.iter().all(|g| g.num_rows() == 2));
+        let metadata = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .metadata()
+            .clone();
+        assert!(matches!(
+            metadata.row_group(0).column(0).compression(),
+            Compression::ZSTD(_)
+        ));
+
+        // Both aircraft are in every snapshot, in order of hex ID.
+        let first = &groups[0];
+        let hexes: Vec<&str> = first
+            .column(0)
+            .as_string::<i32>()
+            .iter()
+            .flatten()
+            .collect();
+        assert_eq!(hexes, vec!["a12345", "ae1234"]);
+        let times = first.column(1).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(times.value(0), responses[0].now.timestamp_millis());
+        let lats = first.column(2).as_primitive::<Float64Type>();
+        assert_eq!(lats.value(0), 34.0);
+        // The target is a potential target the whole time, and the
+        // interceptor becomes a fast mover.
+        let flags: Vec<(bool, bool)> = groups
+            .iter()
+            .map(|g| {
+                (
+                    g.column(6).as_boolean().value(1),
+                    g.column(7).as_boolean().value(0),
+                )
+            })
+            .collect();
+        assert!(flags.iter().all(|(_, toi)| *toi), "{:?}", flags);
+        assert!(!flags[0].0);
+        assert!(flags.iter().any(|(fast, _)| *fast), "{:?}", flags);
+
+        // Dropping the sink finishes the file too.
+        let mut file = tempfile::tempfile().unwrap();
+        let mut sink = ParquetSink::new(&mut file)
+            .unwrap()
+            .compression(ParquetCompression::Uncompressed);
+        sink.write_rows(snapshot_rows(&state, responses.last().unwrap().now))
+            .unwrap();
+        drop(sink);
+        let (_, groups) = read_row_groups(&file);
+        assert_eq!(groups.len(), 1);
+        let alts = groups[0].column(5).as_primitive::<Int32Type>();
+        assert_eq!(alts.len(), 2);
+    }
+}
@@ -0,0 +1,205 @@
+//! Classifies how an interceptor is flying relative to its target, from the
+//! shape of its recent track.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geo::{haversine_distance_m, lon_difference, total_turn_deg},
+    interception::{relative_speed_kts, Ac, DetectionParams},
+};
+
+/// How the interceptor was flying relative to the target. Serialized as
+/// "pass", "shadow", or "orbit".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterceptPattern {
+    /// The interceptor came close, but wasn't matching the target's speed.
+    #[default]
+    Pass,
+    /// The interceptor is flying alongside the target.
+    Shadow,
+    /// The interceptor is circling the target.
+    Orbit,
+}
+
+impl InterceptPattern {
+    /// Classifies an interceptor/target pair that has come close.
+    pub fn classify(interceptor: &Ac, target: &Ac, params: &DetectionParams) -> Self {
+        if is_orbiting(
+            interceptor,
+            target,
+            params.orbit_radius_nm * 1852.0,
+            params.orbit_min_turn_deg,
+        ) {
+            InterceptPattern::Orbit
+        } else if relative_speed_kts(interceptor, target) <= params.max_relative_speed_kts {
+            InterceptPattern::Shadow
+        } else {
+            InterceptPattern::Pass
+        }
+    }
+}
+
+/// Returns the interceptor's positions relative to the target (at the closest
+/// time the target has a position for), as [east, north] in meters, oldest
+/// first.
+fn relative_positions(interceptor: &Ac, target: &Ac) -> Vec<[f64; 2]> {
+    interceptor
+        .coords
+        .iter()
+        .map(|(time, [lon, lat])| {
+            let [target_lon, target_lat] = target
+                .coords
+                .iter()
+                .min_by_key(|(t, _)| (*t - *time).num_milliseconds().abs())
+                .unwrap()
+                .1;
+            let east = lon_difference(target_lon, *lon) * target_lat.to_radians().cos() * 111_320.0;
+            let north = (lat - target_lat) * 110_574.0;
+            [east, north]
+        })
+        .collect()
+}
+
+/// Checks whether the interceptor has been circling the target: whether,
+/// going back from its latest position and staying within radius_m of the
+/// target, its track relative to the target turns through at least
+/// min_turn_deg in one direction.
+///
+/// Working relative to the target means an orbit around a moving target still
+/// looks like a circle, even though over the ground it's a series of loops.
+pub fn is_orbiting(interceptor: &Ac, target: &Ac, radius_m: f64, min_turn_deg: f64) -> bool {
+    let mut num_within = 0;
+    for (time, [lon, lat]) in interceptor.coords.iter().rev() {
+        let target_coords = target
+            .coords
+            .iter()
+            .min_by_key(|(t, _)| (*t - *time).num_milliseconds().abs())
+            .unwrap()
+            .1;
+        let dist = haversine_distance_m([*lon, *lat], target_coords);
+        if dist > radius_m {
+            break;
+        }
+        num_within += 1;
+    }
+    let positions = relative_positions(interceptor, target);
+    let positions = &positions[positions.len() - num_within..];
+    let headings = positions
+        .windows(2)
+        .filter(|w| w[0] != w[1])
+        .map(|w| (w[1][0] - w[0][0]).atan2(w[1][1] - w[0][1]).to_degrees());
+    total_turn_deg(headings).abs() >= min_turn_deg
+}
+
+/// Counts the interceptor's passes of the target: the runs of its positions
+/// within radius_m of the target (at the closest time the target has a
+/// position for).
+pub fn count_passes(interceptor: &Ac, target: &Ac, radius_m: f64) -> usize {
+    let mut num_passes = 0;
+    let mut within = false;
+    for (time, coords) in &interceptor.coords {
+        let target_coords = target
+            .coords
+            .iter()
+            .min_by_key(|(t, _)| (*t - *time).num_milliseconds().abs())
+            .unwrap()
+            .1;
+        let was_within = within;
+        within = haversine_distance_m(*coords, target_coords) <= radius_m;
+        if within && !was_within {
+            num_passes += 1;
+        }
+    }
+    num_passes
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{prelude::*, Duration};
+
+    fn ac(hex: &str, coords: Vec<(DateTime<Utc>, [f64; 2])>) -> Ac {
+        let n = coords.len();
+        Ac {
+            tracks: vec![None; n].into(),
+            speeds: vec![250.0; n].into(),
+            cur_speed: 250.0,
+            ..Ac::for_test(hex, &coords, 10000)
+        }
+    }
+
+    // An interceptor circles at about 2 nm radius, once every 24 snapshots,
+    // around a target that moves north by north_per_step degrees each
+    // snapshot.
+    fn orbit(num_steps: i64, north_per_step: f64) -> (Ac, Ac) {
+        let start = Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap();
+        let mut interceptor = vec![];
+        let mut target = vec![];
+        for i in 0..num_steps {
+            let time = start + Duration::seconds(i * 15);
+            let center = [-118.0, 34.0 + north_per_step * i as f64];
+            let angle = (i as f64 * 15.0).to_radians();
+            interceptor.push((
+                time,
+                [
+                    center[0] + 0.04 * angle.sin(),
+                    center[1] + 0.033 * angle.cos(),
+                ],
+            ));
+            target.push((time, center));
+        }
+        (ac("ae1234", interceptor), ac("a12345", target))
+    }
+
+    #[test]
+    fn test_orbit_around_fixed_point() {
+        let radius_m = 3.0 * 1852.0;
+        // 21 positions make 20 segments, which turn through 19 * 15 = 285°.
+        let (interceptor, target) = orbit(21, 0.0);
+        assert!(!is_orbiting(&interceptor, &target, radius_m, 300.0));
+        // 23 turn through 315°.
+        let (interceptor, target) = orbit(23, 0.0);
+        assert!(is_orbiting(&interceptor, &target, radius_m, 300.0));
+        // But not if the circle is bigger than the radius.
+        assert!(!is_orbiting(&interceptor, &target, 1000.0, 300.0));
+    }
+
+    #[test]
+    fn test_count_passes() {
+        let start = Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap();
+        // Back and forth over a fixed point, 0.1° of longitude each way.
+        let lons = [-0.3, -0.1, 0.0, 0.1, 0.3, 0.1, 0.0, -0.1, -0.3];
+        let interceptor = ac(
+            "ae1234",
+            lons.iter()
+                .enumerate()
+                .map(|(i, lon)| {
+                    (
+                        start + Duration::seconds(i as i64 * 15),
+                        [-118.0 + lon, 34.0],
+                    )
+                })
+                .collect(),
+        );
+        let target = ac("a12345", vec![(start, [-118.0, 34.0])]);
+        assert_eq!(count_passes(&interceptor, &target, 3.0 * 1852.0), 2);
+        // With a bigger radius it never leaves.
+        assert_eq!(count_passes(&interceptor, &target, 50_000.0), 1);
+        assert_eq!(count_passes(&interceptor, &target, 100.0), 2);
+    }
+
+    #[test]
+    fn test_orbit_around_moving_target() {
+        // The target moves about 30 kts north, so over the ground the
+        // interceptor's track is a loop that never quite closes.
+        let radius_m = 3.0 * 1852.0;
+        let (interceptor, target) = orbit(23, 0.002);
+        assert!(is_orbiting(&interceptor, &target, radius_m, 300.0));
+        // If only the interceptor's track moved north, it would be a spiral
+        // away from the target.
+        let target = ac("a12345", vec![target.coords[0]]);
+        assert!(!is_orbiting(&interceptor, &target, radius_m, 300.0));
+    }
+}
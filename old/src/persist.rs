@@ -0,0 +1,99 @@
+//! Saving the detector's state to a file, so that a live run that's
+//! restarted picks up where it left off, instead of forgetting every
+//! aircraft's history and reporting ongoing interceptions as new ones. See
+//! State::save_state and State::load_state. Pipeline checkpoints of a State
+//! are in the same format; see CheckpointState.
+//!
+//! A state file is a magic number and STATE_VERSION, followed by the state as
+//! CBOR. CBOR is compact like bincode or postcard, but it's self-describing,
+//! so the serde(default) and skip_serializing_if fields on Ac and
+//! InterceptionEvent work the same as they do in JSON. Files with any other
+//! version are rejected with Error::StateVersionMismatch.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Error;
+
+const MAGIC: &[u8] = b"TRACON";
+
+/// The version of the state format. It changes whenever saved state can't be
+/// loaded by the new code.
+pub const STATE_VERSION: u16 = 1;
+
+fn state_error(path: &str, e: impl std::fmt::Display) -> Error {
+    Error::StateError(format!("{}: {}", path, e))
+}
+
+/// Encodes state the way save writes it. name is used in error messages.
+pub(crate) fn encode<T: Serialize>(name: &str, state: &T) -> Result<Vec<u8>, Error> {
+    let mut data = MAGIC.to_vec();
+    data.extend(STATE_VERSION.to_le_bytes());
+    ciborium::into_writer(state, &mut data).map_err(|e| state_error(name, e))?;
+    Ok(data)
+}
+
+/// Decodes state encoded by encode.
+pub(crate) fn decode<T: DeserializeOwned>(name: &str, data: &[u8]) -> Result<T, Error> {
+    let (version, state) = data
+        .strip_prefix(MAGIC)
+        .filter(|rest| rest.len() >= 2)
+        .map(|rest| (u16::from_le_bytes([rest[0], rest[1]]), &rest[2..]))
+        .ok_or_else(|| state_error(name, "not a state file"))?;
+    if version != STATE_VERSION {
+        return Err(Error::StateVersionMismatch {
+            path: name.to_string(),
+            found: version,
+            expected: STATE_VERSION,
+        });
+    }
+    ciborium::from_reader(state).map_err(|e| state_error(name, e))
+}
+
+/// Writes state to a file atomically, by writing to a temporary file and
+/// renaming it.
+pub(crate) fn save<T: Serialize>(path: &str, state: &T) -> Result<(), Error> {
+    let data = encode(path, state)?;
+    let tmp_path = format!("{}.tmp", path);
+    std::fs::write(&tmp_path, data).map_err(|e| state_error(&tmp_path, e))?;
+    std::fs::rename(&tmp_path, path).map_err(|e| state_error(path, e))
+}
+
+/// Reads state written by save.
+pub(crate) fn load<T: DeserializeOwned>(path: &str) -> Result<T, Error> {
+    let data = std::fs::read(path).map_err(|e| state_error(path, e))?;
+    decode(path, &data)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("persist.state");
+        let path = path.to_str().unwrap();
+        let state = HashMap::from([(("a".to_string(), "b".to_string()), vec![1.5, 2.5])]);
+        save(path, &state).unwrap();
+        let loaded: HashMap<(String, String), Vec<f64>> = load(path).unwrap();
+        assert_eq!(loaded, state);
+
+        // The version is right after the magic number.
+        let mut data = std::fs::read(path).unwrap();
+        data[MAGIC.len()] += 1;
+        std::fs::write(path, &data).unwrap();
+        match load::<HashMap<(String, String), Vec<f64>>>(path) {
+            Err(Error::StateVersionMismatch {
+                found, expected, ..
+            }) => assert_eq!((found, expected), (STATE_VERSION + 1, STATE_VERSION)),
+            r => panic!("Expected a version mismatch, got {:?}", r),
+        }
+        std::fs::write(path, b"{}").unwrap();
+        assert!(matches!(
+            load::<HashMap<(String, String), Vec<f64>>>(path),
+            Err(Error::StateError(_))
+        ));
+    }
+}
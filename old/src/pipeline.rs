@@ -0,0 +1,899 @@
+//! A configurable pipeline for processing collections of ADS-B Exchange API
+//! responses.
+//!
+//! ```no_run
+//! use tracon::{order::InputOrder, pipeline::AdsbxPipeline};
+//!
+//! let paths = vec!["2023-01-02-153405Z.json.bz2".to_string()];
+//! AdsbxPipeline::new(&paths)
+//!     .skip_errors(true)
+//!     .order(InputOrder::Strict)
+//!     .checkpoint("run.checkpoint", 100)
+//!     .run(|response, _bar| {
+//!         println!("{} aircraft", response.aircraft.len());
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::{
+    io::Read,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use adsbx_json::v2::Response;
+use indicatif::{ProgressBar, ProgressStyle};
+use pariter::IteratorExt;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    error::Error,
+    input::{filter_paths_by_time, TimeRange},
+    interception::{process_adsbx_response, State},
+    load_adsbx_json_reader, open_adsbx_json_file,
+    order::{InputOrder, OrderChecker},
+    stats::{Stats, StatsReport},
+};
+
+/// State that can be saved in a checkpoint, so a long run can resume where it
+/// left off.
+pub trait CheckpointState {
+    fn to_checkpoint(&self) -> Result<Value, Error>;
+    fn restore_checkpoint(&mut self, value: Value) -> Result<(), Error>;
+}
+
+/// Unit state, for runs that don't need anything beyond the position in the
+/// input saved.
+impl CheckpointState for () {
+    fn to_checkpoint(&self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn restore_checkpoint(&mut self, _value: Value) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The detector's state, saved in the format of State::save_state (as a hex
+/// string), along with the interceptions and events found so far, so a
+/// resumed run finds the same ones. The notifiers, metrics, and heatmap
+/// aren't saved.
+impl CheckpointState for State {
+    fn to_checkpoint(&self) -> Result<Value, Error> {
+        let data = self.checkpoint_bytes()?;
+        Ok(Value::String(
+            data.iter().map(|b| format!("{:02x}", b)).collect(),
+        ))
+    }
+
+    fn restore_checkpoint(&mut self, value: Value) -> Result<(), Error> {
+        let data = value
+            .as_str()
+            .filter(|hex| hex.len() % 2 == 0)
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .ok_or_else(|| Error::CheckpointError("the state isn't a hex string".to_string()))?;
+        self.restore_checkpoint_bytes(&data)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CheckpointConfig {
+    path: String,
+    every: usize,
+}
+
+/// A function that loads an input file.
+pub type Loader = fn(&str) -> Result<Response, Error>;
+
+/// A function that's called after each file with (files_done, files_total,
+/// current_path).
+pub type ProgressCallback = Arc<dyn Fn(usize, usize, &str) + Send + Sync>;
+
+/// A function that's called with the path and error for each file that can't
+/// be loaded. The error is always an Error::FileError.
+pub type ErrorCallback = Arc<dyn Fn(&str, &Error) + Send + Sync>;
+
+/// How the pipeline reports progress.
+#[derive(Clone, Default)]
+pub enum ProgressMode {
+    /// Draw a progress bar on the terminal.
+    #[default]
+    Bar,
+    /// Don't report progress.
+    Hidden,
+    /// Call a function after each file.
+    Callback(ProgressCallback),
+}
+
+impl std::fmt::Debug for ProgressMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgressMode::Bar => write!(f, "Bar"),
+            ProgressMode::Hidden => write!(f, "Hidden"),
+            ProgressMode::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+/// Time spent in each phase of a pipeline run.
+///
+/// Decompression and parsing happen on the worker threads, so their durations
+/// are summed across workers and can add up to more than the wall time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseDurations {
+    /// Reading and decompressing files.
+    pub decompress: Duration,
+    /// Parsing JSON. If a custom loader is set, all of its time is counted
+    /// here.
+    pub parse: Duration,
+    /// Running the callback.
+    pub callback: Duration,
+}
+
+impl std::ops::AddAssign for PhaseDurations {
+    fn add_assign(&mut self, other: Self) {
+        self.decompress += other.decompress;
+        self.parse += other.parse;
+        self.callback += other.callback;
+    }
+}
+
+/// Counts and timing from a pipeline run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    /// The number of files that were loaded successfully.
+    pub files_ok: usize,
+    /// The paths of files that couldn't be loaded.
+    pub files_failed: Vec<String>,
+    /// The total number of aircraft records in the responses passed to the
+    /// callback.
+    pub total_aircraft_records: usize,
+    /// The number of those aircraft records that had no position.
+    pub records_missing_position: usize,
+    pub wall_time: Duration,
+    pub phases: PhaseDurations,
+    /// The statistics of the events found by run_detection, if the
+    /// pipeline has stats.
+    pub stats: Option<StatsReport>,
+}
+
+// A reader that adds up the time spent in the reader it wraps.
+struct TimedReader<R> {
+    inner: R,
+    elapsed: Duration,
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = Instant::now();
+        let r = self.inner.read(buf);
+        self.elapsed += start.elapsed();
+        r
+    }
+}
+
+/// Loads a file with the default loader, timing decompression and parsing
+/// separately. The file is parsed as it's decompressed; the time spent
+/// reading from the decompressor counts as decompression, and the rest as
+/// parsing.
+fn load_timed(path: &str) -> (Result<Response, Error>, PhaseDurations) {
+    let mut phases = PhaseDurations::default();
+    let start = Instant::now();
+    let mut reader = match open_adsbx_json_file(path) {
+        Ok(inner) => TimedReader {
+            inner,
+            elapsed: start.elapsed(),
+        },
+        Err(e) => {
+            phases.decompress = start.elapsed();
+            return (Err(e), phases);
+        }
+    };
+    let r = load_adsbx_json_reader(&mut reader).map_err(|e| e.in_file(path));
+    phases.decompress = reader.elapsed;
+    phases.parse = start.elapsed().saturating_sub(reader.elapsed);
+    (r, phases)
+}
+
+/// Builds and runs a pipeline that decompresses and parses input files in
+/// parallel, but calls the callback function serially.
+///
+/// The callback always receives responses in the same order as the input
+/// paths (subject to the InputOrder setting), no matter how long each file
+/// takes to decode.
+#[derive(Clone)]
+pub struct AdsbxPipeline {
+    paths: Vec<String>,
+    loader: Option<Loader>,
+    workers: usize,
+    max_in_flight: Option<usize>,
+    progress: ProgressMode,
+    on_error: Option<ErrorCallback>,
+    skip_errors: bool,
+    order: InputOrder,
+    time_range: Option<TimeRange>,
+    checkpoint: Option<CheckpointConfig>,
+    stats: Option<Stats>,
+}
+
+fn checkpoint_error(path: &str, e: impl std::fmt::Display) -> Error {
+    Error::CheckpointError(format!("{}: {}", path, e))
+}
+
+impl CheckpointConfig {
+    /// Reads the checkpoint, if there is one. Returns the last processed path
+    /// and the saved state.
+    fn load(&self) -> Result<Option<(String, Value)>, Error> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(checkpoint_error(&self.path, e)),
+        };
+        let mut checkpoint: Value =
+            serde_json::from_str(&contents).map_err(|e| checkpoint_error(&self.path, e))?;
+        let last_path = checkpoint["last_path"]
+            .as_str()
+            .ok_or_else(|| checkpoint_error(&self.path, "missing last_path"))?
+            .to_string();
+        Ok(Some((last_path, checkpoint["state"].take())))
+    }
+
+    /// Writes the checkpoint atomically, by writing to a temporary file and
+    /// renaming it.
+    fn save<S: CheckpointState>(&self, last_path: &str, state: &S) -> Result<(), Error> {
+        let checkpoint = json!({
+            "last_path": last_path,
+            "state": state.to_checkpoint()?,
+        });
+        let tmp_path = format!("{}.tmp", self.path);
+        std::fs::write(&tmp_path, checkpoint.to_string())
+            .map_err(|e| checkpoint_error(&tmp_path, e))?;
+        std::fs::rename(&tmp_path, &self.path).map_err(|e| checkpoint_error(&self.path, e))
+    }
+}
+
+// Which inputs are done, so that the checkpoint covers only inputs that are
+// all done. low_water is the number of inputs from the start that are done,
+// and high_water is one past the last one processed.
+struct InputProgress {
+    done: Vec<bool>,
+    low_water: usize,
+    high_water: usize,
+}
+
+impl InputProgress {
+    fn new(num_inputs: usize) -> Self {
+        InputProgress {
+            done: vec![false; num_inputs],
+            low_water: 0,
+            high_water: 0,
+        }
+    }
+
+    /// Marks an input done, either processed or skipped.
+    fn mark_done(&mut self, i: usize, processed: bool) {
+        self.done[i] = true;
+        if processed {
+            self.high_water = self.high_water.max(i + 1);
+        }
+        while self.done.get(self.low_water) == Some(&true) {
+            self.low_water += 1;
+        }
+    }
+
+    /// The index of the input the checkpoint can be saved at, if there's one
+    /// where every input up to it is done and none after it has been
+    /// processed.
+    fn checkpoint_index(&self) -> Option<usize> {
+        (self.low_water > 0 && self.high_water <= self.low_water).then(|| self.low_water - 1)
+    }
+}
+
+impl AdsbxPipeline {
+    pub fn new(paths: &[String]) -> Self {
+        AdsbxPipeline {
+            paths: paths.to_vec(),
+            loader: None,
+            workers: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            max_in_flight: None,
+            progress: ProgressMode::Bar,
+            on_error: None,
+            skip_errors: false,
+            order: InputOrder::Unchecked,
+            time_range: None,
+            checkpoint: None,
+            stats: None,
+        }
+    }
+
+    /// Sets the function used to load each input file. The default is
+    /// equivalent to load_adsbx_json_file.
+    pub fn loader(mut self, loader: Loader) -> Self {
+        self.loader = Some(loader);
+        self
+    }
+
+    /// Sets the number of threads used to decompress and parse files. The
+    /// default is the number of available CPUs.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets the maximum number of files that can be loaded (or loading) but not
+    /// yet processed at any one time, which caps memory use. The default is the
+    /// number of workers.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight.max(1));
+        self
+    }
+
+    /// Sets how progress is reported. The default is a progress bar.
+    pub fn progress(mut self, progress: ProgressMode) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Sets a function to call for each file that can't be loaded. By default
+    /// these errors are logged with the log crate.
+    pub fn on_error(mut self, on_error: ErrorCallback) -> Self {
+        self.on_error = Some(on_error);
+        self
+    }
+
+    /// If true, files that can't be loaded are reported and skipped instead of
+    /// stopping the run.
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    /// Sets how responses that aren't in chronological order are handled.
+    pub fn order(mut self, order: InputOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Only process responses within the time range.
+    pub fn time_range(mut self, range: TimeRange) -> Self {
+        self.time_range = Some(range);
+        self
+    }
+
+    /// Saves a checkpoint to path every `every` files. If the checkpoint
+    /// already exists when the pipeline is run, inputs up to and including the
+    /// one recorded in the checkpoint are skipped.
+    ///
+    /// A checkpoint only covers a run of inputs from the start that are all
+    /// done, so with InputOrder::Reorder, where files are processed out of
+    /// input order, one is saved only once none of the files processed are
+    /// after a file that isn't done yet (e.g. one still in the buffer).
+    pub fn checkpoint(mut self, path: &str, every: usize) -> Self {
+        self.checkpoint = Some(CheckpointConfig {
+            path: path.to_string(),
+            every: every.max(1),
+        });
+        self
+    }
+
+    /// Adds the events found by run_detection to stats, and reports them in
+    /// the summary.
+    pub fn stats(mut self, stats: Stats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Runs the detector on each response with process_adsbx_response, then
+    /// finishes the state. With a checkpoint, the state is saved in it. With
+    /// stats, the state's events are added to them, and the summary has
+    /// their report.
+    pub fn run_detection(mut self, state: &mut State) -> Result<Summary, Error> {
+        let stats = self.stats.take();
+        let mut summary = self.run_with_state(state, process_adsbx_response)?;
+        state.finish();
+        if let Some(mut stats) = stats {
+            for event in &state.events {
+                stats.ingest(event);
+            }
+            summary.stats = Some(stats.finalize());
+        }
+        Ok(summary)
+    }
+
+    /// Runs the pipeline, calling f with each response.
+    pub fn run<F>(self, mut f: F) -> Result<Summary, Error>
+    where
+        F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+    {
+        self.run_with_state(&mut (), |_, response, bar| f(response, bar))
+    }
+
+    /// Runs the pipeline, calling f with the state and each response. If a
+    /// checkpoint is configured the state is saved along with it, and restored
+    /// when resuming.
+    pub fn run_with_state<S, F>(self, state: &mut S, mut f: F) -> Result<Summary, Error>
+    where
+        S: CheckpointState,
+        F: FnMut(&mut S, Response, &ProgressBar) -> Result<(), Error>,
+    {
+        let mut paths = match &self.time_range {
+            Some(range) => filter_paths_by_time(&self.paths, range),
+            None => self.paths.clone(),
+        };
+        if let Some(checkpoint) = &self.checkpoint {
+            if let Some((last_path, saved_state)) = checkpoint.load()? {
+                let i = paths.iter().position(|p| p == &last_path).ok_or_else(|| {
+                    checkpoint_error(
+                        &checkpoint.path,
+                        format!("{} is not one of the inputs", last_path),
+                    )
+                })?;
+                info!("Resuming after {}", last_path);
+                paths.drain(..=i);
+                state.restore_checkpoint(saved_state)?;
+            }
+        }
+
+        let start = Instant::now();
+        let num_files = paths.len();
+        let bar = match self.progress {
+            ProgressMode::Bar => {
+                let bar = ProgressBar::new(num_files.try_into().unwrap());
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{wide_bar} {pos}/{len} {eta} {elapsed_precise} {msg}"),
+                );
+                bar
+            }
+            ProgressMode::Hidden | ProgressMode::Callback(_) => ProgressBar::hidden(),
+        };
+        let mut files_done = 0;
+        let mut summary = Summary::default();
+        let mut files_ok = 0;
+        let mut files_failed = vec![];
+        let mut load_phases = PhaseDurations::default();
+        let mut checker = OrderChecker::new(self.order);
+        let mut progress = InputProgress::new(num_files);
+        let mut num_processed = 0;
+        let mut checkpoint_due = false;
+        let mut process = |state: &mut S,
+                           response: Response,
+                           i: usize,
+                           progress: &mut InputProgress|
+         -> Result<(), Error> {
+            summary.total_aircraft_records += response.aircraft.len();
+            summary.records_missing_position += response
+                .aircraft
+                .iter()
+                .filter(|a| a.lat.is_none() || a.lon.is_none())
+                .count();
+            let start = Instant::now();
+            let r = f(state, response, &bar);
+            summary.phases.callback += start.elapsed();
+            r?;
+            progress.mark_done(i, true);
+            num_processed += 1;
+            if let Some(checkpoint) = &self.checkpoint {
+                checkpoint_due |= num_processed % checkpoint.every == 0;
+                if let (true, Some(i)) = (checkpoint_due, progress.checkpoint_index()) {
+                    checkpoint.save(&paths[i], state)?;
+                    checkpoint_due = false;
+                }
+            }
+            Ok(())
+        };
+        let loader = self.loader;
+        let workers = self.workers;
+        let max_in_flight = self.max_in_flight.unwrap_or(workers);
+        let r = pariter::scope(|scope| {
+            paths
+                .iter()
+                .enumerate()
+                .parallel_map_scoped_custom(
+                    scope,
+                    |b| b.threads(workers).buffer_size(max_in_flight),
+                    |(i, path)| match loader {
+                        Some(loader) => {
+                            let start = Instant::now();
+                            let r = loader(path);
+                            let phases = PhaseDurations {
+                                parse: start.elapsed(),
+                                ..Default::default()
+                            };
+                            (i, path, r, phases)
+                        }
+                        None => {
+                            let (r, phases) = load_timed(path);
+                            (i, path, r, phases)
+                        }
+                    },
+                )
+                .try_for_each(|(i, path, result, phases)| {
+                    load_phases += phases;
+                    let r = match result {
+                        Ok(response) => {
+                            files_ok += 1;
+                            if self
+                                .time_range
+                                .is_none_or(|range| range.contains(response.now))
+                            {
+                                checker.push(response, i)?.into_iter().try_for_each(
+                                    |(response, i)| process(state, response, i, &mut progress),
+                                )
+                            } else {
+                                progress.mark_done(i, false);
+                                Ok(())
+                            }
+                        }
+                        Err(err) => {
+                            let err = err.in_file(path);
+                            match &self.on_error {
+                                Some(on_error) => on_error(path, &err),
+                                None => warn!("{}", err),
+                            }
+                            files_failed.push(path.clone());
+                            if !self.skip_errors {
+                                Err(err)
+                            } else {
+                                progress.mark_done(i, false);
+                                Ok(())
+                            }
+                        }
+                    };
+                    bar.inc(1);
+                    files_done += 1;
+                    if let ProgressMode::Callback(callback) = &self.progress {
+                        callback(files_done, num_files, path);
+                    }
+                    r
+                })?;
+            checker
+                .finish()
+                .into_iter()
+                .try_for_each(|(response, i)| process(state, response, i, &mut progress))
+        })
+        .map_err(|panic| {
+            let msg = match panic.downcast_ref::<&str>() {
+                Some(msg) => msg.to_string(),
+                None => panic
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| "unknown panic".to_string()),
+            };
+            Error::WorkerPanic(msg)
+        });
+        bar.finish();
+        let r = match r {
+            Ok(r) => r,
+            Err(e) => Err(e),
+        };
+        // Save a final checkpoint on success, or if we got partway through.
+        if let (Some(checkpoint), Some(i)) = (&self.checkpoint, progress.checkpoint_index()) {
+            checkpoint.save(&paths[i], state)?;
+        }
+        r?;
+        summary.files_ok = files_ok;
+        summary.files_failed = files_failed;
+        summary.phases += load_phases;
+        summary.wall_time = start.elapsed();
+        Ok(summary)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_adsbx_json_file;
+
+    fn data_path(name: &str) -> String {
+        format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    // Snapshots 5 seconds apart, in files named for their index, e.g.
+    // "3.json" for the one from 15 seconds after the first.
+    fn numbered_snapshots(dir: &std::path::Path, n: i64) -> Vec<String> {
+        (0..n)
+            .map(|i| {
+                let ms = 1614109133000i64 + i * 5000;
+                let path = dir.join(format!("{}.json", i));
+                std::fs::write(
+                    &path,
+                    format!(
+                        r#"{{"now": {}, "ctime": {}, "ptime": 10, "total": 0, "ac": []}}"#,
+                        ms, ms
+                    ),
+                )
+                .unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    // Takes a different time to load each of numbered_snapshots' files, so
+    // that they finish loading out of order.
+    fn slow_loader(path: &str) -> Result<Response, Error> {
+        const DELAYS_MS: [u64; 8] = [120, 0, 60, 10, 90, 0, 30, 5];
+        let index: usize = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(DELAYS_MS[index]));
+        load_adsbx_json_file(path)
+    }
+
+    #[test]
+    fn test_load_timed() {
+        let path = data_path("2021-02-23-193858Z.json.bz2");
+        let start = Instant::now();
+        let (r, phases) = load_timed(&path);
+        let elapsed = start.elapsed();
+        assert_eq!(r.unwrap(), load_adsbx_json_file(&path).unwrap());
+        assert!(phases.decompress > Duration::ZERO);
+        assert!(phases.parse > Duration::ZERO);
+        assert!(phases.decompress + phases.parse <= elapsed);
+        let (r, _) = load_timed(&data_path("corrupt.json"));
+        assert!(matches!(r, Err(Error::FileError { .. })));
+    }
+
+    #[test]
+    fn test_order_is_preserved() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = numbered_snapshots(dir.path(), 8);
+        let mut secs = vec![];
+        AdsbxPipeline::new(&paths)
+            .loader(slow_loader)
+            .workers(4)
+            .run(|response, _| {
+                secs.push(response.now.timestamp() - 1614109133);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(secs, vec![0, 5, 10, 15, 20, 25, 30, 35]);
+    }
+
+    #[test]
+    fn test_progress_and_error_callbacks() {
+        use std::sync::Mutex;
+        let paths = vec![
+            data_path("2021-02-23-193853Z.json"),
+            data_path("corrupt.json"),
+            data_path("2021-02-23-193858Z.json"),
+        ];
+        let progress = Arc::new(Mutex::new(vec![]));
+        let errors = Arc::new(Mutex::new(vec![]));
+        let (p, e) = (progress.clone(), errors.clone());
+        let summary = AdsbxPipeline::new(&paths)
+            .skip_errors(true)
+            .progress(ProgressMode::Callback(Arc::new(
+                move |done, total, path| p.lock().unwrap().push((done, total, path.to_string())),
+            )))
+            .on_error(Arc::new(move |path, _err| {
+                e.lock().unwrap().push(path.to_string())
+            }))
+            .run(|_, _| Ok(()))
+            .unwrap();
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[2], (3, 3, paths[2].clone()));
+        assert_eq!(*errors.lock().unwrap(), vec![paths[1].clone()]);
+        assert_eq!(summary.files_ok, 2);
+        assert_eq!(summary.files_failed, vec![paths[1].clone()]);
+        assert_eq!(summary.total_aircraft_records, 4);
+        assert_eq!(summary.records_missing_position, 0);
+        assert!(summary.phases.decompress > Duration::ZERO);
+        assert!(summary.phases.parse > Duration::ZERO);
+        assert!(summary.wall_time > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_run_detection_stats() {
+        use crate::testutil::standard_intercept;
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = standard_intercept()
+            .iter()
+            .enumerate()
+            .map(|(i, response)| {
+                let path = dir.path().join(format!("{:03}.json", i));
+                std::fs::write(&path, serde_json::to_string(response).unwrap()).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let mut state = State::default();
+        let summary = AdsbxPipeline::new(&paths)
+            .progress(ProgressMode::Hidden)
+            .stats(Stats::new())
+            .run_detection(&mut state)
+            .unwrap();
+        assert_eq!(summary.files_ok, paths.len());
+        assert_eq!(state.events.len(), 1);
+        let stats = summary.stats.unwrap();
+        assert_eq!(stats.num_events, 1);
+        assert_eq!(stats.distinct_interceptors, 1);
+        assert_eq!(stats.start, Some(state.events[0].start));
+
+        let mut state = State::default();
+        let summary = AdsbxPipeline::new(&paths)
+            .progress(ProgressMode::Hidden)
+            .run_detection(&mut state)
+            .unwrap();
+        assert_eq!(state.events.len(), 1);
+        assert_eq!(summary.stats, None);
+    }
+
+    #[derive(Default)]
+    struct Count(u64);
+
+    impl CheckpointState for Count {
+        fn to_checkpoint(&self) -> Result<Value, Error> {
+            Ok(json!(self.0))
+        }
+
+        fn restore_checkpoint(&mut self, value: Value) -> Result<(), Error> {
+            self.0 = value.as_u64().unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint = dir.path().join("checkpoint.json");
+        let checkpoint = checkpoint.to_str().unwrap();
+        let paths = vec![
+            data_path("2021-02-23-193853Z.json"),
+            data_path("corrupt.json"),
+            data_path("2021-02-23-193858Z.json"),
+        ];
+        // The first run stops at the corrupt file.
+        let mut count = Count::default();
+        let r = AdsbxPipeline::new(&paths)
+            .checkpoint(checkpoint, 1)
+            .run_with_state(&mut count, |count, _, _| {
+                count.0 += 1;
+                Ok(())
+            });
+        assert!(r.is_err());
+        assert_eq!(count.0, 1);
+
+        // The second run needs to skip the first file, and restores the count.
+        let paths = vec![paths[0].clone(), paths[2].clone()];
+        let mut count = Count::default();
+        AdsbxPipeline::new(&paths)
+            .checkpoint(checkpoint, 1)
+            .run_with_state(&mut count, |count, _, _| {
+                count.0 += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(count.0, 2);
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(checkpoint).unwrap()).unwrap();
+        assert_eq!(saved["last_path"], json!(paths[1]));
+        assert_eq!(saved["state"], json!(2));
+    }
+
+    #[test]
+    fn test_checkpoint_resume_detection() {
+        use crate::testutil::standard_intercept;
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint = dir.path().join("checkpoint.json");
+        let checkpoint = checkpoint.to_str().unwrap();
+        let paths: Vec<String> = standard_intercept()
+            .iter()
+            .enumerate()
+            .map(|(i, response)| {
+                let path = dir.path().join(format!("{:03}.json", i));
+                std::fs::write(&path, serde_json::to_string(response).unwrap()).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let pipeline = || {
+            AdsbxPipeline::new(&paths)
+                .progress(ProgressMode::Hidden)
+                .checkpoint(checkpoint, 1)
+        };
+        let mut expected = State::default();
+        AdsbxPipeline::new(&paths)
+            .progress(ProgressMode::Hidden)
+            .run_detection(&mut expected)
+            .unwrap();
+        assert_eq!(expected.events.len(), 1);
+
+        // The first run dies a few snapshots into the interception.
+        let mut state = State::default();
+        let mut open_for = 0;
+        let r = pipeline().run_with_state(&mut state, |state, response, bar| {
+            if open_for == 3 {
+                return Err(Error::ExportError("stop".to_string()));
+            }
+            process_adsbx_response(state, response, bar)?;
+            open_for += usize::from(state.tracker.num_open() > 0);
+            Ok(())
+        });
+        assert!(r.is_err());
+        assert_eq!(state.tracker.num_open(), 1);
+        assert_eq!(state.interceptions.len(), 1);
+
+        // The second run carries on with the open event, instead of starting
+        // it again, and ends up with what an uninterrupted run finds.
+        let mut resumed = State::default();
+        let summary = pipeline().run_detection(&mut resumed).unwrap();
+        assert!(summary.files_ok < paths.len());
+        assert_eq!(resumed.interceptions.len(), 1);
+        assert_eq!(resumed.events, expected.events);
+    }
+
+    // The times of the responses processed, in seconds.
+    #[derive(Default)]
+    struct Seen(Vec<i64>);
+
+    impl CheckpointState for Seen {
+        fn to_checkpoint(&self) -> Result<Value, Error> {
+            Ok(json!(self.0))
+        }
+
+        fn restore_checkpoint(&mut self, value: Value) -> Result<(), Error> {
+            self.0 = serde_json::from_value(value).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_resume_reordered() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint = dir.path().join("checkpoint.json");
+        let checkpoint = checkpoint.to_str().unwrap();
+        // Each pair of files swapped.
+        let snapshots = numbered_snapshots(dir.path(), 8);
+        let paths: Vec<String> = [1, 0, 3, 2, 5, 4, 7, 6]
+            .iter()
+            .map(|&i| snapshots[i].clone())
+            .collect();
+        let pipeline = || {
+            AdsbxPipeline::new(&paths)
+                .progress(ProgressMode::Hidden)
+                .order(InputOrder::Reorder(2))
+                .checkpoint(checkpoint, 1)
+        };
+        let secs = |response: &Response| response.now.timestamp() - 1614109133;
+
+        // The first run fails at the snapshot from file 5, when 4 is still
+        // buffered and files after it have been processed, so the checkpoint
+        // is from before that.
+        let mut seen = Seen::default();
+        let r = pipeline().run_with_state(&mut seen, |seen, response, _| {
+            if secs(&response) == 25 {
+                return Err(Error::ExportError("stop".to_string()));
+            }
+            seen.0.push(secs(&response));
+            Ok(())
+        });
+        assert!(r.is_err());
+        assert_eq!(seen.0, vec![0, 5, 10, 15, 20]);
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(checkpoint).unwrap()).unwrap();
+        assert_eq!(saved["last_path"], json!(paths[3]));
+
+        // Resuming processes every snapshot after that exactly once.
+        let mut seen = Seen::default();
+        pipeline()
+            .run_with_state(&mut seen, |seen, response, _| {
+                seen.0.push(secs(&response));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(seen.0, vec![0, 5, 10, 15, 20, 25, 30, 35]);
+    }
+}
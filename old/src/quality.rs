@@ -0,0 +1,134 @@
+//! How much to trust an aircraft's positions.
+//!
+//! MLAT positions in particular can be 30 seconds apart and off by a mile,
+//! which is enough to make two aircraft look like they're in formation. The
+//! quality score combines where the positions come from, how often they're
+//! updated, the accuracy the aircraft reports for them (NIC and NACp), and
+//! how many were rejected as implausible.
+
+use adsbx_json::v2::MessageType;
+
+use crate::interception::Ac;
+
+/// Positions updated at least this often don't lose any score for it.
+pub const NOMINAL_UPDATE_INTERVAL_SECS: f64 = 15.0;
+
+/// A NACp at least this high means an estimated position uncertainty under
+/// 0.05 nm.
+pub const GOOD_NAC_P: u8 = 8;
+
+/// A NIC at least this high means a containment radius under 0.2 nm.
+pub const GOOD_NIC: u8 = 7;
+
+/// Returns the name the feed uses for a message type, e.g. "adsb_icao" or
+/// "mlat".
+pub fn source_name(message_type: &MessageType) -> &'static str {
+    match message_type {
+        MessageType::AdsBIcao => "adsb_icao",
+        MessageType::AdsBIcaoNonTransponder => "adsb_icao_nt",
+        MessageType::AdsBOther => "adsb_other",
+        MessageType::AdsC => "adsc",
+        MessageType::AdsRIcao => "adsr_icao",
+        MessageType::AdsROther => "adsr_other",
+        MessageType::ModeS => "mode_s",
+        MessageType::Multilateration => "mlat",
+        MessageType::Other => "other",
+        MessageType::TisBIcao => "tisb_icao",
+        MessageType::TisBOther => "tisb_other",
+        MessageType::TisBTrackfile => "tisb_trackfile",
+        MessageType::Unknown => "unknown",
+    }
+}
+
+/// Returns how much to trust positions from a source, from 0 to 1: 1 for
+/// ADS-B (including ADS-R, its rebroadcast), 0.7 for TIS-B and ADS-C, 0.5 for
+/// MLAT, and 0.3 for anything else.
+pub fn source_quality(source: Option<&str>) -> f64 {
+    match source {
+        Some(s) if s.starts_with("adsb") || s.starts_with("adsr") => 1.0,
+        Some(s) if s.starts_with("tisb") || s == "adsc" => 0.7,
+        Some("mlat") => 0.5,
+        _ => 0.3,
+    }
+}
+
+/// Returns an aircraft's position quality, from 0 to 1. It's the product of:
+///
+/// - The source_quality of its latest position.
+/// - NOMINAL_UPDATE_INTERVAL_SECS divided by its average update interval, if
+///   that's longer.
+/// - 0.5 if it reports a NACp below GOOD_NAC_P, and 0.5 if it reports a NIC
+///   below GOOD_NIC.
+/// - The fraction of its positions that weren't rejected as implausible.
+///
+/// So an aircraft with ADS-B positions and nothing wrong with them scores 1,
+/// and MLAT updated every 30 seconds scores 0.25.
+pub fn quality_score(ac: &Ac) -> f64 {
+    let mut score = source_quality(ac.source.as_deref());
+    if let Some(interval) = ac.update_interval_secs {
+        score *= (NOMINAL_UPDATE_INTERVAL_SECS / interval).min(1.0);
+    }
+    if ac.nac_p.is_some_and(|nac_p| nac_p < GOOD_NAC_P) {
+        score *= 0.5;
+    }
+    if ac.nic.is_some_and(|nic| nic < GOOD_NIC) {
+        score *= 0.5;
+    }
+    let total_fixes = ac.num_fixes + ac.rejected_fixes;
+    if total_fixes > 0 {
+        score *= ac.num_fixes as f64 / total_fixes as f64;
+    }
+    score
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interception::DetectionParams;
+    use adsbx_json::v2::Aircraft;
+    use chrono::{prelude::*, Duration};
+    use serde_json::json;
+
+    fn aircraft(source: &str, lat: f64, seen_pos: f64) -> Aircraft {
+        serde_json::from_value(json!({
+            "hex": "a12345", "type": source, "messages": 100, "rssi": -20.0, "seen": 0.0,
+            "seen_pos": seen_pos, "lat": lat, "lon": -118.0, "gs": 300.0, "alt_baro": 10000,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_quality_score() {
+        let params = DetectionParams::default();
+        let start = Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap();
+        let ac = Ac::new(start, &aircraft("adsb_icao", 34.0, 0.0), &params).unwrap();
+        assert_eq!(ac.source.as_deref(), Some("adsb_icao"));
+        assert_eq!(ac.quality_score(), 1.0);
+
+        // MLAT, with a new position every 30 seconds, seen in snapshots every
+        // 10 seconds.
+        let mut ac = Ac::new(start, &aircraft("mlat", 34.0, 0.0), &params).unwrap();
+        for i in 1..=12 {
+            let lat = 34.0 + 0.01 * (i / 3) as f64;
+            let seen_pos = (i % 3) as f64 * 10.0;
+            ac.update(
+                start + Duration::seconds(i * 10),
+                &aircraft("mlat", lat, seen_pos),
+                &params,
+            );
+        }
+        assert_eq!(ac.update_interval_secs, Some(30.0));
+        assert_eq!(ac.quality_score(), 0.25);
+
+        // Reported accuracy, and rejected positions, count against it too.
+        ac.source = Some("adsb_icao".to_string());
+        ac.update_interval_secs = Some(1.0);
+        ac.nac_p = Some(5);
+        ac.num_fixes = 9;
+        ac.rejected_fixes = 1;
+        assert!((ac.quality_score() - 0.45).abs() < 1e-9);
+        assert_eq!(source_quality(Some("tisb_other")), 0.7);
+        assert_eq!(source_quality(None), 0.3);
+    }
+}
@@ -0,0 +1,165 @@
+//! Support for the aircraft.json snapshots written by readsb and tar1090.
+//!
+//! The aircraft records are nearly identical to the ADS-B Exchange v2 API, but
+//! the top level of the response differs (the time is in seconds, and aircraft
+//! are in "aircraft" instead of "ac"), and readsb adds a few fields that the v2
+//! parser doesn't know about. Snapshots are converted into the v2
+//! representation.
+
+use std::io::Read;
+
+use adsbx_json::v2;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{error::Error, open_adsbx_json_file};
+
+/// The aircraft fields understood by adsbx_json's v2 parser. Anything else
+/// readsb writes is dropped.
+const V2_AIRCRAFT_FIELDS: &[&str] = &[
+    "acas_ra",
+    "alert",
+    "alt_baro",
+    "alt_geom",
+    "baro_rate",
+    "calc_track",
+    "category",
+    "dbFlags",
+    "dir",
+    "dst",
+    "emergency",
+    "flight",
+    "geom_rate",
+    "gpsOkBefore",
+    "gs",
+    "gva",
+    "hex",
+    "ias",
+    "lastPosition",
+    "lat",
+    "lon",
+    "mach",
+    "mag_heading",
+    "messages",
+    "mlat",
+    "nac_p",
+    "nac_v",
+    "nav_altitude_fms",
+    "nav_altitude_mcp",
+    "nav_heading",
+    "nav_modes",
+    "nav_qnh",
+    "nic",
+    "nic_baro",
+    "oat",
+    "r",
+    "rc",
+    "roll",
+    "rr_lat",
+    "rr_lon",
+    "rssi",
+    "sda",
+    "seen",
+    "seen_pos",
+    "sil",
+    "sil_type",
+    "spi",
+    "squawk",
+    "t",
+    "tas",
+    "tat",
+    "tisb",
+    "track",
+    "track_rate",
+    "true_heading",
+    "type",
+    "version",
+    "wd",
+    "ws",
+];
+
+/// A readsb aircraft.json snapshot.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Response {
+    /// The time the snapshot was written, in seconds since the epoch.
+    pub now: f64,
+    /// The total number of messages received by readsb.
+    #[serde(default)]
+    pub messages: u64,
+    #[serde(default)]
+    pub aircraft: Vec<Map<String, Value>>,
+}
+
+fn to_v2_aircraft(mut aircraft: Map<String, Value>) -> Value {
+    aircraft.retain(|k, _| V2_AIRCRAFT_FIELDS.contains(&k.as_str()));
+    // Older versions of dump1090/readsb don't always write these.
+    for (k, v) in [
+        ("type", json!("unknown")),
+        ("messages", json!(0)),
+        ("rssi", json!(0.0)),
+        ("seen", json!(0.0)),
+    ] {
+        aircraft.entry(k).or_insert(v);
+    }
+    Value::Object(aircraft)
+}
+
+impl Response {
+    /// Converts this snapshot into a v2 response.
+    pub fn to_v2(self) -> Result<v2::Response, Error> {
+        let now_ms = (self.now * 1000.0).round() as i64;
+        let total = self.aircraft.len();
+        let aircraft: Vec<Value> = self.aircraft.into_iter().map(to_v2_aircraft).collect();
+        let v2_json = json!({
+            "now": now_ms,
+            "ctime": now_ms,
+            "ptime": 0,
+            "total": total,
+            "ac": aircraft,
+        });
+        serde_json::from_value(v2_json).map_err(Error::from_json)
+    }
+}
+
+/// Parses a readsb aircraft.json snapshot from a reader and converts it to
+/// a v2 response.
+pub fn load_readsb_json_reader<R: Read>(r: R) -> Result<v2::Response, Error> {
+    let response: Response =
+        serde_json::from_reader(std::io::BufReader::new(r)).map_err(Error::from_json)?;
+    response.to_v2()
+}
+
+/// Loads a (possibly compressed) readsb aircraft.json snapshot and converts it
+/// to a v2 response.
+///
+/// load_adsbx_json_file detects readsb snapshots automatically, so this is
+/// only needed if you want to insist on the readsb format.
+pub fn load_readsb_json_file(path: &str) -> Result<v2::Response, Error> {
+    load_readsb_json_reader(open_adsbx_json_file(path)?).map_err(|e| e.in_file(path))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_adsbx_json_file;
+    use adsbx_json::v2::AltitudeOrGround;
+
+    #[test]
+    fn test_load_readsb() {
+        let path = format!(
+            "{}/tests/data/readsb-aircraft.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let response = load_readsb_json_file(&path).unwrap();
+        assert_eq!(response.now.timestamp_millis(), 1614109133600);
+        assert_eq!(response.aircraft.len(), 2);
+        assert_eq!(response.aircraft[0].hex, "ae1234");
+        assert_eq!(
+            response.aircraft[1].barometric_altitude,
+            Some(AltitudeOrGround::OnGround)
+        );
+        // The format is detected automatically too.
+        assert_eq!(load_adsbx_json_file(&path).unwrap(), response);
+    }
+}
@@ -0,0 +1,164 @@
+//! Recording the full tracks of both aircraft of an interception, so an
+//! event can be plotted again later without going back to the archive.
+//!
+//! Each Ac only keeps a short history (see history_max_points), which is
+//! enough for detection but usually not for the whole of a long event. So
+//! when DetectionParams::record_event_tracks is set, AircraftStateMap keeps a
+//! separate recording for each pair from the moment it's first close, for as
+//! long as it's pending or both aircraft are pinned by an open event, and
+//! then forgets it. The points recorded
+//! since a pair's previous detection go in each Interception's tracks, and
+//! InterceptionTracker appends them to the event's.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{hex_id::HexId, interception::Ac};
+
+/// One position of a recorded track.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrackPoint {
+    pub time: DateTime<Utc>,
+    pub lon: f64,
+    pub lat: f64,
+    pub alt_ft: i32,
+    pub speed_kts: f64,
+    /// The reported track, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_deg: Option<f64>,
+}
+
+/// The recorded tracks of an interceptor and its target, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PairTracks {
+    pub interceptor: Vec<TrackPoint>,
+    pub target: Vec<TrackPoint>,
+}
+
+impl PairTracks {
+    pub fn is_empty(&self) -> bool {
+        self.interceptor.is_empty() && self.target.is_empty()
+    }
+
+    /// Appends the points of other that are later than the ones already
+    /// there.
+    pub fn extend(&mut self, other: &PairTracks) {
+        for (track, more) in [
+            (&mut self.interceptor, &other.interceptor),
+            (&mut self.target, &other.target),
+        ] {
+            let last = track.last().map(|p| p.time);
+            track.extend(
+                more.iter()
+                    .filter(|p| last.is_none_or(|last| p.time > last)),
+            );
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.interceptor.clear();
+        self.target.clear();
+    }
+}
+
+// Returns the points of an aircraft's history from since on, and after
+// last, if it's been recorded up to then.
+fn new_points(
+    ac: &Ac,
+    since: DateTime<Utc>,
+    last: Option<DateTime<Utc>>,
+) -> impl Iterator<Item = TrackPoint> + '_ {
+    let start = ac
+        .coords
+        .partition_point(|(t, _)| *t < since || last.is_some_and(|last| *t <= last));
+    (start..ac.coords.len()).map(|i| {
+        let (time, [lon, lat]) = ac.coords[i];
+        TrackPoint {
+            time,
+            lon,
+            lat,
+            alt_ft: ac.alts.get(i).copied().unwrap_or(ac.cur_alt),
+            speed_kts: ac.speeds.get(i).copied().unwrap_or(ac.cur_speed),
+            track_deg: ac.tracks.get(i).copied().flatten(),
+        }
+    })
+}
+
+/// One pair's recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Recording {
+    since: DateTime<Utc>,
+    /// The points that haven't been taken yet.
+    new: PairTracks,
+    /// The time of the latest point recorded for each aircraft.
+    last: [Option<DateTime<Utc>>; 2],
+    /// The number of points recorded for each aircraft.
+    num_points: [usize; 2],
+}
+
+/// The recordings kept by AircraftStateMap, keyed by (interceptor hex,
+/// target hex).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Recorder {
+    recordings: HashMap<(HexId, HexId), Recording>,
+}
+
+impl Recorder {
+    /// Starts recording a pair from since, unless it's already being
+    /// recorded.
+    pub(crate) fn start(&mut self, key: (HexId, HexId), since: DateTime<Utc>) {
+        self.recordings.entry(key).or_insert(Recording {
+            since,
+            new: PairTracks::default(),
+            last: [None, None],
+            num_points: [0, 0],
+        });
+    }
+
+    /// Forgets the recordings of the pairs keep returns false for.
+    pub(crate) fn retain(&mut self, keep: impl Fn(&(HexId, HexId)) -> bool) {
+        self.recordings.retain(|key, _| keep(key));
+    }
+
+    /// Adds each pair's positions since they were last recorded, up to
+    /// max_points per aircraft. lookup returns the aircraft with a hex ID, if
+    /// it's still tracked.
+    pub(crate) fn record<'a>(
+        &mut self,
+        lookup: impl Fn(&HexId) -> Option<&'a Ac>,
+        max_points: usize,
+    ) {
+        for ((interceptor_hex, target_hex), recording) in &mut self.recordings {
+            let tracks = [
+                (interceptor_hex, &mut recording.new.interceptor),
+                (target_hex, &mut recording.new.target),
+            ];
+            for (i, (hex, track)) in tracks.into_iter().enumerate() {
+                let Some(ac) = lookup(hex) else {
+                    continue;
+                };
+                let room = max_points.saturating_sub(recording.num_points[i]);
+                let before = track.len();
+                track.extend(new_points(ac, recording.since, recording.last[i]).take(room));
+                recording.num_points[i] += track.len() - before;
+                if let Some(point) = track.last() {
+                    recording.last[i] = Some(point.time);
+                }
+            }
+        }
+    }
+
+    /// Returns the points recorded for a pair since the last time this was
+    /// called, if it's being recorded.
+    pub(crate) fn take(&mut self, key: &(HexId, HexId)) -> Option<PairTracks> {
+        self.recordings
+            .get_mut(key)
+            .map(|r| std::mem::take(&mut r.new))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.recordings.len()
+    }
+}
@@ -0,0 +1,204 @@
+//! Regions of interest, like ADIZs or TFRs, for restricting detection to the
+//! areas you care about.
+//!
+//! Regions are GeoJSON Polygons or MultiPolygons, either bare geometries or
+//! inside Features or a FeatureCollection. Polygons that cross the
+//! antimeridian aren't supported.
+
+use ::geo::{Contains, LineString, Point, Polygon};
+use serde_json::Value;
+
+use crate::{
+    error::Error,
+    geo::{lon_difference, EARTH_RADIUS_M},
+};
+
+const METERS_PER_DEGREE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+
+/// A polygon, and its bounding box as [min lon, min lat, max lon, max lat].
+#[derive(Debug, Clone, PartialEq)]
+struct Region {
+    polygon: Polygon<f64>,
+    bbox: [f64; 4],
+}
+
+/// A set of regions of interest.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Regions {
+    regions: Vec<Region>,
+}
+
+fn region_error(e: impl std::fmt::Display) -> Error {
+    Error::RegionError(e.to_string())
+}
+
+fn ring(value: &Value) -> Result<LineString<f64>, Error> {
+    let points: Vec<(f64, f64)> = serde_json::from_value(value.clone()).map_err(region_error)?;
+    if points.len() < 4 {
+        return Err(region_error("A polygon ring needs at least 4 positions"));
+    }
+    Ok(LineString::from(points))
+}
+
+fn polygon(value: &Value) -> Result<Polygon<f64>, Error> {
+    let rings = value
+        .as_array()
+        .filter(|rings| !rings.is_empty())
+        .ok_or_else(|| region_error("Polygon coordinates should be a list of rings"))?;
+    let interiors = rings[1..].iter().map(ring).collect::<Result<_, _>>()?;
+    Ok(Polygon::new(ring(&rings[0])?, interiors))
+}
+
+fn collect_polygons(geojson: &Value, polygons: &mut Vec<Polygon<f64>>) -> Result<(), Error> {
+    match geojson["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in geojson["features"].as_array().into_iter().flatten() {
+                collect_polygons(feature, polygons)?;
+            }
+        }
+        Some("Feature") => collect_polygons(&geojson["geometry"], polygons)?,
+        Some("Polygon") => polygons.push(polygon(&geojson["coordinates"])?),
+        Some("MultiPolygon") => {
+            for p in geojson["coordinates"].as_array().into_iter().flatten() {
+                polygons.push(polygon(p)?);
+            }
+        }
+        other => {
+            return Err(region_error(format!(
+                "Expected a Polygon or MultiPolygon, got {:?}",
+                other
+            )))
+        }
+    }
+    Ok(())
+}
+
+// Returns the distance in meters from a point to a line segment, in a flat
+// projection around the point.
+fn segment_distance_m(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let cos_lat = p[1].to_radians().cos();
+    let project = |q: [f64; 2]| {
+        [
+            lon_difference(p[0], q[0]) * cos_lat * METERS_PER_DEGREE,
+            (q[1] - p[1]) * METERS_PER_DEGREE,
+        ]
+    };
+    let (a, b) = (project(a), project(b));
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let len_2 = ab[0] * ab[0] + ab[1] * ab[1];
+    let t = if len_2 == 0.0 {
+        0.0
+    } else {
+        (-(a[0] * ab[0] + a[1] * ab[1]) / len_2).clamp(0.0, 1.0)
+    };
+    (a[0] + ab[0] * t).hypot(a[1] + ab[1] * t)
+}
+
+impl Region {
+    fn new(polygon: Polygon<f64>) -> Self {
+        let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+        for c in polygon.exterior().coords() {
+            bbox = [
+                bbox[0].min(c.x),
+                bbox[1].min(c.y),
+                bbox[2].max(c.x),
+                bbox[3].max(c.y),
+            ];
+        }
+        Region { polygon, bbox }
+    }
+
+    fn contains(&self, [lon, lat]: [f64; 2], buffer_m: f64) -> bool {
+        // Most aircraft are nowhere near, so check the bounding box (plus the
+        // buffer) first.
+        let dlat = buffer_m / METERS_PER_DEGREE;
+        let dlon = dlat / lat.to_radians().cos().max(0.01);
+        if lon < self.bbox[0] - dlon
+            || lon > self.bbox[2] + dlon
+            || lat < self.bbox[1] - dlat
+            || lat > self.bbox[3] + dlat
+        {
+            return false;
+        }
+        if self.polygon.contains(&Point::new(lon, lat)) {
+            return true;
+        }
+        buffer_m > 0.0
+            && std::iter::once(self.polygon.exterior())
+                .chain(self.polygon.interiors())
+                .flat_map(|ring| ring.lines())
+                .any(|line| {
+                    segment_distance_m(
+                        [lon, lat],
+                        [line.start.x, line.start.y],
+                        [line.end.x, line.end.y],
+                    ) <= buffer_m
+                })
+    }
+}
+
+impl Regions {
+    /// Parses regions from GeoJSON.
+    pub fn from_geojson(geojson: &str) -> Result<Self, Error> {
+        let value: Value = serde_json::from_str(geojson).map_err(region_error)?;
+        let mut polygons = vec![];
+        collect_polygons(&value, &mut polygons)?;
+        Ok(Regions {
+            regions: polygons.into_iter().map(Region::new).collect(),
+        })
+    }
+
+    /// Loads regions from a GeoJSON file.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let geojson =
+            std::fs::read_to_string(path).map_err(|e| region_error(format!("{}: {}", path, e)))?;
+        Regions::from_geojson(&geojson).map_err(|e| region_error(format!("{}: {}", path, e)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Whether a [lon, lat] position is inside any of the regions, or within
+    /// buffer_m meters of one.
+    pub fn contains(&self, coords: [f64; 2], buffer_m: f64) -> bool {
+        self.regions.iter().any(|r| r.contains(coords, buffer_m))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        // A 1° square with a hole in the middle, and a triangle.
+        let regions = Regions::from_geojson(
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {}, "geometry": {"type": "Polygon", "coordinates": [
+                    [[-118, 34], [-117, 34], [-117, 35], [-118, 35], [-118, 34]],
+                    [[-117.6, 34.4], [-117.4, 34.4], [-117.4, 34.6], [-117.6, 34.6], [-117.6, 34.4]]
+                ]}},
+                {"type": "Feature", "properties": {}, "geometry": {"type": "MultiPolygon", "coordinates": [
+                    [[[-80, 25], [-79, 25], [-79.5, 26], [-80, 25]]]
+                ]}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(regions.len(), 2);
+        assert!(regions.contains([-117.9, 34.1], 0.0));
+        assert!(regions.contains([-79.5, 25.5], 0.0));
+        assert!(!regions.contains([-117.5, 34.5], 0.0));
+        // About 0.5 nm west of the square.
+        let outside = [-118.0 - 0.5 / 60.0 / 34.5f64.to_radians().cos(), 34.5];
+        assert!(!regions.contains(outside, 0.0));
+        assert!(!regions.contains(outside, 0.4 * 1852.0));
+        assert!(regions.contains(outside, 0.6 * 1852.0));
+        assert!(Regions::from_geojson(r#"{"type": "Point", "coordinates": [0, 0]}"#).is_err());
+    }
+}
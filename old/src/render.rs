@@ -0,0 +1,357 @@
+//! Static PNG maps of interception events, for posting. Requires the
+//! "render" feature.
+//!
+//! There's no basemap, so nothing is downloaded: just a latitude/longitude
+//! graticule, the interceptor's track in red and the target's in blue (each
+//! with a ring at its start and a square at its end, labeled with its
+//! callsign), and the closest point of approach in yellow. The viewport is
+//! fitted to the tracks with the same equirectangular projection as the
+//! report module's plots. Shapes are drawn and the PNG encoded by tiny-skia;
+//! text is drawn in embedded-graphics' built-in bitmap fonts, so there are no
+//! font files to find either.
+
+use chrono::{DateTime, Utc};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, OriginDimensions, Point, RgbColor, Size},
+    text::{Baseline, Text},
+    Drawable, Pixel,
+};
+use tiny_skia::{
+    Color, FillRule, Paint, PathBuilder, Pixmap, PremultipliedColorU8, Rect, Stroke, Transform,
+};
+
+use crate::{
+    error::Error,
+    geo::{lon_difference, normalize_lon},
+    report::{event_tracks, Fix, Projection},
+    tracker::InterceptionEvent,
+};
+
+type Rgb = [u8; 3];
+
+const BACKGROUND: Rgb = [250, 250, 250];
+const GRATICULE: Rgb = [220, 220, 220];
+const LABEL: Rgb = [110, 110, 110];
+const INTERCEPTOR: Rgb = [214, 39, 40];
+const TARGET: Rgb = [31, 95, 214];
+const CPA: Rgb = [240, 180, 0];
+const OUTLINE: Rgb = [0, 0, 0];
+
+/// The fraction of the image left empty around the tracks, on each side.
+const MARGIN_FRACTION: f64 = 0.1;
+/// The height of the band at the top taken by the title, in pixels.
+const TITLE_HEIGHT: f64 = 16.0;
+
+// Graticule spacings to choose from, in degrees.
+const GRATICULE_STEPS: [f64; 13] = [
+    0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0,
+];
+
+fn paint(color: Rgb) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(color[0], color[1], color[2], 255);
+    paint.anti_alias = true;
+    paint
+}
+
+// Strokes the lines between points, width pixels wide.
+fn polyline(pixmap: &mut Pixmap, points: &[[f64; 2]], width: f32, color: Rgb) {
+    let mut pb = PathBuilder::new();
+    for (i, [x, y]) in points.iter().enumerate() {
+        if i == 0 {
+            pb.move_to(*x as f32, *y as f32);
+        } else {
+            pb.line_to(*x as f32, *y as f32);
+        }
+    }
+    // None if there are fewer than two points.
+    if let Some(path) = pb.finish() {
+        let stroke = Stroke {
+            width,
+            ..Stroke::default()
+        };
+        pixmap.stroke_path(&path, &paint(color), &stroke, Transform::identity(), None);
+    }
+}
+
+// Draws a circle, filled or as a ring width pixels wide.
+fn circle(pixmap: &mut Pixmap, [x, y]: [f64; 2], radius: f32, ring: Option<f32>, color: Rgb) {
+    let Some(path) = PathBuilder::from_circle(x as f32, y as f32, radius) else {
+        return;
+    };
+    let paint = paint(color);
+    match ring {
+        Some(width) => {
+            let stroke = Stroke {
+                width,
+                ..Stroke::default()
+            };
+            pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None)
+        }
+        None => pixmap.fill_path(
+            &path,
+            &paint,
+            FillRule::Winding,
+            Transform::identity(),
+            None,
+        ),
+    }
+}
+
+// A pixmap as an embedded-graphics DrawTarget, to draw text on.
+struct TextTarget<'a>(&'a mut Pixmap);
+
+impl OriginDimensions for TextTarget<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.0.width(), self.0.height())
+    }
+}
+
+impl DrawTarget for TextTarget<'_> {
+    type Color = Rgb888;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let width = self.0.width() as i32;
+        let height = self.0.height() as i32;
+        let data = self.0.pixels_mut();
+        for Pixel(Point { x, y }, color) in pixels {
+            if (0..width).contains(&x) && (0..height).contains(&y) {
+                // Opaque, so premultiplying changes nothing.
+                data[(y * width + x) as usize] =
+                    PremultipliedColorU8::from_rgba(color.r(), color.g(), color.b(), 255).unwrap();
+            }
+        }
+        Ok(())
+    }
+}
+
+// Draws text with its top left corner at (x, y).
+fn text(pixmap: &mut Pixmap, x: i32, y: i32, text: &str, font: &MonoFont, color: Rgb) {
+    let style = MonoTextStyle::new(font, Rgb888::new(color[0], color[1], color[2]));
+    // Drawing on a pixmap can't fail.
+    let _ = Text::with_baseline(text, Point::new(x, y), style, Baseline::Top)
+        .draw(&mut TextTarget(pixmap));
+}
+
+// Returns the position of a track at time t, interpolated between the fixes
+// either side of it, or None if t is outside the track.
+fn position_at(track: &[Fix], t: DateTime<Utc>) -> Option<[f64; 2]> {
+    let i = track.partition_point(|(ft, _)| *ft < t);
+    let (t1, p1) = *track.get(i)?;
+    if t1 == t {
+        return Some(p1);
+    }
+    let (t0, p0) = *track.get(i.checked_sub(1)?)?;
+    let f = (t - t0).num_milliseconds() as f64 / (t1 - t0).num_milliseconds() as f64;
+    Some([p0[0] + (p1[0] - p0[0]) * f, p0[1] + (p1[1] - p0[1]) * f])
+}
+
+// Formats a graticule line's latitude or longitude, e.g. "34.10N", with as
+// many decimals as the spacing needs.
+fn format_degrees(deg: f64, step: f64, positive: char, negative: char) -> String {
+    let decimals = (-step.log10()).ceil().max(0.0) as usize;
+    let hemisphere = if deg < 0.0 { negative } else { positive };
+    format!("{:.*}{}", decimals, deg.abs(), hemisphere)
+}
+
+fn draw_graticule(pixmap: &mut Pixmap, projection: &Projection) {
+    let (w, h) = (pixmap.width() as f64, pixmap.height() as f64);
+    let [west, north] = projection.unproject([0.0, 0.0]);
+    let [_, south] = projection.unproject([0.0, h]);
+    // Longitudes are measured from the center, so the lines don't care about
+    // the antimeridian.
+    let half_width_deg = lon_difference(west, projection.center[0]).abs();
+    let span = (north - south).max(2.0 * half_width_deg);
+    let step = GRATICULE_STEPS
+        .iter()
+        .copied()
+        .find(|step| span / step <= 6.0)
+        .unwrap_or(30.0);
+    let mut labels = vec![];
+    let first = (south / step).ceil() as i64;
+    for k in first..=(north / step).floor() as i64 {
+        let lat = k as f64 * step;
+        let [_, y] = projection.project([projection.center[0], lat]);
+        polyline(pixmap, &[[0.0, y], [w, y]], 1.0, GRATICULE);
+        // Not under the title.
+        if y >= TITLE_HEIGHT {
+            labels.push((3, y as i32 + 3, format_degrees(lat, step, 'N', 'S')));
+        }
+    }
+    let center = projection.center[0];
+    let first = ((center - half_width_deg) / step).ceil() as i64;
+    for k in first..=((center + half_width_deg) / step).floor() as i64 {
+        let lon = k as f64 * step;
+        let [x, _] = projection.project([lon, projection.center[1]]);
+        polyline(pixmap, &[[x, 0.0], [x, h]], 1.0, GRATICULE);
+        let lon = normalize_lon(lon);
+        labels.push((
+            x as i32 + 3,
+            h as i32 - 11,
+            format_degrees(lon, step, 'E', 'W'),
+        ));
+    }
+    // After the lines, so none of them go through a label.
+    for (x, y, label) in labels {
+        text(pixmap, x, y, &label, &FONT_6X10, LABEL);
+    }
+}
+
+/// Renders a map of an event as a PNG image. See the module docs.
+pub fn event_png(event: &InterceptionEvent, width: u32, height: u32) -> Result<Vec<u8>, Error> {
+    if width < 16 || height < 16 {
+        return Err(Error::ExportError(format!(
+            "Image size {}x{} is too small",
+            width, height
+        )));
+    }
+    // Not None, since neither dimension is zero.
+    let mut pixmap = Pixmap::new(width, height).unwrap();
+    pixmap.fill(Color::from_rgba8(
+        BACKGROUND[0],
+        BACKGROUND[1],
+        BACKGROUND[2],
+        255,
+    ));
+    let tracks = event_tracks(event);
+    let cpa = match tracks
+        .iter()
+        .map(|t| position_at(t, event.cpa_time))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(p) => [
+            p[0][0] + lon_difference(p[0][0], p[1][0]) / 2.0,
+            (p[0][1] + p[1][1]) / 2.0,
+        ],
+        None => event.closest.location(),
+    };
+    let all: Vec<[f64; 2]> = tracks
+        .iter()
+        .flatten()
+        .map(|(_, p)| *p)
+        .chain([cpa])
+        .collect();
+    let margin = MARGIN_FRACTION * width.min(height) as f64;
+    let projection = Projection::new(&all, width as f64, height as f64, margin);
+    draw_graticule(&mut pixmap, &projection);
+
+    let aircraft = [&event.closest.interceptor, &event.closest.target];
+    // The target first, so the interceptor is drawn on top of it.
+    for i in [1, 0] {
+        let color = [INTERCEPTOR, TARGET][i];
+        let points: Vec<[f64; 2]> = tracks[i]
+            .iter()
+            .map(|(_, p)| projection.project(*p))
+            .collect();
+        polyline(&mut pixmap, &points, 2.0, color);
+        if let (Some(start), Some(end)) = (points.first(), points.last()) {
+            circle(&mut pixmap, *start, 4.0, Some(2.0), color);
+            if let Some(rect) = Rect::from_xywh(end[0] as f32 - 4.5, end[1] as f32 - 4.5, 9.0, 9.0)
+            {
+                pixmap.fill_rect(rect, &paint(color), Transform::identity(), None);
+            }
+            let ac = aircraft[i];
+            let label = ac.callsign.clone().unwrap_or_else(|| ac.hex.to_string());
+            // The interceptor's label above and the target's below, since
+            // the two often end up next to each other.
+            let y = end[1] as i32 + [-22, 4][i];
+            text(
+                &mut pixmap,
+                end[0] as i32 + 8,
+                y,
+                &label,
+                &FONT_10X20,
+                color,
+            );
+        }
+    }
+    let cpa = projection.project(cpa);
+    circle(&mut pixmap, cpa, 5.0, None, CPA);
+    circle(&mut pixmap, cpa, 5.75, Some(1.5), OUTLINE);
+    let title = format!(
+        "{} / {}  {}",
+        event.interceptor_hex,
+        event.target_hex,
+        event.start.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    text(&mut pixmap, 6, 4, &title, &FONT_6X10, OUTLINE);
+    pixmap
+        .encode_png()
+        .map_err(|e| Error::ExportError(format!("PNG error: {}", e)))
+}
+
+/// Renders a map of an event to a PNG file. See event_png.
+pub fn render_event_png(
+    event: &InterceptionEvent,
+    path: &str,
+    width: u32,
+    height: u32,
+) -> Result<(), Error> {
+    event_png(event, width, height)
+        .and_then(|png| std::fs::write(path, png).map_err(Error::Io))
+        .map_err(|e| e.in_file(path))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::standard_intercept_event;
+
+    fn event() -> InterceptionEvent {
+        standard_intercept_event()
+    }
+
+    // Decodes a PNG and returns its width, height and pixels.
+    fn decode(png: &[u8]) -> (usize, usize, Vec<Rgb>) {
+        let pixmap = Pixmap::decode_png(png).unwrap();
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .map(|p| {
+                assert_eq!(p.alpha(), 255);
+                [p.red(), p.green(), p.blue()]
+            })
+            .collect();
+        (pixmap.width() as usize, pixmap.height() as usize, pixels)
+    }
+
+    #[test]
+    fn test_render_event_png() {
+        let event = event();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("event.png");
+        let path = path.to_str().unwrap();
+        render_event_png(&event, path, 400, 300).unwrap();
+        let (width, height, pixels) = decode(&std::fs::read(path).unwrap());
+        assert_eq!((width, height), (400, 300));
+        for color in [INTERCEPTOR, TARGET, CPA, GRATICULE, LABEL] {
+            let n = pixels.iter().filter(|p| **p == color).count();
+            assert!(n > 20, "{:?}: {}", color, n);
+        }
+        // The tracks are inside the margin (apart from their labels).
+        let edge = |x: usize, y: usize| !(30..370).contains(&x) || !(30..270).contains(&y);
+        let outside = pixels
+            .iter()
+            .enumerate()
+            .filter(|(i, p)| **p == TARGET && edge(i % width, i / width))
+            .count();
+        assert!(outside < 50, "{}", outside);
+        assert!(event_png(&event, 8, 8).is_err());
+    }
+
+    #[test]
+    fn test_graticule_labels() {
+        assert_eq!(format_degrees(34.1, 0.05, 'N', 'S'), "34.10N");
+        assert_eq!(format_degrees(-118.0, 1.0, 'E', 'W'), "118W");
+    }
+}
@@ -0,0 +1,394 @@
+//! Replay files: the snapshots of a run, saved so the detector can be run
+//! over them again without parsing the original archives, e.g. while tuning
+//! DetectionParams.
+//!
+//! Each aircraft record only keeps the fields the detector looks at, so
+//! detection over a replay file finds the same interceptions as detection
+//! over the snapshots it was written from. (The parquet module's state dump
+//! can't be replayed: it has what the detector worked out, not what it was
+//! given.)
+//!
+//! A replay file is a magic number and REPLAY_VERSION, followed by a zstd
+//! stream of CBOR snapshots, one after another. Like state files, they're
+//! self-describing, so records without every field still load.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use adsbx_json::v2::{Aircraft, AltitudeOrGround, DatabaseFlags, Emergency, MessageType, Response};
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{error::Error, input::TimeRange, spinner};
+
+const MAGIC: &[u8] = b"TRACONREPLAY";
+
+/// The version of the replay format. It changes whenever replay files can't
+/// be read by the new code.
+pub const REPLAY_VERSION: u16 = 1;
+
+/// The zstd level replay files are written with.
+const ZSTD_LEVEL: i32 = 3;
+
+fn replay_error(e: impl std::fmt::Display) -> Error {
+    Error::ReplayError(e.to_string())
+}
+
+/// The fields of an aircraft record that detection uses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayAircraft {
+    pub hex: String,
+    pub message_type: MessageType,
+    #[serde(default)]
+    pub database_flags: DatabaseFlags,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_sign: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aircraft_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emitter_category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub squawk: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emergency: Option<Emergency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lat: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lon: Option<f64>,
+    /// The barometric altitude, unless the aircraft was on the ground.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_baro: Option<i32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub on_ground: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_geom: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baro_rate: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geom_rate: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gs: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nic: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nac_p: Option<u8>,
+    pub seen: Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seen_pos: Option<Duration>,
+}
+
+impl From<&Aircraft> for ReplayAircraft {
+    fn from(aircraft: &Aircraft) -> Self {
+        let (alt_baro, on_ground) = match aircraft.barometric_altitude {
+            Some(AltitudeOrGround::Altitude(alt)) => (Some(alt), false),
+            Some(AltitudeOrGround::OnGround) => (None, true),
+            None => (None, false),
+        };
+        ReplayAircraft {
+            hex: aircraft.hex.clone(),
+            message_type: aircraft.message_type.clone(),
+            database_flags: aircraft.database_flags.clone(),
+            call_sign: aircraft.call_sign.clone(),
+            registration: aircraft.registration.clone(),
+            aircraft_type: aircraft.aircraft_type.clone(),
+            emitter_category: aircraft.emitter_category.clone(),
+            squawk: aircraft.squawk.clone(),
+            emergency: aircraft.emergency.clone(),
+            lat: aircraft.lat,
+            lon: aircraft.lon,
+            alt_baro,
+            on_ground,
+            alt_geom: aircraft.geometric_altitude,
+            baro_rate: aircraft.barometric_vertical_rate,
+            geom_rate: aircraft.geometric_vertical_rate,
+            gs: aircraft.ground_speed_knots,
+            track: aircraft.track,
+            nic: aircraft.nic,
+            nac_p: aircraft.nac_p,
+            seen: aircraft.seen,
+            seen_pos: aircraft.seen_pos,
+        }
+    }
+}
+
+// A record with nothing but the required fields, to fill in. Aircraft has a
+// private field, so it can't be built directly.
+fn blank_aircraft() -> &'static Aircraft {
+    static BLANK: OnceLock<Aircraft> = OnceLock::new();
+    BLANK.get_or_init(|| {
+        serde_json::from_value(serde_json::json!({
+            "hex": "", "type": "other", "messages": 0, "rssi": 0.0, "seen": 0.0,
+        }))
+        .unwrap()
+    })
+}
+
+impl From<ReplayAircraft> for Aircraft {
+    /// The fields that weren't kept are left out.
+    fn from(ac: ReplayAircraft) -> Self {
+        let mut aircraft = blank_aircraft().clone();
+        aircraft.barometric_altitude = match (ac.alt_baro, ac.on_ground) {
+            (_, true) => Some(AltitudeOrGround::OnGround),
+            (Some(alt), false) => Some(AltitudeOrGround::Altitude(alt)),
+            (None, false) => None,
+        };
+        aircraft.hex = ac.hex;
+        aircraft.message_type = ac.message_type;
+        aircraft.database_flags = ac.database_flags;
+        aircraft.call_sign = ac.call_sign;
+        aircraft.registration = ac.registration;
+        aircraft.aircraft_type = ac.aircraft_type;
+        aircraft.emitter_category = ac.emitter_category;
+        aircraft.squawk = ac.squawk;
+        aircraft.emergency = ac.emergency;
+        aircraft.lat = ac.lat;
+        aircraft.lon = ac.lon;
+        aircraft.geometric_altitude = ac.alt_geom;
+        aircraft.barometric_vertical_rate = ac.baro_rate;
+        aircraft.geometric_vertical_rate = ac.geom_rate;
+        aircraft.ground_speed_knots = ac.gs;
+        aircraft.track = ac.track;
+        aircraft.nic = ac.nic;
+        aircraft.nac_p = ac.nac_p;
+        aircraft.seen = ac.seen;
+        aircraft.seen_pos = ac.seen_pos;
+        aircraft
+    }
+}
+
+/// One snapshot in a replay file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplaySnapshot {
+    pub now: DateTime<Utc>,
+    pub aircraft: Vec<ReplayAircraft>,
+}
+
+impl From<&Response> for ReplaySnapshot {
+    fn from(response: &Response) -> Self {
+        ReplaySnapshot {
+            now: response.now,
+            aircraft: response.aircraft.iter().map(ReplayAircraft::from).collect(),
+        }
+    }
+}
+
+impl From<ReplaySnapshot> for Response {
+    fn from(snapshot: ReplaySnapshot) -> Self {
+        Response {
+            now: snapshot.now,
+            cache_time: snapshot.now,
+            processing_time: Duration::ZERO,
+            num_aircraft: snapshot.aircraft.len() as u64,
+            aircraft: snapshot.aircraft.into_iter().map(Aircraft::from).collect(),
+            message: None,
+        }
+    }
+}
+
+/// Writes snapshots to a replay file.
+///
+/// The end of the zstd stream is written by finish, or when the writer is
+/// dropped, in which case errors are logged.
+pub struct ReplayWriter<W: Write> {
+    // None once it's finished.
+    encoder: Option<zstd::stream::write::Encoder<'static, W>>,
+}
+
+impl ReplayWriter<BufWriter<File>> {
+    /// Creates a replay file.
+    pub fn create(path: &str) -> Result<Self, Error> {
+        File::create(path)
+            .map_err(Error::Io)
+            .and_then(|f| ReplayWriter::new(BufWriter::new(f)))
+            .map_err(|e| e.in_file(path))
+    }
+}
+
+impl<W: Write> ReplayWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(MAGIC).map_err(Error::Io)?;
+        writer
+            .write_all(&REPLAY_VERSION.to_le_bytes())
+            .map_err(Error::Io)?;
+        let encoder = zstd::stream::write::Encoder::new(writer, ZSTD_LEVEL).map_err(Error::Io)?;
+        Ok(ReplayWriter {
+            encoder: Some(encoder),
+        })
+    }
+
+    /// Adds a snapshot.
+    pub fn write(&mut self, response: &Response) -> Result<(), Error> {
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| replay_error("Replay file already finished"))?;
+        ciborium::into_writer(&ReplaySnapshot::from(response), encoder).map_err(replay_error)
+    }
+
+    /// Ends the zstd stream, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let encoder = self.encoder.take().unwrap();
+        let mut writer = encoder.finish().map_err(Error::Io)?;
+        writer.flush().map_err(Error::Io)?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Drop for ReplayWriter<W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            if let Err(e) = encoder.finish().and_then(|mut w| w.flush()) {
+                warn!("Couldn't finish replay file: {}", e);
+            }
+        }
+    }
+}
+
+/// Reads the snapshots in a replay file, calling f with each one as a
+/// Response.
+pub fn read_replay<R, F>(reader: R, mut f: F) -> Result<(), Error>
+where
+    R: Read,
+    F: FnMut(Response) -> Result<(), Error>,
+{
+    let mut reader = BufReader::new(reader);
+    let mut header = vec![0; MAGIC.len() + 2];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| replay_error("Not a replay file"))?;
+    if !header.starts_with(MAGIC) {
+        return Err(replay_error("Not a replay file"));
+    }
+    let version = u16::from_le_bytes([header[MAGIC.len()], header[MAGIC.len() + 1]]);
+    if version != REPLAY_VERSION {
+        return Err(replay_error(format!(
+            "Replay file has version {}, but only version {} can be read",
+            version, REPLAY_VERSION
+        )));
+    }
+    let mut decoder = BufReader::new(zstd::stream::read::Decoder::new(reader).map_err(Error::Io)?);
+    while !decoder.fill_buf().map_err(Error::Io)?.is_empty() {
+        let snapshot: ReplaySnapshot = ciborium::from_reader(&mut decoder).map_err(replay_error)?;
+        f(snapshot.into())?;
+    }
+    Ok(())
+}
+
+/// Reads the snapshots in a replay file, calling f with each one as a
+/// Response, for the ones in range if there is one. This has the callback
+/// signature for_each_adsbx_json has, and shows a spinner like it does.
+pub fn for_each_replay_snapshot<F>(
+    path: &str,
+    range: Option<&TimeRange>,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+{
+    let file = File::open(path).map_err(|e| Error::Io(e).in_file(path))?;
+    let bar = spinner();
+    let result = read_replay(file, |response| {
+        bar.inc(1);
+        if range.is_some_and(|r| !r.contains(response.now)) {
+            return Ok(());
+        }
+        f(response, &bar)
+    });
+    bar.finish();
+    result.map_err(|e| match e {
+        Error::ReplayError(_) => e.in_file(path),
+        e => e,
+    })
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interception::{detect_frame_interceptions, AircraftStateMap, DetectionParams},
+        load_adsbx_json_file,
+        testutil::{standard_intercept_scenario, ScriptedAircraft},
+    };
+
+    // Writes responses to a replay file in memory and reads them back.
+    fn round_trip(responses: &[Response]) -> Vec<Response> {
+        let mut writer = ReplayWriter::new(vec![]).unwrap();
+        for response in responses {
+            writer.write(response).unwrap();
+        }
+        let file = writer.finish().unwrap();
+        let mut replayed = vec![];
+        read_replay(&file[..], |r| {
+            replayed.push(r);
+            Ok(())
+        })
+        .unwrap();
+        replayed
+    }
+
+    #[test]
+    fn test_replay_detections() {
+        // And an airliner that's ignored, after the real snapshots.
+        let airliner = ScriptedAircraft::straight("a00001", [-118.2, 34.1], 35_000.0, 45.0, 450.0);
+        let mut responses = vec![
+            load_adsbx_json_file("tests/data/2021-02-23-193853Z.json").unwrap(),
+            load_adsbx_json_file("tests/data/2021-02-23-193858Z.json").unwrap(),
+        ];
+        responses.extend(
+            standard_intercept_scenario()
+                .noise(20.0, 50.0)
+                .aircraft(airliner)
+                .responses(),
+        );
+        let replayed = round_trip(&responses);
+        assert_eq!(replayed.len(), responses.len());
+
+        let detect = |responses: &[Response]| {
+            let params = DetectionParams::default();
+            let mut state = AircraftStateMap::new();
+            let found: Vec<_> = responses
+                .iter()
+                .flat_map(|r| detect_frame_interceptions(&mut state, r, &params))
+                .collect();
+            let mut aircraft: Vec<_> = state.iter().cloned().collect();
+            aircraft.sort_by_key(|ac| ac.hex);
+            (found, aircraft, state.drops)
+        };
+        let (found, aircraft, drops) = detect(&responses);
+        assert!(!found.is_empty());
+        assert_eq!(detect(&replayed), (found, aircraft, drops));
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let err = read_replay(&b"TRACON\0\x01"[..], |_| Ok(())).unwrap_err();
+        assert!(matches!(err, Error::ReplayError(_)), "{:?}", err);
+        let mut file = ReplayWriter::new(vec![]).unwrap().finish().unwrap();
+        file[MAGIC.len()] = 2;
+        let err = read_replay(&file[..], |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("version 2"), "{}", err);
+
+        // Dropping the writer ends the stream too, and an empty file has no
+        // snapshots.
+        let mut file = vec![];
+        drop(ReplayWriter::new(&mut file).unwrap());
+        let mut n = 0;
+        read_replay(&file[..], |_| {
+            n += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(n, 0);
+    }
+}
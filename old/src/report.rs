@@ -0,0 +1,389 @@
+//! A self-contained HTML report of a batch run's interception events: some
+//! totals, and a table of the events with a small plot of each one's tracks.
+//!
+//! Everything is in the one file. The plots are inline SVG, with the tracks
+//! projected equirectangularly around the middle of the event, and the table
+//! is sorted by clicking its column headings, with a few lines of inline
+//! JavaScript. Callsigns, types and so on come from the ADS-B data, so
+//! they're escaped.
+
+use std::{collections::HashSet, fmt::Write as _};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::Error,
+    geo::{lon_difference, normalize_lon},
+    interception::Ac,
+    recording::TrackPoint,
+    tracker::InterceptionEvent,
+};
+
+/// The size of each track plot, in pixels.
+const PLOT_WIDTH: f64 = 160.0;
+const PLOT_HEIGHT: f64 = 120.0;
+const PLOT_MARGIN: f64 = 6.0;
+
+const STYLE: &str = r#"body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: middle; }
+th { background: #eee; cursor: pointer; user-select: none; }
+td.num { text-align: right; }
+svg { background: #f8f8f8; }
+"#;
+
+// Sorts the table by a column when its heading is clicked, using each cell's
+// data-sort value, numerically if it's a number. Clicking again reverses it.
+const SCRIPT: &str = r##"document.querySelectorAll("th[data-col]").forEach(function (th) {
+  th.addEventListener("click", function () {
+    var col = +th.dataset.col, body = document.querySelector("#events tbody");
+    var asc = th.dataset.order !== "asc";
+    th.dataset.order = asc ? "asc" : "desc";
+    var key = function (row) {
+      var v = row.children[col].dataset.sort, n = parseFloat(v);
+      return isNaN(n) ? v : n;
+    };
+    Array.from(body.rows).sort(function (a, b) {
+      var x = key(a), y = key(b);
+      return (x < y ? -1 : x > y ? 1 : 0) * (asc ? 1 : -1);
+    }).forEach(function (row) { body.appendChild(row); });
+  });
+});
+"##;
+
+/// Escapes text for HTML element content and quoted attribute values.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A position in a track, as (time, [lon, lat]).
+pub(crate) type Fix = (DateTime<Utc>, [f64; 2]);
+
+/// Returns an event's interceptor and target tracks: the recorded ones if
+/// there are any (see DetectionParams::record_event_tracks), otherwise the
+/// history the closest detection had.
+pub(crate) fn event_tracks(event: &InterceptionEvent) -> [Vec<Fix>; 2] {
+    let recorded = |track: &[TrackPoint], ac: &Ac| {
+        if track.is_empty() {
+            ac.coords.iter().copied().collect()
+        } else {
+            track.iter().map(|p| (p.time, [p.lon, p.lat])).collect()
+        }
+    };
+    [
+        recorded(&event.tracks.interceptor, &event.closest.interceptor),
+        recorded(&event.tracks.target, &event.closest.target),
+    ]
+}
+
+/// Projects [lon, lat]s to [x, y] pixels (y down), equirectangularly around
+/// their centroid, scaled to fit a plot with a margin, with the same scale on
+/// both axes.
+pub(crate) struct Projection {
+    pub(crate) center: [f64; 2],
+    cos_lat: f64,
+    /// Pixels per degree of latitude.
+    pub(crate) scale: f64,
+    size: [f64; 2],
+}
+
+impl Projection {
+    pub(crate) fn new(points: &[[f64; 2]], width: f64, height: f64, margin: f64) -> Self {
+        let n = points.len().max(1) as f64;
+        // Average the longitudes as offsets from the first one, so tracks
+        // across the antimeridian don't average out to the other side of the
+        // world.
+        let lon0 = points.first().map_or(0.0, |p| p[0]);
+        let lon = lon0
+            + points
+                .iter()
+                .map(|p| lon_difference(lon0, p[0]))
+                .sum::<f64>()
+                / n;
+        let lat = points.iter().map(|p| p[1]).sum::<f64>() / n;
+        let mut projection = Projection {
+            center: [normalize_lon(lon), lat],
+            cos_lat: lat.to_radians().cos().max(0.01),
+            scale: 1.0,
+            size: [width, height],
+        };
+        let extent = points
+            .iter()
+            .map(|p| projection.offset(*p))
+            .fold([0.0f64; 2], |[x, y], [dx, dy]| {
+                [x.max(dx.abs()), y.max(dy.abs())]
+            });
+        let half_width = width / 2.0 - margin;
+        let half_height = height / 2.0 - margin;
+        projection.scale =
+            (half_width / extent[0].max(1e-9)).min(half_height / extent[1].max(1e-9));
+        projection
+    }
+
+    // Returns how far east and north of the center a position is, in degrees
+    // of latitude.
+    fn offset(&self, [lon, lat]: [f64; 2]) -> [f64; 2] {
+        [
+            lon_difference(self.center[0], lon) * self.cos_lat,
+            lat - self.center[1],
+        ]
+    }
+
+    pub(crate) fn project(&self, p: [f64; 2]) -> [f64; 2] {
+        let [x, y] = self.offset(p);
+        [
+            self.size[0] / 2.0 + x * self.scale,
+            self.size[1] / 2.0 - y * self.scale,
+        ]
+    }
+
+    /// The inverse of project.
+    #[cfg_attr(not(feature = "render"), allow(dead_code))]
+    pub(crate) fn unproject(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        let dx = (x - self.size[0] / 2.0) / self.scale / self.cos_lat;
+        let dy = (self.size[1] / 2.0 - y) / self.scale;
+        [normalize_lon(self.center[0] + dx), self.center[1] + dy]
+    }
+}
+
+// Returns an inline SVG plot of an event: the interceptor's track in red, the
+// target's in blue, and a dot where they were closest.
+fn track_svg(event: &InterceptionEvent) -> String {
+    let [interceptor, target] =
+        event_tracks(event).map(|t| t.into_iter().map(|(_, p)| p).collect::<Vec<_>>());
+    let location = event.closest.location();
+    let all: Vec<[f64; 2]> = interceptor
+        .iter()
+        .chain(&target)
+        .copied()
+        .chain([location])
+        .collect();
+    let projection = Projection::new(&all, PLOT_WIDTH, PLOT_HEIGHT, PLOT_MARGIN);
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = PLOT_WIDTH,
+        h = PLOT_HEIGHT
+    );
+    for (track, color) in [(&target, "#1f5fd6"), (&interceptor, "#d62728")] {
+        let points: Vec<String> = track
+            .iter()
+            .map(|p| {
+                let [x, y] = projection.project(*p);
+                format!("{:.1},{:.1}", x, y)
+            })
+            .collect();
+        write!(
+            svg,
+            r#"<polyline fill="none" stroke="{}" stroke-width="1.5" points="{}"/>"#,
+            color,
+            points.join(" ")
+        )
+        .unwrap();
+    }
+    let [x, y] = projection.project(location);
+    write!(
+        svg,
+        r##"<circle cx="{:.1}" cy="{:.1}" r="3" fill="#f0b400" stroke="black" stroke-width="0.5"/></svg>"##,
+        x, y
+    )
+    .unwrap();
+    svg
+}
+
+// Formats a duration in seconds as e.g. "1h 05m 10s", "5m 10s" or "10s".
+fn format_duration(secs: i64) -> String {
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if h > 0 {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+fn write_stats(html: &mut String, events: &[InterceptionEvent]) {
+    let interceptors: HashSet<&str> = events.iter().map(|e| e.interceptor_hex.as_str()).collect();
+    let targets: HashSet<&str> = events.iter().map(|e| e.target_hex.as_str()).collect();
+    let total_secs: i64 = events.iter().map(|e| e.duration_secs).sum();
+    let closest = events
+        .iter()
+        .map(|e| e.min_lateral_separation_ft)
+        .min_by(|a, b| a.total_cmp(b));
+    let mut stats = vec![
+        ("Events", events.len().to_string()),
+        ("Interceptors", interceptors.len().to_string()),
+        ("Targets", targets.len().to_string()),
+        ("Total duration", format_duration(total_secs)),
+    ];
+    if let (Some(first), Some(last)) = (
+        events.iter().map(|e| e.start).min(),
+        events.iter().map(|e| e.end).max(),
+    ) {
+        stats.push(("First", first.to_rfc3339()));
+        stats.push(("Last", last.to_rfc3339()));
+    }
+    if let Some(closest) = closest {
+        stats.push(("Closest lateral separation", format!("{:.0} ft", closest)));
+    }
+    html.push_str("<table id=\"stats\">\n");
+    for (name, value) in stats {
+        writeln!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            name,
+            escape_html(&value)
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n");
+}
+
+fn write_event_row(html: &mut String, event: &InterceptionEvent) {
+    let start = event.start.to_rfc3339();
+    let cells = [
+        (escape_html(&start), escape_html(&start), ""),
+        (
+            escape_html(&event.interceptor_hex),
+            escape_html(&event.closest.interceptor.description()),
+            "",
+        ),
+        (
+            escape_html(&event.target_hex),
+            escape_html(&event.closest.target.description()),
+            "",
+        ),
+        (
+            event.duration_secs.to_string(),
+            format_duration(event.duration_secs),
+            "num",
+        ),
+        (
+            format!("{:.0}", event.min_lateral_separation_ft),
+            format!("{:.0} ft", event.min_lateral_separation_ft),
+            "num",
+        ),
+        (
+            event.min_vertical_separation_ft.to_string(),
+            format!("{} ft", event.min_vertical_separation_ft),
+            "num",
+        ),
+    ];
+    html.push_str("<tr>");
+    for (sort, text, class) in cells {
+        write!(html, "<td data-sort=\"{}\"", sort).unwrap();
+        if !class.is_empty() {
+            write!(html, " class=\"{}\"", class).unwrap();
+        }
+        write!(html, ">{}</td>", text).unwrap();
+    }
+    writeln!(html, "<td>{}</td></tr>", track_svg(event)).unwrap();
+}
+
+/// Renders a report of events as an HTML document.
+pub fn events_to_html(events: &[InterceptionEvent]) -> String {
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>Interceptions</title>\n<style>\n");
+    html.push_str(STYLE);
+    html.push_str("</style>\n</head>\n<body>\n<h1>Interceptions</h1>\n");
+    write_stats(&mut html, events);
+    html.push_str("<h2>Events</h2>\n<table id=\"events\">\n<thead><tr>");
+    for (i, heading) in [
+        "Start",
+        "Interceptor",
+        "Target",
+        "Duration",
+        "Min lateral separation",
+        "Min vertical separation",
+    ]
+    .iter()
+    .enumerate()
+    {
+        write!(html, "<th data-col=\"{}\">{}</th>", i, heading).unwrap();
+    }
+    html.push_str("<th>Tracks</th></tr></thead>\n<tbody>\n");
+    for event in events {
+        write_event_row(&mut html, event);
+    }
+    html.push_str("</tbody>\n</table>\n<script>\n");
+    html.push_str(SCRIPT);
+    html.push_str("</script>\n</body>\n</html>\n");
+    html
+}
+
+/// Writes a report of events to an HTML file.
+pub fn write_report(events: &[InterceptionEvent], path: &str) -> Result<(), Error> {
+    std::fs::write(path, events_to_html(events))
+        .map_err(Error::Io)
+        .map_err(|e| e.in_file(path))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::standard_intercept_state;
+
+    fn events() -> Vec<InterceptionEvent> {
+        standard_intercept_state().events
+    }
+
+    #[test]
+    fn test_events_to_html() {
+        let mut events = events();
+        assert_eq!(events.len(), 1);
+        events[0].closest.interceptor.callsign = Some("<script>alert('x')</script>".to_string());
+        let html = events_to_html(&events);
+        assert!(!html.contains("<script>alert"));
+        assert!(html.contains("ae1234 (&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;)"));
+        assert!(html.contains("<th>Events</th><td>1</td>"));
+        assert_eq!(html.matches("<svg ").count(), 1);
+        assert_eq!(html.matches("<polyline ").count(), 2);
+        // Every plotted point is inside the plot.
+        let points = html
+            .split("points=\"")
+            .skip(1)
+            .flat_map(|s| s.split('"').next().unwrap().split(' '))
+            .map(|p| {
+                let (x, y) = p.split_once(',').unwrap();
+                [x.parse::<f64>().unwrap(), y.parse::<f64>().unwrap()]
+            })
+            .collect::<Vec<_>>();
+        assert!(points.len() > 10);
+        for [x, y] in points {
+            assert!((0.0..=PLOT_WIDTH).contains(&x) && (0.0..=PLOT_HEIGHT).contains(&y));
+        }
+        assert_eq!(escape_html("a&b\"c"), "a&amp;b&quot;c");
+    }
+
+    #[test]
+    fn test_projection() {
+        // Across the antimeridian, the centroid is on it, not at 0°.
+        let projection = Projection::new(&[[179.9, 10.0], [-179.9, 10.2]], 160.0, 120.0, 6.0);
+        assert!((projection.center[0].abs() - 180.0).abs() < 1e-9);
+        let [x0, y0] = projection.project([179.9, 10.0]);
+        let [x1, y1] = projection.project([-179.9, 10.2]);
+        assert!(x0 < x1 && y0 > y1);
+        // A degree of longitude is narrower than a degree of latitude away
+        // from the equator.
+        let projection = Projection::new(&[[10.0, 60.0], [11.0, 61.0]], 160.0, 120.0, 6.0);
+        let [x0, y0] = projection.project([10.0, 60.0]);
+        let [x1, y1] = projection.project([11.0, 61.0]);
+        assert!(((x1 - x0) / (y0 - y1) - 60.5f64.to_radians().cos()).abs() < 1e-9);
+        let [lon, lat] = projection.unproject([x1, y1]);
+        assert!((lon - 11.0).abs() < 1e-9 && (lat - 61.0).abs() < 1e-9);
+        assert_eq!(format_duration(3725), "1h 02m 05s");
+        assert_eq!(format_duration(65), "1m 05s");
+    }
+}
@@ -0,0 +1,449 @@
+//! Two-pass detection, for scanning long archives quickly.
+//!
+//! Pass one, screen_files, is a cheap screen: it keeps no history for any
+//! aircraft, only the last time each one was faster than the slowest
+//! interceptor profile's minimum speed, and instead of measuring distances
+//! it bins the potential targets in each snapshot into grid cells about
+//! max_lateral_separation_m across (see DetectionParams::close_radius_m). A
+//! fast mover (or a recent one, which could be an escort) in the same cell as
+//! a potential target, or the next one over, is a hit, and hits close
+//! together in time become CandidateWindows. Pass two, detect_in_windows,
+//! runs the full detector on just the snapshots in each window, padded with
+//! enough lead-in for it to work out fast status and how far apart the
+//! aircraft started.
+//!
+//! The screen is deliberately generous: anything the full detector could
+//! find is a hit, so pass two finds the same interceptions a single pass over
+//! everything would, as long as lead_mins covers their history (see
+//! ScreenParams). detect_two_pass chains the two.
+
+use std::collections::HashMap;
+
+use adsbx_json::v2::Response;
+use chrono::{prelude::*, Duration};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aircraft_is_on_ground,
+    error::Error,
+    hex_id::HexId,
+    input::TimeRange,
+    interception::{process_adsbx_response, DetectionParams, EmergencySquawk, State},
+    pipeline::AdsbxPipeline,
+    tracker::InterceptionEvent,
+};
+
+/// Meters per degree of latitude.
+const M_PER_DEG: f64 = 111_320.0;
+
+/// The highest latitude used to work out how wide grid cells are, so they
+/// don't get infinitely wide at the poles.
+const MAX_CELL_LAT: f64 = 89.0;
+
+/// How candidate windows are padded for the second pass.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenParams {
+    /// How long before its first hit a window starts. The full detector only
+    /// knows what it's seen, so this needs to be long enough for fast status
+    /// (interceptor_entry_window_mins), min_initial_separation_mi, and
+    /// the pattern and descent checks to see the approach.
+    pub lead_mins: i64,
+    /// How long after its last hit a window ends.
+    pub trail_mins: i64,
+}
+
+impl Default for ScreenParams {
+    fn default() -> Self {
+        ScreenParams {
+            lead_mins: 20,
+            trail_mins: 5,
+        }
+    }
+}
+
+/// A stretch of time where the screen found fast movers near potential
+/// targets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CandidateWindow {
+    /// The snapshots with the first and last hits.
+    pub first_hit: DateTime<Utc>,
+    pub last_hit: DateTime<Utc>,
+    /// The part of the input the second pass reads, including the padding:
+    /// from start up to and including end.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// [min_lon, min_lat, max_lon, max_lat] of the aircraft in the hits.
+    pub bbox: [f64; 4],
+    /// The number of fast movers that were near a potential target, summed
+    /// over the snapshots.
+    pub num_hits: usize,
+}
+
+impl CandidateWindow {
+    /// The time range to run the second pass on.
+    pub fn time_range(&self) -> TimeRange {
+        TimeRange::new(self.start, self.end + Duration::milliseconds(1))
+    }
+
+    fn extend_bbox(&mut self, [lon, lat]: [f64; 2]) {
+        self.bbox[0] = self.bbox[0].min(lon);
+        self.bbox[1] = self.bbox[1].min(lat);
+        self.bbox[2] = self.bbox[2].max(lon);
+        self.bbox[3] = self.bbox[3].max(lat);
+    }
+}
+
+// An aircraft and its [lon, lat].
+type Located = (HexId, [f64; 2]);
+
+// A grid of cells cell_m tall, whose width in degrees of longitude depends
+// on the row, so that they're at least cell_m wide even at the poleward edge
+// of the row below or above. Columns wrap around at the antimeridian.
+struct Grid {
+    cell_deg: f64,
+    cells: HashMap<(i64, i64), Vec<Located>>,
+}
+
+impl Grid {
+    fn new(cell_m: f64) -> Self {
+        Grid {
+            cell_deg: cell_m.max(1.0) / M_PER_DEG,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn row(&self, lat: f64) -> i64 {
+        (lat / self.cell_deg).floor() as i64
+    }
+
+    // The number of columns in a row.
+    fn num_cols(&self, row: i64) -> i64 {
+        let edge = (row.abs() + 2) as f64 * self.cell_deg;
+        let width = self.cell_deg / edge.min(MAX_CELL_LAT).to_radians().cos();
+        ((360.0 / width).floor() as i64).max(1)
+    }
+
+    fn col(&self, row: i64, lon: f64) -> i64 {
+        let num_cols = self.num_cols(row);
+        (((lon + 180.0) / 360.0 * num_cols as f64).floor() as i64).rem_euclid(num_cols)
+    }
+
+    fn insert(&mut self, hex: HexId, position: [f64; 2]) {
+        let row = self.row(position[1]);
+        let col = self.col(row, position[0]);
+        self.cells
+            .entry((row, col))
+            .or_default()
+            .push((hex, position));
+    }
+
+    // Returns the aircraft in the cells at and around a position.
+    fn near(&self, [lon, lat]: [f64; 2]) -> impl Iterator<Item = &Located> {
+        let row = self.row(lat);
+        (row - 1..=row + 1)
+            .flat_map(move |row| {
+                let (col, num_cols) = (self.col(row, lon), self.num_cols(row));
+                let mut cols = vec![col, (col - 1).rem_euclid(num_cols), (col + 1) % num_cols];
+                cols.sort_unstable();
+                cols.dedup();
+                cols.into_iter().map(move |col| (row, col))
+            })
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+    }
+}
+
+/// The first pass. Feed it responses in order with screen_response, then
+/// call finish to get the candidate windows.
+#[derive(Clone)]
+pub struct Screener {
+    params: DetectionParams,
+    screen: ScreenParams,
+    // When each aircraft was last seen faster than min_interceptor_speed_kts,
+    // for as long as it could still be a fast mover or an escort.
+    last_fast: HashMap<HexId, DateTime<Utc>>,
+    windows: Vec<CandidateWindow>,
+}
+
+impl Screener {
+    pub fn new(params: &DetectionParams, screen: &ScreenParams) -> Self {
+        Screener {
+            params: params.clone(),
+            screen: screen.clone(),
+            last_fast: HashMap::new(),
+            windows: vec![],
+        }
+    }
+
+    // How long an aircraft that was fast might still count as a fast mover
+    // or an escort.
+    fn fast_window(&self) -> Duration {
+        let mut mins = self.params.max_interceptor_timeout_mins();
+        if self.params.detect_shadows {
+            mins += self.params.shadow_fast_window_mins;
+        }
+        Duration::minutes(mins)
+    }
+
+    /// Screens one snapshot, and returns the number of hits in it.
+    pub fn screen_response(&mut self, response: &Response) -> usize {
+        let now = response.now;
+        let params = &self.params;
+        let min_fast_speed_kts = params.min_interceptor_speed_kts();
+        let mut targets = Grid::new(params.close_radius_m());
+        let mut fast_movers = vec![];
+        for aircraft in &response.aircraft {
+            let (Some(lon), Some(lat), Ok(hex)) =
+                (aircraft.lon, aircraft.lat, aircraft.hex.parse::<HexId>())
+            else {
+                continue;
+            };
+            if aircraft_is_on_ground(aircraft, params.on_ground_max_geom_alt_ft) == Some(true) {
+                continue;
+            }
+            let speed = aircraft.ground_speed_knots;
+            if speed.is_some_and(|s| s > min_fast_speed_kts) {
+                self.last_fast.insert(hex, now);
+            }
+            if self.last_fast.contains_key(&hex) {
+                fast_movers.push((hex, [lon, lat]));
+            }
+            // Records without a speed might be targets, going by the last
+            // one that had one.
+            let emergency = aircraft
+                .squawk
+                .as_deref()
+                .and_then(EmergencySquawk::from_squawk);
+            if speed.is_none_or(|s| params.target.speed_in_band(s) || params.target.is_slow(s))
+                || params
+                    .target
+                    .ignores_speed(aircraft.emitter_category.as_deref(), emergency)
+            {
+                targets.insert(hex, [lon, lat]);
+            }
+        }
+        let fast_window = self.fast_window();
+        self.last_fast.retain(|_, last| now - *last < fast_window);
+
+        let mut num_hits = 0;
+        for (hex, position) in fast_movers {
+            let mut near = targets.near(position).filter(|(t, _)| *t != hex).peekable();
+            if near.peek().is_none() {
+                continue;
+            }
+            let window = self.window_at(now);
+            window.extend_bbox(position);
+            for (_, target_position) in near {
+                window.extend_bbox(*target_position);
+            }
+            window.num_hits += 1;
+            num_hits += 1;
+        }
+        num_hits
+    }
+
+    // Returns the window a hit at now goes in, starting a new one if it's
+    // too long since the last.
+    fn window_at(&mut self, now: DateTime<Utc>) -> &mut CandidateWindow {
+        let lead = Duration::minutes(self.screen.lead_mins);
+        let trail = Duration::minutes(self.screen.trail_mins);
+        let continues = self.windows.last().is_some_and(|w| now - lead <= w.end);
+        if !continues {
+            self.windows.push(CandidateWindow {
+                first_hit: now,
+                last_hit: now,
+                start: now - lead,
+                end: now + trail,
+                bbox: [
+                    f64::INFINITY,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                    f64::NEG_INFINITY,
+                ],
+                num_hits: 0,
+            });
+        }
+        let window = self.windows.last_mut().expect("there's a window");
+        window.last_hit = now;
+        window.end = now + trail;
+        window
+    }
+
+    /// Returns the candidate windows, in order.
+    pub fn finish(self) -> Vec<CandidateWindow> {
+        self.windows
+    }
+}
+
+/// The first pass: screens the files a pipeline reads, and returns the
+/// candidate windows.
+pub fn screen_files(
+    pipeline: AdsbxPipeline,
+    params: &DetectionParams,
+    screen: &ScreenParams,
+) -> Result<Vec<CandidateWindow>, Error> {
+    let mut screener = Screener::new(params, screen);
+    pipeline.run(|response, _| {
+        screener.screen_response(&response);
+        Ok(())
+    })?;
+    Ok(screener.finish())
+}
+
+/// The second pass: runs the full detector on each window's part of the
+/// files a pipeline reads, starting from scratch each time, and returns the
+/// interception events from all of them. The pipeline's time range, if it
+/// has one, is replaced by each window's. Files whose names don't say when
+/// they're from are read for every window.
+pub fn detect_in_windows(
+    pipeline: &AdsbxPipeline,
+    windows: &[CandidateWindow],
+    params: &DetectionParams,
+) -> Result<Vec<InterceptionEvent>, Error> {
+    let mut events = vec![];
+    for window in windows {
+        let mut state = State {
+            params: params.clone(),
+            ..Default::default()
+        };
+        pipeline
+            .clone()
+            .time_range(window.time_range())
+            .run(|response, bar| process_adsbx_response(&mut state, response, bar))?;
+        state.finish();
+        events.extend(state.events);
+    }
+    Ok(events)
+}
+
+/// Runs screen_files and then detect_in_windows on the same files. Returns
+/// the windows and the events.
+pub fn detect_two_pass(
+    pipeline: &AdsbxPipeline,
+    params: &DetectionParams,
+    screen: &ScreenParams,
+) -> Result<(Vec<CandidateWindow>, Vec<InterceptionEvent>), Error> {
+    let windows = screen_files(pipeline.clone(), params, screen)?;
+    let events = detect_in_windows(pipeline, &windows, params)?;
+    Ok((windows, events))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::ProgressMode,
+        testutil::{Scenario, ScriptedAircraft},
+    };
+
+    fn intercept(start: DateTime<Utc>, target_hex: &str, interceptor_hex: &str) -> Vec<Response> {
+        let target = ScriptedAircraft::straight(target_hex, [-118.0, 34.0], 10_000.0, 90.0, 300.0);
+        let interceptor =
+            ScriptedAircraft::intercepting(interceptor_hex, &target, 25.0, 0.0, 20_000.0, 480.0)
+                .vertical_rate(4000.0);
+        Scenario::new()
+            .start(start)
+            .interval_secs(15)
+            .duration_secs(600)
+            .aircraft(target)
+            .aircraft(interceptor)
+            .responses()
+    }
+
+    // Writes each response to a file named for its time, and returns the
+    // paths.
+    fn write_files(dir: &std::path::Path, responses: &[Response]) -> Vec<String> {
+        responses
+            .iter()
+            .map(|r| {
+                let path = dir.join(r.now.format("%Y-%m-%d-%H%M%SZ.json").to_string());
+                std::fs::write(&path, serde_json::to_string(r).unwrap()).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_two_pass_matches_single_pass() {
+        let start = Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap();
+        let mut responses = intercept(start, "a12345", "ae1234");
+        // Two hours of nothing much, far away, then another interception.
+        responses.extend(
+            Scenario::new()
+                .start(start + Duration::minutes(12))
+                .interval_secs(60)
+                .duration_secs(2 * 3600)
+                .aircraft(ScriptedAircraft::straight(
+                    "a11111",
+                    [-100.0, 40.0],
+                    5000.0,
+                    0.0,
+                    120.0,
+                ))
+                .responses(),
+        );
+        responses.extend(intercept(start + Duration::hours(3), "a54321", "ae4321"));
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_files(dir.path(), &responses);
+        let pipeline = AdsbxPipeline::new(&paths).progress(ProgressMode::Hidden);
+        let params = DetectionParams::default();
+
+        let mut state = State {
+            params: params.clone(),
+            ..Default::default()
+        };
+        pipeline
+            .clone()
+            .run(|response, bar| process_adsbx_response(&mut state, response, bar))
+            .unwrap();
+        state.finish();
+        assert_eq!(state.events.len(), 2);
+
+        let (windows, events) =
+            detect_two_pass(&pipeline, &params, &ScreenParams::default()).unwrap();
+        assert_eq!(windows.len(), 2);
+        let starts = [start, start + Duration::hours(3)];
+        for ((window, event), start) in windows.iter().zip(&state.events).zip(starts) {
+            assert!(window.first_hit <= event.start && event.end <= window.last_hit);
+            // The lead-in goes back to before the aircraft showed up.
+            assert!(window.start <= start);
+            let [min_lon, min_lat, max_lon, max_lat] = window.bbox;
+            // The target heads east from -118, 34, and they're close for
+            // the second half of the scenario.
+            assert!(min_lon > -118.0 && max_lon < -116.9, "{:?}", window);
+            assert!(min_lat > 33.99 && max_lat < 34.01, "{:?}", window);
+        }
+        let quiet = start + Duration::hours(1);
+        assert!(!windows.iter().any(|w| w.time_range().contains(quiet)));
+        assert_eq!(events.len(), state.events.len());
+        assert!(events == state.events);
+    }
+
+    #[test]
+    fn test_screen_antimeridian() {
+        let responses = Scenario::new()
+            .duration_secs(0)
+            .aircraft(ScriptedAircraft::straight(
+                "ae1234",
+                [179.9995, 10.0],
+                20_000.0,
+                90.0,
+                500.0,
+            ))
+            .aircraft(ScriptedAircraft::straight(
+                "a12345",
+                [-179.9995, 10.0],
+                20_000.0,
+                90.0,
+                300.0,
+            ))
+            .responses();
+        let mut screener = Screener::new(&DetectionParams::default(), &ScreenParams::default());
+        assert_eq!(screener.screen_response(&responses[0]), 1);
+        let windows = screener.finish();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].bbox, [-179.9995, 10.0, 179.9995, 10.0]);
+    }
+}
@@ -0,0 +1,113 @@
+//! The aircraft state map, split into shards by hex ID, so that threads can
+//! update disjoint sets of aircraft at the same time. See
+//! AircraftStateMap::set_num_threads.
+//!
+//! The number of shards is fixed, and which shard an aircraft is in only
+//! depends on its hex ID, so the state is the same however many threads
+//! update it.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{hex_id::HexId, interception::Ac};
+
+const NUM_SHARDS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub(crate) struct AircraftShards {
+    shards: Vec<HashMap<HexId, Ac>>,
+}
+
+impl Default for AircraftShards {
+    fn default() -> Self {
+        AircraftShards {
+            shards: vec![HashMap::new(); NUM_SHARDS],
+        }
+    }
+}
+
+/// Returns the shard an aircraft belongs in.
+pub(crate) fn shard_index(hex: HexId) -> usize {
+    // Fibonacci hashing, since nearby addresses are common.
+    let hash = u64::from(hex.as_u32()).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (hash >> 32) as usize % NUM_SHARDS
+}
+
+impl AircraftShards {
+    pub(crate) fn get(&self, hex: &HexId) -> Option<&Ac> {
+        self.shards[shard_index(*hex)].get(hex)
+    }
+
+    pub(crate) fn contains_key(&self, hex: &HexId) -> bool {
+        self.shards[shard_index(*hex)].contains_key(hex)
+    }
+
+    pub(crate) fn entry(&mut self, hex: HexId) -> Entry<'_, HexId, Ac> {
+        self.shards[shard_index(hex)].entry(hex)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shards.iter().map(HashMap::len).sum()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shards.iter().all(HashMap::is_empty)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Ac> {
+        self.shards.iter().flat_map(HashMap::values)
+    }
+
+    pub(crate) fn retain<F: FnMut(&HexId, &mut Ac) -> bool>(&mut self, mut f: F) {
+        for shard in &mut self.shards {
+            shard.retain(&mut f);
+        }
+    }
+
+    /// The shards themselves, indexed by shard_index.
+    pub(crate) fn shards_mut(&mut self) -> &mut [HashMap<HexId, Ac>] {
+        &mut self.shards
+    }
+}
+
+impl std::ops::Index<&HexId> for AircraftShards {
+    type Output = Ac;
+
+    fn index(&self, hex: &HexId) -> &Ac {
+        self.get(hex).expect("no aircraft with that hex ID")
+    }
+}
+
+// Serialized as a single map, the same as the unsharded state was.
+impl Serialize for AircraftShards {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_map(self.shards.iter().flatten())
+    }
+}
+
+impl<'de> Deserialize<'de> for AircraftShards {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let mut shards = AircraftShards::default();
+        for (hex, ac) in HashMap::<HexId, Ac>::deserialize(d)? {
+            shards.shards[shard_index(hex)].insert(hex, ac);
+        }
+        Ok(shards)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_index() {
+        // Consecutive addresses are spread over the shards.
+        let mut counts = [0; NUM_SHARDS];
+        for address in 0xa00000..0xa00000 + 64 * NUM_SHARDS {
+            counts[shard_index(format!("{:06x}", address).parse().unwrap())] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 32 && n < 96), "{:?}", counts);
+    }
+}
@@ -0,0 +1,234 @@
+//! Spatial indexes of [lon, lat] positions, for finding the potential
+//! targets near each fast mover in a snapshot.
+//!
+//! The index is rebuilt for every snapshot, so how long it takes to build
+//! matters as much as how fast it is to query. RTreeIndex bulk loads an
+//! r-tree; GridIndex just buckets the positions into fixed-size cells of
+//! latitude and longitude, which is cheaper to build and, with cells about
+//! the size of the query radius, usually about as fast to query. Which one
+//! find_close_pairs uses is DetectionParams::spatial_index.
+
+use std::collections::HashMap;
+
+use rstar::{primitives::GeomWithData, RTree};
+use serde::{Deserialize, Serialize};
+
+use crate::geo::{bounding_box, haversine_distance_m, locate_near, normalize_lon};
+
+/// An item at a [lon, lat] position, as indexed.
+pub type Located<T> = GeomWithData<[f64; 2], T>;
+
+/// An index of items at [lon, lat] positions.
+pub trait SpatialIndex<T>: Send + Sync {
+    /// Returns the items within radius_m of center, by great-circle
+    /// distance, in no particular order.
+    fn locate_within<'a>(
+        &'a self,
+        center: [f64; 2],
+        radius_m: f64,
+    ) -> Box<dyn Iterator<Item = &'a T> + 'a>;
+}
+
+/// The kinds of SpatialIndex. Serialized as "rtree" or "grid".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpatialIndexKind {
+    #[default]
+    Rtree,
+    Grid,
+}
+
+impl SpatialIndexKind {
+    /// Builds an index of this kind. radius_m is the radius it'll usually
+    /// be queried with, which GridIndex uses for its cell size.
+    pub fn build<T: Send + Sync + 'static>(
+        self,
+        points: Vec<Located<T>>,
+        radius_m: f64,
+    ) -> Box<dyn SpatialIndex<T>> {
+        match self {
+            SpatialIndexKind::Rtree => Box::new(RTreeIndex::new(points)),
+            SpatialIndexKind::Grid => Box::new(GridIndex::new(points, radius_m)),
+        }
+    }
+}
+
+/// An r-tree of positions. See geo::locate_near.
+pub struct RTreeIndex<T> {
+    tree: RTree<Located<T>>,
+}
+
+impl<T> RTreeIndex<T> {
+    pub fn new(points: Vec<Located<T>>) -> Self {
+        RTreeIndex {
+            tree: RTree::bulk_load(points),
+        }
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for RTreeIndex<T> {
+    fn locate_within<'a>(
+        &'a self,
+        center: [f64; 2],
+        radius_m: f64,
+    ) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+        Box::new(
+            locate_near(&self.tree, center, radius_m)
+                .filter(move |p| haversine_distance_m(*p.geom(), center) <= radius_m)
+                .map(|p| &p.data),
+        )
+    }
+}
+
+/// Positions bucketed into cells of the same number of degrees of latitude
+/// and longitude. The number of columns divides 360 evenly, so columns wrap
+/// around at the antimeridian.
+pub struct GridIndex<T> {
+    cell_deg: f64,
+    num_cols: i64,
+    cells: HashMap<(i64, i64), Vec<Located<T>>>,
+}
+
+impl<T> GridIndex<T> {
+    /// Makes cells about cell_m tall (and narrower away from the equator).
+    pub fn new(points: Vec<Located<T>>, cell_m: f64) -> Self {
+        let num_cols = (360.0 / (cell_m.max(1.0) / 1852.0 / 60.0)).round().max(1.0) as i64;
+        let mut grid = GridIndex {
+            cell_deg: 360.0 / num_cols as f64,
+            num_cols,
+            cells: HashMap::new(),
+        };
+        for point in points {
+            let [lon, lat] = *point.geom();
+            let key = (grid.row(lat), grid.col(normalize_lon(lon)));
+            grid.cells.entry(key).or_default().push(point);
+        }
+        grid
+    }
+
+    fn row(&self, lat: f64) -> i64 {
+        (lat / self.cell_deg).floor() as i64
+    }
+
+    // Doesn't wrap; see locate_within.
+    fn col(&self, lon: f64) -> i64 {
+        ((lon + 180.0) / self.cell_deg).floor() as i64
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for GridIndex<T> {
+    fn locate_within<'a>(
+        &'a self,
+        center: [f64; 2],
+        radius_m: f64,
+    ) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+        let bbox = bounding_box(center, radius_m);
+        let ([min_lon, min_lat], [max_lon, max_lat]) = (bbox.lower(), bbox.upper());
+        let rows = self.row(min_lat)..=self.row(max_lat);
+        // The box can go past ±180, so the columns are wrapped, but there's
+        // no point going all the way around more than once.
+        let min_col = self.col(min_lon);
+        let max_col = self.col(max_lon).min(min_col + self.num_cols - 1);
+        Box::new(
+            rows.flat_map(move |row| {
+                (min_col..=max_col).map(move |col| (row, col.rem_euclid(self.num_cols)))
+            })
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+            .filter(move |p| haversine_distance_m(*p.geom(), center) <= radius_m)
+            .map(|p| &p.data),
+        )
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // A small linear congruential generator, so the test is the same every
+    // run.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn range(&mut self, min: f64, max: f64) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            min + (max - min) * ((self.0 >> 11) as f64 / (1u64 << 53) as f64)
+        }
+    }
+
+    #[test]
+    fn test_grid_matches_rtree() {
+        let mut rng = Lcg(1);
+        let mut num_found = 0;
+        // Clusters of points around random centers, including ones near the
+        // poles and the antimeridian, queried with a few radii.
+        for _ in 0..200 {
+            let lat = rng.range(-89.9, 89.9);
+            let center = [rng.range(-180.0, 180.0), lat];
+            let spread = rng.range(0.001, 0.2);
+            let points: Vec<_> = (0..200)
+                .map(|i| {
+                    let lon = normalize_lon(center[0] + rng.range(-spread, spread) * 4.0);
+                    let lat = (center[1] + rng.range(-spread, spread)).clamp(-90.0, 90.0);
+                    GeomWithData::new([lon, lat], i)
+                })
+                .collect();
+            let radius_m = rng.range(100.0, 5000.0);
+            let rtree = SpatialIndexKind::Rtree.build(points.clone(), radius_m);
+            let cell_m = radius_m * rng.range(0.5, 2.0);
+            let grid = SpatialIndexKind::Grid.build(points, cell_m);
+            for query in [center, [rng.range(-180.0, 180.0), lat]] {
+                let from_rtree: BTreeSet<_> = rtree.locate_within(query, radius_m).collect();
+                let from_grid: BTreeSet<_> = grid.locate_within(query, radius_m).collect();
+                assert_eq!(from_rtree, from_grid, "{:?} {}", query, radius_m);
+                num_found += from_grid.len();
+            }
+        }
+        assert!(num_found > 1000, "{}", num_found);
+    }
+
+    #[test]
+    fn test_detection_with_grid() {
+        use crate::{
+            interception::{detect_frame_interceptions, AircraftStateMap, DetectionParams},
+            testutil::standard_intercept,
+        };
+        let responses = standard_intercept();
+        let detect = |spatial_index| {
+            let params = DetectionParams {
+                spatial_index,
+                ..Default::default()
+            };
+            let mut state = AircraftStateMap::new();
+            responses
+                .iter()
+                .flat_map(|r| detect_frame_interceptions(&mut state, r, &params))
+                .map(|i| (i.time, i.interceptor.hex, i.target.hex))
+                .collect::<Vec<_>>()
+        };
+        let found = detect(SpatialIndexKind::Rtree);
+        assert!(!found.is_empty());
+        assert_eq!(detect(SpatialIndexKind::Grid), found);
+    }
+
+    #[test]
+    fn test_grid_antimeridian() {
+        let points = vec![
+            GeomWithData::new([-179.995, 52.0], "east"),
+            GeomWithData::new([179.0, 52.0], "far"),
+            GeomWithData::new([179.995, 52.0], "west"),
+        ];
+        let grid = GridIndex::new(points, 926.0);
+        let mut near: Vec<_> = grid.locate_within([179.999, 52.0], 1852.0).collect();
+        near.sort();
+        assert_eq!(near, vec![&"east", &"west"]);
+        // The same grid works for radii bigger than the cells.
+        let near: Vec<_> = grid.locate_within([179.5, 52.0], 40_000.0).collect();
+        assert_eq!(near.len(), 3);
+    }
+}
@@ -0,0 +1,191 @@
+//! Stores interceptions in a SQLite database so they can be queried later.
+//! Requires the "sqlite" feature.
+//!
+//! Interceptions go in the interceptions table, and the track of each aircraft
+//! goes in track_points. Inserting is idempotent: an interception with the same
+//! interceptor, target, and time as an existing one is ignored, so re-running
+//! over overlapping input into the same database doesn't create duplicates.
+
+use rusqlite::{params, Connection};
+
+use crate::{
+    error::Error,
+    interception::{Ac, Interception},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS interceptions (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    interceptor_hex TEXT NOT NULL,
+    interceptor_callsign TEXT,
+    target_hex TEXT NOT NULL,
+    target_callsign TEXT,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    lateral_separation_ft REAL NOT NULL,
+    vertical_separation_ft INTEGER NOT NULL,
+    interceptor_speed REAL NOT NULL,
+    target_speed REAL NOT NULL,
+    interceptor_alt INTEGER NOT NULL,
+    target_alt INTEGER NOT NULL,
+    UNIQUE (interceptor_hex, target_hex, time)
+);
+CREATE TABLE IF NOT EXISTS track_points (
+    interception_id INTEGER NOT NULL REFERENCES interceptions (id) ON DELETE CASCADE,
+    -- 'interceptor' or 'target'.
+    role TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    time TEXT NOT NULL,
+    lon REAL NOT NULL,
+    lat REAL NOT NULL,
+    alt_ft INTEGER,
+    PRIMARY KEY (interception_id, role, seq)
+);
+";
+
+fn db_error(e: rusqlite::Error) -> Error {
+    Error::DatabaseError(e.to_string())
+}
+
+/// A SQLite database of interceptions.
+pub struct InterceptionDb {
+    conn: Connection,
+}
+
+impl InterceptionDb {
+    /// Opens (or creates) a database file, creating the tables if needed.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let conn =
+            Connection::open(path).map_err(|e| Error::DatabaseError(format!("{}: {}", path, e)))?;
+        InterceptionDb::with_connection(conn)
+    }
+
+    /// Opens a temporary in-memory database.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        InterceptionDb::with_connection(Connection::open_in_memory().map_err(db_error)?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")
+            .map_err(db_error)?;
+        conn.execute_batch(SCHEMA).map_err(db_error)?;
+        Ok(InterceptionDb { conn })
+    }
+
+    /// Inserts interceptions and their tracks in a single transaction, and
+    /// returns how many were new.
+    pub fn insert_interceptions(&mut self, interceptions: &[Interception]) -> Result<usize, Error> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        let mut num_inserted = 0;
+        for interception in interceptions {
+            let [lon, lat] = interception.location();
+            let interceptor = &interception.interceptor;
+            let target = &interception.target;
+            let n = tx
+                .execute(
+                    "INSERT OR IGNORE INTO interceptions (
+                        time, interceptor_hex, interceptor_callsign, target_hex,
+                        target_callsign, lat, lon, lateral_separation_ft,
+                        vertical_separation_ft, interceptor_speed, target_speed,
+                        interceptor_alt, target_alt)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                    params![
+                        interception.time.to_rfc3339(),
+                        interceptor.hex.to_string(),
+                        interceptor.callsign,
+                        target.hex.to_string(),
+                        target.callsign,
+                        lat,
+                        lon,
+                        interception.lateral_separation_ft,
+                        interception.vertical_separation_ft,
+                        interceptor.cur_speed,
+                        target.cur_speed,
+                        interceptor.cur_alt,
+                        target.cur_alt,
+                    ],
+                )
+                .map_err(db_error)?;
+            if n == 0 {
+                continue;
+            }
+            num_inserted += 1;
+            let id = tx.last_insert_rowid();
+            insert_track(&tx, id, "interceptor", interceptor)?;
+            insert_track(&tx, id, "target", target)?;
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(num_inserted)
+    }
+
+    /// Returns the underlying connection, for running queries.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+fn insert_track(conn: &Connection, id: i64, role: &str, ac: &Ac) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare_cached(
+            "INSERT INTO track_points (interception_id, role, seq, time, lon, lat, alt_ft)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )
+        .map_err(db_error)?;
+    for (seq, (time, [lon, lat])) in ac.coords.iter().enumerate() {
+        stmt.execute(params![
+            id,
+            role,
+            seq as i64,
+            time.to_rfc3339(),
+            lon,
+            lat,
+            ac.alts.get(seq)
+        ])
+        .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_snapshot_filename_time;
+
+    fn interception(minutes: i64) -> Interception {
+        let start = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap();
+        let time = start + chrono::Duration::minutes(minutes);
+        let ac = |hex: &str, lon: f64| {
+            Ac::for_test(hex, &[(start, [lon, 34.1]), (time, [lon, 34.0])], 10000)
+        };
+        Interception::for_test(ac("ae1234", -118.0), ac("a12345", -118.002), time)
+    }
+
+    #[test]
+    fn test_insert_is_idempotent() {
+        let mut db = InterceptionDb::open_in_memory().unwrap();
+        assert_eq!(
+            db.insert_interceptions(&[interception(0), interception(20)])
+                .unwrap(),
+            2
+        );
+        // Overlapping input.
+        assert_eq!(
+            db.insert_interceptions(&[interception(20), interception(40)])
+                .unwrap(),
+            1
+        );
+        let count = |sql: &str| -> i64 {
+            db.connection()
+                .query_row(sql, [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM interceptions"), 3);
+        assert_eq!(count("SELECT COUNT(*) FROM track_points"), 12);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM track_points WHERE role = 'target' AND alt_ft = 10000"),
+            6
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Summary statistics of interception events, e.g. for a daily report: how
+//! many events there were, how many distinct interceptors and targets, what
+//! hours of the day (UTC) they started in, how long they lasted, and how
+//! close the aircraft got.
+//!
+//! Stats keeps running totals instead of every event, so it can ingest any
+//! number of them. Durations go into a histogram of fixed buckets, so the
+//! median is only as precise as the bucket width.
+
+use std::{collections::HashSet, fmt};
+
+use chrono::{DateTime, Timelike, Utc};
+use serde::Serialize;
+
+use crate::tracker::InterceptionEvent;
+
+/// The width of the duration histogram's buckets, in seconds.
+pub const DURATION_BUCKET_SECS: i64 = 15;
+
+/// The number of buckets in the duration histogram. Events that lasted
+/// longer than that (two hours) all land in the last one.
+pub const NUM_DURATION_BUCKETS: usize = 480;
+
+/// A histogram with fixed-width buckets starting at 0. Values past the last
+/// bucket are counted in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bucket_width: i64,
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Histogram {
+    pub fn new(bucket_width: i64, num_buckets: usize) -> Self {
+        Histogram {
+            bucket_width: bucket_width.max(1),
+            counts: vec![0; num_buckets.max(1)],
+            total: 0,
+        }
+    }
+
+    pub fn add(&mut self, value: i64) {
+        let i = (value.max(0) / self.bucket_width) as usize;
+        let last = self.counts.len() - 1;
+        self.counts[i.min(last)] += 1;
+        self.total += 1;
+    }
+
+    /// Returns the value that p (from 0 to 1) of the values are at or
+    /// below: the middle of the bucket it's in, or the start of the last
+    /// bucket. Returns None if there aren't any values.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let start = (i as i64 * self.bucket_width) as f64;
+                return Some(if i == self.counts.len() - 1 {
+                    start
+                } else {
+                    start + self.bucket_width as f64 / 2.0
+                });
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Accumulates statistics of interception events. Call finalize for the
+/// report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    interceptors: HashSet<String>,
+    targets: HashSet<String>,
+    events_by_hour: [usize; 24],
+    num_events: usize,
+    total_duration_secs: i64,
+    durations: Histogram,
+    total_min_separation_ft: f64,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            interceptors: HashSet::new(),
+            targets: HashSet::new(),
+            events_by_hour: [0; 24],
+            num_events: 0,
+            total_duration_secs: 0,
+            durations: Histogram::new(DURATION_BUCKET_SECS, NUM_DURATION_BUCKETS),
+            total_min_separation_ft: 0.0,
+            start: None,
+            end: None,
+        }
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn ingest(&mut self, event: &InterceptionEvent) {
+        self.interceptors.insert(event.interceptor_hex.clone());
+        self.targets.insert(event.target_hex.clone());
+        self.events_by_hour[event.start.hour() as usize] += 1;
+        self.num_events += 1;
+        self.total_duration_secs += event.duration_secs;
+        self.durations.add(event.duration_secs);
+        self.total_min_separation_ft += event.min_lateral_separation_ft;
+        self.start = Some(self.start.map_or(event.start, |t| t.min(event.start)));
+        self.end = Some(self.end.map_or(event.end, |t| t.max(event.end)));
+    }
+
+    pub fn finalize(&self) -> StatsReport {
+        let mean = |total: f64| (self.num_events > 0).then(|| total / self.num_events as f64);
+        StatsReport {
+            num_events: self.num_events,
+            distinct_interceptors: self.interceptors.len(),
+            distinct_targets: self.targets.len(),
+            events_by_hour: self.events_by_hour,
+            mean_duration_secs: mean(self.total_duration_secs as f64),
+            median_duration_secs: self.durations.percentile(0.5),
+            mean_min_separation_ft: mean(self.total_min_separation_ft),
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// The statistics of a set of events. The Display impl is a short report
+/// for the terminal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsReport {
+    pub num_events: usize,
+    pub distinct_interceptors: usize,
+    pub distinct_targets: usize,
+    /// The number of events that started in each hour of the day, UTC.
+    pub events_by_hour: [usize; 24],
+    pub mean_duration_secs: Option<f64>,
+    /// See Histogram::percentile.
+    pub median_duration_secs: Option<f64>,
+    /// The mean of each event's minimum lateral separation.
+    pub mean_min_separation_ft: Option<f64>,
+    /// When the first event started.
+    pub start: Option<DateTime<Utc>>,
+    /// When the last event ended.
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} interception events", self.num_events)?;
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            write!(f, " from {} to {}", start, end)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{} distinct interceptors, {} distinct targets",
+            self.distinct_interceptors, self.distinct_targets
+        )?;
+        if let (Some(mean), Some(median), Some(separation)) = (
+            self.mean_duration_secs,
+            self.median_duration_secs,
+            self.mean_min_separation_ft,
+        ) {
+            writeln!(f, "Duration: mean {:.0} s, median {:.0} s", mean, median)?;
+            writeln!(f, "Mean minimum separation: {:.0} ft", separation)?;
+            writeln!(f, "Events by hour (UTC):")?;
+            let max = self
+                .events_by_hour
+                .iter()
+                .copied()
+                .max()
+                .unwrap_or(0)
+                .max(1);
+            for (hour, &n) in self.events_by_hour.iter().enumerate() {
+                writeln!(f, "  {:02} {:>5} {}", hour, n, "#".repeat(n * 40 / max))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::tests::event;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(10, 10);
+        assert_eq!(histogram.percentile(0.5), None);
+        for value in [1, 12, 14, 33, 500] {
+            histogram.add(value);
+        }
+        assert_eq!(histogram.percentile(0.0), Some(5.0));
+        assert_eq!(histogram.percentile(0.5), Some(15.0));
+        assert_eq!(histogram.percentile(0.8), Some(35.0));
+        // The overflow bucket.
+        assert_eq!(histogram.percentile(1.0), Some(90.0));
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(Stats::new().finalize().mean_duration_secs, None);
+        let mut stats = Stats::new();
+        let first = event();
+        let mut second = event();
+        second.target_hex = "a67890".to_string();
+        second.start = first.start + chrono::Duration::hours(2);
+        second.end = second.start + chrono::Duration::seconds(300);
+        second.duration_secs = 300;
+        second.min_lateral_separation_ft = first.min_lateral_separation_ft + 1000.0;
+        stats.ingest(&first);
+        stats.ingest(&second);
+        let report = stats.finalize();
+        assert_eq!(report.num_events, 2);
+        assert_eq!(report.distinct_interceptors, 1);
+        assert_eq!(report.distinct_targets, 2);
+        assert_eq!(report.events_by_hour[19], 1);
+        assert_eq!(report.events_by_hour[21], 1);
+        assert_eq!(
+            report.mean_duration_secs,
+            Some((first.duration_secs + 300) as f64 / 2.0)
+        );
+        assert_eq!(
+            report.mean_min_separation_ft,
+            Some(first.min_lateral_separation_ft + 500.0)
+        );
+        assert_eq!(report.end, Some(second.end));
+
+        let text = report.to_string();
+        assert!(text.starts_with("2 interception events from "), "{}", text);
+        assert!(text.contains("1 distinct interceptors, 2 distinct targets"));
+        assert!(text.contains("\n  19     1 #"));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["events_by_hour"].as_array().unwrap().len(), 24);
+    }
+}
@@ -0,0 +1,556 @@
+//! Scripted scenarios for testing the detector end to end, without real
+//! archive files. Available in tests, and with the testing feature.
+//!
+//! A Scenario is a handful of ScriptedAircraft, sampled every interval_secs
+//! into the adsbx_json::v2::Responses the detector reads. Each aircraft
+//! either flies straight (climbing or descending at a constant rate), or
+//! intercepts another one: it flies toward it until it's close enough to
+//! join up, then flies alongside at its speed. For example, the classic
+//! intercept of a slow target by a fast mover that starts 25 nm north of it
+//! and descends to its altitude:
+//!
+//! ```
+//! use tracon::testutil::{Scenario, ScriptedAircraft};
+//!
+//! let target = ScriptedAircraft::straight("a12345", [-118.0, 34.0], 10_000.0, 90.0, 300.0);
+//! let interceptor = ScriptedAircraft::intercepting("ae1234", &target, 25.0, 0.0, 20_000.0, 480.0)
+//!     .vertical_rate(4000.0);
+//! let responses = Scenario::new()
+//!     .interval_secs(15)
+//!     .duration_secs(600)
+//!     .noise(20.0, 25.0)
+//!     .aircraft(target)
+//!     .aircraft(interceptor)
+//!     .responses();
+//! assert_eq!(responses.len(), 41);
+//! ```
+//!
+//! Without the noise, that's the standard intercept that many tests share:
+//! see standard_intercept and the functions next to it.
+//!
+//! Motion is simulated on a flat projection around each aircraft, like
+//! Ac::estimated_position_at, which is plenty for the few minutes and tens
+//! of miles of a scenario.
+
+use adsbx_json::v2::Response;
+use chrono::{prelude::*, Duration};
+use serde_json::{json, Value};
+
+use crate::{
+    geo::{haversine_distance_m, initial_bearing_deg, normalize_lon},
+    interception::{process_adsbx_response, Ac, Interception, State},
+    tracker::InterceptionEvent,
+};
+
+/// How a ScriptedAircraft flies.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motion {
+    /// At a constant track, speed, and vertical rate.
+    Straight,
+    /// Toward the aircraft with hex ID target_hex, changing altitude toward
+    /// its altitude at the vertical rate. Once it's within one snapshot's
+    /// flight of the point offset_m off the target's right wing, it joins up
+    /// and flies alongside, at the target's track and speed.
+    Intercept { target_hex: String, offset_m: f64 },
+}
+
+/// One aircraft in a Scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptedAircraft {
+    pub hex: String,
+    /// The starting position, as [lon, lat].
+    pub position: [f64; 2],
+    pub alt_ft: f64,
+    pub track_deg: f64,
+    pub speed_kts: f64,
+    /// For Straight, feet per minute, negative for a descent. For Intercept,
+    /// how fast (in either direction) it changes altitude toward the
+    /// target's.
+    pub vertical_rate_fpm: f64,
+    pub motion: Motion,
+    /// Times, as [from, to) seconds from the start of the scenario, when the
+    /// aircraft is out of coverage and left out of the responses.
+    pub coverage_gaps: Vec<(i64, i64)>,
+    joined: bool,
+}
+
+// Moves a [lon, lat] position distance_m along a track.
+fn step(position: [f64; 2], track_deg: f64, distance_m: f64) -> [f64; 2] {
+    let [lon, lat] = position;
+    let track = track_deg.to_radians();
+    let dist_deg = distance_m / 1852.0 / 60.0;
+    let dlat = dist_deg * track.cos();
+    let dlon = dist_deg * track.sin() / lat.to_radians().cos().max(0.01);
+    [normalize_lon(lon + dlon), (lat + dlat).clamp(-90.0, 90.0)]
+}
+
+impl ScriptedAircraft {
+    /// An aircraft flying straight and level.
+    pub fn straight(
+        hex: &str,
+        position: [f64; 2],
+        alt_ft: f64,
+        track_deg: f64,
+        speed_kts: f64,
+    ) -> Self {
+        ScriptedAircraft {
+            hex: hex.parse().unwrap(),
+            position,
+            alt_ft,
+            track_deg,
+            speed_kts,
+            vertical_rate_fpm: 0.0,
+            motion: Motion::Straight,
+            coverage_gaps: vec![],
+            joined: false,
+        }
+    }
+
+    /// A fast mover that starts distance_nm from the target's starting
+    /// position, on bearing_deg from it, and intercepts it at speed_kts,
+    /// joining up 150 m off its right wing. It stays at alt_ft unless it's
+    /// given a vertical_rate.
+    pub fn intercepting(
+        hex: &str,
+        target: &ScriptedAircraft,
+        distance_nm: f64,
+        bearing_deg: f64,
+        alt_ft: f64,
+        speed_kts: f64,
+    ) -> Self {
+        let position = step(target.position, bearing_deg, distance_nm * 1852.0);
+        ScriptedAircraft {
+            hex: hex.parse().unwrap(),
+            position,
+            alt_ft,
+            track_deg: initial_bearing_deg(position, target.position),
+            speed_kts,
+            vertical_rate_fpm: 0.0,
+            motion: Motion::Intercept {
+                target_hex: target.hex.clone(),
+                offset_m: 150.0,
+            },
+            coverage_gaps: vec![],
+            joined: false,
+        }
+    }
+
+    pub fn vertical_rate(mut self, fpm: f64) -> Self {
+        self.vertical_rate_fpm = fpm;
+        self
+    }
+
+    /// Sets how far off the target's wing an intercepting aircraft joins up.
+    pub fn offset(mut self, offset_m: f64) -> Self {
+        if let Motion::Intercept { offset_m: o, .. } = &mut self.motion {
+            *o = offset_m;
+        }
+        self
+    }
+
+    /// Leaves the aircraft out of the responses from from_secs until (but
+    /// not including) to_secs after the start. It keeps flying meanwhile.
+    pub fn coverage_gap(mut self, from_secs: i64, to_secs: i64) -> Self {
+        self.coverage_gaps.push((from_secs, to_secs));
+        self
+    }
+
+    fn in_coverage(&self, secs: i64) -> bool {
+        !self
+            .coverage_gaps
+            .iter()
+            .any(|&(from, to)| from <= secs && secs < to)
+    }
+
+    // Moves the aircraft forward dt_secs. Targets have already moved.
+    fn advance(&mut self, dt_secs: f64, others: &[ScriptedAircraft]) {
+        let target = match &self.motion {
+            Motion::Straight => None,
+            Motion::Intercept {
+                target_hex,
+                offset_m,
+            } => others
+                .iter()
+                .find(|ac| &ac.hex == target_hex)
+                .map(|ac| (ac, *offset_m)),
+        };
+        let Some((target, offset_m)) = target else {
+            self.position = step(self.position, self.track_deg, self.distance_m(dt_secs));
+            self.alt_ft += self.vertical_rate_fpm * dt_secs / 60.0;
+            return;
+        };
+        let join_point = step(target.position, target.track_deg + 90.0, offset_m);
+        let distance_m = self.distance_m(dt_secs);
+        if self.joined || haversine_distance_m(self.position, join_point) <= distance_m {
+            self.joined = true;
+            self.position = join_point;
+            self.track_deg = target.track_deg;
+            self.speed_kts = target.speed_kts;
+        } else {
+            self.track_deg = initial_bearing_deg(self.position, join_point);
+            self.position = step(self.position, self.track_deg, distance_m);
+        }
+        let climb_ft = self.vertical_rate_fpm.abs() * dt_secs / 60.0;
+        self.alt_ft += (target.alt_ft - self.alt_ft).clamp(-climb_ft, climb_ft);
+    }
+
+    fn distance_m(&self, dt_secs: f64) -> f64 {
+        self.speed_kts * 1852.0 * dt_secs / 3600.0
+    }
+
+    // The vertical rate it's reporting, which for an interceptor is zero
+    // once it's at the target's altitude.
+    fn reported_vertical_rate(&self, aircraft: &[ScriptedAircraft]) -> f64 {
+        match &self.motion {
+            Motion::Straight => self.vertical_rate_fpm,
+            Motion::Intercept { target_hex, .. } => aircraft
+                .iter()
+                .find(|ac| &ac.hex == target_hex)
+                .map(|target| {
+                    let diff = target.alt_ft - self.alt_ft;
+                    if diff.abs() < 1.0 {
+                        0.0
+                    } else {
+                        self.vertical_rate_fpm.abs().copysign(diff)
+                    }
+                })
+                .unwrap_or(0.0),
+        }
+    }
+}
+
+/// A small, seedable random number generator (xorshift64*), so scenarios
+/// with noise are reproducible.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Returns a normally distributed number, using the Box-Muller transform.
+    fn normal(&mut self, std_dev: f64) -> f64 {
+        let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+        let u2 = self.next_f64();
+        std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// A scripted set of aircraft, and how often to take snapshots of them.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    start: DateTime<Utc>,
+    interval_secs: i64,
+    duration_secs: i64,
+    position_noise_m: f64,
+    alt_noise_ft: f64,
+    seed: u64,
+    aircraft: Vec<ScriptedAircraft>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario::new()
+    }
+}
+
+impl Scenario {
+    /// Creates a scenario starting at 2021-02-23 19:38:53 UTC, with a
+    /// snapshot every 5 seconds for 10 minutes and no noise.
+    pub fn new() -> Self {
+        Scenario {
+            start: Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap(),
+            interval_secs: 5,
+            duration_secs: 600,
+            position_noise_m: 0.0,
+            alt_noise_ft: 0.0,
+            seed: 1,
+            aircraft: vec![],
+        }
+    }
+
+    pub fn start(mut self, start: DateTime<Utc>) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn interval_secs(mut self, interval_secs: i64) -> Self {
+        self.interval_secs = interval_secs.max(1);
+        self
+    }
+
+    /// The last snapshot is at the start plus duration_secs (or before, if
+    /// it isn't a multiple of the interval).
+    pub fn duration_secs(mut self, duration_secs: i64) -> Self {
+        self.duration_secs = duration_secs;
+        self
+    }
+
+    /// Adds Gaussian noise to the reported positions and altitudes, with
+    /// these standard deviations. The aircraft still fly the scripted
+    /// paths.
+    pub fn noise(mut self, position_m: f64, alt_ft: f64) -> Self {
+        self.position_noise_m = position_m;
+        self.alt_noise_ft = alt_ft;
+        self
+    }
+
+    /// Seeds the noise. The same seed gives the same responses.
+    pub fn seed(mut self, seed: u64) -> Self {
+        // Xorshift gets stuck at zero.
+        self.seed = seed.max(1);
+        self
+    }
+
+    pub fn aircraft(mut self, aircraft: ScriptedAircraft) -> Self {
+        self.aircraft.push(aircraft);
+        self
+    }
+
+    fn aircraft_json(&self, ac: &ScriptedAircraft, vertical_rate: f64, rng: &mut Rng) -> Value {
+        let mut position = ac.position;
+        if self.position_noise_m > 0.0 {
+            position = step(position, 0.0, rng.normal(self.position_noise_m));
+            position = step(position, 90.0, rng.normal(self.position_noise_m));
+        }
+        let alt = (ac.alt_ft + rng.normal(self.alt_noise_ft)).round() as i32;
+        json!({
+            "hex": ac.hex, "type": "adsb_icao", "messages": 100, "rssi": -20.0, "seen": 0.0,
+            "seen_pos": 0.0, "lat": position[1], "lon": position[0], "gs": ac.speed_kts,
+            "track": ac.track_deg.rem_euclid(360.0), "alt_baro": alt, "alt_geom": alt,
+            "baro_rate": vertical_rate.round() as i32,
+            "flight": format!("{:<8}", ac.hex.to_uppercase()),
+        })
+    }
+
+    /// Flies the aircraft and returns a snapshot of them every
+    /// interval_secs, starting with their starting positions.
+    pub fn responses(&self) -> Vec<Response> {
+        let mut rng = Rng(self.seed);
+        let mut aircraft = self.aircraft.clone();
+        let mut responses = vec![];
+        let mut secs = 0;
+        while secs <= self.duration_secs {
+            let ac: Vec<Value> = aircraft
+                .iter()
+                .filter(|ac| ac.in_coverage(secs))
+                .map(|ac| {
+                    let vertical_rate = ac.reported_vertical_rate(&aircraft);
+                    self.aircraft_json(ac, vertical_rate, &mut rng)
+                })
+                .collect();
+            let ms = (self.start + Duration::seconds(secs)).timestamp_millis();
+            responses.push(
+                serde_json::from_value(json!({
+                    "now": ms, "ctime": ms, "ptime": 10, "total": ac.len(), "ac": ac,
+                }))
+                .expect("scenario responses should be valid"),
+            );
+            // Straight fliers move first, so interceptors chase where their
+            // targets are now.
+            let dt = self.interval_secs as f64;
+            for ac in aircraft
+                .iter_mut()
+                .filter(|ac| ac.motion == Motion::Straight)
+            {
+                ac.advance(dt, &[]);
+            }
+            for i in 0..aircraft.len() {
+                if aircraft[i].motion != Motion::Straight {
+                    let mut ac = aircraft[i].clone();
+                    ac.advance(dt, &aircraft);
+                    aircraft[i] = ac;
+                }
+            }
+            secs += self.interval_secs;
+        }
+        responses
+    }
+}
+
+impl Ac {
+    /// An aircraft with positions at coords, oldest first, all at alt feet,
+    /// going 450 kt, last seen at its latest position, and with nothing else
+    /// known about it. Fill in the rest with struct update syntax, e.g.
+    /// `Ac { callsign: Some("RCH123".to_string()), ..Ac::for_test(..) }`.
+    pub fn for_test(hex: &str, coords: &[(DateTime<Utc>, [f64; 2])], alt: i32) -> Ac {
+        Ac {
+            hex: hex.parse().expect("for_test needs a valid hex ID"),
+            callsign: None,
+            registration: None,
+            aircraft_type: None,
+            emitter_category: None,
+            squawk: None,
+            military: false,
+            country: None,
+            operator: None,
+            coords: coords.iter().copied().collect(),
+            alts: vec![alt; coords.len()].into(),
+            tracks: Default::default(),
+            speeds: Default::default(),
+            geom_alt: None,
+            baro_alt: None,
+            cur_vertical_rate_fpm: None,
+            max_speed: 450.0,
+            cur_speed: 450.0,
+            cur_alt: alt,
+            alt_time: None,
+            is_on_ground: false,
+            fast: Default::default(),
+            profile_status: Default::default(),
+            rejected_fixes: 0,
+            rejected_fix: None,
+            num_fixes: 0,
+            first_fix: None,
+            source: None,
+            nic: None,
+            nac_p: None,
+            update_interval_secs: None,
+            seen: coords.last().expect("for_test needs a position").0,
+        }
+    }
+}
+
+impl Interception {
+    /// A detection of interceptor close to target at time, 600 ft apart
+    /// laterally and level, first seen close then, with nothing else known
+    /// about it. Fill in the rest with struct update syntax, like
+    /// Ac::for_test.
+    pub fn for_test(interceptor: Ac, target: Ac, time: DateTime<Utc>) -> Interception {
+        Interception {
+            interceptor,
+            target,
+            time,
+            proximity_start: time,
+            lateral_separation_ft: 600.0,
+            vertical_separation_ft: 0,
+            pattern: Default::default(),
+            target_mode: Default::default(),
+            interceptor_profile: None,
+            departure_airfield: None,
+            confidence: None,
+            emergency: None,
+            criteria: None,
+            tracks: None,
+        }
+    }
+}
+
+/// The aircraft of the standard intercept, as (target, interceptor): a
+/// slow target flying east at 10,000 ft, and a fast mover that starts 25 nm
+/// north of it at 20,000 ft and descends to join up with it.
+pub fn standard_intercept_aircraft() -> (ScriptedAircraft, ScriptedAircraft) {
+    let target = ScriptedAircraft::straight("a12345", [-118.0, 34.0], 10_000.0, 90.0, 300.0);
+    let interceptor = ScriptedAircraft::intercepting("ae1234", &target, 25.0, 0.0, 20_000.0, 480.0)
+        .vertical_rate(4000.0);
+    (target, interceptor)
+}
+
+/// The standard intercept, with a snapshot every 15 seconds for 10 minutes.
+pub fn standard_intercept_scenario() -> Scenario {
+    let (target, interceptor) = standard_intercept_aircraft();
+    Scenario::new()
+        .interval_secs(15)
+        .duration_secs(600)
+        .aircraft(target)
+        .aircraft(interceptor)
+}
+
+/// The snapshots of the standard intercept.
+pub fn standard_intercept() -> Vec<Response> {
+    standard_intercept_scenario().responses()
+}
+
+/// The state after the detector, with the default settings, has processed
+/// the standard intercept and finished.
+pub fn standard_intercept_state() -> State {
+    let mut state = State::default();
+    for response in standard_intercept() {
+        process_adsbx_response(&mut state, response, &indicatif::ProgressBar::hidden())
+            .expect("the standard intercept should process");
+    }
+    state.finish();
+    state
+}
+
+/// The one event the detector finds in the standard intercept.
+pub fn standard_intercept_event() -> InterceptionEvent {
+    let mut events = standard_intercept_state().events;
+    assert_eq!(events.len(), 1, "{:?}", events);
+    events.remove(0)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario() {
+        let target = ScriptedAircraft::straight("a12345", [-118.0, 34.0], 10_000.0, 90.0, 300.0)
+            .coverage_gap(90, 150);
+        let interceptor =
+            ScriptedAircraft::intercepting("ae1234", &target, 20.0, 0.0, 20_000.0, 480.0)
+                .vertical_rate(4000.0);
+        let responses = Scenario::new()
+            .interval_secs(10)
+            .duration_secs(300)
+            .aircraft(target)
+            .aircraft(interceptor)
+            .responses();
+        assert_eq!(responses.len(), 31);
+        assert_eq!(responses[1].now - responses[0].now, Duration::seconds(10));
+        let position = |r: &Response, hex: &str| {
+            r.aircraft
+                .iter()
+                .find(|ac| ac.hex == hex)
+                .map(|ac| [ac.lon.unwrap(), ac.lat.unwrap()])
+        };
+        // 300 kts is 5 nm a minute.
+        let moved = haversine_distance_m(
+            position(&responses[0], "a12345").unwrap(),
+            position(&responses[6], "a12345").unwrap(),
+        );
+        assert!((moved / 1852.0 - 5.0).abs() < 0.01, "{}", moved);
+        // The target is out of coverage for a minute.
+        assert!(position(&responses[9], "a12345").is_none());
+        assert!(position(&responses[14], "a12345").is_none());
+        assert!(position(&responses[15], "a12345").is_some());
+        // By the end, the interceptor is alongside at the target's altitude
+        // and speed.
+        let last = responses.last().unwrap();
+        let separation = haversine_distance_m(
+            position(last, "a12345").unwrap(),
+            position(last, "ae1234").unwrap(),
+        );
+        assert!((separation - 150.0).abs() < 1.0, "{}", separation);
+        let interceptor = last.aircraft.iter().find(|ac| ac.hex == "ae1234").unwrap();
+        assert_eq!(interceptor.ground_speed_knots, Some(300.0));
+        assert_eq!(interceptor.geometric_altitude, Some(10_000));
+    }
+
+    #[test]
+    fn test_noise() {
+        let scenario = |seed| {
+            Scenario::new()
+                .noise(50.0, 50.0)
+                .seed(seed)
+                .aircraft(ScriptedAircraft::straight(
+                    "a12345",
+                    [-118.0, 34.0],
+                    10_000.0,
+                    90.0,
+                    300.0,
+                ))
+                .responses()
+        };
+        let lat = |responses: &[Response]| responses[3].aircraft[0].lat.unwrap();
+        assert_eq!(lat(&scenario(7)), lat(&scenario(7)));
+        assert_ne!(lat(&scenario(7)), lat(&scenario(8)));
+        assert!((lat(&scenario(7)) - 34.0).abs() < 0.01);
+    }
+}
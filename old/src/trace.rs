@@ -0,0 +1,265 @@
+//! Loading readsb/tar1090 trace files, an aircraft's dense position history,
+//! e.g. traces/45/trace_full_ae1245.json. They're much better than
+//! snapshots every few seconds for looking at a known event after the fact;
+//! see Trace::track and pair::analyze_track_pair.
+//!
+//! A trace file holds a base timestamp and an array of points, each an
+//! array that starts [seconds after the timestamp, lat, lon, altitude,
+//! ground speed, track, ...]. The altitude is in feet, or "ground", or null
+//! if it wasn't known. Trace files are usually gzipped, whether or not they
+//! have a .gz extension.
+
+use adsbx_json::v2::AltitudeOrGround;
+use chrono::{prelude::*, Duration};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    alt_number,
+    error::Error,
+    interception::{Ac, DetectionParams},
+    open_adsbx_json_file,
+    pair::{analyze_track_pair, TrackPoint},
+    tracker::InterceptionEvent,
+};
+
+/// One point in a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracePoint {
+    pub time: DateTime<Utc>,
+    /// [lon, lat], like everywhere else.
+    pub position: [f64; 2],
+    pub alt: Option<AltitudeOrGround>,
+    pub ground_speed_kts: Option<f64>,
+    pub track_deg: Option<f64>,
+}
+
+/// An aircraft's trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub hex: String,
+    pub registration: Option<String>,
+    pub aircraft_type: Option<String>,
+    pub points: Vec<TracePoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TraceJson {
+    icao: String,
+    timestamp: f64,
+    #[serde(default)]
+    r: Option<String>,
+    #[serde(default)]
+    t: Option<String>,
+    trace: Vec<Vec<Value>>,
+}
+
+// Parses one point, or returns None if it doesn't have a time and position.
+fn parse_point(base: DateTime<Utc>, point: &[Value]) -> Option<TracePoint> {
+    let offset = point.first()?.as_f64()?;
+    let lat = point.get(1)?.as_f64()?;
+    let lon = point.get(2)?.as_f64()?;
+    let alt = match point.get(3) {
+        Some(Value::String(s)) if s == "ground" => Some(AltitudeOrGround::OnGround),
+        Some(v) => v
+            .as_f64()
+            .map(|a| AltitudeOrGround::Altitude(a.round() as i32)),
+        None => None,
+    };
+    Some(TracePoint {
+        time: base + Duration::milliseconds((offset * 1000.0).round() as i64),
+        position: [lon, lat],
+        alt,
+        ground_speed_kts: point.get(4).and_then(Value::as_f64),
+        track_deg: point.get(5).and_then(Value::as_f64),
+    })
+}
+
+impl Trace {
+    /// Parses a trace from a reader that yields its JSON. Points without a
+    /// time or position are skipped.
+    pub fn from_reader<R: std::io::Read>(r: R) -> Result<Trace, Error> {
+        let json: TraceJson =
+            serde_json::from_reader(std::io::BufReader::new(r)).map_err(Error::from_json)?;
+        let base = Utc
+            .timestamp_millis_opt((json.timestamp * 1000.0).round() as i64)
+            .single()
+            .ok_or_else(|| Error::Parse {
+                line: None,
+                column: None,
+                source: format!("Invalid trace timestamp {}", json.timestamp).into(),
+            })?;
+        let mut points: Vec<TracePoint> = json
+            .trace
+            .iter()
+            .filter_map(|p| parse_point(base, p))
+            .collect();
+        points.sort_by_key(|p| p.time);
+        Ok(Trace {
+            hex: json.icao,
+            registration: json.r,
+            aircraft_type: json.t,
+            points,
+        })
+    }
+
+    /// Loads a (possibly compressed) trace file.
+    pub fn load(path: &str) -> Result<Trace, Error> {
+        Trace::from_reader(open_adsbx_json_file(path)?).map_err(|e| e.in_file(path))
+    }
+
+    /// Returns the trace as a track for analyze_track_pair. Points without
+    /// an altitude get the last one before them (or are left out, if
+    /// there's none yet), and "ground" is 0 ft.
+    pub fn track(&self) -> Vec<TrackPoint> {
+        let mut alt = None;
+        self.points
+            .iter()
+            .filter_map(|p| {
+                alt = p.alt.clone().map(alt_number).or(alt);
+                Some((p.time, p.position, alt?))
+            })
+            .collect()
+    }
+
+    // Gives an aircraft from analyze_track_pair this trace's identity.
+    fn label(&self, ac: &mut Ac) {
+        if let Ok(hex) = self.hex.parse() {
+            ac.hex = hex;
+        }
+        ac.registration = self.registration.clone();
+        ac.aircraft_type = self.aircraft_type.clone();
+    }
+}
+
+/// Runs analyze_track_pair on an interceptor's trace and a target's, and
+/// labels the event with the traces' hex IDs, registrations, and types.
+pub fn analyze_traces(
+    interceptor: &Trace,
+    target: &Trace,
+    params: &DetectionParams,
+) -> Option<InterceptionEvent> {
+    let mut event = analyze_track_pair(&interceptor.track(), &target.track(), params)?;
+    event.interceptor_hex = interceptor.hex.clone();
+    event.target_hex = target.hex.clone();
+    interceptor.label(&mut event.closest.interceptor);
+    target.label(&mut event.closest.target);
+    Some(event)
+}
+
+/// Loads two trace files and runs analyze_traces on them.
+pub fn analyze_trace_files(
+    interceptor_path: &str,
+    target_path: &str,
+    params: &DetectionParams,
+) -> Result<Option<InterceptionEvent>, Error> {
+    let interceptor = Trace::load(interceptor_path)?;
+    let target = Trace::load(target_path)?;
+    Ok(analyze_traces(&interceptor, &target, params))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::standard_intercept_scenario;
+    use std::io::Write;
+
+    fn data_path(name: &str) -> String {
+        format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_load_trace() {
+        let trace = Trace::load(&data_path("trace_full_a12345.json")).unwrap();
+        assert_eq!(trace.hex, "a12345");
+        assert_eq!(trace.registration.as_deref(), Some("N123AB"));
+        assert_eq!(trace.aircraft_type.as_deref(), Some("C172"));
+        // The point without a position is skipped.
+        assert_eq!(trace.points.len(), 4);
+        let base = Utc.timestamp_opt(1680868800, 0).unwrap();
+        assert_eq!(trace.points[0].time, base);
+        assert_eq!(trace.points[1].time, base + Duration::milliseconds(5500));
+        assert_eq!(trace.points[0].position, [-118.0, 34.0]);
+        assert_eq!(trace.points[0].alt, Some(AltitudeOrGround::OnGround));
+        assert_eq!(trace.points[1].ground_speed_kts, Some(95.0));
+        assert_eq!(trace.points[2].alt, None);
+        let track = trace.track();
+        assert_eq!(
+            track.iter().map(|p| p.2).collect::<Vec<_>>(),
+            vec![0, 1500, 1500, 2000]
+        );
+    }
+
+    // Writes a scenario aircraft's positions as a gzipped trace file.
+    fn write_trace(responses: &[adsbx_json::v2::Response], hex: &str, path: &str) {
+        let base = responses[0].now;
+        let points: Vec<Value> = responses
+            .iter()
+            .filter_map(|r| {
+                let ac = r.aircraft.iter().find(|ac| ac.hex == hex)?;
+                let offset = (r.now - base).num_milliseconds() as f64 / 1000.0;
+                Some(serde_json::json!([
+                    offset,
+                    ac.lat,
+                    ac.lon,
+                    ac.geometric_altitude,
+                    ac.ground_speed_knots,
+                    ac.track,
+                    0,
+                    null,
+                    null
+                ]))
+            })
+            .collect();
+        let json = serde_json::json!({
+            "icao": hex,
+            "timestamp": base.timestamp_millis() as f64 / 1000.0,
+            "trace": points,
+        });
+        let file = std::fs::File::create(path).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        gz.write_all(json.to_string().as_bytes()).unwrap();
+        gz.finish().unwrap();
+    }
+
+    #[test]
+    fn test_analyze_traces() {
+        let responses = standard_intercept_scenario().interval_secs(5).responses();
+        let dir = tempfile::tempdir().unwrap();
+        // Trace files are gzipped even without a .gz extension.
+        let interceptor_path = dir.path().join("trace_full_ae1234.json");
+        let target_path = dir.path().join("trace_full_a12345.json.gz");
+        let interceptor_path = interceptor_path.to_str().unwrap();
+        let target_path = target_path.to_str().unwrap();
+        write_trace(&responses, "ae1234", interceptor_path);
+        write_trace(&responses, "a12345", target_path);
+        let interceptor = Trace::load(interceptor_path).unwrap();
+        let target = Trace::load(target_path).unwrap();
+        assert_eq!(interceptor.points.len(), responses.len());
+        let event = analyze_track_pair(
+            &interceptor.track(),
+            &target.track(),
+            &DetectionParams::default(),
+        )
+        .unwrap();
+        assert!(event.cpa_lateral_ft < 600.0);
+        let event = analyze_trace_files(interceptor_path, target_path, &DetectionParams::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.interceptor_hex, "ae1234");
+        assert_eq!(event.closest.target.hex.to_string(), "a12345");
+    }
+
+    #[test]
+    fn test_bad_trace() {
+        assert!(matches!(
+            Trace::from_reader(&b"{\"icao\": \"a12345\"}"[..]),
+            Err(Error::Parse { .. })
+        ));
+        match Trace::load(&data_path("no-such-trace.json")) {
+            Err(Error::FileError { source, .. }) => assert!(matches!(*source, Error::Io(_))),
+            other => panic!("Expected FileError, got {:?}", other),
+        }
+    }
+}
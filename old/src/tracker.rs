@@ -0,0 +1,685 @@
+//! Merges the per-snapshot detections of an interception into a single event.
+//!
+//! The detector looks at one snapshot at a time, so an interceptor escorting a
+//! target for ten minutes shows up in dozens of consecutive snapshots.
+//! InterceptionTracker groups those detections by (interceptor, target) pair
+//! and merges detections that are close together in time.
+//!
+//! Interceptors often work in pairs, so group_events can then combine the
+//! events of several interceptors against the same target into one
+//! InterceptionGroup. And on long escorts they take turns, so
+//! link_escort_chains links each target's events that follow on from each
+//! other into an EscortChain.
+
+use std::collections::HashMap;
+
+use chrono::{prelude::*, Duration};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cpa::{compute_cpa, separation_series, Cpa},
+    error::Error,
+    hex_id::HexId,
+    interception::{EmergencySquawk, Interception},
+    persist,
+    recording::PairTracks,
+};
+
+/// The default maximum time between two detections of the same pair for them
+/// to be merged into one event.
+pub const DEFAULT_MERGE_GAP_MINS: i64 = 10;
+
+/// The default maximum time between one event ending and another against the
+/// same target starting for them to be in the same escort chain.
+pub const DEFAULT_HANDOFF_GAP_MINS: i64 = 15;
+
+/// One interception, merged from all the snapshots it was detected in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterceptionEvent {
+    pub interceptor_hex: String,
+    pub target_hex: String,
+    /// The time the aircraft first came close.
+    pub start: DateTime<Utc>,
+    /// The time of the last detection.
+    pub end: DateTime<Utc>,
+    pub duration_secs: i64,
+    pub min_lateral_separation_ft: f64,
+    pub min_vertical_separation_ft: i32,
+    /// The number of snapshots the interception was detected in.
+    pub num_detections: usize,
+    /// The detection with the smallest lateral separation.
+    pub closest: Interception,
+    /// The closest point of approach, interpolated from the aircraft tracks.
+    pub cpa_time: DateTime<Utc>,
+    pub cpa_lateral_ft: f64,
+    pub cpa_vertical_ft: f64,
+    pub closing_speed_kts: f64,
+    /// See Cpa::low_confidence.
+    pub cpa_low_confidence: bool,
+    /// The emergency the target squawked during the interception, if any.
+    /// If it changed, the most recent one.
+    #[serde(default)]
+    pub emergency: Option<EmergencySquawk>,
+    /// The escort chain the event is part of, if link_escort_chains has been
+    /// run. See EscortChain.
+    #[serde(default)]
+    pub escort_chain_id: Option<String>,
+    /// The highest confidence of any of its detections. See the confidence
+    /// module.
+    #[serde(default)]
+    pub confidence: Option<f64>,
+    /// The (time, lateral separation ft, vertical separation ft) at each
+    /// snapshot while both aircraft were tracked, interpolated from their
+    /// tracks. It can be long, so it's left out of JSON when it's empty; see
+    /// InterceptionGroup::clear_separation_series.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub separation_series: Vec<(DateTime<Utc>, f64, i32)>,
+    /// With record_event_tracks, the tracks of both aircraft for the whole
+    /// event, from when they were first close. Like separation_series, it's
+    /// left out of JSON when it's empty; see InterceptionGroup::clear_tracks.
+    #[serde(default, skip_serializing_if = "PairTracks::is_empty")]
+    pub tracks: PairTracks,
+}
+
+/// Computes the CPA from a detection's tracks, or uses the detection itself
+/// if that's not possible.
+fn detection_cpa(interception: &Interception) -> Cpa {
+    compute_cpa(&interception.interceptor, &interception.target).unwrap_or(Cpa {
+        time: interception.time,
+        lateral_ft: interception.lateral_separation_ft,
+        vertical_ft: interception.vertical_separation_ft as f64,
+        closing_speed_kts: 0.0,
+        low_confidence: true,
+    })
+}
+
+// Returns a copy of a detection without its recorded tracks, which are kept
+// in the event instead.
+fn without_tracks(interception: &Interception) -> Interception {
+    Interception {
+        tracks: None,
+        ..interception.clone()
+    }
+}
+
+impl InterceptionEvent {
+    fn new(interception: &Interception) -> Self {
+        let cpa = detection_cpa(interception);
+        InterceptionEvent {
+            interceptor_hex: interception.interceptor.hex.to_string(),
+            target_hex: interception.target.hex.to_string(),
+            start: interception.proximity_start,
+            end: interception.time,
+            duration_secs: 0,
+            min_lateral_separation_ft: interception.lateral_separation_ft,
+            min_vertical_separation_ft: interception.vertical_separation_ft,
+            num_detections: 1,
+            closest: without_tracks(interception),
+            cpa_time: cpa.time,
+            cpa_lateral_ft: cpa.lateral_ft,
+            cpa_vertical_ft: cpa.vertical_ft,
+            closing_speed_kts: cpa.closing_speed_kts,
+            cpa_low_confidence: cpa.low_confidence,
+            emergency: interception.emergency,
+            escort_chain_id: None,
+            confidence: interception.confidence,
+            separation_series: separation_series(&interception.interceptor, &interception.target),
+            tracks: interception.tracks.clone().unwrap_or_default(),
+        }
+    }
+
+    fn cpa_distance_ft(&self) -> f64 {
+        self.cpa_lateral_ft.hypot(self.cpa_vertical_ft)
+    }
+
+    fn merge(&mut self, interception: &Interception) {
+        self.start = self.start.min(interception.proximity_start);
+        self.end = self.end.max(interception.time);
+        self.duration_secs = (self.end - self.start).num_seconds();
+        self.confidence = match (self.confidence, interception.confidence) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.min_vertical_separation_ft = self
+            .min_vertical_separation_ft
+            .min(interception.vertical_separation_ft);
+        if interception.lateral_separation_ft < self.min_lateral_separation_ft {
+            self.min_lateral_separation_ft = interception.lateral_separation_ft;
+            self.closest = without_tracks(interception);
+        }
+        self.num_detections += 1;
+        if let Some(tracks) = &interception.tracks {
+            self.tracks.extend(tracks);
+        }
+        if interception.emergency.is_some() {
+            self.emergency = interception.emergency;
+        }
+        // Each detection has the tracks up to that time, which extend the
+        // series (earlier parts may have been trimmed from the history).
+        let last = self.separation_series.last().map(|s| s.0);
+        self.separation_series.extend(
+            separation_series(&interception.interceptor, &interception.target)
+                .into_iter()
+                .filter(|s| last.is_none_or(|last| s.0 > last)),
+        );
+        // And the CPA may have gotten closer.
+        let cpa = detection_cpa(interception);
+        if cpa.lateral_ft.hypot(cpa.vertical_ft) < self.cpa_distance_ft() {
+            self.cpa_time = cpa.time;
+            self.cpa_lateral_ft = cpa.lateral_ft;
+            self.cpa_vertical_ft = cpa.vertical_ft;
+            self.closing_speed_kts = cpa.closing_speed_kts;
+            self.cpa_low_confidence = cpa.low_confidence;
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Returns how fast the aircraft were closing laterally at time t, in
+    /// knots (negative if they were getting farther apart), from the
+    /// separation_series samples either side of t. Returns None if t is
+    /// outside the series.
+    pub fn closure_rate_kts_at(&self, t: DateTime<Utc>) -> Option<f64> {
+        let series = &self.separation_series;
+        let i = series.partition_point(|s| s.0 < t);
+        // At a sample, use the segment leading up to it (or the first one).
+        let i = if series.get(i)?.0 == t { i.max(1) } else { i };
+        let (t0, lateral0, _) = series.get(i.checked_sub(1)?)?;
+        let (t1, lateral1, _) = series.get(i)?;
+        let hours = (*t1 - *t0).num_milliseconds() as f64 / 3_600_000.0;
+        Some((lateral0 - lateral1) / 6076.12 / hours)
+    }
+}
+
+/// The events of every interceptor that went after the same target at about
+/// the same time, like the two aircraft of a two-ship.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterceptionGroup {
+    /// Identifies the group, from the target and start time, e.g.
+    /// "a12345-20210223T193853Z".
+    pub event_id: String,
+    pub target_hex: String,
+    /// The earliest start and latest end of the interceptors' events.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Each interceptor's event, with its own CPA, in order of start time.
+    pub interceptors: Vec<InterceptionEvent>,
+    /// The emergency the target squawked, if any.
+    #[serde(default)]
+    pub emergency: Option<EmergencySquawk>,
+}
+
+impl InterceptionGroup {
+    fn new(event: &InterceptionEvent) -> Self {
+        InterceptionGroup {
+            event_id: format!(
+                "{}-{}",
+                event.target_hex,
+                event.start.format("%Y%m%dT%H%M%SZ")
+            ),
+            target_hex: event.target_hex.clone(),
+            start: event.start,
+            end: event.end,
+            interceptors: vec![event.clone()],
+            emergency: event.emergency,
+        }
+    }
+
+    fn add(&mut self, event: &InterceptionEvent) {
+        self.end = self.end.max(event.end);
+        if event.emergency.is_some() {
+            self.emergency = event.emergency;
+        }
+        self.interceptors.push(event.clone());
+    }
+
+    /// Removes each interceptor's separation_series, which makes the JSON
+    /// much smaller.
+    pub fn clear_separation_series(&mut self) {
+        for event in &mut self.interceptors {
+            event.separation_series.clear();
+        }
+    }
+
+    /// Removes each interceptor's recorded tracks.
+    pub fn clear_tracks(&mut self) {
+        for event in &mut self.interceptors {
+            event.tracks.clear();
+        }
+    }
+
+    /// The hex IDs of the interceptors.
+    pub fn interceptor_hexes(&self) -> Vec<&str> {
+        self.interceptors
+            .iter()
+            .map(|e| e.interceptor_hex.as_str())
+            .collect()
+    }
+}
+
+/// Groups events against the same target whose times overlap, or are at most
+/// window apart, into InterceptionGroups. If window is None, every event is
+/// in a group of its own. Groups are in order of start time.
+pub fn group_events(
+    events: &[InterceptionEvent],
+    window: Option<Duration>,
+) -> Vec<InterceptionGroup> {
+    let mut sorted: Vec<&InterceptionEvent> = events.iter().collect();
+    sort_events_by_target(&mut sorted);
+    let mut groups: Vec<InterceptionGroup> = vec![];
+    for event in sorted {
+        match (groups.last_mut(), window) {
+            (Some(group), Some(window))
+                if group.target_hex == event.target_hex && event.start - group.end <= window =>
+            {
+                group.add(event)
+            }
+            _ => groups.push(InterceptionGroup::new(event)),
+        }
+    }
+    groups.sort_by(|a, b| (a.start, &a.target_hex).cmp(&(b.start, &b.target_hex)));
+    groups
+}
+
+fn sort_events_by_target(events: &mut [&InterceptionEvent]) {
+    events.sort_by(|a, b| {
+        (&a.target_hex, a.start, &a.interceptor_hex).cmp(&(
+            &b.target_hex,
+            b.start,
+            &b.interceptor_hex,
+        ))
+    });
+}
+
+/// A target's events that follow on from each other, with at most a handoff
+/// gap between one ending and the next starting, whichever interceptor they
+/// were with. E.g. an escort where interceptors took turns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EscortChain {
+    /// Identifies the chain, from the target and start time, e.g.
+    /// "a12345-escort-20210223T193853Z".
+    pub escort_chain_id: String,
+    pub target_hex: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// The interceptors, in the order they first joined.
+    pub interceptor_hexes: Vec<String>,
+    pub num_events: usize,
+}
+
+impl EscortChain {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Describes the chain, e.g. "a12345 escorted continuously for 2h40m by 3
+    /// aircraft".
+    pub fn description(&self) -> String {
+        let mins = self.duration().num_minutes();
+        let duration = if mins >= 60 {
+            format!("{}h{:02}m", mins / 60, mins % 60)
+        } else {
+            format!("{}m", mins)
+        };
+        format!(
+            "{} escorted continuously for {} by {} aircraft",
+            self.target_hex,
+            duration,
+            self.interceptor_hexes.len()
+        )
+    }
+}
+
+/// Links events against the same target into EscortChains, where each event
+/// starts at most gap after the previous ones ended, and sets their
+/// escort_chain_id. The same interceptor coming back extends the chain just
+/// like a different one would. Chains are in order of start time.
+pub fn link_escort_chains(events: &mut [InterceptionEvent], gap: Duration) -> Vec<EscortChain> {
+    let mut order: Vec<usize> = (0..events.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&events[a], &events[b]);
+        (&a.target_hex, a.start, &a.interceptor_hex).cmp(&(
+            &b.target_hex,
+            b.start,
+            &b.interceptor_hex,
+        ))
+    });
+    let mut chains: Vec<EscortChain> = vec![];
+    for i in order {
+        let event = &mut events[i];
+        match chains.last_mut() {
+            Some(chain)
+                if chain.target_hex == event.target_hex && event.start - chain.end <= gap =>
+            {
+                chain.end = chain.end.max(event.end);
+                chain.num_events += 1;
+                if !chain.interceptor_hexes.contains(&event.interceptor_hex) {
+                    chain.interceptor_hexes.push(event.interceptor_hex.clone());
+                }
+            }
+            _ => chains.push(EscortChain {
+                escort_chain_id: format!(
+                    "{}-escort-{}",
+                    event.target_hex,
+                    event.start.format("%Y%m%dT%H%M%SZ")
+                ),
+                target_hex: event.target_hex.clone(),
+                start: event.start,
+                end: event.end,
+                interceptor_hexes: vec![event.interceptor_hex.clone()],
+                num_events: 1,
+            }),
+        }
+        event.escort_chain_id = chains.last().map(|c| c.escort_chain_id.clone());
+    }
+    chains.sort_by(|a, b| (a.start, &a.target_hex).cmp(&(b.start, &b.target_hex)));
+    chains
+}
+
+// Saves a Duration as a number of seconds.
+mod duration_secs {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_i64(duration.num_seconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        i64::deserialize(d).map(Duration::seconds)
+    }
+}
+
+/// Groups per-snapshot detections into InterceptionEvents.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterceptionTracker {
+    #[serde(with = "duration_secs")]
+    gap: Duration,
+    /// Events that could still be extended, keyed by (interceptor hex, target
+    /// hex).
+    open: HashMap<(HexId, HexId), InterceptionEvent>,
+}
+
+impl Default for InterceptionTracker {
+    fn default() -> Self {
+        InterceptionTracker::new(Duration::minutes(DEFAULT_MERGE_GAP_MINS))
+    }
+}
+
+fn sort_events(events: &mut [InterceptionEvent]) {
+    events.sort_by(|a, b| {
+        (a.start, &a.interceptor_hex, &a.target_hex).cmp(&(
+            b.start,
+            &b.interceptor_hex,
+            &b.target_hex,
+        ))
+    });
+}
+
+impl InterceptionTracker {
+    /// Creates a tracker that merges detections of the same pair that are at
+    /// most gap apart.
+    pub fn new(gap: Duration) -> Self {
+        InterceptionTracker {
+            gap,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Changes how far apart detections of the same pair can be and still be
+    /// merged.
+    pub fn set_gap(&mut self, gap: Duration) {
+        self.gap = gap;
+    }
+
+    /// Adds a detection. If it's too long after the last detection of the same
+    /// pair, that pair's previous event is finished and returned.
+    pub fn add(&mut self, interception: &Interception) -> Option<InterceptionEvent> {
+        let key = (interception.interceptor.hex, interception.target.hex);
+        match self.open.get_mut(&key) {
+            Some(event) if interception.time - event.end <= self.gap => {
+                event.merge(interception);
+                None
+            }
+            _ => self.open.insert(key, InterceptionEvent::new(interception)),
+        }
+    }
+
+    /// Finishes and returns the events that can't be extended any more
+    /// because nothing has been added to them for longer than the gap.
+    pub fn close_stale(&mut self, now: DateTime<Utc>) -> Vec<InterceptionEvent> {
+        let gap = self.gap;
+        let mut closed = vec![];
+        self.open.retain(|_, event| {
+            if now - event.end > gap {
+                closed.push(event.clone());
+                false
+            } else {
+                true
+            }
+        });
+        sort_events(&mut closed);
+        closed
+    }
+
+    /// Returns the number of open events.
+    pub fn num_open(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Returns the open event for an interceptor/target pair, if there is
+    /// one.
+    pub fn get(&self, interceptor_hex: HexId, target_hex: HexId) -> Option<&InterceptionEvent> {
+        self.open.get(&(interceptor_hex, target_hex))
+    }
+
+    /// Returns the hex IDs of the aircraft in open events, which should be
+    /// pinned in the AircraftStateMap so they aren't expired mid-event.
+    pub fn open_aircraft(&self) -> impl Iterator<Item = HexId> + '_ {
+        self.open
+            .keys()
+            .flat_map(|&(interceptor, target)| [interceptor, target])
+    }
+
+    /// Saves the open events to a file. See the persist module.
+    pub fn save_state(&self, path: &str) -> Result<(), Error> {
+        persist::save(path, self)
+    }
+
+    /// Loads a tracker saved by save_state, dropping the events that
+    /// close_stale would have finished by now. They're dropped rather than
+    /// returned, since they were presumably already over when the state was
+    /// saved, or soon after.
+    pub fn load_state(path: &str, now: DateTime<Utc>) -> Result<Self, Error> {
+        let mut tracker: InterceptionTracker = persist::load(path)?;
+        tracker.discard_stale(now);
+        Ok(tracker)
+    }
+
+    pub(crate) fn discard_stale(&mut self, now: DateTime<Utc>) {
+        let gap = self.gap;
+        self.open.retain(|_, event| now - event.end <= gap);
+    }
+
+    /// Finishes and returns all the open events.
+    pub fn finish(&mut self) -> Vec<InterceptionEvent> {
+        let mut closed: Vec<InterceptionEvent> = self.open.drain().map(|(_, e)| e).collect();
+        sort_events(&mut closed);
+        closed
+    }
+
+    /// Merges a batch of detections into events.
+    pub fn merge_all(interceptions: &[Interception], gap: Duration) -> Vec<InterceptionEvent> {
+        let mut sorted: Vec<&Interception> = interceptions.iter().collect();
+        sorted.sort_by_key(|i| i.time);
+        let mut tracker = InterceptionTracker::new(gap);
+        let mut events: Vec<InterceptionEvent> =
+            sorted.into_iter().filter_map(|i| tracker.add(i)).collect();
+        events.extend(tracker.finish());
+        sort_events(&mut events);
+        events
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::parse_snapshot_filename_time, interception::Ac};
+
+    fn interception(target: &str, mins: i64, lateral_separation_ft: f64) -> Interception {
+        let time = parse_snapshot_filename_time("2021-02-23-193853Z.json").unwrap()
+            + Duration::minutes(mins);
+        let ac = |hex: &str| Ac::for_test(hex, &[(time, [-118.0, 34.0])], 10000);
+        Interception {
+            lateral_separation_ft,
+            vertical_separation_ft: 200,
+            ..Interception::for_test(ac("ae1234"), ac(target), time)
+        }
+    }
+
+    #[test]
+    fn test_merge_detections() {
+        let detections = vec![
+            interception("a12345", 0, 1000.0),
+            interception("a12345", 1, 300.0),
+            interception("a12345", 8, 600.0),
+            interception("b12345", 2, 900.0),
+            // Same pair, but hours later.
+            interception("a12345", 180, 400.0),
+        ];
+        let events = InterceptionTracker::merge_all(&detections, Duration::minutes(10));
+        assert_eq!(events.len(), 3);
+        let first = &events[0];
+        assert_eq!(first.target_hex, "a12345");
+        assert_eq!(first.num_detections, 3);
+        assert_eq!(first.duration(), Duration::minutes(8));
+        assert_eq!(first.duration_secs, 480);
+        assert_eq!(first.min_lateral_separation_ft, 300.0);
+        assert_eq!(first.closest.time, detections[1].time);
+        // One sample from each detection.
+        assert_eq!(first.separation_series.len(), 3);
+        assert_eq!(events[1].target_hex, "b12345");
+        assert_eq!(events[2].start, detections[4].time);
+        assert_eq!(events[2].num_detections, 1);
+        // Single-fix tracks can't be interpolated.
+        assert_eq!(events[2].cpa_time, detections[4].time);
+        assert!(events[2].cpa_low_confidence);
+    }
+
+    #[test]
+    fn test_group_events() {
+        let wingman = |target, mins| {
+            let mut detection = interception(target, mins, 500.0);
+            detection.interceptor.hex = "ae5678".parse().unwrap();
+            detection
+        };
+        let detections = vec![
+            interception("a12345", 0, 1000.0),
+            interception("a12345", 5, 400.0),
+            wingman("a12345", 2),
+            wingman("a12345", 6),
+            interception("b12345", 3, 900.0),
+            // The wingman comes back an hour later.
+            wingman("a12345", 60),
+        ];
+        let events = InterceptionTracker::merge_all(&detections, Duration::minutes(10));
+        assert_eq!(events.len(), 4);
+        let groups = group_events(&events, Some(Duration::zero()));
+        assert_eq!(groups.len(), 3);
+        let two_ship = &groups[0];
+        assert_eq!(two_ship.event_id, "a12345-20210223T193853Z");
+        assert_eq!(two_ship.interceptor_hexes(), ["ae1234", "ae5678"]);
+        assert_eq!(two_ship.start, detections[0].time);
+        assert_eq!(two_ship.end, detections[3].time);
+        // Each interceptor keeps its own figures.
+        assert_eq!(two_ship.interceptors[0].min_lateral_separation_ft, 400.0);
+        assert_eq!(two_ship.interceptors[1].min_lateral_separation_ft, 500.0);
+        assert_eq!(groups[1].target_hex, "b12345");
+        assert_eq!(groups[2].interceptor_hexes(), ["ae5678"]);
+        // Not grouping at all.
+        assert_eq!(group_events(&events, None).len(), 4);
+    }
+
+    #[test]
+    fn test_escort_chains() {
+        let escort = |hex: &str, mins| {
+            let mut detection = interception("a12345", mins, 500.0);
+            detection.interceptor.hex = hex.parse().unwrap();
+            detection
+        };
+        // ae1234 escorts for an hour, hands off to ae5678 after a 12-minute
+        // gap, and comes back 10 minutes after ae5678 leaves.
+        let mut detections: Vec<Interception> = (0..=60)
+            .step_by(5)
+            .map(|mins| escort("ae1234", mins))
+            .chain((72..=130).step_by(2).map(|mins| escort("ae5678", mins)))
+            .chain((140..=160).step_by(5).map(|mins| escort("ae1234", mins)))
+            .collect();
+        // A different target.
+        detections.push(interception("b12345", 30, 500.0));
+        // Much later, after a gap longer than the handoff gap.
+        detections.push(escort("ae9999", 200));
+        let mut events = InterceptionTracker::merge_all(&detections, Duration::minutes(10));
+        assert_eq!(events.len(), 5);
+        let chains = link_escort_chains(&mut events, Duration::minutes(DEFAULT_HANDOFF_GAP_MINS));
+        assert_eq!(chains.len(), 3);
+        let chain = &chains[0];
+        assert_eq!(chain.escort_chain_id, "a12345-escort-20210223T193853Z");
+        assert_eq!(chain.interceptor_hexes, ["ae1234", "ae5678"]);
+        assert_eq!(chain.num_events, 3);
+        assert_eq!(chain.duration(), Duration::minutes(160));
+        assert_eq!(
+            chain.description(),
+            "a12345 escorted continuously for 2h40m by 2 aircraft"
+        );
+        assert_eq!(chains[1].target_hex, "b12345");
+        assert_eq!(chains[2].interceptor_hexes, ["ae9999"]);
+        let in_chain = events
+            .iter()
+            .filter(|e| e.escort_chain_id.as_deref() == Some(chain.escort_chain_id.as_str()))
+            .count();
+        assert_eq!(in_chain, 3);
+    }
+
+    #[test]
+    fn test_closure_rate() {
+        let mut event = InterceptionEvent::new(&interception("a12345", 0, 500.0));
+        let start = event.start;
+        let at = |secs| start + Duration::seconds(secs);
+        // Closing 6076 ft (1 nm) in 30 seconds is 120 knots, then opening at
+        // 60 knots.
+        event.separation_series = vec![
+            (at(0), 7076.12, 0),
+            (at(30), 1000.0, 0),
+            (at(60), 4038.06, 0),
+        ];
+        assert!((event.closure_rate_kts_at(at(10)).unwrap() - 120.0).abs() < 1e-6);
+        assert!((event.closure_rate_kts_at(at(30)).unwrap() - 120.0).abs() < 1e-6);
+        assert!((event.closure_rate_kts_at(at(45)).unwrap() + 60.0).abs() < 1e-6);
+        assert!((event.closure_rate_kts_at(at(0)).unwrap() - 120.0).abs() < 1e-6);
+        assert_eq!(event.closure_rate_kts_at(at(61)), None);
+        assert_eq!(event.closure_rate_kts_at(at(-1)), None);
+        // The series is only in JSON if it's there.
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            json["separation_series"][1],
+            serde_json::json!(["2021-02-23T19:39:23Z", 1000.0, 0])
+        );
+        event.separation_series.clear();
+        let json = serde_json::to_value(&event).unwrap();
+        assert!(json.get("separation_series").is_none());
+    }
+
+    #[test]
+    fn test_close_stale() {
+        let mut tracker = InterceptionTracker::default();
+        assert!(tracker.add(&interception("a12345", 0, 500.0)).is_none());
+        let now = |mins| interception("a12345", mins, 0.0).time;
+        assert!(tracker.close_stale(now(10)).is_empty());
+        assert_eq!(tracker.close_stale(now(11)).len(), 1);
+        assert!(tracker.finish().is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+//! Trimming snapshots down to a bounding box and a window of time, e.g. to
+//! make small test fixtures out of a day of archive files that are hundreds
+//! of MB each.
+//!
+//! crop_response keeps the aircraft inside the box (and any others on a
+//! list), and write_adsbx_json writes the result as compact JSON, optionally
+//! compressed, that load_adsbx_json_file reads back. trim_files does both
+//! for every snapshot in a time range, writing each to a file named for its
+//! time, like ADS-B Exchange's, so that the trimmed files sort and filter by
+//! time like the originals. That's also a way to share a small reproduction
+//! of a detection bug instead of the whole archive.
+
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use adsbx_json::v2::Response;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::Error,
+    filter::{AircraftFilter, BoundingBoxFilter},
+    for_each_adsbx_json_in_range,
+    input::TimeRange,
+    Compression,
+};
+
+/// Returns a copy of a response with only the aircraft inside the box, or
+/// whose hex is in hexes (ignoring case), wherever they are. The total
+/// number of aircraft is the number that are left; everything else,
+/// including the time, is kept.
+pub fn crop_response(
+    response: &Response,
+    bbox: BoundingBoxFilter,
+    hexes: Option<&[String]>,
+) -> Response {
+    let listed =
+        |hex: &str| hexes.is_some_and(|hexes| hexes.iter().any(|h| h.eq_ignore_ascii_case(hex)));
+    let aircraft: Vec<_> = response
+        .aircraft
+        .iter()
+        .filter(|a| bbox.matches(a) || listed(&a.hex))
+        .cloned()
+        .collect();
+    Response {
+        num_aircraft: aircraft.len() as u64,
+        aircraft,
+        ..response.clone()
+    }
+}
+
+/// Writes a response as compact JSON, compressed with compression.
+pub fn write_adsbx_json<W: Write>(
+    writer: W,
+    response: &Response,
+    compression: Compression,
+) -> Result<(), Error> {
+    let write = |w: &mut dyn Write| serde_json::to_writer(w, response).map_err(Error::from_json);
+    match compression {
+        Compression::None => {
+            let mut writer = writer;
+            write(&mut writer)?;
+            writer.flush().map_err(Error::Io)
+        }
+        Compression::Bzip2 => {
+            let mut encoder = bzip2::write::BzEncoder::new(writer, bzip2::Compression::best());
+            write(&mut encoder)?;
+            encoder.finish().map(|_| ()).map_err(Error::Io)
+        }
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+            write(&mut encoder)?;
+            encoder.finish().map(|_| ()).map_err(Error::Io)
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(writer, 0).map_err(Error::Io)?;
+            write(&mut encoder)?;
+            encoder.finish().map(|_| ()).map_err(Error::Io)
+        }
+    }
+}
+
+/// Writes a response to a file, compressed according to its extension (see
+/// Compression::from_path), or not at all if it isn't one of theirs.
+pub fn write_adsbx_json_file(path: &str, response: &Response) -> Result<(), Error> {
+    let compression = Compression::from_path(path).unwrap_or(Compression::None);
+    std::fs::File::create(path)
+        .map_err(Error::Io)
+        .and_then(|file| write_adsbx_json(BufWriter::new(file), response, compression))
+        .map_err(|e| e.in_file(path))
+}
+
+/// The name of the file trim_files writes a snapshot from a time to, e.g.
+/// "2021-02-23-193853Z.json", or "2021-02-23-193853Z.json.bz2" with Bzip2.
+pub fn snapshot_filename(time: DateTime<Utc>, compression: Compression) -> String {
+    format!(
+        "{}{}",
+        time.format("%Y-%m-%d-%H%M%SZ.json"),
+        compression.extension()
+    )
+}
+
+/// Crops each snapshot in the files that's in the time range (see
+/// crop_response), and writes it to a file in out_dir (which is created if
+/// need be) with the compression. Snapshots with no aircraft left are
+/// written too, so the detector still sees how often there were snapshots.
+/// Returns the number of files written.
+pub fn trim_files(
+    paths: &[String],
+    range: &TimeRange,
+    bbox: BoundingBoxFilter,
+    hexes: Option<&[String]>,
+    out_dir: &Path,
+    compression: Compression,
+) -> Result<usize, Error> {
+    std::fs::create_dir_all(out_dir).map_err(Error::Io)?;
+    let mut num_written = 0;
+    for_each_adsbx_json_in_range(paths, false, range, |response, _bar| {
+        let path = out_dir.join(snapshot_filename(response.now, compression));
+        write_adsbx_json_file(
+            &path.to_string_lossy(),
+            &crop_response(&response, bbox, hexes),
+        )?;
+        num_written += 1;
+        Ok(())
+    })?;
+    Ok(num_written)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        load_adsbx_json_file,
+        testutil::{Scenario, ScriptedAircraft},
+    };
+    use chrono::{Duration, TimeZone};
+
+    fn responses(start: DateTime<Utc>) -> Vec<Response> {
+        Scenario::new()
+            .start(start)
+            .interval_secs(60)
+            .duration_secs(300)
+            .aircraft(ScriptedAircraft::straight(
+                "a12345",
+                [-118.0, 34.0],
+                10_000.0,
+                90.0,
+                300.0,
+            ))
+            .aircraft(ScriptedAircraft::straight(
+                "a67890",
+                [-80.0, 26.0],
+                30_000.0,
+                0.0,
+                450.0,
+            ))
+            .responses()
+    }
+
+    const SOCAL: BoundingBoxFilter = BoundingBoxFilter {
+        min_lon: -121.0,
+        min_lat: 32.0,
+        max_lon: -114.0,
+        max_lat: 35.0,
+    };
+
+    #[test]
+    fn test_crop_response() {
+        let response = &responses(Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap())[0];
+        assert_eq!(response.num_aircraft, 2);
+        let cropped = crop_response(response, SOCAL, None);
+        assert_eq!(cropped.now, response.now);
+        assert_eq!(cropped.num_aircraft, 1);
+        assert_eq!(cropped.aircraft[0].hex, "a12345");
+        let hexes = ["A67890".to_string()];
+        let cropped = crop_response(response, SOCAL, Some(&hexes));
+        assert_eq!(cropped.num_aircraft, 2);
+        assert_eq!(cropped.aircraft, response.aircraft);
+    }
+
+    #[test]
+    fn test_trim_files() {
+        let start = Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap();
+        let responses = responses(start);
+        let dir = tempfile::tempdir().unwrap();
+        let in_dir = dir.path().join("in");
+        // Doesn't exist yet: trim_files creates it.
+        let out_dir = dir.path().join("out");
+        std::fs::create_dir_all(&in_dir).unwrap();
+        let paths: Vec<String> = responses
+            .iter()
+            .map(|r| {
+                let path = in_dir.join(snapshot_filename(r.now, Compression::None));
+                let path = path.to_str().unwrap().to_string();
+                write_adsbx_json_file(&path, r).unwrap();
+                path
+            })
+            .collect();
+
+        // The last two minutes of the five.
+        let range = TimeRange::new(start + Duration::minutes(3), start + Duration::hours(1));
+        let n = trim_files(&paths, &range, SOCAL, None, &out_dir, Compression::Bzip2).unwrap();
+        assert_eq!(n, 3);
+        let path = out_dir.join("2021-02-23-194353Z.json.bz2");
+        let mut magic = [0; 3];
+        std::io::Read::read_exact(&mut std::fs::File::open(&path).unwrap(), &mut magic).unwrap();
+        assert_eq!(Compression::from_magic(&magic), Compression::Bzip2);
+        let trimmed = load_adsbx_json_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            trimmed,
+            crop_response(responses.last().unwrap(), SOCAL, None)
+        );
+        assert!(!out_dir.join("2021-02-23-194053Z.json.bz2").exists());
+    }
+
+    #[test]
+    fn test_write_adsbx_json() {
+        let response = &responses(Utc.with_ymd_and_hms(2021, 2, 23, 19, 38, 53).unwrap())[0];
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [
+            Compression::None,
+            Compression::Bzip2,
+            Compression::Gzip,
+            Compression::Zstd,
+        ] {
+            let mut buf = vec![];
+            write_adsbx_json(&mut buf, response, compression).unwrap();
+            assert_eq!(Compression::from_magic(&buf), compression);
+            let path = dir
+                .path()
+                .join(snapshot_filename(response.now, compression));
+            std::fs::write(&path, &buf).unwrap();
+            let read = load_adsbx_json_file(path.to_str().unwrap()).unwrap();
+            assert_eq!(&read, response, "{:?}", compression);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Support for the older ADS-B Exchange v1 API format (the Virtual Radar
+//! Server-style "acList" JSON found in archives from 2019 and earlier).
+//!
+//! v1 responses are converted into adsbx_json's v2 representation so the rest
+//! of the crate only has to deal with one format.
+
+use adsbx_json::v2;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::Error;
+
+/// A v1 aircraft record. Only the fields we can map to v2 are parsed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Aircraft {
+    pub icao: String,
+    pub lat: Option<f64>,
+    pub long: Option<f64>,
+    /// Ground speed in knots.
+    pub spd: Option<f64>,
+    /// Pressure altitude in feet.
+    pub alt: Option<i32>,
+    pub gnd: Option<bool>,
+    pub on_ground: Option<bool>,
+    pub trak: Option<f64>,
+    pub call: Option<String>,
+    pub reg: Option<String>,
+    #[serde(rename = "Type")]
+    pub aircraft_type: Option<String>,
+    pub sqk: Option<String>,
+    /// Vertical speed in feet per minute.
+    pub vsi: Option<i32>,
+    pub mil: Option<bool>,
+    pub mlat: Option<bool>,
+    pub tisb: Option<bool>,
+    /// The time the position was reported, in milliseconds since the epoch.
+    pub pos_time: Option<i64>,
+    #[serde(rename = "CMsgs")]
+    pub num_messages: Option<i32>,
+}
+
+/// A v1 response.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Response {
+    #[serde(rename = "acList", default)]
+    pub aircraft: Vec<Aircraft>,
+    /// Server time, in milliseconds since the epoch.
+    pub stm: i64,
+    #[serde(rename = "totalAc")]
+    pub total: Option<u64>,
+}
+
+impl Aircraft {
+    fn to_v2_json(&self, stm: i64) -> serde_json::Value {
+        let message_type = if self.mlat == Some(true) {
+            "mlat"
+        } else if self.tisb == Some(true) {
+            "tisb_icao"
+        } else {
+            "adsb_icao"
+        };
+        let seen_pos = self.pos_time.map(|t| ((stm - t) as f64 / 1000.0).max(0.0));
+        let alt_baro = if self.gnd.or(self.on_ground) == Some(true) {
+            Some(json!("ground"))
+        } else {
+            self.alt.map(|alt| json!(alt))
+        };
+        json!({
+            "hex": self.icao.to_lowercase(),
+            "type": message_type,
+            "lat": self.lat,
+            "lon": self.long,
+            "gs": self.spd,
+            "alt_baro": alt_baro,
+            "baro_rate": self.vsi,
+            "track": self.trak,
+            "flight": self.call,
+            "r": self.reg,
+            "t": self.aircraft_type,
+            "squawk": self.sqk,
+            "dbFlags": if self.mil == Some(true) { 1 } else { 0 },
+            "messages": self.num_messages.unwrap_or(0),
+            // v1 doesn't have RSSI.
+            "rssi": 0.0,
+            "seen": seen_pos.unwrap_or(0.0),
+            "seen_pos": seen_pos,
+        })
+    }
+}
+
+impl Response {
+    /// Converts this response into a v2 response. Fields that v1 doesn't have,
+    /// like geometric altitude, are left as None.
+    pub fn to_v2(&self) -> Result<v2::Response, Error> {
+        let aircraft: Vec<serde_json::Value> = self
+            .aircraft
+            .iter()
+            .map(|a| a.to_v2_json(self.stm))
+            .collect();
+        let v2_json = json!({
+            "now": self.stm,
+            "ctime": self.stm,
+            "ptime": 0,
+            "total": self.total.unwrap_or(self.aircraft.len() as u64),
+            "ac": aircraft,
+        });
+        serde_json::from_value(v2_json).map_err(Error::from_json)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_adsbx_json_file;
+    use adsbx_json::v2::AltitudeOrGround;
+
+    #[test]
+    fn test_load_v1() {
+        let path = format!(
+            "{}/tests/data/v1-2019-06-01-120000Z.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let response = load_adsbx_json_file(&path).unwrap();
+        assert_eq!(response.now.timestamp_millis(), 1559390400000);
+        assert_eq!(response.aircraft.len(), 2);
+        let ac = &response.aircraft[0];
+        assert_eq!(ac.hex, "ae1234");
+        assert_eq!(ac.lat, Some(34.1));
+        assert_eq!(ac.lon, Some(-118.3));
+        assert_eq!(ac.ground_speed_knots, Some(452.0));
+        assert_eq!(
+            ac.barometric_altitude,
+            Some(AltitudeOrGround::Altitude(9000))
+        );
+        assert_eq!(ac.geometric_altitude, None);
+        assert_eq!(ac.seen_pos, Some(std::time::Duration::from_secs(2)));
+        assert!(ac.database_flags.is_military());
+        let ac = &response.aircraft[1];
+        assert_eq!(ac.barometric_altitude, Some(AltitudeOrGround::OnGround));
+        assert_eq!(ac.message_type, v2::MessageType::Multilateration);
+    }
+}
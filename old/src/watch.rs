@@ -0,0 +1,396 @@
+//! Watching a spool directory for new snapshot files, for near-real-time
+//! processing of a feeder's output.
+//!
+//! The directory is scanned every scan interval. A file is only loaded once
+//! its size and modification time are the same in two scans in a row, so
+//! files that are still being written are left alone, as are files with
+//! temporary names (like "2023-01-02-153405Z.json.tmp") until they're renamed.
+//!
+//! ```no_run
+//! use tracon::watch::{DirectoryWatcher, ProcessedFiles};
+//!
+//! DirectoryWatcher::new("/var/spool/adsb")
+//!     .processed_files(ProcessedFiles::MoveTo("/var/spool/adsb/processed".to_string()))
+//!     .stop_on_ctrl_c()
+//!     .unwrap()
+//!     .run(|response, _bar| {
+//!         println!("{} aircraft", response.aircraft.len());
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use adsbx_json::v2::Response;
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
+use tracing::warn;
+
+use crate::{
+    error::Error,
+    input::{is_input_file, parse_snapshot_filename_time},
+    load_adsbx_json_file,
+    metrics::Metrics,
+    spinner,
+};
+
+/// The default time between scans of the directory.
+pub const DEFAULT_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often to check whether we've been asked to stop while waiting.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+fn watch_error(path: &Path, e: impl std::fmt::Display) -> Error {
+    Error::InputPathError(format!("{}: {}", path.display(), e))
+}
+
+/// What to do with files after they've been processed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ProcessedFiles {
+    /// Leave them where they are; the watcher remembers which ones it's
+    /// done.
+    #[default]
+    Keep,
+    Delete,
+    /// Move them to this directory, which is created if it doesn't exist.
+    MoveTo(String),
+}
+
+/// Counts and timing from a watcher run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchSummary {
+    /// The number of times the directory was scanned.
+    pub scans: usize,
+    /// The number of files that were loaded successfully.
+    pub files_ok: usize,
+    /// The paths of files that couldn't be loaded.
+    pub files_failed: Vec<String>,
+    /// The paths of files that were skipped because they turned up after a
+    /// newer file had already been processed.
+    pub files_late: Vec<String>,
+    pub wall_time: Duration,
+}
+
+/// Watches a directory and calls a callback with the response in each new
+/// file, serially and in filename timestamp order, until it's stopped.
+#[derive(Debug, Clone)]
+pub struct DirectoryWatcher {
+    dir: String,
+    scan_interval: Duration,
+    processed_files: ProcessedFiles,
+    skip_errors: bool,
+    max_scans: Option<usize>,
+    stop: Arc<AtomicBool>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+// The state of a file the last time we looked.
+type FileStamp = (u64, SystemTime);
+
+// Sorts paths by filename timestamp, like expand_input_paths.
+fn sort_by_filename_time(paths: &mut [String]) {
+    paths.sort_by_cached_key(|p| {
+        let time = parse_snapshot_filename_time(p);
+        (time.is_none(), time, p.clone())
+    });
+}
+
+impl DirectoryWatcher {
+    /// Creates a watcher for a directory. Only files directly in it are
+    /// loaded, not ones in subdirectories.
+    pub fn new(dir: &str) -> Self {
+        DirectoryWatcher {
+            dir: dir.to_string(),
+            scan_interval: DEFAULT_SCAN_INTERVAL,
+            processed_files: ProcessedFiles::Keep,
+            skip_errors: false,
+            max_scans: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            metrics: None,
+        }
+    }
+
+    /// Sets the time between scans. A file has to stay the same for this long
+    /// before it's loaded. The default is DEFAULT_SCAN_INTERVAL.
+    pub fn scan_interval(mut self, scan_interval: Duration) -> Self {
+        self.scan_interval = scan_interval;
+        self
+    }
+
+    /// Sets what to do with files after they've been processed. The default
+    /// is to keep them.
+    pub fn processed_files(mut self, processed_files: ProcessedFiles) -> Self {
+        self.processed_files = processed_files;
+        self
+    }
+
+    /// Whether to log files that can't be loaded and keep going, instead of
+    /// stopping with an error. Files that can't be loaded are never deleted
+    /// or moved.
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    /// Stops after this many scans. The default is to keep going until
+    /// stopped.
+    pub fn max_scans(mut self, max_scans: usize) -> Self {
+        self.max_scans = Some(max_scans);
+        self
+    }
+
+    /// Counts files that couldn't be loaded in metrics, e.g. State::metrics.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Returns a flag that stops the watcher when it's set. The watcher
+    /// finishes the file it's in the middle of, if any, and then run
+    /// returns.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Stops the watcher on ctrl-c or SIGTERM, so that run returns normally
+    /// and whatever is tracking interceptions can finish (or save) them. Can
+    /// only be used once per process.
+    pub fn stop_on_ctrl_c(self) -> Result<Self, Error> {
+        let stop = self.stop_handle();
+        ctrlc::set_handler(move || stop.store(true, Ordering::SeqCst))
+            .map_err(|e| Error::SignalError(e.to_string()))?;
+        Ok(self)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    // Sleeps until deadline, or until we're stopped.
+    fn wait_until(&self, deadline: Instant) {
+        while !self.is_stopped() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep((deadline - now).min(STOP_CHECK_INTERVAL));
+        }
+    }
+
+    // Returns the input files in the directory, and their sizes and
+    // modification times.
+    fn scan(&self) -> Result<HashMap<String, FileStamp>, Error> {
+        let dir = Path::new(&self.dir);
+        let mut files = HashMap::new();
+        for entry in std::fs::read_dir(dir).map_err(|e| watch_error(dir, e))? {
+            let path = entry.map_err(|e| watch_error(dir, e))?.path();
+            if !path.is_file() || !is_input_file(&path) {
+                continue;
+            }
+            // The file may have been renamed or removed since we listed it.
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                let modified = metadata.modified().map_err(|e| watch_error(&path, e))?;
+                files.insert(
+                    path.to_string_lossy().into_owned(),
+                    (metadata.len(), modified),
+                );
+            }
+        }
+        Ok(files)
+    }
+
+    // Deletes or moves a file that's been processed.
+    fn finish_file(&self, path: &str) -> Result<(), Error> {
+        let path = Path::new(path);
+        match &self.processed_files {
+            ProcessedFiles::Keep => Ok(()),
+            ProcessedFiles::Delete => std::fs::remove_file(path).map_err(|e| watch_error(path, e)),
+            ProcessedFiles::MoveTo(dir) => {
+                let dir = Path::new(dir);
+                std::fs::create_dir_all(dir).map_err(|e| watch_error(dir, e))?;
+                let dest = dir.join(path.file_name().unwrap_or_default());
+                std::fs::rename(path, &dest).map_err(|e| watch_error(path, e))
+            }
+        }
+    }
+
+    /// Watches until stopped (or max_scans is reached), calling the callback
+    /// with the response from each new file. Files are loaded the same way
+    /// as by load_adsbx_json_file, so compressed files work too.
+    ///
+    /// A file whose timestamp is older than one that's already been
+    /// processed is skipped, since the detector needs to see snapshots in
+    /// time order. Errors returned by the callback stop watching and are
+    /// passed through as-is.
+    pub fn run<F>(self, mut f: F) -> Result<WatchSummary, Error>
+    where
+        F: FnMut(Response, &ProgressBar) -> Result<(), Error>,
+    {
+        let bar = spinner();
+        let start = Instant::now();
+        let mut summary = WatchSummary::default();
+        let mut previous_scan: HashMap<String, FileStamp> = HashMap::new();
+        let mut done: HashSet<String> = HashSet::new();
+        let mut latest: Option<DateTime<Utc>> = None;
+        let mut next_scan = Instant::now();
+        while !self.is_stopped() && self.max_scans.is_none_or(|max| summary.scans < max) {
+            self.wait_until(next_scan);
+            if self.is_stopped() {
+                break;
+            }
+            next_scan = Instant::now() + self.scan_interval;
+            summary.scans += 1;
+            let scan = self.scan()?;
+            // Files that haven't changed since the last scan.
+            let mut ready: Vec<String> = scan
+                .iter()
+                .filter(|(path, stamp)| {
+                    stamp.0 > 0 && previous_scan.get(*path) == Some(stamp) && !done.contains(*path)
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            sort_by_filename_time(&mut ready);
+            for path in ready {
+                if self.is_stopped() {
+                    break;
+                }
+                let time = parse_snapshot_filename_time(&path);
+                if time.is_some() && time < latest {
+                    warn!(
+                        "Skipping {}, which is older than files already processed",
+                        path
+                    );
+                    summary.files_late.push(path.clone());
+                } else {
+                    match load_adsbx_json_file(&path) {
+                        Ok(response) => {
+                            latest = latest.max(time);
+                            summary.files_ok += 1;
+                            if let Err(e) = f(response, &bar) {
+                                bar.finish();
+                                return Err(e);
+                            }
+                            bar.inc(1);
+                        }
+                        Err(e) => {
+                            warn!("Error loading {}: {}", path, e);
+                            summary.files_failed.push(path.clone());
+                            if let Some(metrics) = &self.metrics {
+                                Metrics::inc(&metrics.parse_errors);
+                            }
+                            if !self.skip_errors {
+                                bar.finish();
+                                return Err(Error::FileError {
+                                    path,
+                                    source: Box::new(e),
+                                });
+                            }
+                            done.insert(path);
+                            continue;
+                        }
+                    }
+                }
+                self.finish_file(&path)?;
+                done.insert(path);
+            }
+            // Forget files that have gone away, so a new file with the same
+            // name gets processed.
+            done.retain(|path| scan.contains_key(path));
+            previous_scan = scan;
+        }
+        bar.finish();
+        summary.wall_time = start.elapsed();
+        Ok(summary)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_path(name: &str) -> String {
+        format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_watch_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let processed = dir.join("processed");
+        let copy = |name: &str, dest: &str| {
+            std::fs::copy(data_path(name), dir.join(dest)).unwrap();
+        };
+        copy("2021-02-23-193858Z.json.bz2", "2021-02-23-193858Z.json.bz2");
+        copy("2021-02-23-193853Z.json.gz", "2021-02-23-193853Z.json.gz");
+        // Still being written, or waiting to be renamed.
+        std::fs::write(dir.join("2021-02-23-193903Z.json"), "").unwrap();
+        copy("2021-02-23-193858Z.json", "2021-02-23-193908Z.json.tmp");
+
+        let watcher = DirectoryWatcher::new(dir.to_str().unwrap())
+            .scan_interval(Duration::from_millis(10))
+            .processed_files(ProcessedFiles::MoveTo(
+                processed.to_string_lossy().into_owned(),
+            ))
+            .max_scans(3);
+        let mut nows = vec![];
+        let summary = watcher
+            .clone()
+            .run(|response, _bar| {
+                nows.push(response.now);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(summary.scans, 3);
+        assert_eq!(summary.files_ok, 2);
+        assert_eq!(nows.len(), 2);
+        assert!(nows[0] < nows[1]);
+        assert!(processed.join("2021-02-23-193853Z.json.gz").exists());
+        assert!(!dir.join("2021-02-23-193853Z.json.gz").exists());
+        assert!(dir.join("2021-02-23-193908Z.json.tmp").exists());
+
+        // Once the files are finished, they're picked up.
+        std::fs::copy(
+            data_path("2021-02-23-193858Z.json"),
+            dir.join("2021-02-23-193903Z.json"),
+        )
+        .unwrap();
+        std::fs::rename(
+            dir.join("2021-02-23-193908Z.json.tmp"),
+            dir.join("2021-02-23-193908Z.json"),
+        )
+        .unwrap();
+        // And one that turns up after a newer one has been processed is
+        // skipped.
+        let late = dir.join("2021-02-23-193853Z.json");
+        let mut num_responses = 0;
+        let summary = watcher
+            .processed_files(ProcessedFiles::Delete)
+            .max_scans(4)
+            .run(|_, _| {
+                if num_responses == 0 {
+                    std::fs::copy(data_path("2021-02-23-193853Z.json"), &late).unwrap();
+                }
+                num_responses += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(summary.files_ok, 2);
+        assert_eq!(num_responses, 2);
+        assert_eq!(
+            summary.files_late,
+            vec![late.to_string_lossy().into_owned()]
+        );
+        assert!(!dir.join("2021-02-23-193903Z.json").exists());
+        assert!(!late.exists());
+    }
+}
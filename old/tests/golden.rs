@@ -0,0 +1,241 @@
+//! Regression tests of the detector on small bundled snapshot archives: the
+//! golden cases in tests/data/golden. Each case is a directory of trimmed
+//! snapshots and an entry in cases.json listing the interceptions the
+//! detector should find in them, if any. See tests/data/golden/README.md for
+//! how to add one. None are from real archives yet, so test_golden_real_cases
+//! is ignored (see the README).
+
+use std::{collections::BTreeSet, path::Path};
+
+use serde::Deserialize;
+use tracon::{
+    for_each_adsbx_json_ordered,
+    input::expand_input_paths,
+    interception::{process_adsbx_response, State},
+    order::InputOrder,
+};
+
+const GOLDEN_DIR: &str = "tests/data/golden";
+
+/// Fixtures bigger than this should be trimmed further.
+const MAX_FIXTURE_BYTES: u64 = 1 << 20;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: String,
+    description: String,
+    /// Where the snapshots came from.
+    source: String,
+    /// The [interceptor_hex, target_hex] of each event that should be
+    /// found. Empty for a known false positive situation.
+    expected: Vec<[String; 2]>,
+}
+
+fn cases() -> Vec<Case> {
+    let json = std::fs::read_to_string(Path::new(GOLDEN_DIR).join("cases.json")).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+// Runs the detector on a case's snapshots, with the default settings, and
+// returns the [interceptor_hex, target_hex] of each event.
+fn detect(case: &Case) -> BTreeSet<[String; 2]> {
+    let dir = Path::new(GOLDEN_DIR).join(&case.name);
+    let paths = expand_input_paths(&[dir.to_str().unwrap().to_string()]).unwrap();
+    assert!(!paths.is_empty(), "{}: no snapshots", case.name);
+    let mut state = State::default();
+    for_each_adsbx_json_ordered(&paths, false, InputOrder::Strict, |response, bar| {
+        process_adsbx_response(&mut state, response, bar)
+    })
+    .unwrap();
+    state.finish();
+    state
+        .events
+        .iter()
+        .map(|e| [e.interceptor_hex.clone(), e.target_hex.clone()])
+        .collect()
+}
+
+#[test]
+fn test_golden_cases() {
+    let cases = cases();
+    assert!(cases.iter().any(|c| c.expected.is_empty()));
+    assert!(cases.iter().any(|c| !c.expected.is_empty()));
+    for case in &cases {
+        assert!(!case.description.is_empty() && !case.source.is_empty());
+        let expected: BTreeSet<_> = case.expected.iter().cloned().collect();
+        assert_eq!(
+            detect(case),
+            expected,
+            "{}: {}",
+            case.name,
+            case.description
+        );
+    }
+}
+
+#[test]
+fn test_golden_fixture_sizes() {
+    for case in cases() {
+        let dir = Path::new(GOLDEN_DIR).join(&case.name);
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let entry = entry.unwrap();
+            let len = entry.metadata().unwrap().len();
+            assert!(
+                len <= MAX_FIXTURE_BYTES,
+                "{:?} is {} bytes",
+                entry.path(),
+                len
+            );
+        }
+    }
+}
+
+/// Checks that there are at least two real interceptions and two real false
+/// positives, cut from ADS-B Exchange archives, among the cases. Their names
+/// start with "archive-". There aren't any yet, so it fails until they're
+/// added.
+#[test]
+#[ignore = "no cases from real archives yet; see tests/data/golden/README.md"]
+fn test_golden_real_cases() {
+    let real: Vec<Case> = cases()
+        .into_iter()
+        .filter(|c| c.name.starts_with("archive-"))
+        .collect();
+    let interceptions = real.iter().filter(|c| !c.expected.is_empty()).count();
+    let false_positives = real.len() - interceptions;
+    assert!(
+        interceptions >= 2 && false_positives >= 2,
+        "{} real interceptions and {} real false positives",
+        interceptions,
+        false_positives
+    );
+}
+
+/// Rewrites the scripted cases' snapshots. They're generated with testutil
+/// with traffic far away that trimming takes out again, like real archives.
+/// Run with `cargo test --features testing --test golden -- --ignored
+/// regenerate_scripted_cases`.
+#[cfg(feature = "testing")]
+#[test]
+#[ignore]
+fn regenerate_scripted_cases() {
+    use tracon::{
+        filter::BoundingBoxFilter,
+        input::TimeRange,
+        testutil::{
+            standard_intercept_aircraft, standard_intercept_scenario, Scenario, ScriptedAircraft,
+        },
+        trim::{snapshot_filename, trim_files, write_adsbx_json_file},
+        Compression,
+    };
+
+    let scenario = || Scenario::new().interval_secs(15).duration_secs(600);
+    // The standard intercept's slow target flying east at 10,000 ft.
+    let target = || standard_intercept_aircraft().0;
+    let scripted: Vec<(&str, Scenario)> = vec![
+        ("scripted-intercept", standard_intercept_scenario()),
+        ("scripted-intercept-noisy", {
+            let (target, interceptor) = standard_intercept_aircraft();
+            scenario()
+                .noise(30.0, 40.0)
+                .seed(3)
+                .aircraft(interceptor)
+                .aircraft(target.coverage_gap(330, 360))
+        }),
+        ("scripted-intercept-from-south", {
+            let target =
+                ScriptedAircraft::straight("a4b2c1", [-118.0, 34.0], 6_500.0, 270.0, 150.0);
+            scenario()
+                .aircraft(
+                    ScriptedAircraft::intercepting("ae5678", &target, 20.0, 160.0, 15_000.0, 450.0)
+                        .vertical_rate(3000.0),
+                )
+                .aircraft(target)
+                .aircraft(ScriptedAircraft::straight(
+                    "a00001",
+                    [-118.3, 34.2],
+                    35_000.0,
+                    90.0,
+                    460.0,
+                ))
+        }),
+        ("scripted-overflight", {
+            // Heads straight south 300 ft above where the target will be
+            // after 3 minutes, without slowing down.
+            let crossing_point = [-118.0 + 15.0 / 60.0 / 34f64.to_radians().cos(), 34.0];
+            let start = [crossing_point[0], 34.0 + 25.0 / 60.0];
+            scenario()
+                .aircraft(target())
+                .aircraft(ScriptedAircraft::straight(
+                    "ae1234", start, 10_300.0, 180.0, 500.0,
+                ))
+        }),
+        ("scripted-fast-formation", {
+            // Two fast jets flying together: neither is slow enough to be a
+            // target.
+            let lead = ScriptedAircraft::straight("ae1234", [-118.0, 34.0], 20_000.0, 90.0, 450.0);
+            scenario()
+                .aircraft(ScriptedAircraft::intercepting(
+                    "ae1235", &lead, 0.2, 270.0, 20_000.0, 450.0,
+                ))
+                .aircraft(lead)
+        }),
+        ("scripted-parallel-airliners", {
+            // Two airliners on parallel tracks 2,000 ft apart, with no fast
+            // mover anywhere.
+            scenario()
+                .aircraft(ScriptedAircraft::straight(
+                    "a11111",
+                    [-118.0, 34.0],
+                    11_000.0,
+                    250.0,
+                    250.0,
+                ))
+                .aircraft(ScriptedAircraft::straight(
+                    "a22222",
+                    [-118.0, 34.0055],
+                    11_000.0,
+                    250.0,
+                    250.0,
+                ))
+        }),
+    ];
+
+    let bbox = BoundingBoxFilter {
+        min_lon: -119.5,
+        min_lat: 33.0,
+        max_lon: -116.5,
+        max_lat: 35.0,
+    };
+    for (name, scenario) in scripted {
+        let responses = scenario
+            .aircraft(ScriptedAircraft::straight(
+                "a99999",
+                [-80.0, 26.0],
+                37_000.0,
+                0.0,
+                480.0,
+            ))
+            .responses();
+        let raw_dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = responses
+            .iter()
+            .map(|r| {
+                let path = raw_dir
+                    .path()
+                    .join(snapshot_filename(r.now, Compression::None));
+                let path = path.to_str().unwrap().to_string();
+                write_adsbx_json_file(&path, r).unwrap();
+                path
+            })
+            .collect();
+        let out_dir = Path::new(GOLDEN_DIR).join(name);
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let range = TimeRange::new(
+            responses[0].now,
+            responses.last().unwrap().now + chrono::Duration::seconds(1),
+        );
+        trim_files(&paths, &range, bbox, None, &out_dir, Compression::None).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+//! End-to-end tests of the detector on scripted scenarios. They double as
+//! examples of what it does and doesn't count as an interception.
+//!
+//! Run with `cargo test --features testing`.
+
+use indicatif::ProgressBar;
+use tracon::{
+    interception::{process_adsbx_response, State},
+    testutil::{Scenario, ScriptedAircraft},
+    tracker::InterceptionEvent,
+};
+
+// Runs the detector on a scenario and returns the interception events.
+fn detect(scenario: Scenario) -> Vec<InterceptionEvent> {
+    let mut state = State::default();
+    let bar = ProgressBar::hidden();
+    for response in scenario.responses() {
+        process_adsbx_response(&mut state, response, &bar).unwrap();
+    }
+    state.finish();
+    state.events
+}
+
+// A slow target flying east at 10,000 ft.
+fn target() -> ScriptedAircraft {
+    ScriptedAircraft::straight("a12345", [-118.0, 34.0], 10_000.0, 90.0, 300.0)
+}
+
+// A fast mover that starts 25 nm north of the target, at 20,000 ft, and
+// descends to join it.
+fn interceptor(target: &ScriptedAircraft) -> ScriptedAircraft {
+    ScriptedAircraft::intercepting("ae1234", target, 25.0, 0.0, 20_000.0, 480.0)
+        .vertical_rate(4000.0)
+}
+
+fn scenario() -> Scenario {
+    Scenario::new().interval_secs(15).duration_secs(600)
+}
+
+/// The classic intercept: a fast mover converges on a slow target from far
+/// away, slows down to its speed, and flies alongside it.
+#[test]
+fn test_intercept() {
+    let target = target();
+    let events = detect(scenario().aircraft(interceptor(&target)).aircraft(target));
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event.interceptor_hex, "ae1234");
+    assert_eq!(event.target_hex, "a12345");
+    assert!(event.min_lateral_separation_ft < 600.0);
+    assert!(event.num_detections > 1);
+}
+
+/// Noisy positions and altitudes don't hide it.
+#[test]
+fn test_intercept_with_noise() {
+    for seed in 1..=5 {
+        let target = target();
+        let events = detect(
+            scenario()
+                .noise(30.0, 40.0)
+                .seed(seed)
+                .aircraft(interceptor(&target))
+                .aircraft(target),
+        );
+        assert_eq!(events.len(), 1, "seed {}", seed);
+    }
+}
+
+/// A fast mover that flies right over the target without slowing down isn't
+/// intercepting it, however close they get.
+#[test]
+fn test_overflight() {
+    let target = target();
+    // It starts 25 nm north of where the target will be after 3 minutes,
+    // and heads straight south 300 ft above it.
+    let crossing_point = [-118.0 + 15.0 / 60.0 / 34f64.to_radians().cos(), 34.0];
+    let start = [crossing_point[0], 34.0 + 25.0 / 60.0];
+    let fast_mover = ScriptedAircraft::straight("ae1234", start, 10_300.0, 180.0, 500.0);
+    assert!(detect(scenario().aircraft(target).aircraft(fast_mover)).is_empty());
+}
+
+/// If the approach happens out of coverage, so the interceptor only shows up
+/// once it's already alongside at the target's speed, there's nothing to
+/// say it was ever a fast mover, or converging.
+#[test]
+fn test_approach_out_of_coverage() {
+    let target = target();
+    let interceptor = interceptor(&target).coverage_gap(0, 300);
+    assert!(detect(scenario().aircraft(interceptor).aircraft(target)).is_empty());
+}
+
+/// A target that briefly drops out of coverage while the interceptor is
+/// joining up (for less than dead_reckoning_horizon_secs) doesn't reset the
+/// count of snapshots they've been close for.
+#[test]
+fn test_gap_while_joining() {
+    let target = target();
+    let without_gap = detect(
+        scenario()
+            .aircraft(interceptor(&target))
+            .aircraft(target.clone()),
+    );
+    let events = detect(
+        scenario()
+            .aircraft(interceptor(&target))
+            .aircraft(target.coverage_gap(330, 360)),
+    );
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].start, without_gap[0].start);
+}